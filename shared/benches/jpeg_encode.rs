@@ -0,0 +1,56 @@
+//! Time JPEG encoding of a 1920×1080 frame at quality 75 with each backend in this build:
+//!
+//! ```text
+//! cargo bench -p shared --bench jpeg_encode
+//! cargo bench -p shared --bench jpeg_encode --features turbojpeg
+//! ```
+
+use std::time::{Duration, Instant};
+
+use shared::config::{BroadcastConfig, EncoderKind};
+use shared::frame::{encode_jpeg, probe_turbojpeg, FrameSource, TestPatternSource};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+const QUALITY: u8 = 75;
+const WARMUP: u32 = 3;
+const ROUNDS: u32 = 30;
+
+fn main() {
+    let frame = TestPatternSource::new(WIDTH, HEIGHT)
+        .capture()
+        .expect("test pattern");
+    println!("{WIDTH}x{HEIGHT}，质量 {QUALITY}，每种编码器 {ROUNDS} 次");
+
+    let mut backends = vec![("image", EncoderKind::Cpu)];
+    match probe_turbojpeg() {
+        Ok(()) => backends.push(("libjpeg-turbo", EncoderKind::Turbojpeg)),
+        Err(err) => println!("[跳过] libjpeg-turbo: {err:#}"),
+    }
+
+    for (name, encoder) in backends {
+        let cfg = BroadcastConfig {
+            jpeg_quality: QUALITY,
+            encoder,
+            ..BroadcastConfig::default()
+        };
+        for _ in 0..WARMUP {
+            encode_jpeg(&frame, &cfg).expect("encode");
+        }
+        let mut total = Duration::ZERO;
+        let mut size = 0;
+        for _ in 0..ROUNDS {
+            let started = Instant::now();
+            let (jpeg, _, _) = encode_jpeg(&frame, &cfg).expect("encode");
+            total += started.elapsed();
+            size = jpeg.len();
+        }
+        let average = total / ROUNDS;
+        println!(
+            "{name:<20} 平均 {:>7.2} ms/帧（上限约 {:>5.1} fps），{} KB",
+            average.as_secs_f64() * 1000.0,
+            1.0 / average.as_secs_f64(),
+            size / 1024
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Time scaling a 3840×2160 frame down to 1280×720 with each `scale_filter`, JPEG encoding
+//! included:
+//!
+//! ```text
+//! cargo bench -p shared --bench scale_filter
+//! ```
+
+use std::time::{Duration, Instant};
+
+use shared::config::{BroadcastConfig, ScaleFilter};
+use shared::frame::{encode_jpeg, FrameSource, TestPatternSource};
+
+const WIDTH: u32 = 3840;
+const HEIGHT: u32 = 2160;
+const TARGET_WIDTH: u32 = 1280;
+const TARGET_HEIGHT: u32 = 720;
+const WARMUP: u32 = 2;
+const ROUNDS: u32 = 10;
+
+fn main() {
+    let frame = TestPatternSource::new(WIDTH, HEIGHT)
+        .capture()
+        .expect("test pattern");
+    println!("{WIDTH}x{HEIGHT} → {TARGET_WIDTH}x{TARGET_HEIGHT}，每种滤镜 {ROUNDS} 次");
+
+    for filter in [
+        ScaleFilter::Nearest,
+        ScaleFilter::Triangle,
+        ScaleFilter::Lanczos,
+    ] {
+        let cfg = BroadcastConfig {
+            max_width: Some(TARGET_WIDTH),
+            max_height: Some(TARGET_HEIGHT),
+            scale_filter: filter,
+            ..BroadcastConfig::default()
+        };
+        for _ in 0..WARMUP {
+            encode_jpeg(&frame, &cfg).expect("encode");
+        }
+        let mut total = Duration::ZERO;
+        for _ in 0..ROUNDS {
+            let started = Instant::now();
+            encode_jpeg(&frame, &cfg).expect("encode");
+            total += started.elapsed();
+        }
+        println!(
+            "{:<10} 平均 {:>7.2} ms/帧",
+            format!("{filter:?}").to_lowercase(),
+            (total / ROUNDS).as_secs_f64() * 1000.0
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Audio helpers shared by both binaries: format conversion and the optional Opus codec.
+//!
+//! Opus is compiled in with the `opus` feature. Without it the coder types still exist so
+//! callers need no `cfg`, but constructing one fails and peers keep exchanging PCM.
+
+#[cfg(not(feature = "opus"))]
+use std::convert::Infallible;
+
+use tracing::debug;
+
+/// Sample rate every Opus frame is encoded at.
+pub const OPUS_SAMPLE_RATE: u32 = 48_000;
+/// Samples per channel in one 20 ms Opus frame, one of the durations Opus accepts.
+pub const OPUS_FRAME_SAMPLES: usize = 960;
+
+/// Whether a device called `name` is the one configured as `wanted` (case-insensitive
+/// substring, so "Headset" picks "Headset Microphone (USB Audio)").
+pub fn device_name_matches(name: &str, wanted: &str) -> bool {
+    let wanted = wanted.trim();
+    !wanted.is_empty() && name.to_lowercase().contains(&wanted.to_lowercase())
+}
+
+/// Converts interleaved i16 audio to the output device's rate and channel count by linear
+/// interpolation. The last input frame and the fractional read position carry over between
+/// chunks so frame boundaries do not click.
+pub struct Resampler {
+    output_rate: u32,
+    output_channels: usize,
+    input_rate: u32,
+    input_channels: usize,
+    /// Read position in input frames, counted from `last`.
+    position: f64,
+    /// Final input frame of the previous chunk, already mixed to the output channels.
+    last: Option<Vec<f32>>,
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32, output_channels: usize) -> Self {
+        Self {
+            output_rate,
+            output_channels,
+            input_rate: output_rate,
+            input_channels: output_channels,
+            position: 0.0,
+            last: None,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        input_rate: u32,
+        input_channels: usize,
+        samples: &[i16],
+        output: &mut impl Extend<i16>,
+    ) {
+        if (input_rate, input_channels) != (self.input_rate, self.input_channels) {
+            debug!(
+                from_rate = input_rate,
+                from_channels = input_channels,
+                to_rate = self.output_rate,
+                to_channels = self.output_channels,
+                "音频格式变化，重置重采样"
+            );
+            self.input_rate = input_rate;
+            self.input_channels = input_channels;
+            self.position = 0.0;
+            self.last = None;
+        }
+
+        let mut frames: Vec<Vec<f32>> = self.last.take().into_iter().collect();
+        frames.extend(
+            samples
+                .chunks_exact(input_channels)
+                .map(|frame| mix_channels(frame, self.output_channels)),
+        );
+        if frames.is_empty() {
+            return;
+        }
+
+        let step = f64::from(input_rate) / f64::from(self.output_rate);
+        let end = (frames.len() - 1) as f64;
+        while self.position < end {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (current, next) = (&frames[index], &frames[index + 1]);
+            output.extend(current.iter().zip(next).map(|(a, b)| {
+                (a + (b - a) * fraction)
+                    .round()
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            }));
+            self.position += step;
+        }
+        self.position -= end;
+        self.last = frames.pop();
+    }
+}
+
+/// Map one input frame onto `channels` outputs: extra outputs repeat the inputs
+/// (mono to stereo), surplus inputs are averaged into the outputs (stereo to mono).
+fn mix_channels(frame: &[i16], channels: usize) -> Vec<f32> {
+    (0..channels)
+        .map(|out| {
+            let sources: Vec<f32> = if frame.len() <= channels {
+                vec![f32::from(frame[out % frame.len()])]
+            } else {
+                frame
+                    .iter()
+                    .skip(out)
+                    .step_by(channels)
+                    .map(|&sample| f32::from(sample))
+                    .collect()
+            };
+            sources.iter().sum::<f32>() / sources.len() as f32
+        })
+        .collect()
+}
+
+/// Teacher side: turns captured PCM of any rate into 20 ms Opus packets.
+///
+/// Input is resampled to 48 kHz and mixed to mono or stereo; samples that do not fill a
+/// whole frame are kept for the next call.
+pub struct OpusFrameEncoder {
+    channels: usize,
+    #[cfg(feature = "opus")]
+    resampler: Resampler,
+    #[cfg(feature = "opus")]
+    pending: Vec<i16>,
+    #[cfg(feature = "opus")]
+    encoder: audiopus::coder::Encoder,
+    #[cfg(not(feature = "opus"))]
+    never: Infallible,
+}
+
+/// Student side: decodes Opus packets back to interleaved 48 kHz PCM.
+pub struct OpusFrameDecoder {
+    channels: u8,
+    #[cfg(feature = "opus")]
+    decoder: audiopus::coder::Decoder,
+    #[cfg(not(feature = "opus"))]
+    never: Infallible,
+}
+
+impl OpusFrameEncoder {
+    /// Channel count of the packets produced (1 or 2).
+    pub fn channels(&self) -> u8 {
+        self.channels as u8
+    }
+}
+
+impl OpusFrameDecoder {
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+}
+
+#[cfg(feature = "opus")]
+mod imp {
+    use anyhow::{Context, Result};
+    use audiopus::coder::{Decoder, Encoder};
+    use audiopus::packet::Packet;
+    use audiopus::{Application, Channels, MutSignals, SampleRate};
+
+    use super::{OpusFrameDecoder, OpusFrameEncoder, Resampler, OPUS_FRAME_SAMPLES};
+
+    /// Recommended upper bound for one Opus packet.
+    const MAX_PACKET_BYTES: usize = 4000;
+
+    fn opus_channels(channels: usize) -> Channels {
+        if channels == 1 {
+            Channels::Mono
+        } else {
+            Channels::Stereo
+        }
+    }
+
+    impl OpusFrameEncoder {
+        pub fn new(input_channels: u16) -> Result<Self> {
+            let channels = usize::from(input_channels).clamp(1, 2);
+            let encoder = Encoder::new(
+                SampleRate::Hz48000,
+                opus_channels(channels),
+                Application::Audio,
+            )
+            .context("无法创建 Opus 编码器")?;
+            Ok(Self {
+                resampler: Resampler::new(super::OPUS_SAMPLE_RATE, channels),
+                channels,
+                pending: Vec::with_capacity(OPUS_FRAME_SAMPLES * channels * 2),
+                encoder,
+            })
+        }
+
+        /// Feed interleaved PCM and return every complete packet now available.
+        pub fn encode(
+            &mut self,
+            sample_rate: u32,
+            channels: usize,
+            samples: &[i16],
+        ) -> Result<Vec<Vec<u8>>> {
+            self.resampler
+                .process(sample_rate, channels, samples, &mut self.pending);
+            let frame_len = OPUS_FRAME_SAMPLES * self.channels;
+            let mut packets = Vec::new();
+            while self.pending.len() >= frame_len {
+                let mut packet = vec![0u8; MAX_PACKET_BYTES];
+                let len = self
+                    .encoder
+                    .encode(&self.pending[..frame_len], &mut packet)
+                    .context("Opus 编码失败")?;
+                packet.truncate(len);
+                packets.push(packet);
+                self.pending.drain(..frame_len);
+            }
+            Ok(packets)
+        }
+    }
+
+    impl OpusFrameDecoder {
+        pub fn new(channels: u8) -> Result<Self> {
+            let channels = channels.clamp(1, 2);
+            let decoder = Decoder::new(SampleRate::Hz48000, opus_channels(channels.into()))
+                .context("无法创建 Opus 解码器")?;
+            Ok(Self { channels, decoder })
+        }
+
+        pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<i16>> {
+            // 120 ms is the longest frame Opus can carry.
+            let mut output = vec![0i16; OPUS_FRAME_SAMPLES * 6 * usize::from(self.channels)];
+            let packet = packet.try_into().context("Opus 数据包无效")?;
+            let signals = (&mut output[..])
+                .try_into()
+                .context("Opus 输出缓冲区无效")?;
+            let samples = self
+                .decoder
+                .decode(Some(packet), signals, false)
+                .context("Opus 解码失败")?;
+            output.truncate(samples * usize::from(self.channels));
+            Ok(output)
+        }
+    }
+}
+
+#[cfg(not(feature = "opus"))]
+mod imp {
+    use anyhow::{bail, Result};
+
+    use super::{OpusFrameDecoder, OpusFrameEncoder};
+
+    impl OpusFrameEncoder {
+        pub fn new(_input_channels: u16) -> Result<Self> {
+            bail!("此构建未启用 `opus` Feature")
+        }
+
+        pub fn encode(
+            &mut self,
+            _sample_rate: u32,
+            _channels: usize,
+            _samples: &[i16],
+        ) -> Result<Vec<Vec<u8>>> {
+            match self.never {}
+        }
+    }
+
+    impl OpusFrameDecoder {
+        pub fn new(_channels: u8) -> Result<Self> {
+            bail!("此构建未启用 `opus` Feature")
+        }
+
+        pub fn decode(&mut self, _packet: &[u8]) -> Result<Vec<i16>> {
+            match self.never {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(resampler: &mut Resampler, rate: u32, channels: usize, samples: &[i16]) -> Vec<i16> {
+        let mut output = Vec::new();
+        resampler.process(rate, channels, samples, &mut output);
+        output
+    }
+
+    #[test]
+    fn device_names_match_by_substring() {
+        let name = "Headset Microphone (USB Audio)";
+        assert!(device_name_matches(name, "headset"));
+        assert!(device_name_matches(name, " USB Audio "));
+        assert!(!device_name_matches(name, "Webcam"));
+        assert!(!device_name_matches(name, ""));
+    }
+
+    #[test]
+    fn matching_format_passes_samples_through() {
+        let mut resampler = Resampler::new(48_000, 2);
+        let first = run(&mut resampler, 48_000, 2, &[1, -1, 2, -2, 3, -3]);
+        let second = run(&mut resampler, 48_000, 2, &[4, -4, 5, -5]);
+        // The last frame of each chunk is held back to interpolate across the boundary.
+        assert_eq!(first, vec![1, -1, 2, -2]);
+        assert_eq!(second, vec![3, -3, 4, -4]);
+    }
+
+    #[test]
+    fn rate_conversion_keeps_duration_across_chunks() {
+        let mut resampler = Resampler::new(44_100, 1);
+        let chunk = vec![1000i16; 480];
+        let total: usize = (0..100)
+            .map(|_| run(&mut resampler, 48_000, 1, &chunk).len())
+            .sum();
+        // One second of 48 kHz input becomes one second at 44.1 kHz.
+        assert!((44_099..=44_101).contains(&total), "got {total} samples");
+
+        let mut halving = Resampler::new(24_000, 1);
+        let output = run(&mut halving, 48_000, 1, &[0, 10, 20, 30, 40]);
+        assert_eq!(output, vec![0, 20]);
+    }
+
+    #[test]
+    fn channels_are_mixed_instead_of_dropped() {
+        let mut to_stereo = Resampler::new(48_000, 2);
+        assert_eq!(run(&mut to_stereo, 48_000, 1, &[5, 7, 9]), vec![5, 5, 7, 7]);
+
+        let mut to_mono = Resampler::new(48_000, 1);
+        assert_eq!(
+            run(&mut to_mono, 48_000, 2, &[10, 20, -4, 0, 0, 0]),
+            vec![15, -2]
+        );
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn opus_round_trip_keeps_frame_timing() {
+        let mut encoder = OpusFrameEncoder::new(2).unwrap();
+        let mut decoder = OpusFrameDecoder::new(encoder.channels()).unwrap();
+        // 10 ms chunks at 44.1 kHz do not line up with 20 ms Opus frames.
+        let chunk: Vec<i16> = (0..882).map(|i| ((i % 100) * 100) as i16).collect();
+        let packets: Vec<Vec<u8>> = (0..10)
+            .flat_map(|_| encoder.encode(44_100, 2, &chunk).unwrap())
+            .collect();
+        assert_eq!(packets.len(), 4);
+        for packet in &packets {
+            let decoded = decoder.decode(packet).unwrap();
+            assert_eq!(decoded.len(), OPUS_FRAME_SAMPLES * 2);
+        }
+    }
+}
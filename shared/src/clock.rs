@@ -0,0 +1,30 @@
+use std::time::Instant;
+
+/// Monotonic clock shared by every capture pipeline of one session.
+///
+/// Video and audio frames carry `capture_monotonic_ms` measured from the same start, so a
+/// recorder can interleave both streams. The wall-clock `timestamp_ms` stays alongside it
+/// for display and logging, but may jump when the system clock is adjusted.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionClock {
+    start: Instant,
+}
+
+impl SessionClock {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// Milliseconds elapsed since the session started.
+    pub fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+impl Default for SessionClock {
+    fn default() -> Self {
+        Self::start()
+    }
+}
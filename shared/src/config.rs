@@ -1,11 +1,166 @@
+use std::fmt::Display;
 use std::fs;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::message::BroadcastMode;
+
+/// Resampling filter applied when captured frames are scaled down.
+///
+/// `cargo bench -p shared --bench scale_filter` times a 3840x2160 -> 1280x720 frame with each.
+/// `image` has no fast path for nearest, so triangle costs about the same and reads far better.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleFilter {
+    /// Nearest-neighbour sampling; text edges look jagged.
+    Nearest,
+    /// Bilinear filtering; good balance between sharpness and speed.
+    #[default]
+    Triangle,
+    /// Lanczos3; sharpest small text but the slowest.
+    Lanczos,
+}
+
+/// Encoder used for the teacher's screen broadcast.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderKind {
+    /// JPEG through the `image` crate on the CPU.
+    #[default]
+    Cpu,
+    /// JPEG through libjpeg-turbo, also on the CPU but several times faster, in builds with
+    /// the `turbojpeg` feature. Falls back to `Cpu` when the build lacks it or the library
+    /// cannot be used. `hw` is still accepted, its name in earlier releases.
+    #[serde(alias = "hw")]
+    Turbojpeg,
+}
+
+/// How uploads are grouped under `save_upload_dir`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadOrganize {
+    /// `<student>/<file>`; a name that is already taken gets a numeric suffix.
+    #[default]
+    Flat,
+    /// `<date>/<student>/<file>`, dated in UTC.
+    PerDate,
+    /// `<session>/<student>/<file>`, one folder per teacher run named after its start time.
+    PerSession,
+}
+
+/// What the teacher does when a student ID that is already connected joins from another
+/// machine.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateStudentPolicy {
+    /// Refuse the newcomer; the student already in class keeps its place.
+    #[default]
+    RejectNew,
+    /// Drop the older session in favour of the newcomer.
+    ReplaceOld,
+}
+
+/// Rectangle in pixels of the captured display, origin at its top-left corner.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The part of this rectangle on a `width` x `height` frame, in full-screen fractions;
+    /// `None` when it lies entirely outside the frame.
+    pub fn to_region(self, width: u32, height: u32) -> Option<Region> {
+        let left = self.x.min(width);
+        let top = self.y.min(height);
+        let right = self.x.saturating_add(self.width).min(width);
+        let bottom = self.y.saturating_add(self.height).min(height);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Region {
+            x: left as f32 / width as f32,
+            y: top as f32 / height as f32,
+            width: (right - left) as f32 / width as f32,
+            height: (bottom - top) as f32 / height as f32,
+        })
+    }
+}
+
+/// Rectangle expressed in fractions (0.0-1.0) of the full captured screen, so it stays valid
+/// across resolutions and DPI settings.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    /// Smallest extent kept by [`Region::clamped`], to avoid degenerate crops.
+    const MIN_EXTENT: f32 = 0.01;
+
+    pub const FULL: Region = Region {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    /// Whether every field is a finite fraction and the region has an area, as a region
+    /// received from a peer must be before it is clamped or used.
+    pub fn is_valid(self) -> bool {
+        [self.x, self.y, self.width, self.height]
+            .iter()
+            .all(|value| (0.0..=1.0).contains(value))
+            && self.width > 0.0
+            && self.height > 0.0
+    }
+
+    /// Clamp into the unit square, keeping at least 1% of the screen in each dimension.
+    pub fn clamped(self) -> Self {
+        let width = self.width.clamp(Self::MIN_EXTENT, 1.0);
+        let height = self.height.clamp(Self::MIN_EXTENT, 1.0);
+        Self {
+            x: self.x.clamp(0.0, 1.0 - width),
+            y: self.y.clamp(0.0, 1.0 - height),
+            width,
+            height,
+        }
+    }
+
+    /// Map a region given relative to `outer` (e.g. the view a student currently sees)
+    /// into full-screen fractions.
+    pub fn within(self, outer: Region) -> Self {
+        Self {
+            x: outer.x + self.x * outer.width,
+            y: outer.y + self.y * outer.height,
+            width: self.width * outer.width,
+            height: self.height * outer.height,
+        }
+        .clamped()
+    }
+
+    /// Pixel rectangle `(x, y, width, height)` of this region on a `width` x `height` frame.
+    pub fn to_pixels(self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let region = self.clamped();
+        let x = ((region.x * width as f32).round() as u32).min(width.saturating_sub(1));
+        let y = ((region.y * height as f32).round() as u32).min(height.saturating_sub(1));
+        let w = ((region.width * width as f32).round() as u32).clamp(1, width - x);
+        let h = ((region.height * height as f32).round() as u32).clamp(1, height - y);
+        (x, y, w, h)
+    }
+}
 
 /// Configuration for the JPEG based screen broadcast pipeline.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 pub struct BroadcastConfig {
     /// Target frames per second for screen capture.
@@ -16,6 +171,37 @@ pub struct BroadcastConfig {
     pub max_width: Option<u32>,
     /// Optional maximum height for captured frames. When set, frames will be scaled down.
     pub max_height: Option<u32>,
+    /// Filter used when frames are scaled down to fit `max_width`/`max_height`.
+    pub scale_filter: ScaleFilter,
+    /// zstd level (1-19) for raw audio/BGRA frames sent to students that support it;
+    /// 0 disables compression. JPEG frames are never recompressed.
+    pub compression_level: i32,
+    /// Requested encoder; see [`EncoderKind`].
+    pub encoder: EncoderKind,
+    /// Only broadcast this part of the screen or `capture_rect`, scaled up to its full size.
+    /// Set at runtime when the teacher accepts a student's zoom request.
+    pub capture_region: Option<Region>,
+    /// Only broadcast this pixel rectangle of the display, at its own size, e.g. the slide
+    /// show instead of the whole desktop with its taskbar and notifications.
+    pub capture_rect: Option<Rect>,
+    /// Only broadcast the window whose title contains this text (Windows only), following
+    /// it as it moves; `capture_rect` or the whole display while no such window shows.
+    pub capture_window: Option<String>,
+    /// Parts of the screen blacked out before encoding, e.g. a notification area.
+    /// Given in full-screen fractions; masks outside the broadcast view are ignored.
+    pub masked_regions: Vec<Region>,
+    /// Like `masked_regions`, but in pixels of the captured display, e.g. around a password
+    /// manager; parts outside the display are ignored. Changed at runtime from the panel.
+    pub redact_rects: Vec<Rect>,
+    /// Draw the mouse pointer into captured frames, which the capture itself leaves out,
+    /// so students can follow what is being pointed at.
+    pub show_cursor: bool,
+    /// Display to capture, counted from 0 in system enumeration order; the primary display
+    /// when unset or out of range.
+    pub monitor_index: Option<usize>,
+    /// 32×32 pixel tiles that may change between captures while the screen still counts as
+    /// static and is not re-encoded, e.g. for a blinking cursor; 0 only skips identical ones.
+    pub static_tile_tolerance: u32,
 }
 
 impl Default for BroadcastConfig {
@@ -25,10 +211,80 @@ impl Default for BroadcastConfig {
             jpeg_quality: 75,
             max_width: None,
             max_height: None,
+            scale_filter: ScaleFilter::default(),
+            compression_level: 3,
+            encoder: EncoderKind::default(),
+            capture_region: None,
+            capture_rect: None,
+            capture_window: None,
+            masked_regions: Vec::new(),
+            redact_rects: Vec::new(),
+            show_cursor: true,
+            monitor_index: None,
+            static_tile_tolerance: 4,
         }
     }
 }
 
+/// Supported `(min, max)` of the numeric broadcast settings.
+const FPS_LIMITS: (u32, u32) = (1, 60);
+const JPEG_QUALITY_LIMITS: (u8, u8) = (1, 100);
+const COMPRESSION_LEVEL_LIMITS: (i32, i32) = (0, 19);
+const AUDIO_BUFFER_MS_LIMITS: (u32, u32) = (20, 500);
+
+/// Clamp `value` into `(min, max)`, noting the change in `adjustments`.
+fn clamp_setting<T: PartialOrd + Copy + Display>(
+    name: &str,
+    value: &mut T,
+    (min, max): (T, T),
+    adjustments: &mut Vec<String>,
+) {
+    let clamped = if *value < min {
+        min
+    } else if *value > max {
+        max
+    } else {
+        return;
+    };
+    adjustments.push(format!(
+        "{name} = {value} 超出范围 {min}-{max}，已改为 {clamped}"
+    ));
+    *value = clamped;
+}
+
+/// Note in `problems` when `value` lies outside `(min, max)`.
+fn check_setting<T: PartialOrd + Copy + Display>(
+    name: &str,
+    value: T,
+    (min, max): (T, T),
+    problems: &mut Vec<String>,
+) {
+    if value < min || value > max {
+        problems.push(format!("{name} = {value} is outside {min}-{max}"));
+    }
+}
+
+/// Create `dir` if needed and prove a file can be written into it.
+fn check_writable(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-test");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+/// Resolve a relative `path` against the directory of the config file at `config_path`.
+fn relative_to_config(config_path: &Path, path: &Path) -> PathBuf {
+    if !path.is_relative() {
+        return path.to_path_buf();
+    }
+    config_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(path)
+}
+
 /// Configuration loaded by the teacher binary.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -37,28 +293,90 @@ pub struct TeacherConfig {
     pub listen_host: String,
     /// TCP port to bind for student connections.
     pub listen_port: u16,
+    /// `host:port` addresses to accept students on instead of `listen_host`/`listen_port`,
+    /// e.g. `["0.0.0.0:5000", "[::]:5000"]` to add IPv6. Unused when empty.
+    pub listen_addrs: Vec<String>,
     /// Whether audio streaming starts enabled.
     pub enable_audio_by_default: bool,
     /// Whether students should be forced out of mute when audio starts.
     pub force_audio: bool,
     /// Directory where uploaded files from students will be stored.
     pub save_upload_dir: PathBuf,
+    /// Folder level inserted above each student's uploads.
+    pub upload_organize: UploadOrganize,
+    /// Largest file a student may upload, in bytes (0 = unlimited).
+    pub max_upload_bytes: u64,
     /// Whether distributed files should request auto open on student side by default.
     pub file_auto_open: bool,
     /// Screen broadcast quality parameters.
     pub broadcast: BroadcastConfig,
     /// Optional list of expected students used for display purposes.
     pub expected_students: Vec<StudentRegistration>,
-    /// Interval (seconds) at which teacher expects heartbeat pings.
+    /// Refuse students whose ID is not listed in `expected_students`.
+    pub enforce_registration: bool,
+    /// Interval (seconds) between the heartbeats the teacher sends every student, which
+    /// lets them detect a dead connection (0 = none).
     pub heartbeat_interval_secs: u64,
-    /// Idle timeout (seconds) before a connection is considered dead.
+    /// Seconds without a heartbeat before a student is dropped (0 = never).
     pub idle_timeout_secs: u64,
+    /// Seconds after which a student spotlight reverts to the previous source (0 = unlimited).
+    pub max_spotlight_secs: u64,
+    /// Queue student zoom requests for the teacher to accept; when false they are rejected.
+    pub honor_zoom_requests: bool,
+    /// Display mode used when the broadcast is started automatically at launch.
+    pub auto_start_mode: BroadcastMode,
+    /// Flag connections whose identity clashes with `expected_students` or another connection.
+    pub verify_identities: bool,
+    /// Which connection stays when a second machine joins with an ID already in class. A
+    /// student reconnecting from the same address always replaces its old session.
+    pub duplicate_student_policy: DuplicateStudentPolicy,
+    /// PEM certificate chain; together with `tls_key_path` enables TLS (`tls` feature).
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key for `tls_cert_path`.
+    pub tls_key_path: Option<PathBuf>,
+    /// Shared secret every `Hello` must carry; connections are open to anyone when unset.
+    pub join_token: Option<String>,
+    /// Record from the first input device whose name contains this text; the system
+    /// default is used when unset or when nothing matches.
+    pub audio_input_device: Option<String>,
+    /// Video/audio messages buffered per student; past this the oldest queued ones are
+    /// dropped so a slow student neither stalls nor bloats the teacher.
+    pub send_queue_capacity: usize,
+    /// Outbound cap per student in kilobits per second, file transfers excepted (0 = none).
+    /// Video the capped link cannot carry is dropped from the send queue.
+    pub max_kbps_per_student: u32,
+    /// Separate cap for file transfers to each student, usually higher (0 = none).
+    pub max_file_kbps_per_student: u32,
+    /// Serve a read-only JSON status page on this port (`http-status` feature).
+    pub status_http_port: Option<u16>,
+    /// Accept console commands from local scripts on this Unix socket (not on Windows).
+    pub control_socket_path: Option<PathBuf>,
+    /// Accept console commands over TCP on 127.0.0.1 at this port. Any local user can
+    /// connect, so prefer `control_socket_path` where it is available.
+    pub control_port: Option<u16>,
+    /// Shared secret a control client must send as its first line. Required with
+    /// `control_port`; optional for the Unix socket, which file permissions already guard.
+    pub control_token: Option<String>,
+    /// Ask students to lock Alt+Tab and the Windows keys during a fullscreen teacher
+    /// broadcast; only students with `allow_input_lock` comply.
+    pub lock_student_input: bool,
 }
 
 impl TeacherConfig {
-    /// Load configuration from a TOML file.
+    /// Load configuration from a TOML file. Out-of-range settings are clamped with a warning.
     pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let path_ref = path.as_ref();
+        Self::load(path.as_ref(), false)
+    }
+
+    /// Load configuration from a TOML file, failing on any setting [`validate`] rejects
+    /// instead of adjusting it.
+    ///
+    /// [`validate`]: Self::validate
+    pub fn load_strict<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load(path.as_ref(), true)
+    }
+
+    fn load(path_ref: &Path, strict: bool) -> Result<Self> {
         if let Some(parent) = path_ref.parent() {
             if !parent.as_os_str().is_empty() && !parent.exists() {
                 fs::create_dir_all(parent).with_context(|| {
@@ -80,7 +398,7 @@ impl TeacherConfig {
                     path_ref.display()
                 )
             })?;
-            config.finalize(path_ref)?;
+            config.finalize(path_ref, strict)?;
             return Ok(config);
         }
 
@@ -89,20 +407,29 @@ impl TeacherConfig {
         })?;
         let mut config: Self = toml::from_str(&content)
             .with_context(|| format!("Failed to parse teacher config {}", path_ref.display()))?;
-        config.finalize(path_ref)?;
+        config.finalize(path_ref, strict)?;
         Ok(config)
     }
 
-    fn finalize(&mut self, path: &Path) -> Result<()> {
-        self.broadcast.fps = self.broadcast.fps.clamp(1, 60);
-        self.broadcast.jpeg_quality = self.broadcast.jpeg_quality.clamp(1, 100);
+    fn finalize(&mut self, path: &Path, strict: bool) -> Result<()> {
+        self.save_upload_dir = relative_to_config(path, &self.save_upload_dir);
+        for file_path in [
+            &mut self.tls_cert_path,
+            &mut self.tls_key_path,
+            &mut self.control_socket_path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file_path = relative_to_config(path, file_path);
+        }
 
-        if self.save_upload_dir.is_relative() {
-            let base = path
-                .parent()
-                .map(Path::to_path_buf)
-                .unwrap_or_else(|| PathBuf::from("."));
-            self.save_upload_dir = base.join(&self.save_upload_dir);
+        if strict {
+            self.validate()
+                .with_context(|| format!("Invalid teacher config {}", path.display()))?;
+        }
+        for adjustment in self.clamp_settings() {
+            warn!("{adjustment}");
         }
 
         fs::create_dir_all(&self.save_upload_dir).with_context(|| {
@@ -115,10 +442,82 @@ impl TeacherConfig {
         Ok(())
     }
 
+    /// Clamp the numeric settings into their supported ranges, describing each change.
+    fn clamp_settings(&mut self) -> Vec<String> {
+        let mut adjustments = Vec::new();
+        let broadcast = &mut self.broadcast;
+        clamp_setting(
+            "broadcast.fps",
+            &mut broadcast.fps,
+            FPS_LIMITS,
+            &mut adjustments,
+        );
+        clamp_setting(
+            "broadcast.jpeg_quality",
+            &mut broadcast.jpeg_quality,
+            JPEG_QUALITY_LIMITS,
+            &mut adjustments,
+        );
+        clamp_setting(
+            "broadcast.compression_level",
+            &mut broadcast.compression_level,
+            COMPRESSION_LEVEL_LIMITS,
+            &mut adjustments,
+        );
+        adjustments
+    }
+
+    /// Check every setting without adjusting any, listing all problems in the error.
+    /// Creates `save_upload_dir` if needed to prove it is writable.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let broadcast = &self.broadcast;
+        check_setting("broadcast.fps", broadcast.fps, FPS_LIMITS, &mut problems);
+        check_setting(
+            "broadcast.jpeg_quality",
+            broadcast.jpeg_quality,
+            JPEG_QUALITY_LIMITS,
+            &mut problems,
+        );
+        check_setting(
+            "broadcast.compression_level",
+            broadcast.compression_level,
+            COMPRESSION_LEVEL_LIMITS,
+            &mut problems,
+        );
+        if self.listen_port == 0 {
+            problems.push(
+                "listen_port must not be 0; students need a fixed port such as 5000".to_string(),
+            );
+        }
+        if let Err(err) = check_writable(&self.save_upload_dir) {
+            problems.push(format!(
+                "save_upload_dir {} is not writable ({err:#}); pick a directory this user can write to",
+                self.save_upload_dir.display()
+            ));
+        }
+
+        if !problems.is_empty() {
+            bail!(problems.join("; "));
+        }
+        Ok(())
+    }
+
     /// Return the socket address string used for binding (`host:port`).
     pub fn listen_addr(&self) -> String {
         format!("{}:{}", self.listen_host, self.listen_port)
     }
+
+    /// Every address to accept students on: `listen_addrs`, or [`listen_addr`] when empty.
+    ///
+    /// [`listen_addr`]: Self::listen_addr
+    pub fn bind_addrs(&self) -> Vec<String> {
+        if self.listen_addrs.is_empty() {
+            vec![self.listen_addr()]
+        } else {
+            self.listen_addrs.clone()
+        }
+    }
 }
 
 impl Default for TeacherConfig {
@@ -126,14 +525,35 @@ impl Default for TeacherConfig {
         Self {
             listen_host: "0.0.0.0".to_string(),
             listen_port: 5000,
+            listen_addrs: Vec::new(),
             enable_audio_by_default: false,
             force_audio: false,
             save_upload_dir: PathBuf::from("uploads"),
+            upload_organize: UploadOrganize::Flat,
+            max_upload_bytes: 1024 * 1024 * 1024,
             file_auto_open: false,
             broadcast: BroadcastConfig::default(),
             expected_students: Vec::new(),
+            enforce_registration: false,
             heartbeat_interval_secs: 10,
             idle_timeout_secs: 30,
+            max_spotlight_secs: 0,
+            honor_zoom_requests: false,
+            auto_start_mode: BroadcastMode::Fullscreen,
+            verify_identities: true,
+            duplicate_student_policy: DuplicateStudentPolicy::RejectNew,
+            tls_cert_path: None,
+            tls_key_path: None,
+            join_token: None,
+            audio_input_device: None,
+            send_queue_capacity: 60,
+            max_kbps_per_student: 0,
+            max_file_kbps_per_student: 0,
+            status_http_port: None,
+            control_socket_path: None,
+            control_port: None,
+            control_token: None,
+            lock_student_input: false,
         }
     }
 }
@@ -146,6 +566,8 @@ pub struct StudentRegistration {
     pub student_name: Option<String>,
     pub seat: Option<String>,
     pub allow_uploads: bool,
+    /// Address of the student's PC; connections from elsewhere are flagged.
+    pub ip: Option<IpAddr>,
 }
 
 impl Default for StudentRegistration {
@@ -155,6 +577,7 @@ impl Default for StudentRegistration {
             student_name: None,
             seat: None,
             allow_uploads: true,
+            ip: None,
         }
     }
 }
@@ -172,6 +595,55 @@ pub struct StudentConfig {
     pub auto_open_file: bool,
     /// When true, teacher directives can override the `auto_fullscreen` flag.
     pub allow_forced_fullscreen: bool,
+    /// Delete received downloads older than this many days. Disabled when unset.
+    pub download_retention_days: Option<u32>,
+    /// Cap on the total size of `download_path` in MiB; the oldest files are evicted first.
+    /// Disabled when unset.
+    pub download_max_total_mb: Option<u64>,
+    /// Tell the teacher whenever the broadcast window loses or regains focus (exam proctoring).
+    pub report_focus_loss: bool,
+    /// Keep the broadcast window above other windows, re-asserted whenever it loses focus.
+    pub keep_window_on_top: bool,
+    /// Connect over TLS (`tls` feature); the teacher must be configured for TLS as well.
+    pub use_tls: bool,
+    /// PEM CA certificate the teacher's certificate must chain to; required with `use_tls`.
+    pub teacher_ca_path: Option<PathBuf>,
+    /// Must match the teacher's `join_token` when one is configured.
+    pub join_token: Option<String>,
+    /// Play broadcast audio on the first output device whose name contains this text; the
+    /// system default is used when unset or when nothing matches.
+    pub audio_output_device: Option<String>,
+    /// Display shared when the teacher spotlights this student; see
+    /// `BroadcastConfig::monitor_index`.
+    pub monitor_index: Option<usize>,
+    /// Frame rate cap while the teacher spotlights this student, below the teacher's own
+    /// broadcast rate since the upload is relayed to the whole class (0 = no cap).
+    pub student_share_max_fps: u32,
+    /// Count down at the console before a spotlight starts sharing this screen, so the
+    /// student can refuse; the teacher is told when they do.
+    pub require_share_consent: bool,
+    /// Broadcast audio queued before playback starts, in milliseconds (20-500). More rides
+    /// out a jittery network such as busy Wi-Fi at the cost of latency.
+    pub audio_buffer_ms: u32,
+    /// Audio arriving this many milliseconds later than the stream usually does is dropped
+    /// instead of played (0 = play everything in order). Audio that stays late for a second
+    /// resyncs to the new delay.
+    pub audio_max_late_ms: u32,
+    /// Seconds without any message from a teacher that sends heartbeats before the
+    /// connection counts as dead and is reopened; keep it above twice the teacher's
+    /// `heartbeat_interval_secs` (0 = never).
+    pub teacher_timeout_secs: u64,
+    /// Consent to the teacher locking task-switching keys (Windows only) while the fullscreen
+    /// broadcast window is active; also requires `allow_forced_fullscreen`.
+    pub allow_input_lock: bool,
+    /// Show broadcast frames pixel for pixel instead of scaling them to fit the window.
+    pub native_size: bool,
+    /// Reopen the broadcast window on the next frame when the student closes it; otherwise it
+    /// stays closed until the teacher starts another broadcast.
+    pub reopen_closed_window: bool,
+    /// Show broadcasts in a window with a status strip naming the source, its resolution and
+    /// frame rate; needs a student built with the `winit-renderer` feature.
+    pub status_strip: bool,
 }
 
 impl StudentConfig {
@@ -213,12 +685,19 @@ impl StudentConfig {
     }
 
     fn finalize(&mut self, path: &Path) -> Result<()> {
-        if self.download_path.is_relative() {
-            let base = path
-                .parent()
-                .map(Path::to_path_buf)
-                .unwrap_or_else(|| PathBuf::from("."));
-            self.download_path = base.join(&self.download_path);
+        self.download_path = relative_to_config(path, &self.download_path);
+        if let Some(ca_path) = &mut self.teacher_ca_path {
+            *ca_path = relative_to_config(path, ca_path);
+        }
+        let mut adjustments = Vec::new();
+        clamp_setting(
+            "audio_buffer_ms",
+            &mut self.audio_buffer_ms,
+            AUDIO_BUFFER_MS_LIMITS,
+            &mut adjustments,
+        );
+        for adjustment in adjustments {
+            warn!("{adjustment}");
         }
 
         fs::create_dir_all(&self.download_path).with_context(|| {
@@ -248,6 +727,96 @@ impl Default for StudentConfig {
             download_path: PathBuf::from("downloads"),
             auto_open_file: false,
             allow_forced_fullscreen: true,
+            download_retention_days: None,
+            download_max_total_mb: None,
+            report_focus_loss: false,
+            keep_window_on_top: false,
+            use_tls: false,
+            teacher_ca_path: None,
+            join_token: None,
+            audio_output_device: None,
+            monitor_index: None,
+            student_share_max_fps: 10,
+            require_share_consent: false,
+            audio_buffer_ms: 80,
+            audio_max_late_ms: 300,
+            teacher_timeout_secs: 30,
+            allow_input_lock: false,
+            native_size: false,
+            reopen_closed_window: false,
+            status_strip: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_settings_are_clamped_or_rejected() {
+        let dir = std::env::temp_dir().join(format!("teacher-config-{}", uuid::Uuid::new_v4()));
+        let mut config = TeacherConfig {
+            listen_port: 0,
+            save_upload_dir: dir.clone(),
+            ..TeacherConfig::default()
+        };
+        config.broadcast.fps = 600;
+        config.broadcast.jpeg_quality = 0;
+
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("broadcast.fps = 600"), "{err}");
+        assert!(err.contains("broadcast.jpeg_quality = 0"), "{err}");
+        assert!(err.contains("listen_port"), "{err}");
+        assert!(!err.contains("save_upload_dir"), "{err}");
+
+        assert_eq!(config.clamp_settings().len(), 2);
+        assert_eq!(
+            (config.broadcast.fps, config.broadcast.jpeg_quality),
+            (60, 1)
+        );
+        assert!(config.clamp_settings().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn non_finite_or_out_of_range_regions_are_invalid() {
+        let region = Region {
+            x: 0.25,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+        };
+        assert!(region.is_valid());
+        assert!(Region::FULL.is_valid());
+        for bad in [
+            Region {
+                x: f32::NAN,
+                ..region
+            },
+            Region {
+                width: f32::INFINITY,
+                ..region
+            },
+            Region { y: -0.1, ..region },
+            Region {
+                height: 0.0,
+                ..region
+            },
+            Region {
+                width: 1.5,
+                ..region
+            },
+        ] {
+            assert!(!bad.is_valid(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn hw_encoder_from_earlier_configs_still_means_turbojpeg() {
+        let cfg: BroadcastConfig = toml::from_str(r#"encoder = "hw""#).unwrap();
+        assert_eq!(cfg.encoder, EncoderKind::Turbojpeg);
+        let cfg: BroadcastConfig = toml::from_str(r#"encoder = "turbojpeg""#).unwrap();
+        assert_eq!(cfg.encoder, EncoderKind::Turbojpeg);
+    }
+}
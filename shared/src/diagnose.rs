@@ -0,0 +1,70 @@
+//! Pass/fail checklist printed by the `--diagnose` mode of both binaries.
+
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::config::BroadcastConfig;
+use crate::frame::{encode_jpeg, FrameSource, ScreenSource};
+
+/// Collects check results and prints each one as soon as it is recorded.
+#[derive(Debug, Default)]
+pub struct DiagnosticReport {
+    failed_critical: usize,
+    warnings: usize,
+    passed: usize,
+}
+
+impl DiagnosticReport {
+    pub fn new(title: &str) -> Self {
+        println!("== {title} 自检 ==");
+        Self::default()
+    }
+
+    /// Record one check. A failed `critical` check fails the whole report; other failures
+    /// are reported as warnings because the program still runs without them.
+    pub fn check(&mut self, name: &str, critical: bool, outcome: Result<String>) {
+        match outcome {
+            Ok(detail) => {
+                self.passed += 1;
+                println!("[通过] {name}: {detail}");
+            }
+            Err(err) if critical => {
+                self.failed_critical += 1;
+                println!("[失败] {name}: {err:#}");
+            }
+            Err(err) => {
+                self.warnings += 1;
+                println!("[警告] {name}: {err:#}");
+            }
+        }
+    }
+
+    /// Note a check that could not run because an earlier one failed.
+    pub fn skip(&mut self, name: &str, reason: &str) {
+        println!("[跳过] {name}: {reason}");
+    }
+
+    /// Print the summary line and return whether every critical check passed.
+    pub fn finish(self) -> bool {
+        println!(
+            "自检完成: {} 项通过, {} 项警告, {} 项失败",
+            self.passed, self.warnings, self.failed_critical
+        );
+        self.failed_critical == 0
+    }
+}
+
+/// Grab one frame from the configured display and run it through the JPEG pipeline.
+pub fn probe_screen_capture(cfg: &BroadcastConfig) -> Result<String> {
+    let frame = ScreenSource::select(cfg.monitor_index)?.capture()?;
+    let started = Instant::now();
+    let (jpeg, width, height) = encode_jpeg(&frame, cfg)?;
+    Ok(format!(
+        "{}x{} -> {width}x{height}, JPEG {} KB, 编码 {} ms",
+        frame.width,
+        frame.height,
+        jpeg.len() / 1024,
+        started.elapsed().as_millis()
+    ))
+}
@@ -0,0 +1,616 @@
+#[cfg(feature = "turbojpeg")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use anyhow::{ensure, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{ColorType, RgbImage};
+use screenshots::Screen;
+use tracing::{debug, info, warn};
+
+use crate::config::{BroadcastConfig, Rect, Region, ScaleFilter};
+
+/// Uncompressed BGRA frame as produced by a capture source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFrame {
+    pub width: u32,
+    pub height: u32,
+    /// Pixel data in BGRA order, `width * height * 4` bytes.
+    pub data: Vec<u8>,
+}
+
+/// Anything that can produce raw frames for the broadcast pipeline.
+pub trait FrameSource: Send + Sync {
+    fn capture(&self) -> Result<RawFrame>;
+}
+
+/// A display that can be captured, as listed by [`list_monitors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// Position in `Screen::all()` order, as used by `BroadcastConfig::monitor_index`.
+    pub index: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+/// Displays currently attached, in the order `monitor_index` counts them.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>> {
+    let screens = Screen::all().context("无法枚举显示器")?;
+    Ok(screens
+        .iter()
+        .enumerate()
+        .map(|(index, screen)| {
+            let info = &screen.display_info;
+            MonitorInfo {
+                index,
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+                primary: info.is_primary,
+            }
+        })
+        .collect())
+}
+
+/// Captures a physical display through the `screenshots` crate.
+pub struct ScreenSource {
+    screen: Screen,
+}
+
+impl ScreenSource {
+    /// Source bound to the display containing the origin (the primary monitor).
+    pub fn primary() -> Result<Self> {
+        let screen = Screen::from_point(0, 0).context("无法找到主显示器")?;
+        Ok(Self { screen })
+    }
+
+    /// Source bound to display `index` in `Screen::all()` order, or the primary display when
+    /// unset. An out-of-range index logs the available displays and falls back to primary.
+    pub fn select(index: Option<usize>) -> Result<Self> {
+        let Some(index) = index else {
+            return Self::primary();
+        };
+        let mut screens = Screen::all().context("无法枚举显示器")?;
+        if index < screens.len() {
+            let screen = screens.swap_remove(index);
+            let info = &screen.display_info;
+            info!(
+                index,
+                width = info.width,
+                height = info.height,
+                "已选择显示器"
+            );
+            return Ok(Self { screen });
+        }
+
+        warn!(
+            index,
+            available = screens.len(),
+            "monitor_index 超出范围，改用主显示器"
+        );
+        for (i, screen) in screens.iter().enumerate() {
+            let info = &screen.display_info;
+            warn!(
+                "  显示器 {i}: {}x{} 位于 ({}, {}){}",
+                info.width,
+                info.height,
+                info.x,
+                info.y,
+                if info.is_primary {
+                    " [主显示器]"
+                } else {
+                    ""
+                }
+            );
+        }
+        Self::primary()
+    }
+
+    /// Desktop position and size of the display, as `(x, y, width, height)`.
+    pub fn bounds(&self) -> (i32, i32, u32, u32) {
+        let info = &self.screen.display_info;
+        (info.x, info.y, info.width, info.height)
+    }
+
+    /// Where the mouse pointer is on this display, in fractions of its size; `None` when it
+    /// is on another display or the platform cannot tell.
+    pub fn cursor_position(&self) -> Option<(f32, f32)> {
+        let (x, y) = desktop_cursor_position()?;
+        let (left, top, width, height) = self.bounds();
+        let fx = (x - left) as f32 / width.max(1) as f32;
+        let fy = (y - top) as f32 / height.max(1) as f32;
+        ((0.0..1.0).contains(&fx) && (0.0..1.0).contains(&fy)).then_some((fx, fy))
+    }
+
+    /// The part of this display covered by the first visible window whose title contains
+    /// `title`, in pixels of the display; `None` when no such window shows on it.
+    pub fn window_rect(&self, title: &str) -> Option<Rect> {
+        let (window_x, window_y, window_width, window_height) = window_bounds(title)?;
+        let (left, top, width, height) = self.bounds();
+        let x0 = (window_x - left).clamp(0, width as i32);
+        let y0 = (window_y - top).clamp(0, height as i32);
+        let x1 = (window_x + window_width as i32 - left).clamp(0, width as i32);
+        let y1 = (window_y + window_height as i32 - top).clamp(0, height as i32);
+        (x0 < x1 && y0 < y1).then(|| Rect {
+            x: x0 as u32,
+            y: y0 as u32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+        })
+    }
+
+    /// Capture the display, drawing the pointer in when `show_cursor` is set, since the
+    /// capture itself leaves it out.
+    pub fn capture_with_cursor(&self, show_cursor: bool) -> Result<RawFrame> {
+        let mut frame = self.capture()?;
+        if show_cursor {
+            if let Some(position) = self.cursor_position() {
+                draw_cursor(&mut frame, position);
+            }
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(windows)]
+fn desktop_cursor_position() -> Option<(i32, i32)> {
+    use winapi::shared::windef::POINT;
+    use winapi::um::winuser::GetCursorPos;
+
+    let mut point = POINT { x: 0, y: 0 };
+    // SAFETY: GetCursorPos only writes the POINT it is handed.
+    let found = unsafe { GetCursorPos(&mut point) } != 0;
+    found.then_some((point.x, point.y))
+}
+
+#[cfg(not(windows))]
+fn desktop_cursor_position() -> Option<(i32, i32)> {
+    None
+}
+
+/// Desktop position and size `(x, y, width, height)` of the first visible, not minimized
+/// top-level window whose title contains `title`. Always `None` off Windows.
+#[cfg(windows)]
+pub fn window_bounds(title: &str) -> Option<(i32, i32, u32, u32)> {
+    use winapi::shared::minwindef::{BOOL, FALSE, LPARAM, TRUE};
+    use winapi::shared::windef::{HWND, RECT};
+    use winapi::um::winuser::{
+        EnumWindows, GetWindowRect, GetWindowTextW, IsIconic, IsWindowVisible,
+    };
+
+    struct Search<'a> {
+        title: &'a str,
+        found: Option<RECT>,
+    }
+
+    unsafe extern "system" fn visit(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        // SAFETY: `lparam` is the `Search` handed to `EnumWindows` below, which outlives it.
+        let search = &mut *(lparam as *mut Search);
+        if IsWindowVisible(hwnd) == 0 || IsIconic(hwnd) != 0 {
+            return TRUE;
+        }
+        let mut text = [0u16; 256];
+        let len = GetWindowTextW(hwnd, text.as_mut_ptr(), text.len() as i32);
+        if len <= 0 || !String::from_utf16_lossy(&text[..len as usize]).contains(search.title) {
+            return TRUE;
+        }
+        let mut rect: RECT = std::mem::zeroed();
+        if GetWindowRect(hwnd, &mut rect) == 0 {
+            return TRUE;
+        }
+        search.found = Some(rect);
+        FALSE
+    }
+
+    let mut search = Search { title, found: None };
+    // SAFETY: `visit` only runs during this call, while `search` is alive.
+    unsafe { EnumWindows(Some(visit), &mut search as *mut Search as LPARAM) };
+    let rect = search.found?;
+    let width = u32::try_from(rect.right - rect.left).ok()?;
+    let height = u32::try_from(rect.bottom - rect.top).ok()?;
+    Some((rect.left, rect.top, width, height))
+}
+
+#[cfg(not(windows))]
+pub fn window_bounds(_title: &str) -> Option<(i32, i32, u32, u32)> {
+    None
+}
+
+/// Arrow pointer drawn by [`draw_cursor`], tip at the top-left: `#` outline, `o` fill.
+const CURSOR_ARROW: [&str; 19] = [
+    "#           ",
+    "##          ",
+    "#o#         ",
+    "#oo#        ",
+    "#ooo#       ",
+    "#oooo#      ",
+    "#ooooo#     ",
+    "#oooooo#    ",
+    "#ooooooo#   ",
+    "#oooooooo#  ",
+    "#ooooooooo# ",
+    "#oooooo#####",
+    "#ooo#oo#    ",
+    "#oo# #oo#   ",
+    "#o#  #oo#   ",
+    "##    #oo#  ",
+    "#     #oo#  ",
+    "       #oo# ",
+    "       ##   ",
+];
+
+/// Draw an arrow pointer with its tip at `position` (fractions of the frame), scaled up on
+/// high-resolution displays. Whatever falls outside the frame is left out.
+pub fn draw_cursor(frame: &mut RawFrame, position: (f32, f32)) {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    if frame.data.len() != width * height * 4 {
+        return;
+    }
+    let scale = ((frame.height + 540) / 1080).max(1) as usize;
+    let tip_x = (position.0 * frame.width as f32) as isize;
+    let tip_y = (position.1 * frame.height as f32) as isize;
+    for (row, line) in CURSOR_ARROW.iter().enumerate() {
+        for (column, mark) in line.bytes().enumerate() {
+            let value = match mark {
+                b'#' => 0,
+                b'o' => 255,
+                _ => continue,
+            };
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let x = tip_x + (column * scale + dx) as isize;
+                    let y = tip_y + (row * scale + dy) as isize;
+                    if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                        continue;
+                    }
+                    let offset = (y as usize * width + x as usize) * 4;
+                    frame.data[offset..offset + 4].copy_from_slice(&[value, value, value, 255]);
+                }
+            }
+        }
+    }
+}
+
+impl FrameSource for ScreenSource {
+    fn capture(&self) -> Result<RawFrame> {
+        let image = self.screen.capture().context("执行屏幕截取失败")?;
+        Ok(RawFrame {
+            width: image.width(),
+            height: image.height(),
+            data: image.into_raw(),
+        })
+    }
+}
+
+/// Deterministic checkerboard-plus-gradient pattern, used to exercise the
+/// pipeline without capture hardware.
+pub struct TestPatternSource {
+    width: u32,
+    height: u32,
+}
+
+impl TestPatternSource {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// RGB value of the pattern at the given pixel.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        // 16px cells keep the pattern smooth enough to survive JPEG within a small tolerance.
+        let checker = if ((x / 16) + (y / 16)).is_multiple_of(2) {
+            224
+        } else {
+            32
+        };
+        let red = (x * 255 / self.width.max(2).saturating_sub(1)) as u8;
+        let green = (y * 255 / self.height.max(2).saturating_sub(1)) as u8;
+        [red, green, checker]
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn capture(&self) -> Result<RawFrame> {
+        let mut data = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let [r, g, b] = self.pixel(x, y);
+                data.extend_from_slice(&[b, g, r, 255]);
+            }
+        }
+        Ok(RawFrame {
+            width: self.width,
+            height: self.height,
+            data,
+        })
+    }
+}
+
+/// Convert a BGRA frame to RGB, scale it to the configured bounds and JPEG encode it.
+///
+/// Returns the JPEG bytes together with the dimensions actually encoded.
+pub fn encode_jpeg(frame: &RawFrame, cfg: &BroadcastConfig) -> Result<(Vec<u8>, u32, u32)> {
+    ensure!(
+        frame.data.len() == (frame.width as usize) * (frame.height as usize) * 4,
+        "帧数据长度 {} 与尺寸 {}x{} 不符",
+        frame.data.len(),
+        frame.width,
+        frame.height
+    );
+
+    // A cropped window goes out at its own size; a zoom applies within it.
+    let base = cfg
+        .capture_rect
+        .and_then(|rect| rect.to_region(frame.width, frame.height))
+        .unwrap_or(Region::FULL);
+    let view = match cfg.capture_region {
+        Some(zoom) => zoom.within(base),
+        None => base,
+    };
+    let (rgb, width, height) = if view == Region::FULL {
+        (bgra_to_rgb(&frame.data), frame.width, frame.height)
+    } else {
+        let (rgb, width, height) = crop_to_rgb(frame, view);
+        if cfg.capture_region.is_some() {
+            // Zoom: bring the region back up to the size of the whole screen or window.
+            let (_, _, base_width, base_height) = base.to_pixels(frame.width, frame.height);
+            let (target_width, target_height) = fit_scaled(width, height, base_width, base_height);
+            let rgb = resize(rgb, width, height, target_width, target_height, cfg)?;
+            (rgb, target_width, target_height)
+        } else {
+            (rgb, width, height)
+        }
+    };
+    let (mut rgb, width, height) = downscale(rgb, width, height, cfg)?;
+
+    // Masking last keeps scaling from blurring masked content into neighbouring pixels.
+    let redactions = cfg
+        .redact_rects
+        .iter()
+        .filter_map(|rect| rect.to_region(frame.width, frame.height));
+    for mask in cfg.masked_regions.iter().copied().chain(redactions) {
+        mask_rgb(&mut rgb, width, height, mask, view);
+    }
+
+    let encode_started = Instant::now();
+    let jpeg = compress_rgb(&rgb, width, height, cfg)?;
+    debug!(
+        elapsed_ms = encode_started.elapsed().as_millis() as u64,
+        "JPEG 编码完成"
+    );
+
+    Ok((jpeg, width, height))
+}
+
+/// Set once libjpeg-turbo has failed, so later frames go straight to the `image` encoder.
+#[cfg(feature = "turbojpeg")]
+static TURBOJPEG_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// JPEG encode an RGB buffer with the backend `cfg.encoder` asks for, falling back to the
+/// `image` encoder when that backend is missing from the build or fails.
+fn compress_rgb(rgb: &[u8], width: u32, height: u32, cfg: &BroadcastConfig) -> Result<Vec<u8>> {
+    #[cfg(feature = "turbojpeg")]
+    if cfg.encoder == crate::config::EncoderKind::Turbojpeg
+        && !TURBOJPEG_FAILED.load(Ordering::Relaxed)
+    {
+        match turbojpeg_compress(rgb, width, height, cfg.jpeg_quality) {
+            Ok(jpeg) => return Ok(jpeg),
+            Err(err) => {
+                TURBOJPEG_FAILED.store(true, Ordering::Relaxed);
+                warn!(?err, "libjpeg-turbo 编码失败，改用 image 编码器");
+            }
+        }
+    }
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, cfg.jpeg_quality).encode(
+        rgb,
+        width,
+        height,
+        ColorType::Rgb8,
+    )?;
+    Ok(jpeg)
+}
+
+#[cfg(feature = "turbojpeg")]
+fn turbojpeg_compress(rgb: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>> {
+    let image = turbojpeg::Image {
+        pixels: rgb,
+        width: width as usize,
+        pitch: width as usize * 3,
+        height: height as usize,
+        format: turbojpeg::PixelFormat::RGB,
+    };
+    let jpeg = turbojpeg::compress(image, i32::from(quality), turbojpeg::Subsamp::Sub2x2)?;
+    Ok(jpeg.to_vec())
+}
+
+/// Check that [`EncoderKind::Turbojpeg`] can be used: this build must include libjpeg-turbo (the
+/// `turbojpeg` feature) and the library must encode a test image.
+pub fn probe_turbojpeg() -> Result<()> {
+    #[cfg(feature = "turbojpeg")]
+    {
+        turbojpeg_compress(&[0; 8 * 8 * 3], 8, 8, 75).context("libjpeg-turbo 不可用")?;
+        Ok(())
+    }
+    #[cfg(not(feature = "turbojpeg"))]
+    anyhow::bail!("当前构建未包含 libjpeg-turbo（turbojpeg 功能）")
+}
+
+pub fn bgra_to_rgb(bgra: &[u8]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(bgra.len() / 4 * 3);
+    for pixel in bgra.chunks_exact(4) {
+        rgb.push(pixel[2]);
+        rgb.push(pixel[1]);
+        rgb.push(pixel[0]);
+    }
+    rgb
+}
+
+/// Cut `region` out of a BGRA frame, converting it to RGB on the way.
+fn crop_to_rgb(frame: &RawFrame, region: Region) -> (Vec<u8>, u32, u32) {
+    let (x, y, width, height) = region.to_pixels(frame.width, frame.height);
+    let stride = frame.width as usize * 4;
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for row in y..y + height {
+        let start = row as usize * stride + x as usize * 4;
+        let end = start + width as usize * 4;
+        rgb.extend(bgra_to_rgb(&frame.data[start..end]));
+    }
+    (rgb, width, height)
+}
+
+/// Black out `mask` (full-screen fractions) in an RGB buffer showing the `view` part of the
+/// screen. Edges are rounded outwards so no partially covered pixel stays visible.
+fn mask_rgb(rgb: &mut [u8], width: u32, height: u32, mask: Region, view: Region) {
+    let left = ((mask.x - view.x) / view.width).max(0.0);
+    let top = ((mask.y - view.y) / view.height).max(0.0);
+    let right = ((mask.x + mask.width - view.x) / view.width).min(1.0);
+    let bottom = ((mask.y + mask.height - view.y) / view.height).min(1.0);
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let x0 = (left * width as f32).floor() as usize;
+    let x1 = ((right * width as f32).ceil() as usize).min(width as usize);
+    let y0 = (top * height as f32).floor() as usize;
+    let y1 = ((bottom * height as f32).ceil() as usize).min(height as usize);
+    let stride = width as usize * 3;
+    for row in y0..y1 {
+        rgb[row * stride + x0 * 3..row * stride + x1 * 3].fill(0);
+    }
+}
+
+/// Scale the RGB buffer down to fit within `max_width`/`max_height`, preserving aspect ratio.
+fn downscale(
+    rgb: Vec<u8>,
+    width: u32,
+    height: u32,
+    cfg: &BroadcastConfig,
+) -> Result<(Vec<u8>, u32, u32)> {
+    let (target_width, target_height) = fit_within(width, height, cfg.max_width, cfg.max_height);
+    let rgb = resize(rgb, width, height, target_width, target_height, cfg)?;
+    Ok((rgb, target_width, target_height))
+}
+
+fn resize(
+    rgb: Vec<u8>,
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+    cfg: &BroadcastConfig,
+) -> Result<Vec<u8>> {
+    if (target_width, target_height) == (width, height) {
+        return Ok(rgb);
+    }
+
+    let started = Instant::now();
+    let image = RgbImage::from_raw(width, height, rgb).context("截屏数据尺寸不匹配")?;
+    let scaled = imageops::resize(
+        &image,
+        target_width,
+        target_height,
+        filter_type(cfg.scale_filter),
+    );
+    debug!(
+        from = %format!("{width}x{height}"),
+        to = %format!("{target_width}x{target_height}"),
+        filter = ?cfg.scale_filter,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "屏幕帧已缩放"
+    );
+    Ok(scaled.into_raw())
+}
+
+/// Largest size with the aspect ratio of `width`x`height` that fits `bound_width`x`bound_height`,
+/// scaling up as well as down.
+fn fit_scaled(width: u32, height: u32, bound_width: u32, bound_height: u32) -> (u32, u32) {
+    let scale = (bound_width as f64 / width as f64).min(bound_height as f64 / height as f64);
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// Largest size that fits inside the optional bounds without changing the aspect ratio.
+pub fn fit_within(
+    width: u32,
+    height: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> (u32, u32) {
+    let scale_w = max_width.map_or(1.0, |max| max.max(1) as f64 / width as f64);
+    let scale_h = max_height.map_or(1.0, |max| max.max(1) as f64 / height as f64);
+    let scale = scale_w.min(scale_h);
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+fn filter_type(filter: ScaleFilter) -> FilterType {
+    match filter {
+        ScaleFilter::Nearest => FilterType::Nearest,
+        ScaleFilter::Triangle => FilterType::Triangle,
+        ScaleFilter::Lanczos => FilterType::Lanczos3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_is_drawn_at_its_tip_and_clipped_at_the_frame_edge() {
+        let mut frame = TestPatternSource::new(64, 48).capture().unwrap();
+        let untouched = frame.clone();
+        let pixel = |frame: &RawFrame, x: u32, y: u32| {
+            let offset = ((y * frame.width + x) * 4) as usize;
+            frame.data[offset..offset + 4].to_vec()
+        };
+
+        // Tip just inside the bottom-right corner: most of the arrow hangs off the frame.
+        draw_cursor(&mut frame, (62.0 / 64.0, 40.0 / 48.0));
+        assert_eq!(pixel(&frame, 62, 40), [0, 0, 0, 255]);
+        assert_eq!(pixel(&frame, 63, 47), [255, 255, 255, 255]);
+        assert_eq!(pixel(&frame, 61, 40), pixel(&untouched, 61, 40));
+        assert_eq!(frame.data.len(), untouched.data.len());
+    }
+
+    #[test]
+    fn redact_rects_reaching_past_the_frame_are_clamped() {
+        let (width, height) = (64, 48);
+        let raw = TestPatternSource::new(width, height).capture().unwrap();
+        let cfg = BroadcastConfig {
+            redact_rects: vec![
+                Rect {
+                    x: width - 16,
+                    y: height - 16,
+                    width: u32::MAX,
+                    height: u32::MAX,
+                },
+                Rect {
+                    x: width * 2,
+                    y: 0,
+                    width: 8,
+                    height: 8,
+                },
+            ],
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, _, _) = encode_jpeg(&raw, &cfg).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        let corner = decoded.get_pixel(width - 1, height - 1).0;
+        assert!(corner.iter().all(|&c| c < 16), "corner was {corner:?}");
+        let [r, _, _] = decoded.get_pixel(width - 1, 0).0;
+        assert!(r > 200, "unredacted corner was red {r}");
+    }
+}
@@ -1,20 +1,39 @@
 //! Shared types and utilities used by both teacher and student binaries.
 
+pub mod audio;
+pub mod clock;
 pub mod config;
+pub mod diagnose;
+pub mod frame;
 pub mod logging;
 pub mod message;
 pub mod net;
+pub mod tls;
 pub mod util;
 
 pub mod prelude {
     //! Common imports that are frequently used across binaries.
-    pub use crate::config::{BroadcastConfig, StudentConfig, StudentRegistration, TeacherConfig};
+    pub use crate::clock::SessionClock;
+    pub use crate::config::{
+        BroadcastConfig, DuplicateStudentPolicy, EncoderKind, Rect, Region, ScaleFilter,
+        StudentConfig, StudentRegistration, TeacherConfig, UploadOrganize,
+    };
     pub use crate::logging::init_tracing;
     pub use crate::message::{
-        AudioFrame, BroadcastCommand, BroadcastMode, BroadcastSource, FileChunk, FileOffer,
-        FileTransferComplete, Heartbeat, HelloAck, HelloMessage, StudentCapabilities,
-        StudentToTeacher, TeacherToStudent, VideoCodec, VideoFrame,
+        sanitize_chat, AudioCodec, AudioFrame, AudioState, BroadcastCommand, BroadcastMode,
+        BroadcastSource, FileChunk, FileOffer, FileTransferComplete, FocusState, Heartbeat,
+        HelloAck, HelloMessage, MessageCodec, Notice, NoticeSeverity, ProtocolVersion,
+        SettingsUpdate, StudentCapabilities, StudentToTeacher, TeacherToStudent, VideoCodec,
+        VideoFrame, AUDIO_STATE_PROTOCOL_VERSION, BLACKOUT_PROTOCOL_VERSION, CHAT_PROTOCOL_VERSION,
+        FOCUS_PROTOCOL_VERSION, KEYFRAME_PROTOCOL_VERSION, KICK_PROTOCOL_VERSION,
+        LOCK_INPUT_PROTOCOL_VERSION, MAX_CHAT_CHARS, NOTICE_PROTOCOL_VERSION,
+        PACING_PROTOCOL_VERSION, PROTOCOL_VERSION, RAISE_HAND_PROTOCOL_VERSION,
+        REPLACE_PROTOCOL_VERSION, SETTINGS_PROTOCOL_VERSION, SHARE_DECLINE_PROTOCOL_VERSION,
+        SHARE_END_PROTOCOL_VERSION, THUMBNAIL_PROTOCOL_VERSION, ZOOM_PROTOCOL_VERSION,
+    };
+    pub use crate::net::{
+        compression_supported, connect_as_student, read_message, write_message,
+        write_message_compressed, FramedReader, FramedStream, FramedWriter, Framing, StudentStream,
     };
-    pub use crate::net::{read_message, write_message, FramedStream};
-    pub use crate::util::sanitize_filename;
+    pub use crate::util::{sanitize_filename, tokens_match};
 }
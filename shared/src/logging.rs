@@ -1,24 +1,126 @@
-use anyhow::Result;
-use tracing_subscriber::{fmt, EnvFilter};
+use std::io;
+use std::path::PathBuf;
 
-/// Initialize a compact tracing subscriber that honors `RUST_LOG`.
-pub fn init_tracing(app_name: &str) -> Result<()> {
+use anyhow::{Context, Result};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Environment variable choosing the log line format: `compact` (default) or `json`.
+pub const LOG_FORMAT_ENV: &str = "FJCPC_LOG_FORMAT";
+/// Environment variable naming a directory that also receives the logs, one file per day.
+pub const LOG_DIR_ENV: &str = "FJCPC_LOG_DIR";
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Shape of the log lines written to stdout and the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One short human-readable line per event.
+    #[default]
+    Compact,
+    /// One JSON object per event with its fields and enclosing spans, for log collectors.
+    Json,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "compact" | "" => Some(Self::Compact),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Layer writing events in this format to `writer`; colours are left out of files.
+    fn layer<W>(self, writer: W, ansi: bool) -> BoxedLayer
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let mut layer = fmt::layer()
+            .with_writer(writer)
+            .with_thread_ids(true)
+            .with_thread_names(true)
+            .with_target(false);
+        if !ansi {
+            layer = layer.with_ansi(false);
+        }
+        match self {
+            Self::Compact => layer.compact().boxed(),
+            Self::Json => layer
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        }
+    }
+}
+
+/// Keeps the log file writer running. Hold it until the process exits; dropping it flushes
+/// the lines still buffered.
+#[must_use = "dropping the guard stops writing the log file"]
+pub struct LogGuard {
+    _worker: Option<WorkerGuard>,
+}
+
+/// Initialize a tracing subscriber that honors `RUST_LOG`, [`LOG_FORMAT_ENV`] and
+/// [`LOG_DIR_ENV`]. Logs go to stdout, and with a log directory also to
+/// `<dir>/<app_name>.<date>.log`.
+pub fn init_tracing(app_name: &str) -> Result<LogGuard> {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let requested = std::env::var(LOG_FORMAT_ENV).ok();
+    let format = requested
+        .as_deref()
+        .and_then(LogFormat::parse)
+        .unwrap_or_default();
 
-    // Multiple initialisation attempts are benign; ignore the second one.
-    let subscriber = fmt()
-        .with_env_filter(filter)
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_target(false)
-        .compact()
-        .finish();
+    let mut layers = vec![format.layer(io::stdout, true)];
+    let log_dir = std::env::var_os(LOG_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+    let mut guard = None;
+    if let Some(dir) = &log_dir {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(app_name)
+            .filename_suffix("log")
+            .build(dir)
+            .with_context(|| format!("Failed to open log directory {}", dir.display()))?;
+        let (writer, worker) = tracing_appender::non_blocking(appender);
+        layers.push(format.layer(writer, false));
+        guard = Some(worker);
+    }
 
+    // Multiple initialisation attempts are benign; ignore the second one.
+    let subscriber = tracing_subscriber::registry().with(layers).with(filter);
     if tracing::subscriber::set_global_default(subscriber).is_err() {
         // Another subscriber is already set (likely in tests); treat as success.
-        return Ok(());
+        return Ok(LogGuard { _worker: None });
     }
 
-    tracing::info!(application = app_name, "logging initialized");
-    Ok(())
+    if let Some(value) = requested.filter(|value| LogFormat::parse(value).is_none()) {
+        tracing::warn!(%value, "{LOG_FORMAT_ENV} 取值无效，已使用 compact 格式");
+    }
+    tracing::info!(
+        application = app_name,
+        ?format,
+        ?log_dir,
+        "logging initialized"
+    );
+    Ok(LogGuard { _worker: guard })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_format_values_are_case_insensitive() {
+        assert_eq!(LogFormat::parse("JSON"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::parse(" compact "), Some(LogFormat::Compact));
+        assert_eq!(LogFormat::parse(""), Some(LogFormat::Compact));
+        assert_eq!(LogFormat::parse("pretty"), None);
+    }
 }
@@ -1,6 +1,73 @@
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::config::{BroadcastConfig, Region};
+
+// Fields that are optional or were added after the first release carry `#[serde(default)]`
+// so that JSON from an older peer still parses.
+
+/// Declare an enum that is tagged as `#[json(...)]` says in JSON, which every peer speaks,
+/// and with serde's external tagging in binary codecs: bincode cannot read internal or
+/// adjacent tags back. The JSON form is derived on the private `$mirror` enum.
+macro_rules! wire_enum {
+    (
+        #[json($mirror:ident, $($json:tt)*)]
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$vmeta:meta])*
+                $variant:ident
+                $(( $tuple:ty ))?
+                $({ $( $(#[$fmeta:meta])* $field:ident : $fty:ty ),* $(,)? })?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Serialize, Deserialize)]
+        #[serde(remote = "Self", rename_all = "snake_case")]
+        pub enum $name {
+            $(
+                $(#[$vmeta])*
+                $variant
+                $(( $tuple ))?
+                $({ $( $(#[$fmeta])* $field : $fty ),* })?
+            ),*
+        }
+
+        #[allow(dead_code)]
+        #[derive(Serialize, Deserialize)]
+        #[serde(rename_all = "snake_case", $($json)*)]
+        enum $mirror {
+            $(
+                $variant
+                $(( $tuple ))?
+                $({ $( $(#[$fmeta])* $field : $fty ),* })?
+            ),*
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    $mirror::serialize(self, serializer)
+                } else {
+                    $name::serialize(self, serializer)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    $mirror::deserialize(deserializer)
+                } else {
+                    $name::deserialize(deserializer)
+                }
+            }
+        }
+    };
+}
+
 /// Broadcast display mode requested by the teacher.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -15,15 +82,18 @@ impl Default for BroadcastMode {
     }
 }
 
-/// Identifies the source of a broadcast feed.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
-pub enum BroadcastSource {
-    Teacher,
-    Student {
-        student_id: String,
-        student_name: Option<String>,
-    },
+wire_enum! {
+    #[json(BroadcastSourceJson, remote = "BroadcastSource", tag = "type", content = "payload")]
+    /// Identifies the source of a broadcast feed.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum BroadcastSource {
+        Teacher,
+        Student {
+            student_id: String,
+            #[serde(default)]
+            student_name: Option<String>,
+        },
+    }
 }
 
 /// Supported codecs for video transport.
@@ -41,12 +111,57 @@ pub enum VideoCodec {
 pub struct VideoFrame {
     pub frame_id: u64,
     pub timestamp_ms: u64,
+    /// Milliseconds on the sender's `SessionClock` when the data was captured; shared with
+    /// the other media stream, so use this rather than `timestamp_ms` to align audio and video.
+    #[serde(default)]
+    pub capture_monotonic_ms: u64,
     pub source: BroadcastSource,
     pub codec: VideoCodec,
     pub width: u32,
     pub height: u32,
+    #[serde(default)]
     pub fullscreen: bool,
-    pub data: Vec<u8>,
+    /// Cheap to clone; serialized exactly like the `Vec<u8>` it replaced.
+    pub data: Bytes,
+    /// Low-res monitoring snapshot for the teacher only; never relayed to other students.
+    #[serde(default)]
+    pub thumbnail: bool,
+    /// One-shot screenshot to keep on screen, rather than one frame of a running broadcast.
+    /// Added in 1.8. The default only helps JSON peers: bincode ignores `serde(default)`,
+    /// which is fine because it is only negotiated between peers on the same version.
+    #[serde(default)]
+    pub still: bool,
+}
+
+/// Encoding of `AudioFrame::data`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioCodec {
+    /// Interleaved little-endian i16 samples.
+    #[default]
+    Pcm,
+    /// One 20 ms Opus packet at 48 kHz; see `shared::audio`.
+    Opus,
+}
+
+impl AudioCodec {
+    /// Codecs to offer in `StudentCapabilities::audio_codecs`.
+    pub fn supported() -> &'static [AudioCodec] {
+        if cfg!(feature = "opus") {
+            &[AudioCodec::Opus, AudioCodec::Pcm]
+        } else {
+            &[AudioCodec::Pcm]
+        }
+    }
+
+    /// Our most preferred codec that the peer also offered, falling back to PCM.
+    pub fn negotiate(offered: &[AudioCodec]) -> AudioCodec {
+        Self::supported()
+            .iter()
+            .copied()
+            .find(|codec| offered.contains(codec))
+            .unwrap_or(AudioCodec::Pcm)
+    }
 }
 
 /// Audio frame chunk transmitted alongside video.
@@ -54,10 +169,17 @@ pub struct VideoFrame {
 pub struct AudioFrame {
     pub frame_id: u64,
     pub timestamp_ms: u64,
+    /// Milliseconds on the sender's `SessionClock` when the data was captured; shared with
+    /// the other media stream, so use this rather than `timestamp_ms` to align audio and video.
+    #[serde(default)]
+    pub capture_monotonic_ms: u64,
     pub sample_rate: u32,
     pub channels: u8,
+    #[serde(default)]
     pub force_play: bool,
-    pub data: Vec<u8>,
+    pub data: Bytes,
+    #[serde(default)]
+    pub codec: AudioCodec,
 }
 
 /// Metadata describing a file that will be sent.
@@ -66,7 +188,12 @@ pub struct FileOffer {
     pub transfer_id: Uuid,
     pub file_name: String,
     pub total_size: u64,
+    #[serde(default)]
     pub auto_open: bool,
+    /// Re-offer of a transfer the student may have partly received; answered with
+    /// `StudentToTeacher::ResumeFrom`.
+    #[serde(default)]
+    pub resume: bool,
 }
 
 /// Data chunk for an ongoing file transfer.
@@ -75,7 +202,29 @@ pub struct FileChunk {
     pub transfer_id: Uuid,
     pub offset: u64,
     pub bytes: Vec<u8>,
+    #[serde(default)]
     pub final_chunk: bool,
+    /// CRC-32 of `bytes`; 0 means the sender did not compute one (older peers).
+    #[serde(default)]
+    pub crc32: u32,
+}
+
+impl FileChunk {
+    /// Chunk with its checksum filled in.
+    pub fn new(transfer_id: Uuid, offset: u64, bytes: Vec<u8>, final_chunk: bool) -> Self {
+        Self {
+            transfer_id,
+            offset,
+            crc32: crc32fast::hash(&bytes),
+            bytes,
+            final_chunk,
+        }
+    }
+
+    /// Whether `bytes` match the checksum; chunks without one are accepted unchecked.
+    pub fn checksum_matches(&self) -> bool {
+        self.crc32 == 0 || crc32fast::hash(&self.bytes) == self.crc32
+    }
 }
 
 /// Completion notification for a file transfer.
@@ -83,9 +232,120 @@ pub struct FileChunk {
 pub struct FileTransferComplete {
     pub transfer_id: Uuid,
     pub success: bool,
+    #[serde(default)]
     pub message: Option<String>,
 }
 
+/// Serialization format of the messages after the handshake.
+///
+/// `Hello` and `Welcome` are always plain JSON frames so that any peer can connect; the
+/// teacher then picks a codec from the student's offer, if it made one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageCodec {
+    #[default]
+    Json,
+    Bincode,
+}
+
+/// Version of the wire protocol, kept apart from the crate versions in `client_version` and
+/// `server_version`. Peers must share the major version; minor bumps add `#[serde(default)]`
+/// fields, or messages only sent to peers that announced the new minor version.
+///
+/// New fields and variants go after all existing ones so that the bincode layout only ever
+/// grows at the end; bincode itself is only negotiated between peers on the same version.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 1,
+    minor: 12,
+};
+
+// Messages added before versions were exchanged are known to every peer that announces one,
+// i.e. 1.1 on; a peer without a version may be from the first release and know none of them.
+
+/// First protocol version whose students understand `TeacherToStudent::SettingsUpdate`.
+pub const SETTINGS_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose students understand `BroadcastCommand::Blackout`.
+pub const BLACKOUT_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose students understand `TeacherToStudent::Notice`.
+pub const NOTICE_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::AudioState`.
+pub const AUDIO_STATE_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::FocusState`.
+pub const FOCUS_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::RequestZoom`.
+pub const ZOOM_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+/// First protocol version whose students understand `BroadcastCommand::RequestThumbnail`.
+pub const THUMBNAIL_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 2 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::RequestKeyframe`.
+pub const KEYFRAME_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 3 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::RaiseHand`.
+pub const RAISE_HAND_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 4 };
+
+/// First protocol version whose peers understand the `Chat` messages in both directions.
+pub const CHAT_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 5 };
+
+/// First protocol version whose students understand `TeacherToStudent::Pacing`.
+pub const PACING_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 6 };
+
+/// First protocol version whose teachers understand `StudentToTeacher::ShareEnded`.
+pub const SHARE_END_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 7 };
+
+/// First protocol version whose students understand `TeacherToStudent::Kicked`.
+pub const KICK_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 9 };
+
+/// First protocol version whose students understand `lock_input` in `BroadcastCommand::Start`.
+pub const LOCK_INPUT_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 1,
+    minor: 10,
+};
+
+/// First protocol version whose students understand `TeacherToStudent::Replaced`.
+pub const REPLACE_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 1,
+    minor: 11,
+};
+
+/// First protocol version whose teachers understand `StudentToTeacher::ShareDeclined`.
+pub const SHARE_DECLINE_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 1,
+    minor: 12,
+};
+
+// Field order makes the derived ordering compare the major version first.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl Default for ProtocolVersion {
+    /// Peers from before the version was exchanged speak 1.0.
+    fn default() -> Self {
+        ProtocolVersion { major: 1, minor: 0 }
+    }
+}
+
+impl ProtocolVersion {
+    /// Whether a peer speaking `other` can talk to us.
+    pub fn is_compatible(self, other: ProtocolVersion) -> bool {
+        self.major == other.major
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
 /// Initial message sent by a student when connecting.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HelloMessage {
@@ -93,15 +353,41 @@ pub struct HelloMessage {
     pub student_name: String,
     pub client_version: String,
     #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    #[serde(default)]
     pub capabilities: StudentCapabilities,
+    /// Codecs the student can speak, most preferred first; empty means JSON only.
+    #[serde(default)]
+    pub codecs: Vec<MessageCodec>,
+    /// Whether the student can read zstd-compressed frames.
+    #[serde(default)]
+    pub compression: bool,
+    /// Shared secret matching the teacher's `join_token`.
+    #[serde(default)]
+    pub join_token: Option<String>,
 }
 
 /// Acknowledgement from teacher after successful registration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HelloAck {
     pub server_version: String,
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    #[serde(default)]
     pub force_fullscreen: bool,
+    #[serde(default)]
     pub broadcast_mode: BroadcastMode,
+    /// Codec for everything after this message, in codec-tagged frames; `None` (older
+    /// teachers, or students that offered no codecs) keeps plain JSON frames.
+    #[serde(default)]
+    pub codec: Option<MessageCodec>,
+    /// Teacher run this connection belongs to, for matching up both sides' logs; `None`
+    /// from older teachers.
+    #[serde(default)]
+    pub session_id: Option<Uuid>,
+    /// Largest upload the teacher accepts, in bytes; 0 means no limit (or an older teacher).
+    #[serde(default)]
+    pub max_upload_bytes: u64,
 }
 
 /// Reported capabilities of a student client.
@@ -117,6 +403,9 @@ pub struct StudentCapabilities {
     pub send_audio: bool,
     #[serde(default)]
     pub file_transfer: bool,
+    /// Audio codecs the student can decode; empty (older clients) means PCM only.
+    #[serde(default)]
+    pub audio_codecs: Vec<AudioCodec>,
 }
 
 /// Periodic heartbeat between peers.
@@ -125,46 +414,376 @@ pub struct Heartbeat {
     pub timestamp_ms: u64,
 }
 
-/// Command to start/stop broadcasts or switch source.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "action", rename_all = "snake_case")]
-pub enum BroadcastCommand {
-    Start {
-        source: BroadcastSource,
-        mode: BroadcastMode,
-    },
-    Stop,
-    RequestStudentShare {
-        student_id: String,
-    },
-}
-
-/// Messages sent from teacher to student.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
-pub enum TeacherToStudent {
-    Welcome(HelloAck),
-    Broadcast(BroadcastCommand),
-    Video(VideoFrame),
-    Audio(AudioFrame),
-    FileOffer(FileOffer),
-    FileChunk(FileChunk),
-    FileComplete(FileTransferComplete),
-    Heartbeat(Heartbeat),
-    Error(String),
-}
-
-/// Messages sent from student to teacher.
+/// Local playback state reported by a student whenever it changes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AudioState {
+    pub muted: bool,
+    /// Playback volume in percent (0-100).
+    pub volume: u8,
+}
+
+impl Default for AudioState {
+    fn default() -> Self {
+        Self {
+            muted: false,
+            volume: 100,
+        }
+    }
+}
+
+/// Focus change of the student's broadcast window, only sent when `report_focus_loss` is enabled.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FocusState {
+    pub focused: bool,
+    #[serde(default)]
+    pub timestamp_ms: u64,
+}
+
+/// Broadcast settings pushed to students on connect and whenever they change.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
-pub enum StudentToTeacher {
-    Hello(HelloMessage),
-    Heartbeat(Heartbeat),
-    Ack(String),
-    Video(VideoFrame),
-    Audio(AudioFrame),
-    FileOffer(FileOffer),
-    FileChunk(FileChunk),
-    FileComplete(FileTransferComplete),
-    Error(String),
+pub struct SettingsUpdate {
+    /// Monotonically increasing revision, echoed back in `StudentToTeacher::SettingsAck`.
+    pub revision: u64,
+    #[serde(default)]
+    pub broadcast: BroadcastConfig,
+}
+
+wire_enum! {
+    #[json(BroadcastCommandJson, remote = "BroadcastCommand", tag = "action")]
+    /// Command to start/stop broadcasts or switch source.
+    #[derive(Debug, Clone)]
+    pub enum BroadcastCommand {
+        Start {
+            source: BroadcastSource,
+            mode: BroadcastMode,
+            /// Ask students that consented to swallow task-switching keys while the
+            /// fullscreen broadcast window is active. Only set for students on
+            /// `LOCK_INPUT_PROTOCOL_VERSION` or later.
+            #[serde(default)]
+            lock_input: bool,
+        },
+        Stop,
+        RequestStudentShare {
+            student_id: String,
+        },
+        /// Black out every student screen (ignoring broadcast frames) until disabled.
+        Blackout {
+            enabled: bool,
+        },
+        /// Send the teacher a `thumbnail` frame of the screen every `interval_secs`; 0 stops.
+        RequestThumbnail {
+            interval_secs: u64,
+        },
+    }
+}
+
+/// How prominently a notice is shown.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NoticeSeverity {
+    #[default]
+    Info,
+    Warning,
+    Urgent,
+}
+
+/// Short text banner shown on student screens for `duration_ms`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Notice {
+    pub text: String,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub severity: NoticeSeverity,
+}
+
+/// Longest chat line in characters; the rest is cut off.
+pub const MAX_CHAT_CHARS: usize = 500;
+
+/// Clean up a chat line before sending or showing it: control characters become spaces,
+/// the ends are trimmed and at most [`MAX_CHAT_CHARS`] are kept. `None` if nothing is left.
+pub fn sanitize_chat(text: &str) -> Option<String> {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let text: String = text.trim().chars().take(MAX_CHAT_CHARS).collect();
+    let text = text.trim_end().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+wire_enum! {
+    #[json(TeacherToStudentJson, remote = "TeacherToStudent", tag = "type", content = "payload")]
+    /// Messages sent from teacher to student.
+    #[derive(Debug, Clone)]
+    pub enum TeacherToStudent {
+        Welcome(HelloAck),
+        Broadcast(BroadcastCommand),
+        Video(VideoFrame),
+        Audio(AudioFrame),
+        FileOffer(FileOffer),
+        FileChunk(FileChunk),
+        FileComplete(FileTransferComplete),
+        Heartbeat(Heartbeat),
+        SettingsUpdate(SettingsUpdate),
+        Error(String),
+        Notice(Notice),
+        /// Chat line from the teacher; `from` is the name to show it under.
+        Chat {
+            from: String,
+            text: String,
+        },
+        /// Frame rate a spotlighted student should capture at while its link keeps up no
+        /// better; 0 lifts the limit.
+        Pacing {
+            fps: u32,
+        },
+        /// The teacher removed this student from the class and closes the connection right
+        /// after; the student does not reconnect straight away.
+        Kicked {
+            reason: String,
+        },
+        /// Another connection with this student ID took over; the student stops
+        /// reconnecting so two machines sharing an ID do not keep replacing each other.
+        Replaced {
+            reason: String,
+        },
+    }
+}
+
+impl TeacherToStudent {
+    /// Whether the message carries raw data that zstd can shrink (PCM audio, BGRA frames).
+    /// JPEG video is already compressed.
+    pub fn is_compressible(&self) -> bool {
+        match self {
+            TeacherToStudent::Audio(_) => true,
+            TeacherToStudent::Video(frame) => frame.codec == VideoCodec::Bgra,
+            _ => false,
+        }
+    }
+}
+
+wire_enum! {
+    #[json(StudentToTeacherJson, remote = "StudentToTeacher", tag = "type", content = "payload")]
+    /// Messages sent from student to teacher.
+    #[derive(Debug, Clone)]
+    pub enum StudentToTeacher {
+        Hello(HelloMessage),
+        Heartbeat(Heartbeat),
+        Ack(String),
+        Video(VideoFrame),
+        Audio(AudioFrame),
+        FileOffer(FileOffer),
+        FileChunk(FileChunk),
+        FileComplete(FileTransferComplete),
+        AudioState(AudioState),
+        FocusState(FocusState),
+        /// Ask the teacher to broadcast only `region` of the current view (fractions of what
+        /// the student sees), or the whole screen again when `None`.
+        RequestZoom {
+            #[serde(default)]
+            region: Option<Region>,
+        },
+        /// Acknowledges the `SettingsUpdate` with the given revision.
+        SettingsAck(u64),
+        Error(String),
+        /// Answer to a resumed `FileOffer`: `offset` bytes are already on disk.
+        ResumeFrom {
+            transfer_id: Uuid,
+            offset: u64,
+        },
+        /// Ask for the latest broadcast frame again, e.g. after one failed to decode.
+        RequestKeyframe,
+        /// The student raised or lowered their hand.
+        RaiseHand {
+            raised: bool,
+        },
+        /// Chat line to the teacher.
+        Chat {
+            text: String,
+        },
+        /// The spotlight capture stopped cleanly; no more `Video` follows until the next one.
+        ShareEnded,
+        /// The student refused the spotlight request; nothing is captured.
+        ShareDeclined,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_frame_without_optional_fields_parses() {
+        let json = r#"{"type":"video","payload":{"frame_id":1,"timestamp_ms":2,"source":{"type":"teacher"},"codec":"jpeg","width":4,"height":3,"data":[1,2]}}"#;
+        let TeacherToStudent::Video(frame) = serde_json::from_str(json).unwrap() else {
+            panic!("expected a video frame");
+        };
+        assert_eq!(frame.capture_monotonic_ms, 0);
+        assert!(!frame.fullscreen);
+        assert_eq!(frame.data, vec![1, 2]);
+    }
+
+    #[cfg(feature = "codec-bincode")]
+    #[test]
+    fn bytes_payloads_keep_the_vec_wire_format() {
+        let payload = vec![0xffu8, 0xd8, 0x00];
+        let shared = Bytes::from(payload.clone());
+        assert_eq!(
+            bincode::serialize(&shared).unwrap(),
+            bincode::serialize(&payload).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&shared).unwrap(),
+            serde_json::to_string(&payload).unwrap()
+        );
+        let decoded: Bytes = bincode::deserialize(&bincode::serialize(&payload).unwrap()).unwrap();
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn hello_and_ack_without_optional_fields_parse() {
+        let json = r#"{"type":"hello","payload":{"student_id":"S01","student_name":"Alice","client_version":"0.1.0"}}"#;
+        let StudentToTeacher::Hello(hello) = serde_json::from_str(json).unwrap() else {
+            panic!("expected hello");
+        };
+        assert!(!hello.capabilities.receive_video);
+        assert_eq!(hello.protocol_version, ProtocolVersion::default());
+
+        let json = r#"{"type":"welcome","payload":{"server_version":"0.1.0"}}"#;
+        let TeacherToStudent::Welcome(ack) = serde_json::from_str(json).unwrap() else {
+            panic!("expected welcome");
+        };
+        assert!(!ack.force_fullscreen);
+        assert_eq!(ack.broadcast_mode, BroadcastMode::default());
+        assert_eq!(ack.codec, None);
+    }
+
+    #[cfg(feature = "codec-bincode")]
+    #[test]
+    fn bincode_uses_external_tags_while_json_keeps_the_first_release_tags() {
+        let command = TeacherToStudent::Broadcast(BroadcastCommand::Start {
+            source: BroadcastSource::Teacher,
+            mode: BroadcastMode::Window,
+            lock_input: false,
+        });
+        assert_eq!(
+            serde_json::to_string(&command).unwrap(),
+            r#"{"type":"broadcast","payload":{"action":"start","source":{"type":"teacher"},"mode":"window","lock_input":false}}"#
+        );
+        let encoded = bincode::serialize(&command).unwrap();
+        let TeacherToStudent::Broadcast(BroadcastCommand::Start { source, mode, .. }) =
+            bincode::deserialize(&encoded).unwrap()
+        else {
+            panic!("expected start command");
+        };
+        assert_eq!(source, BroadcastSource::Teacher);
+        assert_eq!(mode, BroadcastMode::Window);
+    }
+
+    #[cfg(feature = "codec-bincode")]
+    #[test]
+    fn bincode_layout_only_grows_at_the_end() {
+        // `VideoFrame` and the variant indices as bincode was first negotiated.
+        #[derive(Deserialize)]
+        struct FirstVideoFrame {
+            frame_id: u64,
+            _timestamp_ms: u64,
+            _capture_monotonic_ms: u64,
+            _source: BroadcastSource,
+            _codec: VideoCodec,
+            _width: u32,
+            _height: u32,
+            _fullscreen: bool,
+            data: Vec<u8>,
+        }
+        let frame = VideoFrame {
+            frame_id: 7,
+            timestamp_ms: 1,
+            capture_monotonic_ms: 2,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 4,
+            height: 3,
+            fullscreen: true,
+            data: vec![1, 2].into(),
+            thumbnail: true,
+            still: true,
+        };
+        let first: FirstVideoFrame =
+            bincode::deserialize(&bincode::serialize(&frame).unwrap()).unwrap();
+        assert_eq!((first.frame_id, first.data), (7, vec![1, 2]));
+
+        let index = |encoded: Vec<u8>| u32::from_le_bytes(encoded[..4].try_into().unwrap());
+        let error = TeacherToStudent::Error("x".into());
+        assert_eq!(index(bincode::serialize(&error).unwrap()), 9);
+        let error = StudentToTeacher::Error("x".into());
+        assert_eq!(index(bincode::serialize(&error).unwrap()), 12);
+    }
+
+    #[test]
+    fn chat_is_flattened_trimmed_and_capped() {
+        assert_eq!(
+            sanitize_chat("  hi\nthere\t "),
+            Some("hi there".to_string())
+        );
+        assert_eq!(sanitize_chat(" \r\n "), None);
+        let long = "字".repeat(MAX_CHAT_CHARS + 10);
+        assert_eq!(
+            sanitize_chat(&long).unwrap().chars().count(),
+            MAX_CHAT_CHARS
+        );
+    }
+
+    #[test]
+    fn only_the_major_protocol_version_must_match() {
+        let newer_minor = ProtocolVersion {
+            minor: PROTOCOL_VERSION.minor + 1,
+            ..PROTOCOL_VERSION
+        };
+        let next_major = ProtocolVersion {
+            major: PROTOCOL_VERSION.major + 1,
+            minor: 0,
+        };
+        assert!(PROTOCOL_VERSION.is_compatible(newer_minor));
+        assert!(PROTOCOL_VERSION.is_compatible(ProtocolVersion::default()));
+        assert!(!PROTOCOL_VERSION.is_compatible(next_major));
+    }
+
+    #[test]
+    fn partial_state_messages_fall_back_to_defaults() {
+        let json = r#"{"type":"audio_state","payload":{"muted":true}}"#;
+        let StudentToTeacher::AudioState(state) = serde_json::from_str(json).unwrap() else {
+            panic!("expected audio state");
+        };
+        assert_eq!(
+            state,
+            AudioState {
+                muted: true,
+                volume: 100
+            }
+        );
+
+        let json = r#"{"type":"settings_update","payload":{"revision":2}}"#;
+        let TeacherToStudent::SettingsUpdate(update) = serde_json::from_str(json).unwrap() else {
+            panic!("expected settings update");
+        };
+        assert_eq!(update.broadcast.fps, BroadcastConfig::default().fps);
+
+        let json = r#"{"type":"broadcast","payload":{"action":"start","source":{"type":"student","payload":{"student_id":"S02"}},"mode":"window"}}"#;
+        let TeacherToStudent::Broadcast(BroadcastCommand::Start {
+            source, lock_input, ..
+        }) = serde_json::from_str(json).unwrap()
+        else {
+            panic!("expected start command");
+        };
+        assert!(!lock_input);
+        assert_eq!(
+            source,
+            BroadcastSource::Student {
+                student_id: "S02".into(),
+                student_name: None
+            }
+        );
+    }
 }
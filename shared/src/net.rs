@@ -1,35 +1,248 @@
 use std::marker::PhantomData;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, ensure, Context, Result};
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tracing::warn;
+
+use crate::message::{
+    HelloAck, HelloMessage, MessageCodec, ProtocolVersion, StudentToTeacher, TeacherToStudent,
+    PROTOCOL_VERSION,
+};
+use crate::tls::ClientTls;
 
 /// Maximum serialized message size (32 MiB) to guard against malicious clients.
 pub const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
 
-/// Write a length-prefixed bincode encoded message to the provided async writer.
-pub async fn write_message<W, T>(writer: &mut W, message: &T) -> Result<()>
+/// Payloads smaller than this are never compressed; zstd gains little on them.
+pub const COMPRESSION_THRESHOLD: usize = 2 * 1024;
+
+/// How much of an undecodable payload is logged for diagnosis.
+const PAYLOAD_PREVIEW_BYTES: usize = 256;
+
+impl MessageCodec {
+    /// Codecs this build can speak, most preferred first.
+    #[cfg(feature = "codec-bincode")]
+    const SUPPORTED: &'static [MessageCodec] = &[MessageCodec::Bincode, MessageCodec::Json];
+    #[cfg(not(feature = "codec-bincode"))]
+    const SUPPORTED: &'static [MessageCodec] = &[MessageCodec::Json];
+
+    /// Codecs to offer in `HelloMessage::codecs`.
+    pub fn supported() -> &'static [MessageCodec] {
+        Self::SUPPORTED
+    }
+
+    /// Our most preferred codec that the peer also offered, falling back to JSON. `None`
+    /// when the peer offered none: it predates codec tags and only reads plain frames.
+    ///
+    /// Bincode carries no field names and skips `serde(default)`, so it is only picked when
+    /// the peer speaks exactly our `PROTOCOL_VERSION`; any other version gets JSON.
+    pub fn negotiate(offered: &[MessageCodec], peer: ProtocolVersion) -> Option<MessageCodec> {
+        if offered.is_empty() {
+            return None;
+        }
+        Some(
+            Self::SUPPORTED
+                .iter()
+                .copied()
+                .filter(|&codec| codec == MessageCodec::Json || peer == PROTOCOL_VERSION)
+                .find(|codec| offered.contains(codec))
+                .unwrap_or(MessageCodec::Json),
+        )
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            MessageCodec::Json => b'J',
+            MessageCodec::Bincode => b'B',
+        }
+    }
+
+    /// Codec of a frame tag and whether the payload is zstd-compressed (lowercase tag).
+    fn from_tag(tag: u8) -> Option<(Self, bool)> {
+        let codec = match tag.to_ascii_uppercase() {
+            b'J' => MessageCodec::Json,
+            b'B' => MessageCodec::Bincode,
+            _ => return None,
+        };
+        Some((codec, tag.is_ascii_lowercase()))
+    }
+
+    fn encode<T: Serialize + ?Sized>(self, message: &T) -> Result<Vec<u8>> {
+        match self {
+            MessageCodec::Json => Ok(serde_json::to_vec(message)?),
+            #[cfg(feature = "codec-bincode")]
+            MessageCodec::Bincode => Ok(bincode::serialize(message)?),
+            #[cfg(not(feature = "codec-bincode"))]
+            MessageCodec::Bincode => bail!("bincode codec is not enabled in this build"),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T> {
+        let result = match self {
+            MessageCodec::Json => serde_json::from_slice(payload).map_err(anyhow::Error::from),
+            #[cfg(feature = "codec-bincode")]
+            MessageCodec::Bincode => bincode::deserialize(payload).map_err(anyhow::Error::from),
+            #[cfg(not(feature = "codec-bincode"))]
+            MessageCodec::Bincode => bail!("bincode codec is not enabled in this build"),
+        };
+        result.map_err(|err| {
+            // Usually a schema mismatch between versions; keep the raw bytes for diagnosis.
+            warn!(
+                error = %err,
+                len = payload.len(),
+                payload = %self.preview(payload),
+                "消息解析失败"
+            );
+            err.context(format!("failed to decode {:?} message", self))
+        })
+    }
+
+    /// Truncated, printable rendering of a payload for logs.
+    fn preview(self, payload: &[u8]) -> String {
+        let head = &payload[..payload.len().min(PAYLOAD_PREVIEW_BYTES)];
+        let mut preview = match self {
+            MessageCodec::Json => String::from_utf8_lossy(head).into_owned(),
+            MessageCodec::Bincode => head.iter().map(|byte| format!("{byte:02x}")).collect(),
+        };
+        if head.len() < payload.len() {
+            preview.push_str("...");
+        }
+        preview
+    }
+}
+
+/// Whether this build can read and write zstd-compressed frames.
+pub fn compression_supported() -> bool {
+    cfg!(feature = "compression")
+}
+
+/// How messages are framed on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// A `u32` length and a JSON payload, which every peer reads. Used for the handshake and
+    /// afterwards with peers that offered no codec.
+    #[default]
+    Plain,
+    /// A codec tag byte before the length, once the handshake agreed on `codec`. The reader
+    /// goes by the tag, so the payload may also be compressed.
+    Tagged(MessageCodec),
+}
+
+/// Write a length-prefixed message to the provided async writer, framed as `framing` says.
+pub async fn write_message<W, T>(writer: &mut W, framing: Framing, message: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize + ?Sized,
+{
+    match framing {
+        Framing::Plain => {
+            let payload = MessageCodec::Json.encode(message)?;
+            write_frame(writer, None, &payload).await
+        }
+        Framing::Tagged(codec) => {
+            let payload = codec.encode(message)?;
+            write_frame(writer, Some(codec.tag()), &payload).await
+        }
+    }
+}
+
+/// Like [`write_message`] with tagged framing, but zstd-compresses payloads of at least
+/// [`COMPRESSION_THRESHOLD`] bytes. The tag is lowercased to mark compressed frames, so only
+/// use this for peers that announced `HelloMessage::compression`.
+pub async fn write_message_compressed<W, T>(
+    writer: &mut W,
+    codec: MessageCodec,
+    level: i32,
+    message: &T,
+) -> Result<()>
 where
     W: AsyncWrite + Unpin,
     T: Serialize + ?Sized,
 {
-    let payload = serde_json::to_vec(message)?;
+    let payload = codec.encode(message)?;
+    if payload.len() >= COMPRESSION_THRESHOLD {
+        if let Some(compressed) = compress(&payload, level) {
+            let tag = codec.tag().to_ascii_lowercase();
+            return write_frame(writer, Some(tag), &compressed).await;
+        }
+    }
+    write_frame(writer, Some(codec.tag()), &payload).await
+}
+
+async fn write_frame<W>(writer: &mut W, tag: Option<u8>, payload: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
     if payload.len() > MAX_MESSAGE_SIZE {
         bail!("message too large: {} bytes", payload.len());
     }
 
+    if let Some(tag) = tag {
+        writer.write_u8(tag).await?;
+    }
     writer.write_u32_le(payload.len() as u32).await?;
-    writer.write_all(&payload).await?;
+    writer.write_all(payload).await?;
     writer.flush().await?;
     Ok(())
 }
 
-/// Read a length-prefixed bincode encoded message from the provided async reader.
-pub async fn read_message<R, T>(reader: &mut R) -> Result<T>
+/// Compressed payload, or `None` when compression is unavailable or does not pay off.
+#[cfg(feature = "compression")]
+fn compress(payload: &[u8], level: i32) -> Option<Vec<u8>> {
+    match zstd::bulk::compress(payload, level) {
+        Ok(compressed) if compressed.len() < payload.len() => Some(compressed),
+        Ok(_) => None,
+        Err(err) => {
+            warn!(?err, "消息压缩失败，改为不压缩发送");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_payload: &[u8], _level: i32) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "compression")]
+fn decompress(payload: &[u8]) -> Result<Vec<u8>> {
+    // Bounded like uncompressed frames so a small frame cannot expand without limit.
+    zstd::bulk::decompress(payload, MAX_MESSAGE_SIZE).context("failed to decompress message")
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_payload: &[u8]) -> Result<Vec<u8>> {
+    bail!("peer sent a compressed frame, but this build does not support compression")
+}
+
+/// Read a length-prefixed message framed as `framing` says from the provided async reader,
+/// decompressing it if needed. With tagged framing the codec comes from each frame's tag.
+pub async fn read_message<R, T>(reader: &mut R, framing: Framing) -> Result<T>
 where
     R: AsyncRead + Unpin,
     T: DeserializeOwned,
 {
+    let (codec, compressed) = match framing {
+        Framing::Plain => (MessageCodec::Json, false),
+        Framing::Tagged(_) => {
+            let tag = reader.read_u8().await?;
+            match MessageCodec::from_tag(tag) {
+                Some((codec, compressed)) if MessageCodec::SUPPORTED.contains(&codec) => {
+                    (codec, compressed)
+                }
+                Some((codec, _)) => bail!(
+                    "peer uses the {:?} codec, which this build does not support",
+                    codec
+                ),
+                None => bail!(
+                    "unknown codec tag {tag:#04x}; peer may be running an incompatible version"
+                ),
+            }
+        }
+    };
+
     let len = reader.read_u32_le().await? as usize;
     if len > MAX_MESSAGE_SIZE {
         bail!(
@@ -41,13 +254,17 @@ where
 
     let mut buf = vec![0u8; len];
     reader.read_exact(&mut buf).await?;
-    let msg = serde_json::from_slice::<T>(&buf)?;
-    Ok(msg)
+    if compressed {
+        buf = decompress(&buf)?;
+    }
+    codec.decode(&buf)
 }
 
 /// Convenience wrapper that binds a transport type to the shared codec helpers.
 pub struct FramedStream<T, Incoming = (), Outgoing = ()> {
     inner: T,
+    /// Plain until a handshake negotiates otherwise; applies to both directions.
+    framing: Framing,
     _marker_in: PhantomData<Incoming>,
     _marker_out: PhantomData<Outgoing>,
 }
@@ -61,20 +278,586 @@ where
     pub fn new(inner: T) -> Self {
         Self {
             inner,
+            framing: Framing::Plain,
             _marker_in: PhantomData,
             _marker_out: PhantomData,
         }
     }
 
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
     pub async fn send(&mut self, message: &Outgoing) -> Result<()> {
-        write_message(&mut self.inner, message).await
+        write_message(&mut self.inner, self.framing, message).await
     }
 
     pub async fn recv(&mut self) -> Result<Incoming> {
-        read_message(&mut self.inner).await
+        read_message(&mut self.inner, self.framing).await
     }
 
     pub fn into_inner(self) -> T {
         self.inner
     }
+
+    /// Split into halves that can be driven from separate tasks; both keep the framing.
+    pub fn split(
+        self,
+    ) -> (
+        FramedReader<ReadHalf<T>, Incoming>,
+        FramedWriter<WriteHalf<T>, Outgoing>,
+    ) {
+        let (reader, writer) = tokio::io::split(self.inner);
+        let mut reader = FramedReader::new(reader);
+        reader.set_framing(self.framing);
+        let mut writer = FramedWriter::new(writer);
+        writer.set_framing(self.framing);
+        (reader, writer)
+    }
+}
+
+/// Receiving half of a [`FramedStream`], or a wrapper around any reader such as
+/// `tokio::net::tcp::OwnedReadHalf`.
+pub struct FramedReader<R, Incoming = ()> {
+    inner: R,
+    /// Plain unless set otherwise.
+    framing: Framing,
+    _marker: PhantomData<Incoming>,
+}
+
+impl<R, Incoming> FramedReader<R, Incoming>
+where
+    R: AsyncRead + Unpin,
+    Incoming: DeserializeOwned,
+{
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            framing: Framing::Plain,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    pub async fn recv(&mut self) -> Result<Incoming> {
+        read_message(&mut self.inner, self.framing).await
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Sending half of a [`FramedStream`], or a wrapper around any writer such as
+/// `tokio::net::tcp::OwnedWriteHalf`.
+pub struct FramedWriter<W, Outgoing = ()> {
+    inner: W,
+    /// Plain unless set otherwise.
+    framing: Framing,
+    _marker: PhantomData<Outgoing>,
+}
+
+impl<W, Outgoing> FramedWriter<W, Outgoing>
+where
+    W: AsyncWrite + Unpin,
+    Outgoing: Serialize,
+{
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            framing: Framing::Plain,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    pub async fn send(&mut self, message: &Outgoing) -> Result<()> {
+        write_message(&mut self.inner, self.framing, message).await
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Byte stream a connection runs over: plain TCP or, with the `tls` feature, TLS.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> Transport for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+/// Student side of a connection to the teacher.
+pub type StudentStream<T = Box<dyn Transport>> =
+    FramedStream<T, TeacherToStudent, StudentToTeacher>;
+
+/// Connect to the teacher at `addr`, wrap the connection in TLS when `tls` is given,
+/// and perform the `Hello`/`Welcome` handshake.
+pub async fn connect_as_student<A>(
+    addr: A,
+    hello: HelloMessage,
+    tls: Option<&ClientTls>,
+) -> Result<(StudentStream, HelloAck)>
+where
+    A: ToSocketAddrs,
+{
+    let stream = TcpStream::connect(addr).await.context("无法连接教师端")?;
+    stream.set_nodelay(true)?;
+    let transport: Box<dyn Transport> = match tls {
+        Some(tls) => tls.connect(stream).await?,
+        None => Box::new(stream),
+    };
+    handshake_as_student(transport, hello).await
+}
+
+/// Perform the student handshake over an already established transport.
+///
+/// On success the returned stream already uses the framing chosen by the teacher.
+pub async fn handshake_as_student<T>(
+    transport: T,
+    hello: HelloMessage,
+) -> Result<(StudentStream<T>, HelloAck)>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = StudentStream::new(transport);
+    stream.send(&StudentToTeacher::Hello(hello)).await?;
+    let ack = match stream.recv().await.context("等待教师端 Welcome 失败")? {
+        TeacherToStudent::Welcome(ack) => ack,
+        TeacherToStudent::Error(message) => bail!("教师端拒绝连接: {message}"),
+        other => bail!("期望 Welcome 消息, 收到 {:?}", other),
+    };
+    ensure!(
+        PROTOCOL_VERSION.is_compatible(ack.protocol_version),
+        "教师端协议版本 {} 与本客户端 {} 不兼容，请使用同一主版本的程序",
+        ack.protocol_version,
+        PROTOCOL_VERSION
+    );
+    // Older teachers choose no codec and keep plain frames.
+    if let Some(codec) = ack.codec {
+        ensure!(
+            MessageCodec::SUPPORTED.contains(&codec),
+            "教师端选择了不支持的消息编码 {:?}",
+            codec
+        );
+        stream.set_framing(Framing::Tagged(codec));
+    }
+    Ok((stream, ack))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::config::{BroadcastConfig, Region};
+    use crate::message::*;
+
+    fn hello() -> HelloMessage {
+        HelloMessage {
+            student_id: "S01".into(),
+            student_name: "Alice".into(),
+            client_version: "test".into(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: StudentCapabilities::default(),
+            codecs: MessageCodec::supported().to_vec(),
+            compression: compression_supported(),
+            join_token: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_returns_welcome() {
+        let (client, server) = duplex(64 * 1024);
+        let teacher = tokio::spawn(async move {
+            let mut stream = FramedStream::<_, StudentToTeacher, TeacherToStudent>::new(server);
+            let greeting = stream.recv().await.unwrap();
+            let StudentToTeacher::Hello(hello) = greeting else {
+                panic!("expected hello, got {greeting:?}");
+            };
+            let codec = MessageCodec::negotiate(&hello.codecs, hello.protocol_version);
+            stream
+                .send(&TeacherToStudent::Welcome(HelloAck {
+                    server_version: "1.0".into(),
+                    protocol_version: PROTOCOL_VERSION,
+                    force_fullscreen: true,
+                    broadcast_mode: BroadcastMode::Fullscreen,
+                    codec,
+                    session_id: None,
+                    max_upload_bytes: 0,
+                }))
+                .await
+                .unwrap();
+            stream.set_framing(Framing::Tagged(codec.unwrap()));
+            stream
+                .send(&TeacherToStudent::Error("next".into()))
+                .await
+                .unwrap();
+            hello
+        });
+
+        let (mut stream, ack) = handshake_as_student(client, hello()).await.unwrap();
+        assert_eq!(ack.server_version, "1.0");
+        assert_eq!(
+            stream.framing(),
+            Framing::Tagged(MessageCodec::supported()[0])
+        );
+        assert!(ack.force_fullscreen);
+        assert_eq!(teacher.await.unwrap().student_id, "S01");
+        // The stream stays usable for regular traffic after the handshake.
+        assert!(matches!(
+            stream.recv().await.unwrap(),
+            TeacherToStudent::Error(message) if message == "next"
+        ));
+    }
+
+    #[tokio::test]
+    async fn handshake_surfaces_rejection() {
+        let (client, server) = duplex(64 * 1024);
+        tokio::spawn(async move {
+            let mut stream = FramedStream::<_, StudentToTeacher, TeacherToStudent>::new(server);
+            stream.recv().await.unwrap();
+            stream
+                .send(&TeacherToStudent::Error("未登记的学生".into()))
+                .await
+                .unwrap();
+        });
+
+        let Err(err) = handshake_as_student(client, hello()).await else {
+            panic!("handshake should fail when the teacher rejects it");
+        };
+        assert!(err.to_string().contains("未登记的学生"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn incompatible_teacher_protocol_is_refused() {
+        let (client, server) = duplex(64 * 1024);
+        tokio::spawn(async move {
+            let mut stream = FramedStream::<_, StudentToTeacher, TeacherToStudent>::new(server);
+            stream.recv().await.unwrap();
+            stream
+                .send(&TeacherToStudent::Welcome(HelloAck {
+                    server_version: "9.0".into(),
+                    protocol_version: ProtocolVersion {
+                        major: PROTOCOL_VERSION.major + 1,
+                        minor: 0,
+                    },
+                    force_fullscreen: false,
+                    broadcast_mode: BroadcastMode::Window,
+                    codec: None,
+                    session_id: None,
+                    max_upload_bytes: 0,
+                }))
+                .await
+                .unwrap();
+        });
+
+        let Err(err) = handshake_as_student(client, hello()).await else {
+            panic!("handshake should fail on a protocol major mismatch");
+        };
+        assert!(err.to_string().contains("协议版本"), "{err:#}");
+    }
+
+    fn teacher_messages() -> Vec<TeacherToStudent> {
+        let transfer_id = Uuid::new_v4();
+        vec![
+            TeacherToStudent::Welcome(HelloAck {
+                server_version: "1.0".into(),
+                protocol_version: PROTOCOL_VERSION,
+                force_fullscreen: false,
+                broadcast_mode: BroadcastMode::Window,
+                codec: Some(MessageCodec::Bincode),
+                session_id: None,
+                max_upload_bytes: 0,
+            }),
+            TeacherToStudent::Broadcast(BroadcastCommand::Start {
+                source: BroadcastSource::Student {
+                    student_id: "S01".into(),
+                    student_name: Some("Alice".into()),
+                },
+                mode: BroadcastMode::Fullscreen,
+                lock_input: true,
+            }),
+            TeacherToStudent::Broadcast(BroadcastCommand::Stop),
+            TeacherToStudent::Broadcast(BroadcastCommand::RequestThumbnail { interval_secs: 15 }),
+            TeacherToStudent::Broadcast(BroadcastCommand::RequestStudentShare {
+                student_id: "S02".into(),
+            }),
+            TeacherToStudent::Video(VideoFrame {
+                frame_id: 7,
+                timestamp_ms: 1_700_000_000_000,
+                capture_monotonic_ms: 1_500,
+                source: BroadcastSource::Teacher,
+                codec: VideoCodec::Jpeg,
+                width: 1280,
+                height: 720,
+                fullscreen: true,
+                thumbnail: false,
+                data: vec![0xff, 0xd8, 0x00, 0x10].into(),
+                still: false,
+            }),
+            TeacherToStudent::Audio(AudioFrame {
+                frame_id: 8,
+                timestamp_ms: 1,
+                capture_monotonic_ms: 1_480,
+                sample_rate: 48_000,
+                channels: 2,
+                force_play: true,
+                codec: AudioCodec::Opus,
+                data: vec![1, 2, 3].into(),
+            }),
+            TeacherToStudent::FileOffer(FileOffer {
+                transfer_id,
+                file_name: "讲义.pdf".into(),
+                total_size: 1024,
+                auto_open: true,
+                resume: false,
+            }),
+            TeacherToStudent::FileChunk(FileChunk::new(transfer_id, 512, vec![9; 16], true)),
+            TeacherToStudent::FileComplete(FileTransferComplete {
+                transfer_id,
+                success: true,
+                message: None,
+            }),
+            TeacherToStudent::Heartbeat(Heartbeat { timestamp_ms: 42 }),
+            TeacherToStudent::SettingsUpdate(SettingsUpdate {
+                revision: 3,
+                broadcast: BroadcastConfig {
+                    max_width: Some(1280),
+                    ..BroadcastConfig::default()
+                },
+            }),
+            TeacherToStudent::Notice(Notice {
+                text: "5 分钟后小测".into(),
+                duration_ms: 10_000,
+                severity: NoticeSeverity::Warning,
+            }),
+            TeacherToStudent::Error("boom".into()),
+            TeacherToStudent::Kicked {
+                reason: "请专心听课".into(),
+            },
+            TeacherToStudent::Replaced {
+                reason: "学号已在另一台机器上登录".into(),
+            },
+        ]
+    }
+
+    fn student_messages() -> Vec<StudentToTeacher> {
+        vec![
+            StudentToTeacher::Hello(hello()),
+            StudentToTeacher::Heartbeat(Heartbeat { timestamp_ms: 42 }),
+            StudentToTeacher::Ack("ok".into()),
+            StudentToTeacher::AudioState(AudioState {
+                muted: true,
+                volume: 30,
+            }),
+            StudentToTeacher::FocusState(FocusState {
+                focused: false,
+                timestamp_ms: 5,
+            }),
+            StudentToTeacher::RequestZoom {
+                region: Some(Region {
+                    x: 0.5,
+                    y: 0.25,
+                    width: 0.5,
+                    height: 0.5,
+                }),
+            },
+            StudentToTeacher::SettingsAck(3),
+            StudentToTeacher::Error("bad".into()),
+            StudentToTeacher::ShareEnded,
+            StudentToTeacher::ShareDeclined,
+        ]
+    }
+
+    fn assert_round_trips(codec: MessageCodec) {
+        for message in teacher_messages() {
+            let encoded = codec.encode(&message).unwrap();
+            let decoded: TeacherToStudent = codec.decode(&encoded).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+        }
+        for message in student_messages() {
+            let encoded = codec.encode(&message).unwrap();
+            let decoded: StudentToTeacher = codec.decode(&encoded).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+        }
+    }
+
+    #[test]
+    fn json_round_trips_all_messages() {
+        assert_round_trips(MessageCodec::Json);
+    }
+
+    #[cfg(feature = "codec-bincode")]
+    #[test]
+    fn bincode_round_trips_all_messages() {
+        assert_round_trips(MessageCodec::Bincode);
+    }
+
+    #[test]
+    fn negotiation_falls_back_to_json() {
+        // Older students send no codec list at all and keep plain frames.
+        assert_eq!(MessageCodec::negotiate(&[], PROTOCOL_VERSION), None);
+        assert_eq!(
+            MessageCodec::negotiate(&[MessageCodec::Json], PROTOCOL_VERSION),
+            Some(MessageCodec::Json)
+        );
+        assert_eq!(
+            MessageCodec::negotiate(MessageCodec::supported(), PROTOCOL_VERSION),
+            Some(MessageCodec::supported()[0])
+        );
+        // A peer a minor version apart may lay messages out differently.
+        let older = ProtocolVersion {
+            minor: PROTOCOL_VERSION.minor - 1,
+            ..PROTOCOL_VERSION
+        };
+        assert_eq!(
+            MessageCodec::negotiate(MessageCodec::supported(), older),
+            Some(MessageCodec::Json)
+        );
+    }
+
+    #[tokio::test]
+    async fn framed_round_trip_with_every_framing() {
+        let (mut client, mut server) = duplex(64 * 1024);
+        let framings = std::iter::once(Framing::Plain).chain(
+            MessageCodec::supported()
+                .iter()
+                .map(|&codec| Framing::Tagged(codec)),
+        );
+        for framing in framings {
+            for message in student_messages() {
+                write_message(&mut client, framing, &message).await.unwrap();
+                let decoded: StudentToTeacher = read_message(&mut server, framing).await.unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_frames_keep_the_first_release_format() {
+        let mut raw = Vec::new();
+        let message = StudentToTeacher::Heartbeat(Heartbeat { timestamp_ms: 42 });
+        write_message(&mut raw, Framing::Plain, &message)
+            .await
+            .unwrap();
+        let json = br#"{"type":"heartbeat","payload":{"timestamp_ms":42}}"#;
+        assert_eq!(&raw[..4], (json.len() as u32).to_le_bytes());
+        assert_eq!(&raw[4..], json);
+
+        // A hello exactly as the first release wrote it, framing included.
+        let hello = br#"{"type":"hello","payload":{"student_id":"S01","student_name":"Alice","client_version":"0.1.0","capabilities":{"receive_video":true}}}"#;
+        let mut frame = (hello.len() as u32).to_le_bytes().to_vec();
+        frame.extend_from_slice(hello);
+        let decoded: StudentToTeacher = read_message(&mut frame.as_slice(), Framing::Plain)
+            .await
+            .unwrap();
+        let StudentToTeacher::Hello(hello) = decoded else {
+            panic!("expected hello, got {decoded:?}");
+        };
+        assert!(hello.codecs.is_empty());
+        assert_eq!(
+            MessageCodec::negotiate(&hello.codecs, hello.protocol_version),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn split_halves_round_trip_both_directions() {
+        let (client, server) = duplex(64 * 1024);
+        let mut student = StudentStream::new(client);
+        let mut teacher = FramedStream::<_, StudentToTeacher, TeacherToStudent>::new(server);
+        let framing = Framing::Tagged(MessageCodec::supported()[0]);
+        student.set_framing(framing);
+        teacher.set_framing(framing);
+        let (mut teacher_rx, mut teacher_tx) = teacher.split();
+        assert_eq!(teacher_rx.framing(), framing);
+        assert_eq!(teacher_tx.framing(), framing);
+        let (mut student_rx, mut student_tx) = student.split();
+
+        for message in student_messages() {
+            student_tx.send(&message).await.unwrap();
+            let decoded = teacher_rx.recv().await.unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+        }
+        for message in teacher_messages() {
+            teacher_tx.send(&message).await.unwrap();
+            let decoded = student_rx.recv().await.unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn large_frames_are_compressed_transparently() {
+        let audio = TeacherToStudent::Audio(AudioFrame {
+            frame_id: 1,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            sample_rate: 48_000,
+            channels: 2,
+            force_play: false,
+            codec: AudioCodec::Pcm,
+            data: vec![0; 8 * 1024].into(),
+        });
+        let small = TeacherToStudent::Heartbeat(Heartbeat { timestamp_ms: 1 });
+
+        for &codec in MessageCodec::supported() {
+            let (mut client, mut server) = duplex(64 * 1024);
+            write_message_compressed(&mut client, codec, 3, &audio)
+                .await
+                .unwrap();
+            write_message_compressed(&mut client, codec, 3, &small)
+                .await
+                .unwrap();
+            drop(client);
+
+            let mut raw = Vec::new();
+            server.read_to_end(&mut raw).await.unwrap();
+            assert_eq!(raw[0], codec.tag().to_ascii_lowercase());
+            let len = u32::from_le_bytes(raw[1..5].try_into().unwrap()) as usize;
+            assert!(len < 1024, "compressed audio frame was {len} bytes");
+            // Below the threshold the frame is left alone.
+            assert_eq!(raw[5 + len], codec.tag());
+
+            let mut reader = raw.as_slice();
+            let framing = Framing::Tagged(codec);
+            let decoded: TeacherToStudent = read_message(&mut reader, framing).await.unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{audio:?}"));
+            let decoded: TeacherToStudent = read_message(&mut reader, framing).await.unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{small:?}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_codec_tag_is_reported() {
+        let (mut client, mut server) = duplex(1024);
+        client.write_u8(b'?').await.unwrap();
+        client.write_u32_le(2).await.unwrap();
+        client.write_all(b"{}").await.unwrap();
+
+        let err =
+            read_message::<_, StudentToTeacher>(&mut server, Framing::Tagged(MessageCodec::Json))
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("codec"), "{err:#}");
+    }
 }
@@ -0,0 +1,223 @@
+//! Optional TLS for teacher/student links, compiled in with the `tls` feature.
+//!
+//! Without the feature the types still exist so callers need no `cfg`, but loading a
+//! certificate fails with a clear error instead of silently falling back to plaintext.
+
+#[cfg(not(feature = "tls"))]
+use std::convert::Infallible;
+
+/// Teacher side: wraps accepted connections in TLS.
+#[derive(Clone)]
+pub struct ServerTls {
+    #[cfg(feature = "tls")]
+    acceptor: tokio_rustls::TlsAcceptor,
+    #[cfg(not(feature = "tls"))]
+    never: Infallible,
+}
+
+/// Student side: connects to the teacher over TLS, trusting only the configured CA.
+#[derive(Clone)]
+pub struct ClientTls {
+    #[cfg(feature = "tls")]
+    connector: tokio_rustls::TlsConnector,
+    #[cfg(feature = "tls")]
+    server_name: rustls_pki_types::ServerName<'static>,
+    #[cfg(not(feature = "tls"))]
+    never: Infallible,
+}
+
+#[cfg(feature = "tls")]
+mod imp {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, Context, Result};
+    use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{ClientTls, ServerTls};
+    use crate::net::Transport;
+
+    fn open(path: &Path) -> Result<BufReader<File>> {
+        let file =
+            File::open(path).with_context(|| format!("无法打开 TLS 文件 {}", path.display()))?;
+        Ok(BufReader::new(file))
+    }
+
+    fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+        let certs = rustls_pemfile::certs(&mut open(path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("无法解析证书 {}", path.display()))?;
+        if certs.is_empty() {
+            return Err(anyhow!("{} 中没有证书", path.display()));
+        }
+        Ok(certs)
+    }
+
+    fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+        rustls_pemfile::private_key(&mut open(path)?)
+            .with_context(|| format!("无法解析私钥 {}", path.display()))?
+            .ok_or_else(|| anyhow!("{} 中没有私钥", path.display()))
+    }
+
+    impl ServerTls {
+        pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+            let config = ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)
+                .context("TLS 证书与私钥不匹配")?;
+            Ok(Self {
+                acceptor: TlsAcceptor::from(Arc::new(config)),
+            })
+        }
+
+        pub async fn accept(&self, stream: TcpStream) -> Result<Box<dyn Transport>> {
+            let stream = self.acceptor.accept(stream).await.context("TLS 握手失败")?;
+            Ok(Box::new(stream))
+        }
+    }
+
+    impl ClientTls {
+        /// `server_name` must match a subject alternative name (host name or IP) of the
+        /// teacher's certificate.
+        pub fn load(ca_path: &Path, server_name: &str) -> Result<Self> {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots.add(cert).context("无法加载 CA 证书")?;
+            }
+            let config = ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            let server_name = ServerName::try_from(server_name.to_string())
+                .with_context(|| format!("无效的 TLS 服务器名称 {server_name}"))?;
+            Ok(Self {
+                connector: TlsConnector::from(Arc::new(config)),
+                server_name,
+            })
+        }
+
+        pub async fn connect(&self, stream: TcpStream) -> Result<Box<dyn Transport>> {
+            let stream = self
+                .connector
+                .connect(self.server_name.clone(), stream)
+                .await
+                .context("TLS 握手失败")?;
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod imp {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+    use tokio::net::TcpStream;
+
+    use super::{ClientTls, ServerTls};
+    use crate::net::Transport;
+
+    impl ServerTls {
+        pub fn load(_cert_path: &Path, _key_path: &Path) -> Result<Self> {
+            bail!("配置了 TLS 证书，但此构建未启用 `tls` Feature")
+        }
+
+        pub async fn accept(&self, _stream: TcpStream) -> Result<Box<dyn Transport>> {
+            match self.never {}
+        }
+    }
+
+    impl ClientTls {
+        pub fn load(_ca_path: &Path, _server_name: &str) -> Result<Self> {
+            bail!("已启用 use_tls，但此构建未启用 `tls` Feature")
+        }
+
+        pub async fn connect(&self, _stream: TcpStream) -> Result<Box<dyn Transport>> {
+            match self.never {}
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::message::*;
+    use crate::net::{connect_as_student, read_message, write_message, Framing};
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn handshake_runs_over_tls() {
+        let server = ServerTls::load(&testdata("teacher.pem"), &testdata("teacher.key")).unwrap();
+        let client = ClientTls::load(&testdata("ca.pem"), "127.0.0.1").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let teacher = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tokio::io::split(server.accept(stream).await.unwrap());
+            let greeting: StudentToTeacher =
+                read_message(&mut reader, Framing::Plain).await.unwrap();
+            let welcome = TeacherToStudent::Welcome(HelloAck {
+                server_version: "1.0".into(),
+                protocol_version: PROTOCOL_VERSION,
+                force_fullscreen: false,
+                broadcast_mode: BroadcastMode::Window,
+                codec: None,
+                session_id: None,
+                max_upload_bytes: 0,
+            });
+            write_message(&mut writer, Framing::Plain, &welcome)
+                .await
+                .unwrap();
+            greeting
+        });
+
+        let hello = HelloMessage {
+            student_id: "S01".into(),
+            student_name: "Alice".into(),
+            client_version: "test".into(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: StudentCapabilities::default(),
+            codecs: Vec::new(),
+            compression: false,
+            join_token: None,
+        };
+        let (_stream, ack) = connect_as_student(addr, hello, Some(&client))
+            .await
+            .unwrap();
+        assert_eq!(ack.server_version, "1.0");
+        assert!(matches!(
+            teacher.await.unwrap(),
+            StudentToTeacher::Hello(hello) if hello.student_id == "S01"
+        ));
+    }
+
+    #[tokio::test]
+    async fn untrusted_certificate_is_rejected() {
+        // The teacher's own certificate is not a CA, so nothing chains to it.
+        let server = ServerTls::load(&testdata("teacher.pem"), &testdata("teacher.key")).unwrap();
+        let client = ClientTls::load(&testdata("teacher.pem"), "127.0.0.1").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = server.accept(stream).await;
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(client.connect(stream).await.is_err());
+    }
+}
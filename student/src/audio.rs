@@ -1,49 +1,63 @@
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{SampleFormat, Stream, StreamConfig};
+use cpal::{SampleFormat, Stream, StreamConfig, SupportedStreamConfig};
 use parking_lot::Mutex;
-use tracing::{debug, error};
+use tracing::{debug, error, info, warn};
 
+use shared::audio::{device_name_matches, OpusFrameDecoder, Resampler, OPUS_SAMPLE_RATE};
 use shared::prelude::*;
 
+use crate::jitter::{FrameCheck, JitterBuffer, LateFrameFilter};
+
 pub struct AudioPlayer {
-    queue: Arc<Mutex<VecDeque<i16>>>,
+    queue: Arc<Mutex<JitterBuffer>>,
     muted: Arc<AtomicBool>,
-    channels: usize,
-    #[allow(dead_code)]
-    sample_rate: u32,
+    /// Playback gain in percent (0-100), applied on top of mute.
+    volume: Arc<AtomicU32>,
+    resampler: Mutex<Resampler>,
+    late_frames: Mutex<LateFrameFilter>,
+    /// Underruns already logged; the output callback only counts them, since logging there
+    /// could stall the audio thread.
+    underruns_logged: AtomicU64,
+    /// Local clock the arrival of frames is measured on.
+    started: Instant,
+    /// Created on the first Opus frame and recreated when its channel count changes.
+    opus: Mutex<Option<OpusFrameDecoder>>,
     _stream: Stream,
 }
 
 impl AudioPlayer {
-    pub fn new() -> Result<Self> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or_else(|| anyhow!("未检测到默认音频输出设备"))?;
-
-        let supported = device
-            .supported_output_configs()?
-            .filter(|cfg| cfg.sample_format() == SampleFormat::I16)
-            .max_by_key(|cfg| cfg.max_sample_rate().0)
-            .ok_or_else(|| anyhow!("输出设备不支持 i16 格式"))?;
-
-        let config = supported.with_max_sample_rate();
+    /// Play on the output device whose name contains `preferred`, or the system default,
+    /// keeping about `buffer_ms` of audio queued against network jitter and dropping frames
+    /// more than `max_late_ms` behind.
+    pub fn new(preferred: Option<&str>, buffer_ms: u32, max_late_ms: u32) -> Result<Self> {
+        let (device, config) = select_output(preferred)?;
         let sample_rate = config.sample_rate().0;
         let channels = config.channels() as usize;
+        info!(
+            device = %device.name().unwrap_or_else(|_| "未知设备".to_string()),
+            sample_rate,
+            channels,
+            buffer_ms,
+            "音频输出设备已选定"
+        );
         let stream_config: StreamConfig = config.into();
 
-        let queue = Arc::new(Mutex::new(VecDeque::<i16>::with_capacity(
-            sample_rate as usize,
+        let queue = Arc::new(Mutex::new(JitterBuffer::new(
+            buffer_ms,
+            sample_rate,
+            channels,
         )));
         let muted = Arc::new(AtomicBool::new(false));
+        let volume = Arc::new(AtomicU32::new(100));
 
         let queue_cb = queue.clone();
         let muted_cb = muted.clone();
+        let volume_cb = volume.clone();
 
         let stream = device.build_output_stream(
             &stream_config,
@@ -55,9 +69,11 @@ impl AudioPlayer {
                     return;
                 }
 
-                let mut buffer = queue_cb.lock();
+                queue_cb.lock().fill(output);
+
+                let volume = volume_cb.load(Ordering::Relaxed);
                 for sample in output.iter_mut() {
-                    *sample = buffer.pop_front().unwrap_or(0);
+                    *sample = apply_volume(*sample, volume);
                 }
             },
             move |err| {
@@ -71,12 +87,18 @@ impl AudioPlayer {
         Ok(Self {
             queue,
             muted,
-            channels,
-            sample_rate,
+            volume,
+            resampler: Mutex::new(Resampler::new(sample_rate, channels)),
+            late_frames: Mutex::new(LateFrameFilter::new(u64::from(max_late_ms))),
+            underruns_logged: AtomicU64::new(0),
+            started: Instant::now(),
+            opus: Mutex::new(None),
             _stream: stream,
         })
     }
 
+    /// Queue `frame` for playback. A `force_play` frame lifts the mute but leaves the
+    /// volume the student chose.
     pub fn enqueue(&self, frame: AudioFrame) {
         if frame.force_play {
             self.muted.store(false, Ordering::SeqCst);
@@ -86,32 +108,189 @@ impl AudioPlayer {
             return;
         }
 
-        if frame.channels as usize != self.channels {
+        if frame.channels == 0 || frame.sample_rate == 0 {
             debug!(
-                expected = self.channels,
-                received = frame.channels,
-                "忽略声道数不匹配的音频帧"
+                channels = frame.channels,
+                sample_rate = frame.sample_rate,
+                "忽略格式无效的音频帧"
             );
             return;
         }
 
+        // Older teachers leave the session clock at 0; their wall clock still orders frames.
+        let capture_ms = match frame.capture_monotonic_ms {
+            0 => frame.timestamp_ms,
+            ms => ms,
+        };
+        let now_ms = self.started.elapsed().as_millis() as u64;
+        let mut late_frames = self.late_frames.lock();
+        match late_frames.check(frame.frame_id, capture_ms, now_ms) {
+            FrameCheck::Play => {}
+            FrameCheck::Discard => {
+                debug!(
+                    frame_id = frame.frame_id,
+                    discarded = late_frames.discarded(),
+                    "丢弃乱序或过时的音频帧"
+                );
+                return;
+            }
+            FrameCheck::Resync => {
+                debug!(
+                    frame_id = frame.frame_id,
+                    "音频流中断或切换，清空缓冲重新同步"
+                );
+                self.queue.lock().clear();
+            }
+        }
+        drop(late_frames);
+
+        let (sample_rate, samples) = match frame.codec {
+            AudioCodec::Pcm => {
+                let samples = frame
+                    .data
+                    .chunks_exact(2)
+                    .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
+                    .collect();
+                (frame.sample_rate, samples)
+            }
+            AudioCodec::Opus => match self.decode_opus(&frame) {
+                Ok(samples) => (OPUS_SAMPLE_RATE, samples),
+                Err(err) => {
+                    warn!(frame_id = frame.frame_id, "Opus 音频帧解码失败: {err:#}");
+                    return;
+                }
+            },
+        };
+        let mut resampler = self.resampler.lock();
         let mut buffer = self.queue.lock();
-        for chunk in frame.data.chunks_exact(2) {
-            let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
-            buffer.push_back(sample);
+        let underruns = buffer.underruns();
+        if self.underruns_logged.swap(underruns, Ordering::Relaxed) != underruns {
+            debug!(underruns, "音频缓冲耗尽，重新缓冲");
         }
+        let overruns = buffer.overruns();
+        resampler.process(sample_rate, frame.channels as usize, &samples, &mut *buffer);
+        if buffer.overruns() != overruns {
+            debug!(
+                overruns = buffer.overruns(),
+                "音频积压过多，已丢弃最旧的部分"
+            );
+        }
+    }
+
+    fn decode_opus(&self, frame: &AudioFrame) -> Result<Vec<i16>> {
+        let mut opus = self.opus.lock();
+        if opus.as_ref().map(OpusFrameDecoder::channels) != Some(frame.channels) {
+            *opus = Some(OpusFrameDecoder::new(frame.channels)?);
+        }
+        opus.as_mut()
+            .expect("decoder was just created")
+            .decode(&frame.data)
     }
 
     pub fn set_muted(&self, muted: bool) {
         self.muted.store(muted, Ordering::SeqCst);
     }
 
-    #[allow(dead_code)]
     pub fn is_muted(&self) -> bool {
         self.muted.load(Ordering::SeqCst)
     }
 
-    pub fn muted_handle(&self) -> Arc<AtomicBool> {
-        self.muted.clone()
+    /// Thread-safe handle to the playback controls (the cpal stream itself is not `Send`).
+    pub fn control(&self) -> AudioControl {
+        AudioControl {
+            muted: self.muted.clone(),
+            volume: self.volume.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AudioControl {
+    muted: Arc<AtomicBool>,
+    volume: Arc<AtomicU32>,
+}
+
+impl AudioControl {
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::SeqCst);
+    }
+
+    /// Set the playback volume in percent; values above 100 are capped.
+    pub fn set_volume(&self, percent: u8) {
+        self.volume
+            .store(u32::from(percent.min(100)), Ordering::Relaxed);
+    }
+
+    /// Snapshot of the local playback state reported to the teacher.
+    pub fn state(&self) -> AudioState {
+        AudioState {
+            muted: self.muted.load(Ordering::SeqCst),
+            volume: self.volume.load(Ordering::Relaxed) as u8,
+        }
+    }
+}
+
+/// Scale `sample` by `volume` percent.
+fn apply_volume(sample: i16, volume: u32) -> i16 {
+    if volume >= 100 {
+        return sample;
+    }
+    (i32::from(sample) * volume as i32 / 100) as i16
+}
+
+/// Output device whose name contains `preferred`, falling back to the system default.
+fn find_output_device(host: &cpal::Host, preferred: Option<&str>) -> Option<cpal::Device> {
+    if let Some(wanted) = preferred {
+        let found = host.output_devices().ok().and_then(|mut devices| {
+            devices.find(|device| {
+                device
+                    .name()
+                    .is_ok_and(|name| device_name_matches(&name, wanted))
+            })
+        });
+        if found.is_some() {
+            return found;
+        }
+        warn!(wanted, "未找到配置的音频输出设备，改用系统默认设备");
+    }
+    host.default_output_device()
+}
+
+/// Configured (or default) output device and the highest-rate i16 configuration it supports.
+fn select_output(preferred: Option<&str>) -> Result<(cpal::Device, SupportedStreamConfig)> {
+    let host = cpal::default_host();
+    let device =
+        find_output_device(&host, preferred).ok_or_else(|| anyhow!("未检测到默认音频输出设备"))?;
+
+    let supported = device
+        .supported_output_configs()?
+        .filter(|cfg| cfg.sample_format() == SampleFormat::I16)
+        .max_by_key(|cfg| cfg.max_sample_rate().0)
+        .ok_or_else(|| anyhow!("输出设备不支持 i16 格式"))?;
+
+    Ok((device, supported.with_max_sample_rate()))
+}
+
+/// Describe the device broadcast audio would play on, for `--diagnose`.
+pub fn probe_output_device(preferred: Option<&str>) -> Result<String> {
+    let (device, config) = select_output(preferred)?;
+    let name = device.name().unwrap_or_else(|_| "未知设备".to_string());
+    Ok(format!(
+        "{name} ({} Hz, {} 声道)",
+        config.sample_rate().0,
+        config.channels()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_scales_samples_without_overflow() {
+        assert_eq!(apply_volume(1000, 100), 1000);
+        assert_eq!(apply_volume(1000, 25), 250);
+        assert_eq!(apply_volume(i16::MIN, 50), i16::MIN / 2);
+        assert_eq!(apply_volume(i16::MAX, 0), 0);
     }
 }
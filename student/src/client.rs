@@ -4,22 +4,23 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use parking_lot::Mutex;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
-use tokio::net::TcpStream;
 use tokio::signal;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 use tokio::time;
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 
 use shared::prelude::*;
+use shared::tls::ClientTls;
 
-use crate::audio::AudioPlayer;
-use crate::files::FileDownloadManager;
+use crate::audio::{AudioControl, AudioPlayer};
+use crate::consent::ShareConsent;
+use crate::files::{FileDownloadManager, RetentionPolicy, UploadTracker};
 use crate::screen::ScreenStreamer;
-use crate::video::VideoRenderer;
+use crate::video::{FocusReports, VideoRenderer, WindowPolicy};
 
 pub struct StudentApp {
     config: StudentConfig,
@@ -32,100 +33,306 @@ impl StudentApp {
 
     pub async fn run(self) -> Result<()> {
         let address = self.config.teacher_addr();
-        info!(%address, "连接教师端");
-        let stream = TcpStream::connect(&address)
-            .await
-            .with_context(|| format!("无法连接教师端 {address}"))?;
-        stream.set_nodelay(true)?;
-
-        let (mut reader, mut writer) = stream.into_split();
+        let tls = match (self.config.use_tls, &self.config.teacher_ca_path) {
+            (true, Some(ca_path)) => Some(ClientTls::load(ca_path, &self.config.teacher_ip)?),
+            (true, None) => bail!("启用 use_tls 时必须设置 teacher_ca_path"),
+            (false, _) => None,
+        };
+
+        // The outgoing queue outlives individual connections so the heartbeat and command
+        // loop keep working across reconnects.
         let (tx, mut rx) = mpsc::unbounded_channel::<StudentToTeacher>();
 
-        let writer_task = tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                if let Err(err) = write_message(&mut writer, &message).await {
-                    error!(?err, "向教师端发送数据失败");
-                    break;
-                }
-            }
-        });
-
-        let video = VideoRenderer::new();
-        let audio = Arc::new(AudioPlayer::new()?);
+        let video = VideoRenderer::new(
+            WindowPolicy {
+                focus_reports: self
+                    .config
+                    .report_focus_loss
+                    .then(|| FocusReports::new(tx.clone())),
+                keep_on_top: self.config.keep_window_on_top,
+                native_size: self.config.native_size,
+                reopen_closed: self.config.reopen_closed_window,
+                status_strip: self.config.status_strip,
+            },
+            tx.clone(),
+        );
+        let audio = Arc::new(AudioPlayer::new(
+            self.config.audio_output_device.as_deref(),
+            self.config.audio_buffer_ms,
+            self.config.audio_max_late_ms,
+        )?);
         let files = Arc::new(FileDownloadManager::new(
             self.config.download_path.clone(),
             self.config.auto_open_file,
+            RetentionPolicy::from_config(&self.config),
         ));
-        let screen_streamer = ScreenStreamer::new();
+        if let Err(err) = files.apply_retention().await {
+            warn!(?err, "清理下载目录失败");
+        }
+        let consent = Arc::new(ShareConsent::default());
+        let screen_streamer = ScreenStreamer::new(
+            SessionClock::start(),
+            self.config.monitor_index,
+            self.config.student_share_max_fps,
+            self.config.require_share_consent.then(|| consent.clone()),
+        );
+        let share_frames = screen_streamer.frames();
 
         let running = Arc::new(AtomicBool::new(true));
+        let quit = Arc::new(Notify::new());
         let forced_fullscreen = Arc::new(AtomicBool::new(false));
         let current_mode = Arc::new(Mutex::new(BroadcastMode::Window));
+        // Protocol version of the connected teacher; `None` while offline.
+        let teacher_version = Arc::new(Mutex::new(None::<ProtocolVersion>));
 
-        send_hello(&self.config, &tx)?;
+        let teacher_timeout = Duration::from_secs(self.config.teacher_timeout_secs);
 
         spawn_heartbeat(tx.clone(), running.clone());
-        let muted_handle = audio.muted_handle();
-        spawn_command_loop(tx.clone(), muted_handle, running.clone());
-
-        tokio::select! {
-            _ = signal::ctrl_c() => {
-                info!("收到 Ctrl+C, 正在退出");
-            }
-            _ = async {
-                while running.load(Ordering::SeqCst) {
-                    match read_message::<_, TeacherToStudent>(&mut reader).await {
-                        Ok(message) => {
-                            if let Err(err) = handle_message(
-                                &self.config,
-                                &video,
-                                audio.clone(),
-                                files.clone(),
-                                &screen_streamer,
-                                &tx,
-                                message,
-                                current_mode.clone(),
-                                forced_fullscreen.clone(),
-                            ).await {
-                                error!(?err, "处理教师端消息失败");
+        spawn_command_loop(
+            tx.clone(),
+            audio.control(),
+            files.clone(),
+            teacher_version.clone(),
+            consent,
+            quit.clone(),
+        );
+
+        let mut delay = RECONNECT_DELAY_MIN;
+        loop {
+            let session = async {
+                info!(%address, "连接教师端");
+                let (stream, ack) =
+                    connect_as_student(&address, hello_message(&self.config), tls.as_ref())
+                        .await
+                        .with_context(|| format!("无法连接教师端 {address}"))?;
+
+                forced_fullscreen.store(ack.force_fullscreen, Ordering::SeqCst);
+                video.set_teacher_version(ack.protocol_version);
+                *teacher_version.lock() = Some(ack.protocol_version);
+                files.uploads().set_limit(ack.max_upload_bytes);
+                share_frames.set_teacher_version(ack.protocol_version);
+                *current_mode.lock() = ack.broadcast_mode;
+                info!(
+                    session = ?ack.session_id,
+                    "已连接教师端，默认模式: {:?}，消息编码: {:?}",
+                    ack.broadcast_mode, ack.codec
+                );
+                // The teacher's session id tags everything logged for this connection.
+                let span = match ack.session_id {
+                    Some(session) => info_span!("session", session = %session),
+                    None => Span::none(),
+                };
+
+                // Heartbeats and frames queued while offline mean nothing to the new connection.
+                while rx.try_recv().is_ok() {}
+                share_frames.clear();
+                report_audio_state(&audio.control(), ack.protocol_version, &tx);
+
+                let (mut reader, mut writer) = stream.split();
+                // Spotlight frames go out only when nothing else is waiting; their queue sheds
+                // old frames on its own when the uplink is slow. Chunks of an upload the
+                // teacher refused are dropped here rather than sent.
+                let writing = async {
+                    loop {
+                        let message = tokio::select! {
+                            biased;
+                            message = rx.recv() => match message {
+                                Some(message) => message,
+                                None => break,
+                            },
+                            message = share_frames.pop() => message,
+                        };
+                        match &message {
+                            StudentToTeacher::FileChunk(chunk)
+                                if files.uploads().is_refused(&chunk.transfer_id) =>
+                            {
+                                continue;
+                            }
+                            StudentToTeacher::FileComplete(done) => {
+                                files.uploads().sent(&done.transfer_id);
                             }
+                            _ => {}
+                        }
+                        if let Err(err) = writer.send(&message).await {
+                            error!(?err, "向教师端发送数据失败");
+                            break;
+                        }
+                    }
+                };
+                // An error or kick that is the last message before the teacher hangs up is the
+                // reason for it. Once the teacher has sent a heartbeat, a silence
+                // longer than `teacher_timeout_secs` means a half-open connection; teachers
+                // without heartbeats are quiet while idle and are never timed out.
+                let reading = async {
+                    let mut farewell = None;
+                    let mut heartbeats_seen = false;
+                    loop {
+                        let next = reader.recv();
+                        let result = if heartbeats_seen && !teacher_timeout.is_zero() {
+                            match time::timeout(teacher_timeout, next).await {
+                                Ok(result) => result,
+                                Err(_) => {
+                                    warn!(
+                                        timeout_secs = teacher_timeout.as_secs(),
+                                        "长时间未收到教师端消息，视为连接已断开"
+                                    );
+                                    break None;
+                                }
+                            }
+                        } else {
+                            next.await
+                        };
+                        match result {
+                            Ok(message) => {
+                                heartbeats_seen |=
+                                    matches!(message, TeacherToStudent::Heartbeat(_));
+                                farewell = match &message {
+                                    TeacherToStudent::Error(msg) => {
+                                        Some(Farewell::Error(msg.clone()))
+                                    }
+                                    TeacherToStudent::Kicked { reason } => {
+                                        Some(Farewell::Kicked(reason.clone()))
+                                    }
+                                    TeacherToStudent::Replaced { reason } => {
+                                        Some(Farewell::Replaced(reason.clone()))
+                                    }
+                                    _ => None,
+                                };
+                                if let Err(err) = handle_message(
+                                    &self.config,
+                                    &video,
+                                    audio.clone(),
+                                    files.clone(),
+                                    &screen_streamer,
+                                    &tx,
+                                    ack.protocol_version,
+                                    message,
+                                    current_mode.clone(),
+                                    forced_fullscreen.clone(),
+                                )
+                                .await
+                                {
+                                    error!(?err, "处理教师端消息失败");
+                                }
+                            }
+                            Err(err) => {
+                                warn!(?err, "读取教师端消息失败，准备断开");
+                                break farewell;
+                            }
+                        }
+                    }
+                };
+                let reason = async {
+                    tokio::select! {
+                        _ = writing => None,
+                        reason = reading => reason,
+                    }
+                }
+                .instrument(span)
+                .await;
+                anyhow::Ok(reason)
+            };
+
+            let outcome = tokio::select! {
+                _ = signal::ctrl_c() => {
+                    info!("收到 Ctrl+C, 正在退出");
+                    break;
+                }
+                _ = quit.notified() => break,
+                outcome = session => outcome,
+            };
+
+            teacher_version.lock().take();
+            screen_streamer.stop().await;
+            // The teacher re-sends an active blackout or thumbnail request when we reconnect.
+            screen_streamer.stop_thumbnails();
+            video.set_input_lock(false);
+            video.set_blackout(false);
+            video.stop();
+            files.suspend_all().await;
+            match outcome {
+                // The connection was up, so start over with a short delay unless kicked,
+                // or not at all once another machine with our ID took over.
+                Ok(farewell) => {
+                    delay = RECONNECT_DELAY_MIN;
+                    match farewell {
+                        Some(Farewell::Kicked(reason)) => {
+                            warn!(%reason, "已被教师移出课堂");
+                            delay = KICKED_RECONNECT_DELAY;
                         }
-                        Err(err) => {
-                            warn!(?err, "读取教师端消息失败，准备断开");
+                        Some(Farewell::Replaced(reason)) => {
+                            error!(%reason, "同一学号的新连接取代了本机，不再自动重连");
                             break;
                         }
+                        Some(Farewell::Error(reason)) => warn!(%reason, "教师端断开了连接"),
+                        None => {}
                     }
                 }
-            } => {}
+                Err(err) => warn!(?err, "连接教师端失败"),
+            }
+            info!(delay_secs = delay.as_secs(), "与教师端断开，稍后重连");
+
+            tokio::select! {
+                _ = signal::ctrl_c() => {
+                    info!("收到 Ctrl+C, 正在退出");
+                    break;
+                }
+                _ = quit.notified() => break,
+                _ = time::sleep(delay) => {}
+            }
+            delay = next_reconnect_delay(delay);
         }
 
         running.store(false, Ordering::SeqCst);
         screen_streamer.stop().await;
+        screen_streamer.stop_thumbnails();
         video.stop();
         audio.set_muted(true);
-        drop(tx);
-        writer_task.abort();
+        files.abort_all().await;
 
         info!("学生端已退出");
         Ok(())
     }
 }
 
-fn send_hello(config: &StudentConfig, tx: &mpsc::UnboundedSender<StudentToTeacher>) -> Result<()> {
-    let message = StudentToTeacher::Hello(HelloMessage {
+/// Wait before the first reconnect attempt; doubled after every failed attempt.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+/// Upper bound for the reconnect backoff.
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+/// Wait before reconnecting after the teacher kicked us, so the kick sticks for a while.
+const KICKED_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Last word from the teacher before it hung up.
+enum Farewell {
+    /// An error, e.g. a kick reason from a teacher that predates `Kicked`.
+    Error(String),
+    Kicked(String),
+    /// A newer connection with our student ID took over.
+    Replaced(String),
+}
+
+fn next_reconnect_delay(delay: Duration) -> Duration {
+    (delay * 2).min(RECONNECT_DELAY_MAX)
+}
+
+fn hello_message(config: &StudentConfig) -> HelloMessage {
+    HelloMessage {
         student_id: config.student_id.clone(),
         student_name: config.student_name.clone(),
         client_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
         capabilities: StudentCapabilities {
             receive_video: true,
             send_video: true,
             receive_audio: true,
             send_audio: false,
             file_transfer: true,
+            audio_codecs: AudioCodec::supported().to_vec(),
         },
-    });
-    tx.send(message)?;
-    Ok(())
+        codecs: MessageCodec::supported().to_vec(),
+        compression: compression_supported(),
+        join_token: config.join_token.clone(),
+    }
 }
 
 fn spawn_heartbeat(tx: mpsc::UnboundedSender<StudentToTeacher>, running: Arc<AtomicBool>) {
@@ -143,11 +350,40 @@ fn spawn_heartbeat(tx: mpsc::UnboundedSender<StudentToTeacher>, running: Arc<Ato
     });
 }
 
+/// Tell a `teacher` new enough to understand it our mute state and volume.
+fn report_audio_state(
+    audio: &AudioControl,
+    teacher: ProtocolVersion,
+    tx: &mpsc::UnboundedSender<StudentToTeacher>,
+) {
+    if teacher >= AUDIO_STATE_PROTOCOL_VERSION {
+        let _ = tx.send(StudentToTeacher::AudioState(audio.state()));
+    }
+}
+
 fn spawn_command_loop(
     tx: mpsc::UnboundedSender<StudentToTeacher>,
-    muted_flag: Arc<AtomicBool>,
-    running: Arc<AtomicBool>,
+    audio: AudioControl,
+    files: Arc<FileDownloadManager>,
+    teacher_version: Arc<Mutex<Option<ProtocolVersion>>>,
+    consent: Arc<ShareConsent>,
+    quit: Arc<Notify>,
 ) {
+    // Older teachers drop the connection on messages they cannot parse.
+    let teacher_supports = {
+        let teacher_version = teacher_version.clone();
+        move |version| {
+            teacher_version
+                .lock()
+                .is_some_and(|teacher| teacher >= version)
+        }
+    };
+    // Audio changes offline are reported on the next connect.
+    let report_audio = move |audio: &AudioControl, tx: &mpsc::UnboundedSender<StudentToTeacher>| {
+        if let Some(teacher) = *teacher_version.lock() {
+            report_audio_state(audio, teacher, tx);
+        }
+    };
     tokio::spawn(async move {
         let stdin = tokio::io::stdin();
         let reader = BufReader::new(stdin);
@@ -162,23 +398,78 @@ fn spawn_command_loop(
                 "help" => print_help(),
                 "upload" => {
                     if let Some(path) = parts.next() {
-                        if let Err(err) = upload_file(path.into(), &tx).await {
+                        if let Err(err) = upload_file(path.into(), files.uploads(), &tx).await {
                             error!(?err, "上传文件失败");
                         }
                     } else {
                         warn!("用法: upload <文件路径>");
                     }
                 }
+                "zoom" => {
+                    let args: Vec<&str> = parts.collect();
+                    match parse_zoom_args(&args) {
+                        Some(_) if !teacher_supports(ZOOM_PROTOCOL_VERSION) => {
+                            warn!("未连接教师端，或教师端版本过旧不支持放大请求");
+                        }
+                        Some(region) => {
+                            if tx.send(StudentToTeacher::RequestZoom { region }).is_ok() {
+                                info!("已向教师端发送放大请求");
+                            }
+                        }
+                        None => warn!("用法: zoom <x%> <y%> <宽%> <高%> 或 zoom reset"),
+                    }
+                }
+                "hand" => {
+                    let raised = match parts.next() {
+                        Some("up") => true,
+                        Some("down") => false,
+                        _ => {
+                            warn!("用法: hand up|down");
+                            continue;
+                        }
+                    };
+                    if !teacher_supports(RAISE_HAND_PROTOCOL_VERSION) {
+                        warn!("未连接教师端，或教师端版本过旧不支持举手");
+                    } else if tx.send(StudentToTeacher::RaiseHand { raised }).is_ok() {
+                        info!(raised, "已向教师端发送举手状态");
+                    }
+                }
+                "say" => {
+                    let Some(text) = sanitize_chat(trimmed["say".len()..].trim()) else {
+                        warn!("用法: say <文字>");
+                        continue;
+                    };
+                    if !teacher_supports(CHAT_PROTOCOL_VERSION) {
+                        warn!("未连接教师端，或教师端版本过旧不支持聊天");
+                    } else if tx.send(StudentToTeacher::Chat { text }).is_ok() {
+                        info!("消息已发送给教师");
+                    }
+                }
                 "mute" => {
-                    muted_flag.store(true, Ordering::SeqCst);
+                    audio.set_muted(true);
+                    report_audio(&audio, &tx);
                     info!("已静音");
                 }
                 "unmute" => {
-                    muted_flag.store(false, Ordering::SeqCst);
+                    audio.set_muted(false);
+                    report_audio(&audio, &tx);
                     info!("已取消静音");
                 }
+                "volume" => match parts.next().and_then(|value| value.parse::<u8>().ok()) {
+                    Some(percent) if percent <= 100 => {
+                        audio.set_volume(percent);
+                        report_audio(&audio, &tx);
+                        info!(percent, "音量已调整");
+                    }
+                    _ => warn!("用法: volume <0-100>"),
+                },
+                command @ ("allow" | "deny") => {
+                    if !consent.answer(command == "allow") {
+                        warn!("当前没有待确认的屏幕共享请求");
+                    }
+                }
                 "quit" | "exit" => {
-                    running.store(false, Ordering::SeqCst);
+                    quit.notify_one();
                     break;
                 }
                 other => {
@@ -189,13 +480,36 @@ fn spawn_command_loop(
     });
 }
 
+/// Parse `zoom` arguments given as percentages of the current view; `reset` yields `Some(None)`.
+fn parse_zoom_args(args: &[&str]) -> Option<Option<Region>> {
+    match args {
+        ["reset"] => Some(None),
+        [x, y, width, height] => {
+            let percent = |value: &str| value.trim_end_matches('%').parse::<f32>().ok();
+            let region = Region {
+                x: percent(x)? / 100.0,
+                y: percent(y)? / 100.0,
+                width: percent(width)? / 100.0,
+                height: percent(height)? / 100.0,
+            };
+            Some(Some(region.clamped()))
+        }
+        _ => None,
+    }
+}
+
 fn print_help() {
     println!(
-        "命令列表:\n  help               显示帮助\n  upload <路径>     向教师端上传文件\n  mute/unmute       切换音频播放\n  quit              退出学生客户端"
+        "命令列表:\n  help               显示帮助\n  upload <路径>     向教师端上传文件\n  zoom <x% y% 宽% 高%>|reset 请求教师放大广播画面\n  hand up|down      举手/放下手\n  say <文字>        给教师发送消息\n  mute/unmute       切换音频播放\n  volume <0-100>    调整播放音量\n  allow/deny        同意/拒绝教师的屏幕共享请求\n  quit              退出学生客户端"
     );
 }
 
-async fn upload_file(path: PathBuf, tx: &mpsc::UnboundedSender<StudentToTeacher>) -> Result<()> {
+/// Send `path` to the teacher, stopping early if the teacher refuses it.
+async fn upload_file(
+    path: PathBuf,
+    uploads: &UploadTracker,
+    tx: &mpsc::UnboundedSender<StudentToTeacher>,
+) -> Result<()> {
     let metadata = tokio::fs::metadata(&path)
         .await
         .with_context(|| format!("无法读取文件信息: {}", path.display()))?;
@@ -203,6 +517,13 @@ async fn upload_file(path: PathBuf, tx: &mpsc::UnboundedSender<StudentToTeacher>
     if !metadata.is_file() {
         return Err(anyhow!("{} 不是有效文件", path.display()));
     }
+    let limit = uploads.limit();
+    ensure!(
+        limit == 0 || metadata.len() <= limit,
+        "{} 超过教师端的上传大小限制 {} MB",
+        path.display(),
+        limit.div_ceil(1024 * 1024)
+    );
 
     let file_name = path
         .file_name()
@@ -210,40 +531,55 @@ async fn upload_file(path: PathBuf, tx: &mpsc::UnboundedSender<StudentToTeacher>
         .ok_or_else(|| anyhow!("无法解析文件名"))?;
 
     let transfer_id = Uuid::new_v4();
+    uploads.begin(transfer_id);
     tx.send(StudentToTeacher::FileOffer(FileOffer {
         transfer_id,
         file_name: file_name.clone(),
         total_size: metadata.len(),
         auto_open: false,
+        resume: false,
     }))?;
 
     let mut file = tokio::fs::File::open(&path).await?;
     let mut buffer = vec![0u8; 64 * 1024];
     let mut offset = 0u64;
 
+    // The teacher still expects a FileComplete after a refusal, to forget the transfer.
+    let mut refused = false;
     loop {
+        if uploads.is_refused(&transfer_id) {
+            refused = true;
+            break;
+        }
         let read = file.read(&mut buffer).await?;
         if read == 0 {
             break;
         }
-        tx.send(StudentToTeacher::FileChunk(FileChunk {
+        tx.send(StudentToTeacher::FileChunk(FileChunk::new(
             transfer_id,
             offset,
-            bytes: buffer[..read].to_vec(),
-            final_chunk: false,
-        }))?;
+            buffer[..read].to_vec(),
+            false,
+        )))?;
         offset += read as u64;
     }
 
+    let (success, message) = if refused {
+        (false, format!("{file_name} 已停止上传"))
+    } else {
+        (true, format!("{} 上传完成", file_name))
+    };
     tx.send(StudentToTeacher::FileComplete(FileTransferComplete {
         transfer_id,
-        success: true,
-        message: Some(format!("{} 上传完成", file_name)),
+        success,
+        message: Some(message),
     }))?;
 
     Ok(())
 }
 
+/// Act on one message from a `teacher` speaking that protocol version.
+#[allow(clippy::too_many_arguments)]
 async fn handle_message(
     config: &StudentConfig,
     video: &VideoRenderer,
@@ -251,6 +587,7 @@ async fn handle_message(
     files: Arc<FileDownloadManager>,
     screen_streamer: &ScreenStreamer,
     tx: &mpsc::UnboundedSender<StudentToTeacher>,
+    teacher: ProtocolVersion,
     message: TeacherToStudent,
     current_mode: Arc<Mutex<BroadcastMode>>,
     forced_fullscreen: Arc<AtomicBool>,
@@ -259,7 +596,10 @@ async fn handle_message(
         TeacherToStudent::Welcome(ack) => {
             forced_fullscreen.store(ack.force_fullscreen, Ordering::SeqCst);
             *current_mode.lock() = ack.broadcast_mode;
-            info!("已连接教师端，默认模式: {:?}", ack.broadcast_mode);
+            info!(
+                "已连接教师端，默认模式: {:?}，消息编码: {:?}",
+                ack.broadcast_mode, ack.codec
+            );
         }
         TeacherToStudent::Broadcast(command) => {
             handle_broadcast_command(
@@ -273,19 +613,45 @@ async fn handle_message(
             )
             .await?;
         }
+        TeacherToStudent::Pacing { fps } => screen_streamer.set_pacing(fps),
+        TeacherToStudent::Chat { from, text } => {
+            if let Some(text) = sanitize_chat(&text) {
+                info!(%from, %text, "收到教师消息");
+            }
+        }
         TeacherToStudent::Video(frame) => {
             let mode = *current_mode.lock();
             video.display_frame(frame, mode);
         }
         TeacherToStudent::Audio(frame) => {
+            let was_muted = audio.is_muted();
             audio.enqueue(frame);
+            if was_muted && !audio.is_muted() {
+                info!("教师端强制播放，已取消静音");
+                report_audio_state(&audio.control(), teacher, tx);
+            }
         }
         TeacherToStudent::FileOffer(offer) => {
-            let path = files.handle_offer(&offer).await?;
-            info!(transfer = %offer.transfer_id, file = %offer.file_name, path = %path.display(), "收到文件传输请求");
+            let (path, offset) = files.handle_offer(&offer).await?;
+            if offer.resume {
+                let _ = tx.send(StudentToTeacher::ResumeFrom {
+                    transfer_id: offer.transfer_id,
+                    offset,
+                });
+                info!(transfer = %offer.transfer_id, file = %offer.file_name, offset, "继续下载中断的文件");
+            } else {
+                info!(transfer = %offer.transfer_id, file = %offer.file_name, path = %path.display(), "收到文件传输请求");
+            }
         }
         TeacherToStudent::FileChunk(chunk) => {
-            files.handle_chunk(&chunk).await?;
+            if let Err(err) = files.handle_chunk(&chunk).await {
+                let _ = tx.send(StudentToTeacher::FileComplete(FileTransferComplete {
+                    transfer_id: chunk.transfer_id,
+                    success: false,
+                    message: Some(format!("{err:#}")),
+                }));
+                return Err(err);
+            }
         }
         TeacherToStudent::FileComplete(done) => {
             if let Some(path) = files.handle_complete(&done).await? {
@@ -299,14 +665,27 @@ async fn handle_message(
                     .unwrap_or_else(|| "文件传输完成".into()),
             ));
         }
+        TeacherToStudent::SettingsUpdate(update) => {
+            info!(
+                revision = update.revision,
+                fps = update.broadcast.fps,
+                jpeg_quality = update.broadcast.jpeg_quality,
+                "收到广播参数更新"
+            );
+            screen_streamer.apply_settings(update.broadcast);
+            let _ = tx.send(StudentToTeacher::SettingsAck(update.revision));
+        }
         TeacherToStudent::Heartbeat(_) => {
             let _ = tx.send(StudentToTeacher::Heartbeat(Heartbeat {
                 timestamp_ms: current_millis(),
             }));
         }
+        TeacherToStudent::Notice(notice) => video.show_notice(notice),
         TeacherToStudent::Error(msg) => {
             warn!(?msg, "教师端错误提示");
         }
+        // Acted on once the teacher hangs up, which follows right away.
+        TeacherToStudent::Kicked { .. } | TeacherToStudent::Replaced { .. } => {}
     }
     Ok(())
 }
@@ -321,17 +700,30 @@ async fn handle_broadcast_command(
     tx: &mpsc::UnboundedSender<StudentToTeacher>,
 ) -> Result<()> {
     match command {
-        BroadcastCommand::Start { source, mode } => {
-            let should_fullscreen = matches!(mode, BroadcastMode::Fullscreen)
-                && (config.auto_fullscreen
-                    || (forced_fullscreen.load(Ordering::SeqCst)
-                        && config.allow_forced_fullscreen));
+        BroadcastCommand::Start {
+            source,
+            mode,
+            lock_input,
+        } => {
+            let forced = matches!(mode, BroadcastMode::Fullscreen)
+                && forced_fullscreen.load(Ordering::SeqCst)
+                && config.allow_forced_fullscreen;
+            let should_fullscreen =
+                forced || (matches!(mode, BroadcastMode::Fullscreen) && config.auto_fullscreen);
             let actual_mode = if should_fullscreen {
                 BroadcastMode::Fullscreen
             } else {
                 BroadcastMode::Window
             };
             *current_mode.lock() = actual_mode;
+            video.start(forced);
+            // Locking needs both the student's consent and the forced-fullscreen policy.
+            video.set_input_lock(
+                lock_input
+                    && should_fullscreen
+                    && config.allow_input_lock
+                    && config.allow_forced_fullscreen,
+            );
 
             match source {
                 BroadcastSource::Teacher => {
@@ -340,11 +732,7 @@ async fn handle_broadcast_command(
                 BroadcastSource::Student { student_id, .. } => {
                     if student_id == config.student_id {
                         screen_streamer
-                            .start(
-                                tx.clone(),
-                                config.student_id.clone(),
-                                config.student_name.clone(),
-                            )
+                            .start(config.student_id.clone(), config.student_name.clone())
                             .await?;
                     } else {
                         screen_streamer.stop().await;
@@ -354,20 +742,30 @@ async fn handle_broadcast_command(
         }
         BroadcastCommand::Stop => {
             screen_streamer.stop().await;
+            video.set_input_lock(false);
             video.stop();
             *current_mode.lock() = BroadcastMode::Window;
         }
         BroadcastCommand::RequestStudentShare { student_id } => {
             if student_id == config.student_id {
                 screen_streamer
-                    .start(
-                        tx.clone(),
-                        config.student_id.clone(),
-                        config.student_name.clone(),
-                    )
+                    .start(config.student_id.clone(), config.student_name.clone())
                     .await?;
             }
         }
+        BroadcastCommand::Blackout { enabled } => {
+            if !enabled {
+                video.set_input_lock(false);
+            }
+            video.set_blackout(enabled);
+        }
+        BroadcastCommand::RequestThumbnail { interval_secs } => screen_streamer
+            .set_thumbnail_interval(
+                interval_secs,
+                tx.clone(),
+                config.student_id.clone(),
+                config.student_name.clone(),
+            ),
     }
     Ok(())
 }
@@ -381,9 +779,25 @@ fn open_file(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn current_millis() -> u64 {
+pub(crate) fn current_millis() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_millis() as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_cap() {
+        let mut delay = RECONNECT_DELAY_MIN;
+        let mut delays = Vec::new();
+        for _ in 0..7 {
+            delays.push(delay.as_secs());
+            delay = next_reconnect_delay(delay);
+        }
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+    }
+}
@@ -0,0 +1,83 @@
+//! Asking the student before a spotlight shares their screen (`require_share_consent`).
+//!
+//! The request is announced at the console with a countdown. Typing `deny` refuses it and
+//! `allow` starts sharing right away; when the countdown runs out unanswered, sharing starts.
+
+use std::io::Write;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tokio::time;
+use tracing::info;
+
+/// How long the student has to refuse before sharing starts.
+pub const SHARE_CONSENT_COUNTDOWN: Duration = Duration::from_secs(10);
+
+/// The pending share request, answered from the console command loop.
+#[derive(Default)]
+pub struct ShareConsent {
+    pending: Mutex<Option<oneshot::Sender<bool>>>,
+}
+
+impl ShareConsent {
+    /// Announce a share request and wait for the student; `true` when the screen may be
+    /// shared, which includes the countdown running out.
+    pub async fn ask(&self, countdown: Duration) -> bool {
+        let (answer, mut answered) = oneshot::channel();
+        *self.pending.lock() = Some(answer);
+        println!();
+        println!("================================================");
+        println!(" 教师请求共享你的屏幕");
+        println!(" 输入 deny 拒绝，输入 allow 立即开始");
+        println!("================================================");
+
+        let mut remaining = countdown.as_secs();
+        let allowed = loop {
+            print!("\r {remaining} 秒后开始共享屏幕… ");
+            let _ = std::io::stdout().flush();
+            if remaining == 0 {
+                break true;
+            }
+            tokio::select! {
+                answer = &mut answered => break answer.unwrap_or(false),
+                _ = time::sleep(Duration::from_secs(1)) => remaining -= 1,
+            }
+        };
+        println!();
+        self.pending.lock().take();
+        info!(allowed, "屏幕共享请求已处理");
+        allowed
+    }
+
+    /// Answer the pending request; `false` when there is none.
+    pub fn answer(&self, allow: bool) -> bool {
+        match self.pending.lock().take() {
+            Some(answer) => answer.send(allow).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn refusal_wins_and_silence_counts_as_consent() {
+        let consent = Arc::new(ShareConsent::default());
+        assert!(!consent.answer(true));
+
+        let asking = tokio::spawn({
+            let consent = consent.clone();
+            async move { consent.ask(SHARE_CONSENT_COUNTDOWN).await }
+        });
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(consent.answer(false));
+        assert!(!asking.await.unwrap());
+
+        assert!(consent.ask(Duration::ZERO).await);
+    }
+}
@@ -0,0 +1,84 @@
+//! `--diagnose`: check the devices and network path a classroom PC needs.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use shared::diagnose::{probe_screen_capture, DiagnosticReport};
+use shared::prelude::*;
+
+use crate::audio;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Run every check and print the report. Returns `false` if a critical check failed.
+pub fn run(config_path: &Path) -> bool {
+    let mut report = DiagnosticReport::new("学生端");
+
+    let (output_device, monitor_index) = match load_config(config_path) {
+        Ok(config) => {
+            report.check("配置文件", true, Ok(config_path.display().to_string()));
+            report.check("教师端连接", true, probe_teacher(&config.teacher_addr()));
+            (config.audio_output_device, config.monitor_index)
+        }
+        Err(err) => {
+            report.check("配置文件", true, Err(err));
+            report.skip("教师端连接", "配置文件无效");
+            (None, None)
+        }
+    };
+
+    // Only needed when the teacher spotlights this student.
+    report.check(
+        "屏幕截取",
+        false,
+        probe_screen_capture(&BroadcastConfig {
+            monitor_index,
+            ..BroadcastConfig::default()
+        }),
+    );
+    report.check(
+        "音频输出设备",
+        false,
+        audio::probe_output_device(output_device.as_deref()),
+    );
+
+    report.finish()
+}
+
+/// `--check-config`: load the config like startup does and print the values in effect,
+/// without connecting or opening devices.
+pub fn check_config(path: &Path) -> Result<()> {
+    let config = load_config(path)?;
+    let effective = serde_json::to_string_pretty(&config).context("无法序列化学生端配置")?;
+    println!(
+        "配置文件 {} 有效，生效配置如下：\n{effective}",
+        path.display()
+    );
+    Ok(())
+}
+
+fn load_config(path: &Path) -> Result<StudentConfig> {
+    // Loading would otherwise write a default file and report success.
+    if !path.exists() {
+        bail!("配置文件 {} 不存在", path.display());
+    }
+    StudentConfig::load_from_path(path)
+}
+
+/// Plain TCP connect; the teacher logs the dropped connection but no student is registered.
+fn probe_teacher(addr: &str) -> Result<String> {
+    let target = addr
+        .to_socket_addrs()
+        .with_context(|| format!("无法解析教师端地址 {addr}"))?
+        .next()
+        .with_context(|| format!("教师端地址 {addr} 没有可用的解析结果"))?;
+    let started = Instant::now();
+    TcpStream::connect_timeout(&target, CONNECT_TIMEOUT)
+        .with_context(|| format!("无法连接教师端 {addr}"))?;
+    Ok(format!(
+        "{addr} 可达 ({} ms)",
+        started.elapsed().as_millis()
+    ))
+}
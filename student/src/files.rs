@@ -1,32 +1,170 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use parking_lot::Mutex;
-use tokio::fs::File;
+use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use tracing::warn;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use shared::prelude::*;
 
+/// Limits applied when pruning old files from the download directory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_total_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &StudentConfig) -> Self {
+        Self {
+            max_age: config
+                .download_retention_days
+                .map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60)),
+            max_total_bytes: config
+                .download_max_total_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_total_bytes.is_some()
+    }
+}
+
 pub struct FileDownloadManager {
     root: PathBuf,
     default_auto_open: bool,
+    retention: RetentionPolicy,
     sessions: Arc<Mutex<HashMap<Uuid, DownloadSession>>>,
+    /// Downloads cut off by a lost connection, kept on disk until the teacher re-offers them.
+    interrupted: Arc<Mutex<HashMap<Uuid, InterruptedDownload>>>,
+    uploads: UploadTracker,
 }
 
 impl FileDownloadManager {
-    pub fn new(root: PathBuf, default_auto_open: bool) -> Self {
+    pub fn new(root: PathBuf, default_auto_open: bool, retention: RetentionPolicy) -> Self {
         Self {
             root,
             default_auto_open,
+            retention,
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            interrupted: Arc::new(Mutex::new(HashMap::new())),
+            uploads: UploadTracker::default(),
+        }
+    }
+
+    /// Uploads this student is sending to the teacher.
+    pub fn uploads(&self) -> &UploadTracker {
+        &self.uploads
+    }
+
+    /// Delete downloads that exceed the retention policy. Files with an active
+    /// transfer are never touched. Runs before each new download is created, so the file
+    /// just received always survives until the next transfer.
+    pub async fn apply_retention(&self) -> Result<()> {
+        if !self.retention.is_enabled() {
+            return Ok(());
+        }
+
+        let mut active: HashSet<PathBuf> = self
+            .sessions
+            .lock()
+            .values()
+            .map(|session| session.path.clone())
+            .collect();
+        active.extend(
+            self.interrupted
+                .lock()
+                .values()
+                .map(|download| download.path.clone()),
+        );
+
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.root)
+            .await
+            .with_context(|| format!("无法读取下载目录 {}", self.root.display()))?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if active.contains(&path) {
+                continue;
+            }
+            let metadata = match entry.metadata().await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((path, modified, metadata.len()));
         }
+
+        // Oldest first so both rules evict the same files first.
+        entries.sort_by_key(|(_, modified, _)| *modified);
+
+        let now = SystemTime::now();
+        let mut total: u64 = entries.iter().map(|(_, _, len)| len).sum();
+        for (path, modified, len) in entries {
+            let expired = self
+                .retention
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+            let over_quota = self
+                .retention
+                .max_total_bytes
+                .is_some_and(|limit| total > limit);
+            if !expired && !over_quota {
+                continue;
+            }
+
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    total = total.saturating_sub(len);
+                    info!(path = %path.display(), size = len, expired, over_quota, "已清理旧下载文件");
+                }
+                Err(err) => {
+                    warn!(?err, path = %path.display(), "清理下载文件失败");
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn handle_offer(&self, offer: &FileOffer) -> Result<PathBuf> {
+    /// Start (or, for a resumed offer, continue) a download. Returns the target path and the
+    /// number of bytes already on disk.
+    pub async fn handle_offer(&self, offer: &FileOffer) -> Result<(PathBuf, u64)> {
+        if offer.resume {
+            let interrupted = self.interrupted.lock().remove(&offer.transfer_id);
+            if let Some(download) = interrupted {
+                match reopen(&download).await {
+                    Ok(file) => {
+                        self.sessions.lock().insert(
+                            offer.transfer_id,
+                            DownloadSession {
+                                file,
+                                path: download.path.clone(),
+                                expected: download.expected,
+                                received: download.received,
+                                auto_open: download.auto_open,
+                            },
+                        );
+                        return Ok((download.path, download.received));
+                    }
+                    Err(err) => {
+                        warn!(?err, path = %download.path.display(), "无法续传，将重新下载");
+                    }
+                }
+            }
+        }
+
+        if let Err(err) = self.apply_retention().await {
+            warn!(?err, "清理下载目录失败");
+        }
+
         let sanitized = sanitize_filename(&offer.file_name);
         let target = self.root.join(&sanitized);
 
@@ -52,12 +190,32 @@ impl FileDownloadManager {
             },
         );
 
-        Ok(target)
+        Ok((target, 0))
     }
 
+    /// Append a chunk. A chunk failing its checksum aborts the download and deletes the
+    /// partial file.
     pub async fn handle_chunk(&self, chunk: &FileChunk) -> Result<()> {
+        if !chunk.checksum_matches() {
+            let session = self.sessions.lock().remove(&chunk.transfer_id);
+            if let Some(DownloadSession { file, path, .. }) = session {
+                drop(file);
+                remove_partial(&path).await;
+            }
+            bail!("文件分片校验失败 (偏移 {})", chunk.offset);
+        }
+
         let mut sessions = self.sessions.lock();
         if let Some(session) = sessions.get_mut(&chunk.transfer_id) {
+            if chunk.offset != session.received {
+                warn!(
+                    transfer = %chunk.transfer_id,
+                    offset = chunk.offset,
+                    received = session.received,
+                    "文件分片偏移不连续，已忽略"
+                );
+                return Ok(());
+            }
             session
                 .file
                 .write_all(&chunk.bytes)
@@ -70,35 +228,195 @@ impl FileDownloadManager {
         Ok(())
     }
 
+    /// Finish a download. A failed, canceled or short transfer deletes the partial file.
     pub async fn handle_complete(
         &self,
         complete: &FileTransferComplete,
     ) -> Result<Option<PathBuf>> {
-        let mut sessions = self.sessions.lock();
-        if let Some(mut session) = sessions.remove(&complete.transfer_id) {
+        if self.uploads.settle(complete) {
+            if complete.success {
+                info!(transfer = %complete.transfer_id, "文件已上传到教师端");
+            } else {
+                warn!(reason = ?complete.message, "教师端拒绝了上传，已停止发送");
+            }
+            return Ok(None);
+        }
+        let session = self.sessions.lock().remove(&complete.transfer_id);
+        if let Some(mut session) = session {
             session.file.flush().await?;
             if !complete.success {
                 warn!("文件传输失败: {:?}", complete.message);
+                drop(session.file);
+                remove_partial(&session.path).await;
                 return Ok(None);
             }
 
+            // Never keep, let alone open, a file that is missing its end.
             if session.received != session.expected {
                 warn!(
                     expected = session.expected,
                     received = session.received,
-                    "文件大小与期望不符"
+                    "文件大小与期望不符，已丢弃"
                 );
+                drop(session.file);
+                remove_partial(&session.path).await;
+                return Ok(None);
             }
 
             if session.auto_open {
                 return Ok(Some(session.path));
             }
+        } else if let Some(download) = self.take_failed_interrupted(complete) {
+            // The teacher gave up on a transfer this student was waiting to resume.
+            remove_partial(&download.path).await;
         } else {
             warn!(transfer = %complete.transfer_id, "收到未知的完成通知");
         }
 
         Ok(None)
     }
+
+    fn take_failed_interrupted(
+        &self,
+        complete: &FileTransferComplete,
+    ) -> Option<InterruptedDownload> {
+        if complete.success {
+            return None;
+        }
+        self.interrupted.lock().remove(&complete.transfer_id)
+    }
+
+    /// Close unfinished downloads after the connection dropped, keeping the partial files so
+    /// a resumed offer of the same transfer can continue where it stopped.
+    pub async fn suspend_all(&self) {
+        let sessions: Vec<(Uuid, DownloadSession)> = self.sessions.lock().drain().collect();
+        for (transfer_id, mut session) in sessions {
+            if let Err(err) = session.file.flush().await {
+                warn!(?err, path = %session.path.display(), "写入未完成的下载文件失败");
+            }
+            info!(
+                transfer = %transfer_id,
+                received = session.received,
+                expected = session.expected,
+                "下载已中断，等待续传"
+            );
+            self.interrupted.lock().insert(
+                transfer_id,
+                InterruptedDownload {
+                    path: session.path,
+                    expected: session.expected,
+                    received: session.received,
+                    auto_open: session.auto_open,
+                },
+            );
+        }
+    }
+
+    /// Drop every unfinished or interrupted download and delete its partial file.
+    pub async fn abort_all(&self) {
+        self.suspend_all().await;
+        let interrupted: Vec<InterruptedDownload> = self
+            .interrupted
+            .lock()
+            .drain()
+            .map(|(_, download)| download)
+            .collect();
+        for InterruptedDownload { path, .. } in interrupted {
+            remove_partial(&path).await;
+        }
+    }
+}
+
+/// Where one of this student's uploads stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UploadState {
+    Sending,
+    /// The teacher refused it; its remaining chunks are dropped instead of sent.
+    Refused,
+    /// Its closing `FileComplete` went out; only the teacher's verdict is left.
+    Sent,
+}
+
+/// Uploads in flight, so the teacher's verdict on one is not mistaken for a download's and a
+/// refusal stops the chunks still queued behind it.
+#[derive(Debug, Default)]
+pub struct UploadTracker {
+    uploads: Mutex<HashMap<Uuid, UploadState>>,
+    /// `max_upload_bytes` from the teacher's `HelloAck`; 0 means no limit.
+    limit: AtomicU64,
+}
+
+impl UploadTracker {
+    pub fn set_limit(&self, bytes: u64) {
+        self.limit.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn begin(&self, transfer_id: Uuid) {
+        self.uploads
+            .lock()
+            .insert(transfer_id, UploadState::Sending);
+    }
+
+    /// Whether chunks of `transfer_id` should no longer be sent.
+    pub fn is_refused(&self, transfer_id: &Uuid) -> bool {
+        self.uploads.lock().get(transfer_id) == Some(&UploadState::Refused)
+    }
+
+    /// Note that the closing `FileComplete` of `transfer_id` has been sent.
+    pub fn sent(&self, transfer_id: &Uuid) {
+        let mut uploads = self.uploads.lock();
+        match uploads.get(transfer_id) {
+            Some(UploadState::Refused) => {
+                uploads.remove(transfer_id);
+            }
+            Some(UploadState::Sending) => {
+                uploads.insert(*transfer_id, UploadState::Sent);
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the teacher's verdict; false when `complete` is not about one of our uploads.
+    fn settle(&self, complete: &FileTransferComplete) -> bool {
+        let mut uploads = self.uploads.lock();
+        match uploads.remove(&complete.transfer_id) {
+            None => false,
+            Some(UploadState::Sent) => true,
+            Some(_) => {
+                if !complete.success {
+                    uploads.insert(complete.transfer_id, UploadState::Refused);
+                }
+                true
+            }
+        }
+    }
+}
+
+async fn remove_partial(path: &Path) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => info!(path = %path.display(), "已删除未完成的下载文件"),
+        Err(err) => warn!(?err, path = %path.display(), "删除未完成的下载文件失败"),
+    }
+}
+
+/// Open an interrupted download for appending, checking nothing changed on disk since.
+async fn reopen(download: &InterruptedDownload) -> Result<File> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(&download.path)
+        .await
+        .with_context(|| format!("无法打开文件 {}", download.path.display()))?;
+    let len = file.metadata().await?.len();
+    ensure!(
+        len == download.received,
+        "文件长度 {len} 与已接收的 {} 字节不符",
+        download.received
+    );
+    Ok(file)
 }
 
 struct DownloadSession {
@@ -108,3 +426,238 @@ struct DownloadSession {
     received: u64,
     auto_open: bool,
 }
+
+struct InterruptedDownload {
+    path: PathBuf,
+    expected: u64,
+    received: u64,
+    auto_open: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(transfer_id: Uuid, resume: bool) -> FileOffer {
+        FileOffer {
+            transfer_id,
+            file_name: "notes.txt".into(),
+            total_size: 10,
+            auto_open: false,
+            resume,
+        }
+    }
+
+    fn chunk(transfer_id: Uuid, offset: u64, bytes: &[u8]) -> FileChunk {
+        FileChunk::new(
+            transfer_id,
+            offset,
+            bytes.to_vec(),
+            offset + bytes.len() as u64 >= 10,
+        )
+    }
+
+    fn temp_root() -> PathBuf {
+        std::env::temp_dir().join(format!("student-files-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn interrupted_download_resumes_from_received_bytes() {
+        let root = temp_root();
+        let files = FileDownloadManager::new(root.clone(), false, RetentionPolicy::default());
+        let transfer_id = Uuid::new_v4();
+        let (path, offset) = files
+            .handle_offer(&offer(transfer_id, false))
+            .await
+            .unwrap();
+        assert_eq!(offset, 0);
+        files
+            .handle_chunk(&chunk(transfer_id, 0, b"hello"))
+            .await
+            .unwrap();
+
+        files.suspend_all().await;
+        // Tail chunks of the original broadcast arriving after the drop are ignored.
+        files
+            .handle_chunk(&chunk(transfer_id, 5, b"world"))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        let (resumed, offset) = files.handle_offer(&offer(transfer_id, true)).await.unwrap();
+        assert_eq!((resumed.as_path(), offset), (path.as_path(), 5));
+        files
+            .handle_chunk(&chunk(transfer_id, 5, b"world"))
+            .await
+            .unwrap();
+        files
+            .handle_complete(&FileTransferComplete {
+                transfer_id,
+                success: true,
+                message: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"helloworld");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn corrupted_chunk_discards_the_download() {
+        let root = temp_root();
+        let files = FileDownloadManager::new(root.clone(), false, RetentionPolicy::default());
+        let transfer_id = Uuid::new_v4();
+        let (path, _) = files
+            .handle_offer(&offer(transfer_id, false))
+            .await
+            .unwrap();
+        files
+            .handle_chunk(&chunk(transfer_id, 0, b"hello"))
+            .await
+            .unwrap();
+
+        let mut corrupted = chunk(transfer_id, 5, b"world");
+        corrupted.bytes[0] ^= 0x01;
+        assert!(files.handle_chunk(&corrupted).await.is_err());
+        assert!(!path.exists());
+
+        // Chunks from peers without checksums are still accepted.
+        let (path, _) = files
+            .handle_offer(&offer(transfer_id, false))
+            .await
+            .unwrap();
+        let mut unchecked = chunk(transfer_id, 0, b"hello");
+        unchecked.crc32 = 0;
+        files.handle_chunk(&unchecked).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn canceled_transfer_deletes_the_partial_file() {
+        let root = temp_root();
+        let files = FileDownloadManager::new(root.clone(), false, RetentionPolicy::default());
+        let transfer_id = Uuid::new_v4();
+        let (path, _) = files
+            .handle_offer(&offer(transfer_id, false))
+            .await
+            .unwrap();
+        files
+            .handle_chunk(&chunk(transfer_id, 0, b"hello"))
+            .await
+            .unwrap();
+
+        let canceled = FileTransferComplete {
+            transfer_id,
+            success: false,
+            message: Some("文件发送已取消".into()),
+        };
+        assert_eq!(files.handle_complete(&canceled).await.unwrap(), None);
+        assert!(!path.exists());
+
+        // A transfer reported as finished that came up short is discarded too.
+        let transfer_id = Uuid::new_v4();
+        let (path, _) = files
+            .handle_offer(&FileOffer {
+                auto_open: true,
+                ..offer(transfer_id, false)
+            })
+            .await
+            .unwrap();
+        files
+            .handle_chunk(&chunk(transfer_id, 0, b"hello"))
+            .await
+            .unwrap();
+        let short = FileTransferComplete {
+            transfer_id,
+            success: true,
+            message: None,
+        };
+        assert_eq!(files.handle_complete(&short).await.unwrap(), None);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn retention_never_removes_the_download_just_completed() {
+        let root = temp_root();
+        let retention = RetentionPolicy {
+            max_age: None,
+            max_total_bytes: Some(8),
+        };
+        let files = FileDownloadManager::new(root.clone(), false, retention);
+        let first = Uuid::new_v4();
+        let (path, _) = files.handle_offer(&offer(first, false)).await.unwrap();
+        files
+            .handle_chunk(&chunk(first, 0, b"helloworld"))
+            .await
+            .unwrap();
+        files
+            .handle_complete(&FileTransferComplete {
+                transfer_id: first,
+                success: true,
+                message: None,
+            })
+            .await
+            .unwrap();
+        // Over the quota on its own, but kept until the next download makes room.
+        assert!(path.exists());
+
+        let second = FileOffer {
+            file_name: "next.txt".into(),
+            ..offer(Uuid::new_v4(), false)
+        };
+        let (next, _) = files.handle_offer(&second).await.unwrap();
+        assert!(!path.exists());
+        assert!(next.exists());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn aborted_downloads_leave_no_partial_files() {
+        let root = temp_root();
+        let files = FileDownloadManager::new(root.clone(), false, RetentionPolicy::default());
+        let transfer_id = Uuid::new_v4();
+        let (path, _) = files
+            .handle_offer(&offer(transfer_id, false))
+            .await
+            .unwrap();
+        files
+            .handle_chunk(&chunk(transfer_id, 0, b"hello"))
+            .await
+            .unwrap();
+        files.suspend_all().await;
+        assert!(path.exists());
+
+        files.abort_all().await;
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn refused_upload_stops_until_its_completion_is_sent() {
+        let files = FileDownloadManager::new(temp_root(), false, RetentionPolicy::default());
+        let uploads = files.uploads();
+        let transfer_id = Uuid::new_v4();
+        uploads.begin(transfer_id);
+        assert!(!uploads.is_refused(&transfer_id));
+
+        let refusal = FileTransferComplete {
+            transfer_id,
+            success: false,
+            message: Some("too big".into()),
+        };
+        assert_eq!(files.handle_complete(&refusal).await.unwrap(), None);
+        assert!(uploads.is_refused(&transfer_id));
+
+        uploads.sent(&transfer_id);
+        assert!(!uploads.is_refused(&transfer_id));
+        // Nothing is left to match a later reply against.
+        assert!(!uploads.settle(&refusal));
+    }
+}
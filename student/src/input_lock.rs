@@ -0,0 +1,160 @@
+//! Locked broadcast mode: swallows the task-switching shortcuts (Alt+Tab, Alt+Esc,
+//! Ctrl+Esc and the Windows keys) through a low-level keyboard hook. Keys are only
+//! swallowed while the broadcast window is the foreground window, and Ctrl+Alt+Del always
+//! works. Only Windows is supported; elsewhere engaging the lock fails.
+
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use minifb::Window;
+
+/// Handle of the broadcast window the lock applies to; 0 while there is none.
+static LOCKED_WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+/// Whether a key press with the given virtual-key code is part of a task-switching shortcut.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn is_task_switch(vk_code: u32, alt: bool, ctrl: bool) -> bool {
+    const VK_TAB: u32 = 0x09;
+    const VK_ESCAPE: u32 = 0x1b;
+    const VK_LWIN: u32 = 0x5b;
+    const VK_RWIN: u32 = 0x5c;
+    match vk_code {
+        VK_LWIN | VK_RWIN => true,
+        VK_TAB => alt,
+        VK_ESCAPE => alt || ctrl,
+        _ => false,
+    }
+}
+
+/// An installed keyboard lock; dropping it removes the hook.
+pub struct InputLock {
+    #[cfg(windows)]
+    thread_id: u32,
+    #[cfg(windows)]
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl InputLock {
+    /// Point the lock at the current broadcast window, or at nothing.
+    pub fn follow(&self, window: Option<&Window>) {
+        self.follow_handle(window.map_or(0, |window| window.get_window_handle() as isize));
+    }
+
+    /// Point the lock at a window by its raw handle (`HWND`), or at nothing with 0.
+    pub fn follow_handle(&self, handle: isize) {
+        LOCKED_WINDOW.store(handle, Ordering::Relaxed);
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use std::{io, mem, ptr, thread};
+
+    use anyhow::{Context, Result};
+    use winapi::ctypes::c_int;
+    use winapi::shared::minwindef::{LPARAM, LRESULT, WPARAM};
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::processthreadsapi::GetCurrentThreadId;
+    use winapi::um::winuser::{
+        CallNextHookEx, GetAsyncKeyState, GetForegroundWindow, GetMessageW, PostThreadMessageW,
+        SetWindowsHookExW, UnhookWindowsHookEx, HC_ACTION, KBDLLHOOKSTRUCT, LLKHF_ALTDOWN, MSG,
+        VK_CONTROL, WH_KEYBOARD_LL, WM_QUIT,
+    };
+
+    use super::{is_task_switch, InputLock, LOCKED_WINDOW};
+
+    impl InputLock {
+        /// Install the hook on its own thread, which pumps the messages the hook runs on.
+        pub fn engage() -> Result<Self> {
+            let (ready_tx, ready_rx) = mpsc::channel();
+            let thread = thread::Builder::new()
+                .name("student-input-lock".into())
+                .spawn(move || unsafe {
+                    let hook = SetWindowsHookExW(
+                        WH_KEYBOARD_LL,
+                        Some(keyboard_hook),
+                        GetModuleHandleW(ptr::null()),
+                        0,
+                    );
+                    if hook.is_null() {
+                        let _ = ready_tx.send(Err(io::Error::last_os_error()));
+                        return;
+                    }
+                    let _ = ready_tx.send(Ok(GetCurrentThreadId()));
+                    let mut msg: MSG = mem::zeroed();
+                    while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {}
+                    UnhookWindowsHookEx(hook);
+                })
+                .context("无法启动锁定输入线程")?;
+            let thread_id = ready_rx
+                .recv()
+                .context("锁定输入线程意外退出")?
+                .context("无法安装键盘钩子")?;
+            Ok(Self {
+                thread_id,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for InputLock {
+        fn drop(&mut self) {
+            LOCKED_WINDOW.store(0, Ordering::Relaxed);
+            unsafe {
+                PostThreadMessageW(self.thread_id, WM_QUIT, 0, 0);
+            }
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    unsafe extern "system" fn keyboard_hook(
+        code: c_int,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        if code == HC_ACTION {
+            let locked = LOCKED_WINDOW.load(Ordering::Relaxed);
+            if locked != 0 && GetForegroundWindow() as isize == locked {
+                let event = &*(lparam as *const KBDLLHOOKSTRUCT);
+                let alt = event.flags & LLKHF_ALTDOWN != 0;
+                let ctrl = GetAsyncKeyState(VK_CONTROL) < 0;
+                if is_task_switch(event.vkCode, alt, ctrl) {
+                    return 1;
+                }
+            }
+        }
+        CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
+    }
+}
+
+#[cfg(not(windows))]
+impl InputLock {
+    pub fn engage() -> anyhow::Result<Self> {
+        anyhow::bail!("锁定输入仅支持 Windows")
+    }
+}
+
+#[cfg(not(windows))]
+impl Drop for InputLock {
+    fn drop(&mut self) {
+        LOCKED_WINDOW.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_task_switching_shortcuts_are_swallowed() {
+        assert!(is_task_switch(0x5b, false, false));
+        assert!(is_task_switch(0x09, true, false));
+        assert!(!is_task_switch(0x09, false, false));
+        assert!(is_task_switch(0x1b, false, true));
+        assert!(!is_task_switch(0x1b, false, false));
+        assert!(!is_task_switch(u32::from(b'A'), true, true));
+    }
+}
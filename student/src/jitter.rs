@@ -0,0 +1,254 @@
+//! Playback buffer that absorbs network jitter in the broadcast audio.
+//!
+//! Playback starts only once `target` samples are queued, and starts over the same way after
+//! the buffer runs dry. When bursts pile up far past the target, the oldest audio is dropped
+//! so latency does not keep growing. [`LateFrameFilter`] keeps stale frames out of the
+//! buffer in the first place.
+
+use std::collections::VecDeque;
+
+/// Depth, as a multiple of the target, past which queued audio is cut back to the target.
+const OVERRUN_FACTOR: usize = 4;
+/// Jump in frame IDs taken as a new stream (teacher restart, another student's audio) or a
+/// long stall rather than reordering.
+const RESYNC_ID_GAP: u64 = 50;
+/// How long audio may stay late before its delay is accepted as the new normal.
+const LATE_RESYNC_MS: u64 = 1000;
+/// The usual delay is re-anchored to the smallest one seen over this long, so it follows
+/// clocks that drift apart instead of staying at the minimum of the whole stream.
+const BASE_DELAY_WINDOW_MS: u64 = 10_000;
+
+pub struct JitterBuffer {
+    samples: VecDeque<i16>,
+    /// Interleaved samples to queue before playback (re)starts.
+    target: usize,
+    channels: usize,
+    /// Whether playback is running, as opposed to filling up to `target`.
+    playing: bool,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl JitterBuffer {
+    /// Buffer for `target_ms` of audio at the output's `sample_rate` and `channels`.
+    pub fn new(target_ms: u32, sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let frames = (sample_rate as usize * target_ms as usize / 1000).max(1);
+        Self {
+            samples: VecDeque::with_capacity(frames * channels * OVERRUN_FACTOR),
+            target: frames * channels,
+            channels,
+            playing: false,
+            underruns: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Fill `output` from the buffer, with silence while (re)buffering.
+    pub fn fill(&mut self, output: &mut [i16]) {
+        if !self.playing && self.samples.len() >= self.target {
+            self.playing = true;
+        }
+        let mut filled = 0;
+        if self.playing {
+            for sample in output.iter_mut() {
+                match self.samples.pop_front() {
+                    Some(value) => *sample = value,
+                    None => break,
+                }
+                filled += 1;
+            }
+            if filled < output.len() {
+                self.playing = false;
+                self.underruns += 1;
+            }
+        }
+        output[filled..].fill(0);
+    }
+
+    /// Drop everything queued, e.g. to resync after a stall.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.playing = false;
+    }
+
+    /// Times playback ran dry and had to rebuffer.
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Times queued audio piled up and was cut back to the target.
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+}
+
+impl Extend<i16> for JitterBuffer {
+    fn extend<I: IntoIterator<Item = i16>>(&mut self, samples: I) {
+        self.samples.extend(samples);
+        if self.samples.len() > self.target * OVERRUN_FACTOR {
+            // Keep whole frames so the channels stay in order.
+            let excess = self.samples.len() - self.target;
+            self.samples.drain(..excess - excess % self.channels);
+            self.overruns += 1;
+        }
+    }
+}
+
+/// What to do with an incoming audio frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCheck {
+    Play,
+    /// Out of order or too late; skip it.
+    Discard,
+    /// The stream restarted or stalled: flush what is queued, then play this frame.
+    Resync,
+}
+
+/// Sorts out audio frames that arrive out of order or much later than the stream usually
+/// does, judged by their frame ID and capture time.
+pub struct LateFrameFilter {
+    /// Lateness past which frames are discarded; 0 keeps every in-order frame.
+    max_late_ms: u64,
+    last_frame_id: Option<u64>,
+    /// Smallest `arrival - capture` over the last window or so: the delay of an undisturbed
+    /// frame.
+    base_delay_ms: i64,
+    /// Smallest delay in the current window, and when that window began.
+    window_delay_ms: i64,
+    window_start_ms: u64,
+    /// When the current run of late frames began.
+    late_since_ms: Option<u64>,
+    discarded: u64,
+}
+
+impl LateFrameFilter {
+    pub fn new(max_late_ms: u64) -> Self {
+        Self {
+            max_late_ms,
+            last_frame_id: None,
+            base_delay_ms: 0,
+            window_delay_ms: 0,
+            window_start_ms: 0,
+            late_since_ms: None,
+            discarded: 0,
+        }
+    }
+
+    /// Judge a frame captured at `capture_ms` (sender clock) that arrived at `now_ms`
+    /// (local clock).
+    pub fn check(&mut self, frame_id: u64, capture_ms: u64, now_ms: u64) -> FrameCheck {
+        let delay = now_ms as i64 - capture_ms as i64;
+        let Some(last) = self.last_frame_id else {
+            return self.resync(frame_id, delay, now_ms);
+        };
+        if frame_id.abs_diff(last) > RESYNC_ID_GAP {
+            return self.resync(frame_id, delay, now_ms);
+        }
+        if frame_id <= last {
+            self.discarded += 1;
+            return FrameCheck::Discard;
+        }
+        self.last_frame_id = Some(frame_id);
+        self.base_delay_ms = self.base_delay_ms.min(delay);
+        self.window_delay_ms = self.window_delay_ms.min(delay);
+        if now_ms.saturating_sub(self.window_start_ms) >= BASE_DELAY_WINDOW_MS {
+            self.base_delay_ms = self.window_delay_ms;
+            self.window_delay_ms = delay;
+            self.window_start_ms = now_ms;
+        }
+
+        if self.max_late_ms == 0 || delay - self.base_delay_ms <= self.max_late_ms as i64 {
+            self.late_since_ms = None;
+            return FrameCheck::Play;
+        }
+        let late_since = *self.late_since_ms.get_or_insert(now_ms);
+        if now_ms.saturating_sub(late_since) >= LATE_RESYNC_MS {
+            return self.resync(frame_id, delay, now_ms);
+        }
+        self.discarded += 1;
+        FrameCheck::Discard
+    }
+
+    /// Frames discarded so far.
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    fn resync(&mut self, frame_id: u64, delay: i64, now_ms: u64) -> FrameCheck {
+        self.last_frame_id = Some(frame_id);
+        self.base_delay_ms = delay;
+        self.window_delay_ms = delay;
+        self.window_start_ms = now_ms;
+        self.late_since_ms = None;
+        FrameCheck::Resync
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_the_target_and_rebuffers_after_running_dry() {
+        // 10 ms of mono audio at 1 kHz: 10 samples.
+        let mut buffer = JitterBuffer::new(10, 1000, 1);
+        let mut output = [7; 4];
+
+        buffer.extend([1; 6]);
+        buffer.fill(&mut output);
+        assert_eq!(output, [0; 4]);
+
+        buffer.extend([1; 4]);
+        buffer.fill(&mut output);
+        assert_eq!(output, [1; 4]);
+        buffer.fill(&mut output);
+        buffer.fill(&mut output);
+        assert_eq!(output, [1, 1, 0, 0]);
+        assert_eq!(buffer.underruns(), 1);
+
+        buffer.extend([1; 4]);
+        buffer.fill(&mut output);
+        assert_eq!(output, [0; 4]);
+    }
+
+    #[test]
+    fn piled_up_audio_is_cut_back_to_whole_frames() {
+        let mut buffer = JitterBuffer::new(10, 1000, 2);
+        buffer.extend((0..83).map(|sample| sample as i16));
+        assert_eq!(buffer.overruns(), 1);
+        // 63 samples over the target; the oldest 31 whole stereo frames go.
+        assert_eq!(buffer.samples.len(), 21);
+        assert_eq!(buffer.samples.front(), Some(&62));
+    }
+
+    #[test]
+    fn stale_and_reordered_frames_are_discarded_until_a_resync() {
+        let mut filter = LateFrameFilter::new(200);
+        assert_eq!(filter.check(1, 0, 50), FrameCheck::Resync);
+        assert_eq!(filter.check(2, 20, 70), FrameCheck::Play);
+        assert_eq!(filter.check(2, 20, 75), FrameCheck::Discard);
+        // Held up by 300 ms past the usual 50 ms.
+        assert_eq!(filter.check(3, 40, 390), FrameCheck::Discard);
+        assert_eq!(filter.check(4, 60, 400), FrameCheck::Discard);
+        assert_eq!(filter.discarded(), 3);
+        // Still late a second later: accept the longer delay.
+        assert_eq!(filter.check(5, 1100, 1450), FrameCheck::Resync);
+        assert_eq!(filter.check(6, 1120, 1470), FrameCheck::Play);
+        // Another sender's numbering.
+        assert_eq!(filter.check(900, 5000, 1490), FrameCheck::Resync);
+    }
+
+    #[test]
+    fn usual_delay_follows_a_drifting_clock() {
+        let mut filter = LateFrameFilter::new(200);
+        assert_eq!(filter.check(0, 0, 50), FrameCheck::Resync);
+        // The sender's clock falls 1 ms behind every 100 ms: 600 ms over a minute.
+        for frame_id in 1..3000 {
+            let capture_ms = frame_id * 20;
+            let now_ms = 50 + capture_ms + capture_ms / 100;
+            assert_eq!(filter.check(frame_id, capture_ms, now_ms), FrameCheck::Play);
+        }
+        assert_eq!(filter.discarded(), 0);
+    }
+}
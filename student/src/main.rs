@@ -1,6 +1,10 @@
 mod audio;
 mod client;
+mod consent;
+mod diagnose;
 mod files;
+mod input_lock;
+mod jitter;
 mod screen;
 mod video;
 
@@ -18,13 +22,31 @@ struct Cli {
     /// Path to student configuration JSON file
     #[arg(short, long, default_value = "configs/student_config.json")]
     config: PathBuf,
+
+    /// Check config, teacher reachability, screen capture and audio output, then exit
+    #[arg(long)]
+    diagnose: bool,
+
+    /// Load the config, print the values in effect and exit without starting
+    #[arg(long)]
+    check_config: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_tracing("student")?;
+    let log_guard = init_tracing("student")?;
 
     let cli = Cli::parse();
+    if cli.diagnose {
+        let passed = diagnose::run(&cli.config);
+        // `exit` skips destructors, so flush the log file first.
+        drop(log_guard);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if cli.check_config {
+        return diagnose::check_config(&cli.config);
+    }
+
     let config = StudentConfig::load_from_path(&cli.config)?;
     StudentApp::new(config).run().await
 }
@@ -1,90 +1,351 @@
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use parking_lot::Mutex;
-use screenshots::Screen;
-use tokio::sync::mpsc;
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use shared::frame::{encode_jpeg, ScreenSource};
+use tokio::sync::{mpsc, Notify};
 use tokio::task::JoinHandle;
 use tokio::time;
-use tracing::{debug, error, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
 
 use shared::prelude::*;
 
+use crate::consent::{ShareConsent, SHARE_CONSENT_COUNTDOWN};
+
+/// Bounds of the monitoring thumbnails requested by the teacher.
+const THUMBNAIL_MAX_WIDTH: u32 = 320;
+const THUMBNAIL_MAX_HEIGHT: u32 = 180;
+const THUMBNAIL_JPEG_QUALITY: u8 = 50;
+/// Spotlight frames waiting for the connection before the oldest is dropped.
+const SHARE_QUEUE_FRAMES: usize = 2;
+/// How long a stopping capture may take to finish its frame before it is aborted.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Spotlight frames on their way to the teacher. When the uplink falls behind, the oldest
+/// queued frame makes room for the newest instead of piling up in memory.
+pub struct FrameQueue {
+    messages: Mutex<VecDeque<StudentToTeacher>>,
+    ready: Notify,
+    dropped: AtomicU64,
+    /// Whether the teacher understands `StudentToTeacher::ShareEnded`.
+    end_marker: AtomicBool,
+    /// Whether the teacher understands `StudentToTeacher::ShareDeclined`.
+    decline_marker: AtomicBool,
+}
+
+impl FrameQueue {
+    fn new() -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(SHARE_QUEUE_FRAMES + 1)),
+            ready: Notify::new(),
+            dropped: AtomicU64::new(0),
+            end_marker: AtomicBool::new(false),
+            decline_marker: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, frame: VideoFrame) {
+        let mut messages = self.messages.lock();
+        let frames = messages
+            .iter()
+            .filter(|message| matches!(message, StudentToTeacher::Video(_)))
+            .count();
+        if frames >= SHARE_QUEUE_FRAMES {
+            if let Some(oldest) = messages
+                .iter()
+                .position(|message| matches!(message, StudentToTeacher::Video(_)))
+            {
+                messages.remove(oldest);
+            }
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!(dropped, "上传跟不上，丢弃最旧的屏幕帧");
+        }
+        messages.push_back(StudentToTeacher::Video(frame));
+        drop(messages);
+        self.ready.notify_one();
+    }
+
+    /// Close the current share with `ShareEnded`, behind the frames still queued.
+    fn finish(&self) {
+        if !self.end_marker.load(Ordering::Relaxed) {
+            return;
+        }
+        self.messages.lock().push_back(StudentToTeacher::ShareEnded);
+        self.ready.notify_one();
+    }
+
+    /// Tell the teacher the student refused to share their screen, so it can end the
+    /// spotlight. Older teachers only log the refusal as an error.
+    fn decline(&self) {
+        let message = if self.decline_marker.load(Ordering::Relaxed) {
+            StudentToTeacher::ShareDeclined
+        } else {
+            StudentToTeacher::Error("学生拒绝共享屏幕".into())
+        };
+        self.messages.lock().push_back(message);
+        self.ready.notify_one();
+    }
+
+    /// Pick the share markers the connected teacher's `version` understands.
+    pub fn set_teacher_version(&self, version: ProtocolVersion) {
+        self.end_marker
+            .store(version >= SHARE_END_PROTOCOL_VERSION, Ordering::Relaxed);
+        self.decline_marker
+            .store(version >= SHARE_DECLINE_PROTOCOL_VERSION, Ordering::Relaxed);
+    }
+
+    /// Wait for the next message to send.
+    pub async fn pop(&self) -> StudentToTeacher {
+        loop {
+            if let Some(message) = self.messages.lock().pop_front() {
+                return message;
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    /// Forget queued frames, e.g. ones captured for a connection that is gone.
+    pub fn clear(&self) {
+        self.messages.lock().clear();
+    }
+}
+
 pub struct ScreenStreamer {
-    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// The running spotlight capture and the token that asks it to stop.
+    task: Mutex<Option<(JoinHandle<()>, CancellationToken)>>,
+    thumbnail_task: Mutex<Option<JoinHandle<()>>>,
     running: Arc<AtomicBool>,
     frame_counter: Arc<AtomicU64>,
+    settings: Arc<RwLock<BroadcastConfig>>,
+    clock: SessionClock,
+    /// `StudentConfig::monitor_index`; the teacher's own choice is not applied here.
+    monitor_index: Option<usize>,
+    /// `StudentConfig::student_share_max_fps`.
+    max_fps: u32,
+    /// Rate the teacher asked for while our uplink lags; 0 when it did not.
+    pacing_fps: Arc<AtomicU32>,
+    frames: Arc<FrameQueue>,
+    /// Asked before each spotlight capture under `StudentConfig::require_share_consent`.
+    consent: Option<Arc<ShareConsent>>,
 }
 
 impl ScreenStreamer {
-    pub fn new() -> Self {
-        Self {
-            task: Arc::new(Mutex::new(None)),
+    pub fn new(
+        clock: SessionClock,
+        monitor_index: Option<usize>,
+        max_fps: u32,
+        consent: Option<Arc<ShareConsent>>,
+    ) -> Self {
+        let streamer = Self {
+            clock,
+            monitor_index,
+            max_fps,
+            consent,
+            pacing_fps: Arc::new(AtomicU32::new(0)),
+            frames: Arc::new(FrameQueue::new()),
+            task: Mutex::new(None),
+            thumbnail_task: Mutex::new(None),
             running: Arc::new(AtomicBool::new(false)),
             frame_counter: Arc::new(AtomicU64::new(0)),
+            settings: Arc::new(RwLock::new(BroadcastConfig::default())),
+        };
+        // Until the teacher sends its settings.
+        streamer.apply_settings(BroadcastConfig::default());
+        streamer
+    }
+
+    /// Replace the capture settings; a running capture picks them up on its next frame. The
+    /// teacher's size limits apply as they are, its frame rate only up to our own cap.
+    pub fn apply_settings(&self, mut broadcast: BroadcastConfig) {
+        // Zoom, mask regions and the display choice describe the teacher's screen, not ours.
+        broadcast.capture_region = None;
+        broadcast.capture_rect = None;
+        broadcast.capture_window = None;
+        broadcast.masked_regions.clear();
+        broadcast.redact_rects.clear();
+        broadcast.monitor_index = self.monitor_index;
+        if self.max_fps > 0 {
+            broadcast.fps = broadcast.fps.min(self.max_fps);
         }
+        *self.settings.write() = broadcast;
     }
 
-    pub async fn start(
-        &self,
-        sender: mpsc::UnboundedSender<StudentToTeacher>,
-        student_id: String,
-        student_name: String,
-    ) -> Result<()> {
+    /// Frames captured while spotlighted, for the connection's writer to send.
+    pub fn frames(&self) -> Arc<FrameQueue> {
+        self.frames.clone()
+    }
+
+    /// Capture at no more than `fps` (0 = no limit) while the teacher reports a slow uplink.
+    pub fn set_pacing(&self, fps: u32) {
+        if self.pacing_fps.swap(fps, Ordering::Relaxed) != fps {
+            info!(fps, "教师端调整屏幕分享帧率");
+        }
+    }
+
+    pub async fn start(&self, student_id: String, student_name: String) -> Result<()> {
         if self.running.swap(true, Ordering::SeqCst) {
             debug!("屏幕广播已在运行");
             return Ok(());
         }
+        self.pacing_fps.store(0, Ordering::Relaxed);
 
-        let running = self.running.clone();
+        let frames = self.frames.clone();
+        let pacing_fps = self.pacing_fps.clone();
+        let cancel = CancellationToken::new();
+        let stopping = cancel.clone();
         let frame_counter = self.frame_counter.clone();
-        let task_handle = tokio::spawn(async move {
-            if let Err(err) =
-                capture_loop(sender, running, frame_counter, student_id, student_name).await
-            {
-                error!(?err, "学生屏幕捕获失败");
+        let settings = self.settings.clone();
+        let clock = self.clock;
+        let monitor_index = self.monitor_index;
+        let consent = self.consent.clone();
+        let running = self.running.clone();
+        let task_handle = tokio::spawn(
+            async move {
+                if let Some(consent) = consent {
+                    tokio::select! {
+                        allowed = consent.ask(SHARE_CONSENT_COUNTDOWN) => if !allowed {
+                            warn!("学生拒绝共享屏幕");
+                            frames.decline();
+                            running.store(false, Ordering::SeqCst);
+                            return;
+                        },
+                        // The teacher withdrew the request; stop asking.
+                        _ = stopping.cancelled() => {
+                            consent.answer(false);
+                            return;
+                        }
+                    }
+                }
+                if let Err(err) = capture_loop(
+                    frames,
+                    pacing_fps,
+                    stopping,
+                    frame_counter,
+                    settings,
+                    clock,
+                    monitor_index,
+                    student_id,
+                    student_name,
+                )
+                .await
+                {
+                    error!(?err, "学生屏幕捕获失败");
+                }
             }
-        });
+            .in_current_span(),
+        );
 
-        *self.task.lock() = Some(task_handle);
+        *self.task.lock() = Some((task_handle, cancel));
         Ok(())
     }
 
+    /// Stop the spotlight capture once its current frame is done, ending the stream with
+    /// `ShareEnded` for teachers that understand it.
     pub async fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
-        if let Some(handle) = self.task.lock().take() {
+        let Some((mut handle, cancel)) = self.task.lock().take() else {
+            return;
+        };
+        cancel.cancel();
+        if time::timeout(STOP_TIMEOUT, &mut handle).await.is_err() {
+            warn!("屏幕捕获未能及时结束，强制停止");
+            handle.abort();
+        }
+    }
+
+    /// Send the teacher a low-res snapshot every `interval_secs`, independent of any
+    /// spotlight; 0 stops. A new interval replaces the previous one.
+    pub fn set_thumbnail_interval(
+        &self,
+        interval_secs: u64,
+        sender: mpsc::UnboundedSender<StudentToTeacher>,
+        student_id: String,
+        student_name: String,
+    ) {
+        self.stop_thumbnails();
+        if interval_secs == 0 {
+            return;
+        }
+        debug!(interval_secs, "开始定时发送屏幕缩略图");
+        let frame_counter = self.frame_counter.clone();
+        let settings = self.settings.clone();
+        let clock = self.clock;
+        let monitor_index = self.monitor_index;
+        let task = tokio::spawn(
+            async move {
+                if let Err(err) = thumbnail_loop(
+                    sender,
+                    frame_counter,
+                    settings,
+                    clock,
+                    monitor_index,
+                    Duration::from_secs(interval_secs),
+                    student_id,
+                    student_name,
+                )
+                .await
+                {
+                    error!(?err, "屏幕缩略图捕获失败");
+                }
+            }
+            .in_current_span(),
+        );
+        *self.thumbnail_task.lock() = Some(task);
+    }
+
+    pub fn stop_thumbnails(&self) {
+        if let Some(handle) = self.thumbnail_task.lock().take() {
             handle.abort();
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn capture_loop(
-    sender: mpsc::UnboundedSender<StudentToTeacher>,
-    running: Arc<AtomicBool>,
+    frames: Arc<FrameQueue>,
+    pacing_fps: Arc<AtomicU32>,
+    stopping: CancellationToken,
     counter: Arc<AtomicU64>,
+    settings: Arc<RwLock<BroadcastConfig>>,
+    clock: SessionClock,
+    monitor_index: Option<usize>,
     student_id: String,
     student_name: String,
 ) -> Result<()> {
-    let base_cfg = BroadcastConfig::default();
-    let interval = Duration::from_millis((1000.0 / base_cfg.fps as f64) as u64);
-    let primary = Screen::from_point(0, 0).context("无法访问主显示器")?;
-    let screen = Arc::new(primary);
-    let mut ticker = time::interval(interval.max(Duration::from_millis(16)));
+    let screen = Arc::new(ScreenSource::select(monitor_index)?);
+    let mut fps = settings.read().fps;
+    let mut ticker = time::interval(frame_interval(fps));
 
-    while running.load(Ordering::SeqCst) {
-        ticker.tick().await;
+    loop {
+        tokio::select! {
+            biased;
+            _ = stopping.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let cfg = settings.read().clone();
+        let target = match pacing_fps.load(Ordering::Relaxed) {
+            0 => cfg.fps,
+            paced => cfg.fps.min(paced),
+        };
+        if target != fps {
+            fps = target;
+            ticker = time::interval(frame_interval(fps));
+            debug!(fps, "屏幕捕获帧率已更新");
+        }
         let frame_id = counter.fetch_add(1, Ordering::Relaxed) + 1;
         let screen_clone = screen.clone();
         let student_id_clone = student_id.clone();
         let student_name_clone = student_name.clone();
-        let cfg = base_cfg.clone();
 
         let result = tokio::task::spawn_blocking(move || {
             capture_frame(
                 screen_clone,
                 frame_id,
+                clock,
                 &student_id_clone,
                 &student_name_clone,
                 &cfg,
@@ -93,12 +354,7 @@ async fn capture_loop(
         .await;
 
         match result {
-            Ok(Ok(frame)) => {
-                if sender.send(StudentToTeacher::Video(frame)).is_err() {
-                    warn!("发送屏幕帧失败，教师端可能已断开");
-                    break;
-                }
-            }
+            Ok(Ok(frame)) => frames.push(frame),
             Ok(Err(err)) => {
                 warn!(?err, "截屏失败");
             }
@@ -114,34 +370,88 @@ async fn capture_loop(
         }
     }
 
+    frames.finish();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn thumbnail_loop(
+    sender: mpsc::UnboundedSender<StudentToTeacher>,
+    counter: Arc<AtomicU64>,
+    settings: Arc<RwLock<BroadcastConfig>>,
+    clock: SessionClock,
+    monitor_index: Option<usize>,
+    interval: Duration,
+    student_id: String,
+    student_name: String,
+) -> Result<()> {
+    let screen = Arc::new(ScreenSource::select(monitor_index)?);
+    let mut ticker = time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let cfg = thumbnail_config(&settings.read());
+        let frame_id = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        let screen_clone = screen.clone();
+        let student_id_clone = student_id.clone();
+        let student_name_clone = student_name.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            capture_frame(
+                screen_clone,
+                frame_id,
+                clock,
+                &student_id_clone,
+                &student_name_clone,
+                &cfg,
+            )
+        })
+        .await?;
+
+        match result {
+            Ok(frame) => {
+                let thumbnail = VideoFrame {
+                    fullscreen: false,
+                    thumbnail: true,
+                    ..frame
+                };
+                if sender.send(StudentToTeacher::Video(thumbnail)).is_err() {
+                    warn!("发送屏幕缩略图失败，教师端可能已断开");
+                    break;
+                }
+            }
+            Err(err) => warn!(?err, "缩略图截屏失败"),
+        }
+    }
+
     Ok(())
 }
 
+/// Broadcast settings shrunk to thumbnail size and quality.
+fn thumbnail_config(broadcast: &BroadcastConfig) -> BroadcastConfig {
+    BroadcastConfig {
+        max_width: Some(THUMBNAIL_MAX_WIDTH),
+        max_height: Some(THUMBNAIL_MAX_HEIGHT),
+        jpeg_quality: THUMBNAIL_JPEG_QUALITY,
+        ..broadcast.clone()
+    }
+}
+
+fn frame_interval(fps: u32) -> Duration {
+    Duration::from_millis((1000.0 / fps.max(1) as f64) as u64).max(Duration::from_millis(16))
+}
+
 fn capture_frame(
-    screen: Arc<Screen>,
+    source: Arc<ScreenSource>,
     frame_id: u64,
+    clock: SessionClock,
     student_id: &str,
     student_name: &str,
     cfg: &BroadcastConfig,
 ) -> Result<VideoFrame> {
-    let image = screen.capture().context("执行截屏失败")?;
-    let width = image.width();
-    let height = image.height();
-    let raw = image.into_raw();
-
-    let mut rgb = Vec::with_capacity((width * height * 3) as usize);
-    for pixel in raw.chunks_exact(4) {
-        rgb.push(pixel[2]);
-        rgb.push(pixel[1]);
-        rgb.push(pixel[0]);
-    }
-
-    let mut jpeg = Vec::new();
-    {
-        let mut encoder =
-            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, cfg.jpeg_quality);
-        encoder.encode(&rgb, width as u32, height as u32, image::ColorType::Rgb8)?;
-    }
+    let capture_monotonic_ms = clock.elapsed_ms();
+    let raw = source.capture_with_cursor(cfg.show_cursor)?;
+    let (jpeg, width, height) = encode_jpeg(&raw, cfg)?;
 
     let timestamp_ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -151,6 +461,7 @@ fn capture_frame(
     Ok(VideoFrame {
         frame_id,
         timestamp_ms,
+        capture_monotonic_ms,
         source: BroadcastSource::Student {
             student_id: student_id.to_string(),
             student_name: Some(student_name.to_string()),
@@ -159,6 +470,62 @@ fn capture_frame(
         width,
         height,
         fullscreen: true,
-        data: jpeg,
+        thumbnail: false,
+        data: jpeg.into(),
+        still: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_id: u64) -> VideoFrame {
+        VideoFrame {
+            frame_id,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 1,
+            height: 1,
+            fullscreen: true,
+            thumbnail: false,
+            data: Default::default(),
+            still: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_refusal_is_recognisable_to_teachers_that_know_it() {
+        let queue = FrameQueue::new();
+        queue.set_teacher_version(PROTOCOL_VERSION);
+        queue.decline();
+        assert!(matches!(queue.pop().await, StudentToTeacher::ShareDeclined));
+
+        queue.set_teacher_version(REPLACE_PROTOCOL_VERSION);
+        queue.decline();
+        assert!(matches!(queue.pop().await, StudentToTeacher::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn slow_uplink_drops_the_oldest_frames_but_not_the_end_marker() {
+        let queue = FrameQueue::new();
+        queue.set_teacher_version(PROTOCOL_VERSION);
+        for frame_id in 1..=4 {
+            queue.push(frame(frame_id));
+        }
+        queue.finish();
+
+        let mut sent = Vec::new();
+        for _ in 0..3 {
+            sent.push(match queue.pop().await {
+                StudentToTeacher::Video(frame) => Some(frame.frame_id),
+                StudentToTeacher::ShareEnded => None,
+                other => panic!("unexpected {other:?}"),
+            });
+        }
+        assert_eq!(sent, [Some(3), Some(4), None]);
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,25 +1,111 @@
-use std::sync::mpsc::{self, Sender};
+use std::cell::OnceCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use image::imageops::{self, FilterType};
+use image::RgbImage;
 use minifb::{Scale, ScaleMode, Window, WindowOptions};
-use tracing::{debug, error, warn};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, info, warn};
 
+use shared::frame::ScreenSource;
 use shared::prelude::*;
 
+use crate::client::current_millis;
+use crate::input_lock::InputLock;
+
+#[cfg(feature = "winit-renderer")]
+mod strip;
+mod text;
+
+use text::NoticeFont;
+
+/// How the broadcast window behaves when the student switches away from it.
+pub struct WindowPolicy {
+    /// Where to report focus changes; `None` disables reporting.
+    pub focus_reports: Option<FocusReports>,
+    pub keep_on_top: bool,
+    /// Show frames 1:1 rather than letterboxed into the window.
+    pub native_size: bool,
+    /// Reopen the broadcast window on the next frame after the student closes it, instead of
+    /// leaving it closed until the next broadcast.
+    pub reopen_closed: bool,
+    /// Use the window with a status strip (`winit-renderer` feature) instead of minifb.
+    pub status_strip: bool,
+}
+
+/// Focus changes bound for the teacher, held back until it is known to understand them.
+pub struct FocusReports {
+    teacher: UnboundedSender<StudentToTeacher>,
+    supported: Arc<AtomicBool>,
+}
+
+impl FocusReports {
+    pub fn new(teacher: UnboundedSender<StudentToTeacher>) -> Self {
+        Self {
+            teacher,
+            supported: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
 pub struct VideoRenderer {
     sender: Sender<VideoCommand>,
+    keyframes_supported: Arc<AtomicBool>,
+    focus_supported: Option<Arc<AtomicBool>>,
 }
 
 impl VideoRenderer {
-    pub fn new() -> Self {
+    /// `teacher` receives the keyframe requests sent when a frame fails to decode.
+    pub fn new(policy: WindowPolicy, teacher: UnboundedSender<StudentToTeacher>) -> Self {
         let (tx, rx) = mpsc::channel::<VideoCommand>();
+        let keyframes_supported = Arc::new(AtomicBool::new(false));
+        let keyframes = KeyframeRequests {
+            teacher,
+            supported: keyframes_supported.clone(),
+            last_sent: None,
+        };
+        let focus_supported = policy
+            .focus_reports
+            .as_ref()
+            .map(|reports| reports.supported.clone());
         thread::Builder::new()
             .name("student-video-renderer".into())
-            .spawn(move || render_loop(rx))
+            .spawn(move || {
+                #[cfg(feature = "winit-renderer")]
+                if policy.status_strip {
+                    match strip::event_loop() {
+                        Ok(event_loop) => return strip::run(event_loop, rx, policy, keyframes),
+                        Err(err) => warn!(?err, "无法启动状态栏窗口，改用默认窗口"),
+                    }
+                }
+                #[cfg(not(feature = "winit-renderer"))]
+                if policy.status_strip {
+                    warn!("配置了 status_strip，但此构建未启用 `winit-renderer` Feature，改用默认窗口");
+                }
+                render_loop(rx, policy, keyframes)
+            })
             .expect("Failed to spawn video renderer thread");
 
-        Self { sender: tx }
+        Self {
+            sender: tx,
+            keyframes_supported,
+            focus_supported,
+        }
+    }
+
+    /// Enable the keyframe requests and focus reports the connected teacher understands.
+    pub fn set_teacher_version(&self, version: ProtocolVersion) {
+        self.keyframes_supported
+            .store(version >= KEYFRAME_PROTOCOL_VERSION, Ordering::Relaxed);
+        if let Some(supported) = &self.focus_supported {
+            supported.store(version >= FOCUS_PROTOCOL_VERSION, Ordering::Relaxed);
+        }
     }
 
     pub fn display_frame(&self, frame: VideoFrame, mode: BroadcastMode) {
@@ -28,11 +114,41 @@ impl VideoRenderer {
         }
     }
 
+    /// A new broadcast began; a window the student closed during the last one opens again.
+    /// While `forced`, closing the window only brings it back.
+    pub fn start(&self, forced: bool) {
+        if let Err(err) = self.sender.send(VideoCommand::Start { forced }) {
+            warn!(?err, "开始视频渲染失败");
+        }
+    }
+
     pub fn stop(&self) {
         if let Err(err) = self.sender.send(VideoCommand::Stop) {
             warn!(?err, "停止视频渲染失败");
         }
     }
+
+    /// Queue a notice banner; it is shown once the notices before it have expired.
+    pub fn show_notice(&self, notice: Notice) {
+        if let Err(err) = self.sender.send(VideoCommand::Notice(notice)) {
+            warn!(?err, "显示教师通知失败");
+        }
+    }
+
+    /// Swallow task-switching keys while the broadcast window is active, until called
+    /// with `false`.
+    pub fn set_input_lock(&self, locked: bool) {
+        if let Err(err) = self.sender.send(VideoCommand::InputLock(locked)) {
+            warn!(?err, "切换输入锁定失败");
+        }
+    }
+
+    /// Show a black screen instead of broadcast frames until called with `false`.
+    pub fn set_blackout(&self, enabled: bool) {
+        if let Err(err) = self.sender.send(VideoCommand::Blackout(enabled)) {
+            warn!(?err, "切换黑屏状态失败");
+        }
+    }
 }
 
 impl Drop for VideoRenderer {
@@ -46,76 +162,683 @@ enum VideoCommand {
         frame: VideoFrame,
         mode: BroadcastMode,
     },
+    /// `forced` when the teacher forces the fullscreen window, which the student cannot close.
+    Start {
+        forced: bool,
+    },
     Stop,
+    Blackout(bool),
+    InputLock(bool),
+    Notice(Notice),
     Exit,
 }
 
-fn render_loop(receiver: mpsc::Receiver<VideoCommand>) {
+const WINDOW_TITLE: &str = "课堂广播";
+/// Size of the broadcast window outside fullscreen, and of a blackout before any frame set
+/// one.
+const WINDOW_SIZE: (usize, usize) = (1280, 720);
+/// Size of the standalone notice window shown when no broadcast is on screen.
+const NOTICE_WINDOW_SIZE: (usize, usize) = (640, 48);
+/// Largest frame width or height accepted, so a malformed frame cannot force a huge buffer.
+const MAX_FRAME_DIMENSION: usize = 8192;
+/// How often the standalone notice window is pumped while nothing else arrives.
+const NOTICE_REFRESH: Duration = Duration::from_millis(100);
+/// Color of notice text drawn over the banner.
+const NOTICE_TEXT_COLOR: u32 = 0xff_ff_ff;
+/// How often focus is checked while no frame arrives to check it on.
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Desktop area a fullscreen broadcast window covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DisplayArea {
+    x: isize,
+    y: isize,
+    width: usize,
+    height: usize,
+}
+
+impl DisplayArea {
+    fn primary() -> Option<Self> {
+        match ScreenSource::primary() {
+            Ok(screen) => {
+                let (x, y, width, height) = screen.bounds();
+                Some(Self {
+                    x: x as isize,
+                    y: y as isize,
+                    width: width as usize,
+                    height: height as usize,
+                })
+            }
+            Err(err) => {
+                warn!(?err, "无法获取主显示器尺寸，全屏广播将按画面尺寸显示");
+                None
+            }
+        }
+    }
+}
+
+/// Client size and desktop position of the broadcast window for a `width` x `height`
+/// frame: the whole display in fullscreen mode, otherwise [`WINDOW_SIZE`], or the frame
+/// size when frames are shown at `native_size`.
+fn window_geometry(
+    width: usize,
+    height: usize,
+    mode: BroadcastMode,
+    display: Option<DisplayArea>,
+    native_size: bool,
+) -> ((usize, usize), Option<(isize, isize)>) {
+    match (mode, display) {
+        (BroadcastMode::Fullscreen, Some(area)) => {
+            ((area.width, area.height), Some((area.x, area.y)))
+        }
+        _ if native_size => ((width, height), None),
+        _ => (WINDOW_SIZE, None),
+    }
+}
+
+/// Scale an XRGB frame to fit `target` without distorting it, centered between black bars.
+fn letterbox(
+    buffer: &[u32],
+    width: usize,
+    height: usize,
+    (target_width, target_height): (usize, usize),
+) -> (Vec<u32>, usize, usize) {
+    if (width, height) == (target_width, target_height) || width == 0 || height == 0 {
+        return (buffer.to_vec(), width, height);
+    }
+    let scale = f64::min(
+        target_width as f64 / width as f64,
+        target_height as f64 / height as f64,
+    );
+    let scaled_width = ((width as f64 * scale).round() as u32).clamp(1, target_width as u32);
+    let scaled_height = ((height as f64 * scale).round() as u32).clamp(1, target_height as u32);
+
+    let source = RgbImage::from_fn(width as u32, height as u32, |x, y| {
+        let [_, r, g, b] = buffer[y as usize * width + x as usize].to_be_bytes();
+        image::Rgb([r, g, b])
+    });
+    let scaled = imageops::resize(&source, scaled_width, scaled_height, FilterType::Triangle);
+    let mut canvas = RgbImage::new(target_width as u32, target_height as u32);
+    imageops::replace(
+        &mut canvas,
+        &scaled,
+        i64::from((target_width as u32 - scaled_width) / 2),
+        i64::from((target_height as u32 - scaled_height) / 2),
+    );
+    rgb_to_u32(&canvas)
+}
+
+/// Least time between two keyframe requests, so a run of bad frames asks only once.
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Asks the teacher to resend the latest frame after one failed to decode.
+struct KeyframeRequests {
+    teacher: UnboundedSender<StudentToTeacher>,
+    supported: Arc<AtomicBool>,
+    last_sent: Option<Instant>,
+}
+
+impl KeyframeRequests {
+    fn request(&mut self, now: Instant) {
+        if !self.supported.load(Ordering::Relaxed)
+            || self
+                .last_sent
+                .is_some_and(|last| now.duration_since(last) < KEYFRAME_REQUEST_INTERVAL)
+        {
+            return;
+        }
+        self.last_sent = Some(now);
+        debug!("请求教师端重发最新画面");
+        let _ = self.teacher.send(StudentToTeacher::RequestKeyframe);
+    }
+}
+
+/// How often skipped frames are logged while the renderer falls behind.
+const SKIPPED_FRAMES_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Frames dropped because a newer one was already waiting, logged in batches.
+struct SkippedFrames {
+    count: u64,
+    since: Instant,
+}
+
+impl SkippedFrames {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, skipped: u64, now: Instant) {
+        self.count += skipped;
+        if self.count > 0 && now.duration_since(self.since) >= SKIPPED_FRAMES_LOG_INTERVAL {
+            warn!(skipped = self.count, "解码跟不上广播帧率，已跳过过时的画面");
+            self.count = 0;
+            self.since = now;
+        }
+    }
+}
+
+/// Drop every queued frame except the newest and the newest still, keeping the other
+/// commands in order, so a screenshot the teacher sent is shown at least once. Returns how
+/// many frames were dropped.
+fn drop_superseded_frames(commands: &mut VecDeque<VideoCommand>) -> u64 {
+    let Some(newest) = commands
+        .iter()
+        .rposition(|command| matches!(command, VideoCommand::Frame { .. }))
+    else {
+        return 0;
+    };
+    let newest_still = commands
+        .iter()
+        .rposition(|command| matches!(command, VideoCommand::Frame { frame, .. } if frame.still));
+    let before = commands.len();
+    let mut index = 0;
+    commands.retain(|command| {
+        index += 1;
+        index - 1 == newest
+            || Some(index - 1) == newest_still
+            || !matches!(command, VideoCommand::Frame { .. })
+    });
+    (before - commands.len()) as u64
+}
+
+/// Notices waiting to be shown, one at a time, each for its own duration.
+#[derive(Default)]
+struct NoticeQueue {
+    pending: VecDeque<Notice>,
+    current: Option<(Notice, Instant)>,
+}
+
+impl NoticeQueue {
+    fn push(&mut self, notice: Notice, now: Instant) {
+        self.pending.push_back(notice);
+        self.advance(now);
+    }
+
+    fn current(&self) -> Option<&Notice> {
+        self.current.as_ref().map(|(notice, _)| notice)
+    }
+
+    /// When the current notice expires.
+    fn deadline(&self) -> Option<Instant> {
+        self.current.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    /// Expire the current notice if its time is up and start the next one. Returns whether
+    /// the notice on screen changed.
+    fn advance(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        while self.deadline().is_none_or(|deadline| deadline <= now) {
+            changed |= self.current.take().is_some();
+            let Some(next) = self.pending.pop_front() else {
+                break;
+            };
+            let deadline = now + Duration::from_millis(next.duration_ms);
+            self.current = Some((next, deadline));
+            changed = true;
+        }
+        changed
+    }
+}
+
+fn render_loop(
+    receiver: mpsc::Receiver<VideoCommand>,
+    policy: WindowPolicy,
+    mut keyframes: KeyframeRequests,
+) {
     let mut window: Option<Window> = None;
     let mut current_mode = BroadcastMode::Window;
+    let display = DisplayArea::primary();
+    let mut focused: Option<bool> = None;
+    let mut blackout = false;
+    // Whether the window shows a running broadcast, as opposed to only the blackout.
+    let mut broadcasting = false;
+    let mut size = WINDOW_SIZE;
+    let mut notices = NoticeQueue::default();
+    // Shows the current notice while there is no broadcast window to put it on.
+    let mut notice_window: Option<Window> = None;
+    // What the broadcast window shows under the notice, to draw a new notice over.
+    let mut shown: Shown = None;
+    let font = LazyFont::new();
+    let mut input_lock: Option<InputLock> = None;
+    // Set when the student closes the broadcast window; frames are dropped until the next
+    // broadcast.
+    let mut closed_by_user = false;
+    // Whether the teacher forces the fullscreen window, so a close is not honoured.
+    let mut forced = false;
+    // Commands already taken off the channel while looking for a newer frame.
+    let mut backlog: VecDeque<VideoCommand> = VecDeque::new();
+    let mut skipped = SkippedFrames::new();
+
+    loop {
+        let command = match backlog.pop_front() {
+            Some(command) => Some(command),
+            None => {
+                let notice_wait = notices.deadline().map(|deadline| {
+                    deadline
+                        .saturating_duration_since(Instant::now())
+                        .min(NOTICE_REFRESH)
+                });
+                // A static screen sends no frames, so focus is also checked on a timer.
+                let focus_wait = (window.is_some()
+                    && (policy.focus_reports.is_some() || policy.keep_on_top))
+                    .then_some(FOCUS_POLL_INTERVAL);
+                match notice_wait.into_iter().chain(focus_wait).min() {
+                    Some(wait) => match receiver.recv_timeout(wait) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    None => match receiver.recv() {
+                        Ok(command) => Some(command),
+                        Err(_) => break,
+                    },
+                }
+            }
+        };
+        // Decoding is the slow part: when frames queue up behind it, show only the newest.
+        let command = match command {
+            Some(frame @ VideoCommand::Frame { .. }) => {
+                backlog.push_front(frame);
+                backlog.extend(receiver.try_iter());
+                skipped.add(drop_superseded_frames(&mut backlog), Instant::now());
+                backlog.pop_front()
+            }
+            other => other,
+        };
+
+        if notices.advance(Instant::now()) {
+            show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+        }
+
+        let Some(command) = command else {
+            if let Some(win) = notice_window.as_mut() {
+                win.update();
+            }
+            if let Some(win) = window.as_mut().filter(|win| win.is_open()) {
+                win.update();
+                track_focus(win, &mut focused, &policy);
+            }
+            continue;
+        };
 
-    for command in receiver {
         match command {
+            // Frames keep arriving during a blackout; repainting black keeps the window alive.
+            VideoCommand::Frame { .. } if blackout => {
+                broadcasting = true;
+                paint_black(
+                    &mut window,
+                    size,
+                    current_mode,
+                    display,
+                    &policy,
+                    &mut shown,
+                );
+                present(&mut window, &shown, notices.current(), &font);
+            }
+            // A still was sent on purpose, so it opens the window even after the student
+            // closed it; the broadcast frames after it stay hidden.
+            VideoCommand::Frame { frame, .. } if closed_by_user && !frame.still => {}
+            VideoCommand::Frame { frame, .. }
+                if !frame.still
+                    && !policy.reopen_closed
+                    && !forced
+                    && window.as_ref().is_some_and(|win| !win.is_open()) =>
+            {
+                info!("广播窗口已被关闭，本次广播不再显示");
+                closed_by_user = true;
+                window = None;
+                focused = None;
+                show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+            }
             VideoCommand::Frame { frame, mode } => match decode_frame(&frame) {
                 Ok((buffer, width, height)) => {
-                    ensure_window(&mut window, width, height, mode);
+                    if frame.still {
+                        info!(width, height, "显示教师发送的截图");
+                    }
+                    broadcasting = true;
+                    let (buffer, width, height) = if policy.native_size {
+                        (buffer, width, height)
+                    } else {
+                        let (target, _) =
+                            window_geometry(width, height, mode, display, policy.native_size);
+                        letterbox(&buffer, width, height, target)
+                    };
+                    size = (width, height);
+                    shown = Some((buffer, width, height));
+                    if current_mode != mode {
+                        // minifb cannot restyle a window, so switching modes means a new one;
+                        // the frame in hand is painted into it right away.
+                        info!(?mode, "切换广播显示模式");
+                        window = None;
+                        current_mode = mode;
+                    }
+                    ensure_window(&mut window, width, height, mode, display, &policy);
                     if let Some(win) = window.as_mut() {
                         if !win.is_open() {
+                            if forced {
+                                info!("教师端要求全屏广播，重新打开被关闭的窗口");
+                            }
                             debug!("视频窗口已关闭，重新创建");
-                            window = create_window(width, height, mode).ok();
+                            window = None;
+                            ensure_window(&mut window, width, height, mode, display, &policy);
                         }
 
                         if let Some(win) = window.as_mut() {
-                            if current_mode != mode {
-                                apply_mode(win, mode);
-                                current_mode = mode;
-                            }
-
-                            if let Err(err) = win.update_with_buffer(&buffer, width, height) {
-                                error!(?err, "刷新视频窗口失败");
-                                window = None;
+                            if let Some(notice) = notices.current() {
+                                // The window may have just been recreated with the plain title.
+                                win.set_title(&window_title(Some(notice)));
                             }
                         }
                     }
+                    present(&mut window, &shown, notices.current(), &font);
+                    if let Some(win) = window.as_mut() {
+                        track_focus(win, &mut focused, &policy);
+                    }
+                    if window.is_some() && notice_window.is_some() {
+                        // The broadcast window carries the notice from now on.
+                        notice_window = None;
+                        show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+                    }
                 }
                 Err(err) => {
                     error!(?err, "解码视频帧失败");
+                    keyframes.request(Instant::now());
                 }
             },
+            VideoCommand::Start { forced: now_forced } => {
+                closed_by_user = false;
+                forced = now_forced;
+            }
             VideoCommand::Stop => {
-                if let Some(win) = window.take() {
-                    drop(win);
+                broadcasting = false;
+                closed_by_user = false;
+                forced = false;
+                // A blackout outlasts the broadcast it interrupted.
+                if !blackout {
+                    if let Some(win) = window.take() {
+                        drop(win);
+                    }
+                    focused = None;
+                    show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
                 }
             }
+            VideoCommand::Blackout(true) => {
+                info!("教师端开启黑屏");
+                blackout = true;
+                paint_black(
+                    &mut window,
+                    size,
+                    current_mode,
+                    display,
+                    &policy,
+                    &mut shown,
+                );
+                notice_window = None;
+                show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+            }
+            VideoCommand::Blackout(false) => {
+                info!("教师端结束黑屏");
+                blackout = false;
+                // The next broadcast frame replaces the black screen; without one, close it.
+                if !broadcasting {
+                    window = None;
+                    focused = None;
+                    show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+                }
+            }
+            VideoCommand::InputLock(true) if input_lock.is_none() => match InputLock::engage() {
+                Ok(lock) => {
+                    info!("已锁定切换窗口快捷键");
+                    input_lock = Some(lock);
+                }
+                Err(err) => warn!(?err, "无法锁定输入"),
+            },
+            VideoCommand::InputLock(true) => {}
+            VideoCommand::InputLock(false) => {
+                if input_lock.take().is_some() {
+                    info!("已解除输入锁定");
+                }
+            }
+            VideoCommand::Notice(notice) => {
+                info!(text = %notice.text, severity = ?notice.severity, "收到教师通知");
+                notices.push(notice, Instant::now());
+                show_notice(&notices, &mut window, &mut notice_window, &shown, &font);
+            }
             VideoCommand::Exit => break,
         }
+
+        // The window may have been recreated, or closed, by the command above.
+        if let Some(lock) = &input_lock {
+            lock.follow(window.as_ref());
+        }
     }
 }
 
-fn ensure_window(window: &mut Option<Window>, width: usize, height: usize, mode: BroadcastMode) {
-    if let Some(existing) = window {
-        if existing.get_size().0 != width || existing.get_size().1 != height {
-            *window = create_window(width, height, mode).ok();
+/// Pixels the broadcast window shows before a notice is drawn over them.
+type Shown = Option<(Vec<u32>, usize, usize)>;
+
+/// Notice font, looked up the first time a notice is drawn.
+type LazyFont = OnceCell<Option<NoticeFont>>;
+
+/// Draw the current notice over the broadcast window, or show it in a small standalone
+/// window when no broadcast is on screen. The text also goes in the window title, where it
+/// stays readable when no CJK font is installed.
+fn show_notice(
+    notices: &NoticeQueue,
+    window: &mut Option<Window>,
+    notice_window: &mut Option<Window>,
+    shown: &Shown,
+    font: &LazyFont,
+) {
+    let title = window_title(notices.current());
+    if let Some(win) = window.as_mut() {
+        win.set_title(&title);
+        *notice_window = None;
+        present(window, shown, notices.current(), font);
+        return;
+    }
+    let Some(notice) = notices.current() else {
+        *notice_window = None;
+        return;
+    };
+
+    let (width, height) = NOTICE_WINDOW_SIZE;
+    if notice_window.is_none() {
+        let options = WindowOptions {
+            topmost: true,
+            ..WindowOptions::default()
+        };
+        *notice_window = Window::new(&title, width, height, options)
+            .map_err(|err| warn!(?err, "无法创建通知窗口"))
+            .ok();
+    }
+    if let Some(win) = notice_window.as_mut() {
+        win.set_title(&title);
+        let mut buffer = vec![0; width * height];
+        draw_notice(&mut buffer, width, height, notice, font);
+        if let Err(err) = win.update_with_buffer(&buffer, width, height) {
+            warn!(?err, "刷新通知窗口失败");
+            *notice_window = None;
+        }
+    }
+}
+
+/// Show `shown` in the broadcast window with `notice` drawn over it, dropping the window if
+/// that fails.
+fn present(window: &mut Option<Window>, shown: &Shown, notice: Option<&Notice>, font: &LazyFont) {
+    let (Some(win), Some((buffer, width, height))) = (window.as_mut(), shown) else {
+        return;
+    };
+    let result = match notice {
+        Some(notice) => {
+            let mut canvas = buffer.clone();
+            draw_notice(&mut canvas, *width, *height, notice, font);
+            win.update_with_buffer(&canvas, *width, *height)
         }
-    } else {
-        *window = create_window(width, height, mode).ok();
+        None => win.update_with_buffer(buffer, *width, *height),
+    };
+    if let Err(err) = result {
+        error!(?err, "刷新视频窗口失败");
+        *window = None;
+    }
+}
+
+fn window_title(notice: Option<&Notice>) -> String {
+    match notice {
+        Some(notice) => format!("{WINDOW_TITLE} - {}", notice.text),
+        None => WINDOW_TITLE.to_string(),
+    }
+}
+
+fn notice_color(severity: NoticeSeverity) -> u32 {
+    match severity {
+        NoticeSeverity::Info => 0x1e_6f_d9,
+        NoticeSeverity::Warning => 0xe0_a0_00,
+        NoticeSeverity::Urgent => 0xd0_2b_2b,
     }
 }
 
-fn create_window(width: usize, height: usize, mode: BroadcastMode) -> Result<Window> {
-    let mut options = WindowOptions::default();
-    options.resize = false;
-    options.scale = Scale::X1;
-    options.scale_mode = ScaleMode::AspectRatioStretch;
+/// Paint the banner across the top of an XRGB buffer in the color of `severity`, returning
+/// its height.
+fn draw_notice_bar(
+    buffer: &mut [u32],
+    width: usize,
+    height: usize,
+    severity: NoticeSeverity,
+) -> usize {
+    let bar_height = (height / 12).max(24).min(height);
+    buffer[..width * bar_height].fill(notice_color(severity));
+    bar_height
+}
+
+/// Paint the banner of `notice` with its text on it, or the bare banner when no font that
+/// can draw the text is installed.
+fn draw_notice(buffer: &mut [u32], width: usize, height: usize, notice: &Notice, font: &LazyFont) {
+    let bar_height = draw_notice_bar(buffer, width, height, notice.severity);
+    if let Some(font) = font.get_or_init(NoticeFont::system) {
+        let size = bar_height as f32 * 0.6;
+        let top = (bar_height as f32 * 0.2) as usize;
+        font.draw(
+            buffer,
+            (width, bar_height),
+            (bar_height / 2, top),
+            size,
+            NOTICE_TEXT_COLOR,
+            &notice.text,
+        );
+    }
+}
 
-    let mut window = Window::new("课堂广播", width, height, options)?;
+/// Make sure the window fits a black screen of `size` and put one in `shown`; present it
+/// to paint the window.
+fn paint_black(
+    window: &mut Option<Window>,
+    (width, height): (usize, usize),
+    mode: BroadcastMode,
+    display: Option<DisplayArea>,
+    policy: &WindowPolicy,
+    shown: &mut Shown,
+) {
+    ensure_window(window, width, height, mode, display, policy);
+    *shown = Some((vec![0; width * height], width, height));
+}
+
+/// Make sure there is a window fit for a `width` x `height` frame in `mode`, recreating it
+/// when its size no longer matches.
+fn ensure_window(
+    window: &mut Option<Window>,
+    width: usize,
+    height: usize,
+    mode: BroadcastMode,
+    display: Option<DisplayArea>,
+    policy: &WindowPolicy,
+) {
+    let (size, position) = window_geometry(width, height, mode, display, policy.native_size);
+    if window
+        .as_ref()
+        .is_some_and(|existing| existing.get_size() == size)
+    {
+        return;
+    }
+    *window = create_window(size, position, mode, policy)
+        .map_err(|err| error!(?err, "无法创建视频窗口"))
+        .ok();
+}
+
+/// Fullscreen windows are borderless and cover the display; the frame is centered and
+/// letterboxed in either mode.
+fn create_window(
+    (width, height): (usize, usize),
+    position: Option<(isize, isize)>,
+    mode: BroadcastMode,
+    policy: &WindowPolicy,
+) -> Result<Window> {
+    let fullscreen = mode == BroadcastMode::Fullscreen;
+    let options = WindowOptions {
+        borderless: fullscreen,
+        title: !fullscreen,
+        resize: false,
+        scale: Scale::X1,
+        // Frames already arrive fitted to the window unless shown at native size, which
+        // keeps them 1:1 in the middle of a fullscreen window.
+        scale_mode: if policy.native_size {
+            ScaleMode::Center
+        } else {
+            ScaleMode::AspectRatioStretch
+        },
+        topmost: policy.keep_on_top || fullscreen,
+        ..WindowOptions::default()
+    };
+
+    let mut window = Window::new(WINDOW_TITLE, width, height, options)?;
     window.limit_update_rate(None);
-    apply_mode(&mut window, mode);
+    if let Some((x, y)) = position {
+        window.set_position(x, y);
+    }
     Ok(window)
 }
 
-fn apply_mode(_window: &mut Window, _mode: BroadcastMode) {
-    // minifb 0.24 不支持在运行时切换全屏，这里仅保留占位逻辑。
+/// Detect focus transitions after each update or focus poll, reporting them and re-raising the window
+/// as the policy asks.
+fn track_focus(window: &mut Window, focused: &mut Option<bool>, policy: &WindowPolicy) {
+    let active = window.is_active();
+    if *focused == Some(active) {
+        return;
+    }
+    let first_check = focused.is_none();
+    *focused = Some(active);
+
+    if !active && policy.keep_on_top {
+        window.topmost(true);
+    }
+    // Only report transitions, not the initial state of a freshly opened window.
+    if !first_check {
+        report_focus(policy, active);
+    }
+}
+
+/// Tell the teacher the broadcast window gained or lost focus, if the policy asks for it and
+/// the teacher understands `FocusState`.
+fn report_focus(policy: &WindowPolicy, active: bool) {
+    if let Some(reports) = &policy.focus_reports {
+        if active {
+            info!("广播窗口重新获得焦点");
+        } else {
+            warn!("广播窗口失去焦点");
+        }
+        if !reports.supported.load(Ordering::Relaxed) {
+            return;
+        }
+        let _ = reports
+            .teacher
+            .send(StudentToTeacher::FocusState(FocusState {
+                focused: active,
+                timestamp_ms: current_millis(),
+            }));
+    }
 }
 
 fn decode_frame(frame: &VideoFrame) -> Result<(Vec<u32>, usize, usize)> {
@@ -128,6 +851,16 @@ fn decode_frame(frame: &VideoFrame) -> Result<(Vec<u32>, usize, usize)> {
         VideoCodec::Bgra => {
             let width = frame.width as usize;
             let height = frame.height as usize;
+            if width > MAX_FRAME_DIMENSION || height > MAX_FRAME_DIMENSION {
+                bail!("BGRA 帧尺寸 {width}x{height} 超出上限 {MAX_FRAME_DIMENSION}");
+            }
+            if frame.data.len() != width * height * 4 {
+                bail!(
+                    "BGRA 帧数据长度 {} 与尺寸 {width}x{height} 不符 (应为 {})",
+                    frame.data.len(),
+                    width * height * 4
+                );
+            }
             let mut buffer = Vec::with_capacity(width * height);
             for pixel in frame.data.chunks_exact(4) {
                 let r = pixel[2] as u32;
@@ -152,3 +885,323 @@ fn rgb_to_u32(image: &image::RgbImage) -> (Vec<u32>, usize, usize) {
     }
     (buffer, width, height)
 }
+
+#[cfg(test)]
+mod tests {
+    use shared::frame::{encode_jpeg, FrameSource, TestPatternSource};
+
+    use super::*;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 48;
+
+    fn frame(codec: VideoCodec, width: u32, height: u32, data: Vec<u8>) -> VideoFrame {
+        VideoFrame {
+            frame_id: 1,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec,
+            width,
+            height,
+            fullscreen: false,
+            thumbnail: false,
+            data: data.into(),
+            still: false,
+        }
+    }
+
+    fn notice(text: &str, duration_ms: u64) -> Notice {
+        Notice {
+            text: text.into(),
+            duration_ms,
+            severity: NoticeSeverity::Info,
+        }
+    }
+
+    #[test]
+    fn notices_queue_instead_of_replacing_each_other() {
+        let start = Instant::now();
+        let mut queue = NoticeQueue::default();
+        queue.push(notice("first", 1000), start);
+        queue.push(notice("second", 500), start);
+        assert_eq!(queue.current().unwrap().text, "first");
+
+        assert!(!queue.advance(start + Duration::from_millis(999)));
+        assert!(queue.advance(start + Duration::from_millis(1000)));
+        assert_eq!(queue.current().unwrap().text, "second");
+
+        assert!(queue.advance(start + Duration::from_millis(1500)));
+        assert!(queue.current().is_none());
+        assert!(queue.deadline().is_none());
+    }
+
+    #[test]
+    fn notice_bar_covers_only_the_top_rows() {
+        let (width, height) = (10, 48);
+        let mut buffer = vec![0; width * height];
+        draw_notice_bar(&mut buffer, width, height, NoticeSeverity::Urgent);
+        assert!(buffer[..width * 24].iter().all(|&pixel| pixel != 0));
+        assert!(buffer[width * 24..].iter().all(|&pixel| pixel == 0));
+    }
+
+    fn unpack(pixel: u32) -> [u8; 3] {
+        [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]
+    }
+
+    #[test]
+    fn jpeg_round_trip_stays_close_to_source() {
+        let source = TestPatternSource::new(WIDTH, HEIGHT);
+        let raw = source.capture().unwrap();
+        let cfg = BroadcastConfig {
+            jpeg_quality: 95,
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        assert_eq!((width, height), (WIDTH, HEIGHT));
+
+        let (buffer, width, height) =
+            decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        assert_eq!((width, height), (WIDTH as usize, HEIGHT as usize));
+        assert_eq!(buffer.len(), width * height);
+
+        let mut total_error = 0u64;
+        for (index, pixel) in buffer.iter().enumerate() {
+            let x = (index % width) as u32;
+            let y = (index / width) as u32;
+            let expected = source.pixel(x, y);
+            let actual = unpack(*pixel);
+            for channel in 0..3 {
+                let diff = expected[channel].abs_diff(actual[channel]);
+                assert!(
+                    diff <= 48,
+                    "pixel ({x},{y}) channel {channel} off by {diff}"
+                );
+                total_error += diff as u64;
+            }
+        }
+        let mean_error = total_error as f64 / (buffer.len() * 3) as f64;
+        assert!(mean_error < 4.0, "mean error {mean_error}");
+    }
+
+    #[test]
+    fn jpeg_round_trip_honours_downscale() {
+        let raw = TestPatternSource::new(WIDTH, HEIGHT).capture().unwrap();
+        let cfg = BroadcastConfig {
+            max_width: Some(32),
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        assert_eq!((width, height), (32, 24));
+
+        let (buffer, width, height) =
+            decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        assert_eq!((width, height), (32, 24));
+        assert_eq!(buffer.len(), 32 * 24);
+    }
+
+    #[test]
+    fn capture_region_is_zoomed_to_full_size() {
+        let raw = TestPatternSource::new(WIDTH, HEIGHT).capture().unwrap();
+        let cfg = BroadcastConfig {
+            capture_region: Some(Region {
+                x: 0.5,
+                y: 0.5,
+                width: 0.5,
+                height: 0.5,
+            }),
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        assert_eq!((width, height), (WIDTH, HEIGHT));
+
+        let (buffer, _, _) = decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        // The bottom-right quarter has full red and green at its far corner.
+        let [r, g, _] = unpack(buffer[buffer.len() - 1]);
+        assert!(r > 200 && g > 200, "corner was ({r}, {g})");
+        let [r, g, _] = unpack(buffer[0]);
+        assert!(r > 100 && g > 100, "origin was ({r}, {g})");
+    }
+
+    #[test]
+    fn masked_regions_are_blacked_out() {
+        let raw = TestPatternSource::new(WIDTH, HEIGHT).capture().unwrap();
+        let mask = Region {
+            x: 0.5,
+            y: 0.5,
+            width: 0.5,
+            height: 0.5,
+        };
+        let cfg = BroadcastConfig {
+            masked_regions: vec![mask],
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        let (buffer, _, _) = decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        let at = |x: u32, y: u32| unpack(buffer[(y * WIDTH + x) as usize]);
+
+        for (x, y) in [(40, 30), (WIDTH - 1, HEIGHT - 1), (48, 40)] {
+            let pixel = at(x, y);
+            assert!(pixel.iter().all(|&c| c < 16), "({x}, {y}) was {pixel:?}");
+        }
+        // Just outside the mask the pattern is untouched.
+        let [r, _, _] = at(WIDTH - 1, 8);
+        assert!(r > 200, "unmasked corner was red {r}");
+
+        // When zoomed, the mask follows the screen content into the scaled-up view.
+        let cfg = BroadcastConfig {
+            capture_region: Some(Region {
+                x: 0.25,
+                y: 0.25,
+                width: 0.5,
+                height: 0.5,
+            }),
+            ..cfg
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        let (buffer, _, _) = decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        let pixel = unpack(buffer[buffer.len() - 1]);
+        assert!(pixel.iter().all(|&c| c < 16), "zoomed corner was {pixel:?}");
+        let [_, g, _] = unpack(buffer[0]);
+        assert!(g > 32, "zoomed origin was green {g}");
+    }
+
+    #[test]
+    fn capture_rect_is_sent_at_its_own_size_and_zoomed_within() {
+        let raw = TestPatternSource::new(WIDTH, HEIGHT).capture().unwrap();
+        let cfg = BroadcastConfig {
+            capture_rect: Some(Rect {
+                x: WIDTH / 2,
+                y: HEIGHT / 2,
+                width: WIDTH,
+                height: HEIGHT,
+            }),
+            ..BroadcastConfig::default()
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        assert_eq!((width, height), (WIDTH / 2, HEIGHT / 2));
+        let (buffer, _, _) = decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        let [r, g, _] = unpack(buffer[buffer.len() - 1]);
+        assert!(r > 200 && g > 200, "corner was ({r}, {g})");
+
+        // A zoom is relative to the cropped view and scaled back up to its size.
+        let cfg = BroadcastConfig {
+            capture_region: Some(Region {
+                x: 0.0,
+                y: 0.0,
+                width: 0.5,
+                height: 0.5,
+            }),
+            ..cfg
+        };
+        let (jpeg, width, height) = encode_jpeg(&raw, &cfg).unwrap();
+        assert_eq!((width, height), (WIDTH / 2, HEIGHT / 2));
+        let (buffer, _, _) = decode_frame(&frame(VideoCodec::Jpeg, width, height, jpeg)).unwrap();
+        let [r, g, _] = unpack(buffer[0]);
+        assert!(
+            (100..180).contains(&r) && (100..180).contains(&g),
+            "origin was ({r}, {g})"
+        );
+    }
+
+    #[test]
+    fn only_the_newest_queued_frame_survives() {
+        let show = |frame_id| VideoCommand::Frame {
+            frame: VideoFrame {
+                frame_id,
+                ..frame(VideoCodec::Bgra, 0, 0, Vec::new())
+            },
+            mode: BroadcastMode::Window,
+        };
+        let mut commands = VecDeque::from([
+            show(1),
+            VideoCommand::Blackout(true),
+            show(2),
+            VideoCommand::Stop,
+            show(3),
+            VideoCommand::Blackout(false),
+        ]);
+        assert_eq!(drop_superseded_frames(&mut commands), 2);
+        assert!(matches!(commands[0], VideoCommand::Blackout(true)));
+        assert!(matches!(commands[1], VideoCommand::Stop));
+        assert!(matches!(&commands[2], VideoCommand::Frame { frame, .. } if frame.frame_id == 3));
+        assert!(matches!(commands[3], VideoCommand::Blackout(false)));
+
+        let still = VideoCommand::Frame {
+            frame: VideoFrame {
+                frame_id: 4,
+                still: true,
+                ..frame(VideoCodec::Bgra, 0, 0, Vec::new())
+            },
+            mode: BroadcastMode::Window,
+        };
+        let mut commands = VecDeque::from([show(3), still, show(5), show(6)]);
+        assert_eq!(drop_superseded_frames(&mut commands), 2);
+        assert!(matches!(&commands[0], VideoCommand::Frame { frame, .. } if frame.still));
+        assert!(matches!(&commands[1], VideoCommand::Frame { frame, .. } if frame.frame_id == 6));
+    }
+
+    #[test]
+    fn malformed_bgra_frames_are_rejected() {
+        let short = frame(VideoCodec::Bgra, 4, 4, vec![0; 4 * 4 * 4 - 3]);
+        assert!(decode_frame(&short).is_err());
+        let huge = frame(VideoCodec::Bgra, 100_000, 100_000, Vec::new());
+        assert!(decode_frame(&huge).is_err());
+    }
+
+    #[test]
+    fn bgra_frame_decodes_exactly() {
+        let source = TestPatternSource::new(WIDTH, HEIGHT);
+        let raw = source.capture().unwrap();
+
+        let (buffer, width, height) =
+            decode_frame(&frame(VideoCodec::Bgra, raw.width, raw.height, raw.data)).unwrap();
+        assert_eq!((width, height), (WIDTH as usize, HEIGHT as usize));
+
+        let expected: Vec<u32> = (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let [r, g, b] = source.pixel(x, y);
+                ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+            })
+            .collect();
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn fullscreen_covers_the_display_and_windowed_fits_the_frame() {
+        let display = Some(DisplayArea {
+            x: -1920,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        });
+        assert_eq!(
+            window_geometry(800, 600, BroadcastMode::Fullscreen, display, false),
+            ((1920, 1080), Some((-1920, 0)))
+        );
+        assert_eq!(
+            window_geometry(800, 600, BroadcastMode::Window, display, false),
+            (WINDOW_SIZE, None)
+        );
+        assert_eq!(
+            window_geometry(800, 600, BroadcastMode::Window, display, true),
+            ((800, 600), None)
+        );
+        assert_eq!(
+            window_geometry(800, 600, BroadcastMode::Fullscreen, None, true),
+            ((800, 600), None)
+        );
+    }
+
+    #[test]
+    fn letterbox_keeps_the_aspect_ratio_between_black_bars() {
+        // A 4:3 white frame in a 16:9 window: 60 x 45 inside 80 x 45.
+        let (buffer, width, height) = letterbox(&[0xff_ff_ff; 40 * 30], 40, 30, (80, 45));
+        assert_eq!((width, height), (80, 45));
+        let row: Vec<u32> = buffer[22 * 80..23 * 80].to_vec();
+        assert!(row[..10].iter().all(|&pixel| pixel == 0));
+        assert!(row[10..70].iter().all(|&pixel| pixel == 0xff_ff_ff));
+        assert!(row[70..].iter().all(|&pixel| pixel == 0));
+    }
+}
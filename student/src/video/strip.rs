@@ -0,0 +1,640 @@
+//! Broadcast window built on winit and softbuffer, compiled in with the `winit-renderer`
+//! feature and picked with `status_strip`. A strip above the frame names the broadcast
+//! source and shows its resolution and frame rate, so students can tell the teacher's
+//! screen from a classmate's. The event loop sleeps until a command or a notice deadline
+//! arrives, also while the window is minimized.
+
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use softbuffer::{Context, Surface};
+use tracing::{error, info, warn};
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
+use winit::event::{StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use winit::window::{Fullscreen, Window, WindowId, WindowLevel};
+
+use shared::prelude::*;
+
+use super::{
+    decode_frame, letterbox, notice_color, report_focus, window_title, InputLock, KeyframeRequests,
+    LazyFont, NoticeFont, NoticeQueue, SkippedFrames, VideoCommand, WindowPolicy,
+    NOTICE_TEXT_COLOR, NOTICE_WINDOW_SIZE, WINDOW_SIZE,
+};
+
+/// Height of the status strip in pixels.
+const STRIP_HEIGHT: usize = 24;
+const STRIP_COLOR: u32 = 0x20_20_20;
+const TEXT_COLOR: u32 = 0xf0_f0_f0;
+/// Each font pixel is drawn as a square this many pixels wide.
+const TEXT_SCALE: usize = 2;
+
+/// Event loop for the renderer thread; winit otherwise insists on the main thread.
+pub(super) fn event_loop() -> Result<EventLoop<VideoCommand>> {
+    if cfg!(target_os = "macos") {
+        bail!("macOS 上窗口只能在主线程创建");
+    }
+    let mut builder = EventLoop::with_user_event();
+    #[cfg(windows)]
+    winit::platform::windows::EventLoopBuilderExtWindows::with_any_thread(&mut builder, true);
+    #[cfg(target_os = "linux")]
+    winit::platform::x11::EventLoopBuilderExtX11::with_any_thread(&mut builder, true);
+    Ok(builder.build()?)
+}
+
+pub(super) fn run(
+    event_loop: EventLoop<VideoCommand>,
+    receiver: Receiver<VideoCommand>,
+    policy: WindowPolicy,
+    keyframes: KeyframeRequests,
+) {
+    // Commands reach the event loop as user events, which wake it without polling.
+    let proxy = event_loop.create_proxy();
+    let forwarder = thread::Builder::new()
+        .name("student-video-commands".into())
+        .spawn(move || {
+            while let Ok(command) = receiver.recv() {
+                if proxy.send_event(command).is_err() {
+                    return;
+                }
+            }
+            let _ = proxy.send_event(VideoCommand::Exit);
+        });
+    if let Err(err) = forwarder {
+        error!(?err, "无法启动视频命令转发线程");
+        return;
+    }
+
+    let mut app = StripApp {
+        policy,
+        keyframes,
+        window: None,
+        mode: BroadcastMode::Window,
+        frame: None,
+        status: String::new(),
+        fps: FpsMeter::default(),
+        blackout: false,
+        broadcasting: false,
+        notices: NoticeQueue::default(),
+        font: LazyFont::new(),
+        input_lock: None,
+        closed_by_user: false,
+        forced: false,
+        pending: None,
+        skipped: SkippedFrames::new(),
+    };
+    if let Err(err) = event_loop.run_app(&mut app) {
+        error!(?err, "视频窗口事件循环异常退出");
+    }
+}
+
+struct StripWindow {
+    window: Rc<Window>,
+    surface: Surface<Rc<Window>, Rc<Window>>,
+    /// Sized for a notice alone, with no broadcast on screen.
+    compact: bool,
+    focused: Option<bool>,
+}
+
+struct StripApp {
+    policy: WindowPolicy,
+    keyframes: KeyframeRequests,
+    window: Option<StripWindow>,
+    mode: BroadcastMode,
+    /// Latest decoded frame at its own size.
+    frame: Option<(Vec<u32>, usize, usize)>,
+    status: String,
+    fps: FpsMeter,
+    blackout: bool,
+    /// Whether a broadcast is running, as opposed to only the blackout.
+    broadcasting: bool,
+    notices: NoticeQueue,
+    font: LazyFont,
+    input_lock: Option<InputLock>,
+    /// Set when the student closes the window; frames are dropped until the next broadcast.
+    closed_by_user: bool,
+    /// Set while the teacher forces the fullscreen window; closing it is then ignored.
+    forced: bool,
+    /// Newest frame received, decoded once the queued events are handled so frames that
+    /// arrive faster than they decode are skipped.
+    pending: Option<(VideoFrame, BroadcastMode)>,
+    skipped: SkippedFrames,
+}
+
+impl StripApp {
+    fn handle(&mut self, event_loop: &ActiveEventLoop, command: VideoCommand) {
+        match command {
+            VideoCommand::Frame { frame, mode } => {
+                if self.pending.replace((frame, mode)).is_some() {
+                    self.skipped.add(1, Instant::now());
+                }
+                return;
+            }
+            VideoCommand::Start { forced } => {
+                self.closed_by_user = false;
+                self.forced = forced;
+            }
+            VideoCommand::Stop => {
+                self.broadcasting = false;
+                self.closed_by_user = false;
+                self.forced = false;
+                self.frame = None;
+                self.pending = None;
+            }
+            VideoCommand::Blackout(enabled) => {
+                if enabled {
+                    info!("教师端开启黑屏");
+                } else {
+                    info!("教师端结束黑屏");
+                }
+                self.blackout = enabled;
+            }
+            VideoCommand::InputLock(true) if self.input_lock.is_none() => {
+                match InputLock::engage() {
+                    Ok(lock) => {
+                        info!("已锁定切换窗口快捷键");
+                        self.input_lock = Some(lock);
+                    }
+                    Err(err) => warn!(?err, "无法锁定输入"),
+                }
+            }
+            VideoCommand::InputLock(true) => {}
+            VideoCommand::InputLock(false) => {
+                if self.input_lock.take().is_some() {
+                    info!("已解除输入锁定");
+                }
+            }
+            VideoCommand::Notice(notice) => {
+                info!(text = %notice.text, severity = ?notice.severity, "收到教师通知");
+                self.notices.push(notice, Instant::now());
+            }
+            VideoCommand::Exit => {
+                event_loop.exit();
+                return;
+            }
+        }
+        self.refresh(event_loop);
+    }
+
+    /// Show the newest pending frame, if any.
+    fn show_pending(&mut self, event_loop: &ActiveEventLoop) {
+        let Some((frame, mode)) = self.pending.take() else {
+            return;
+        };
+        if self.closed_by_user {
+            return;
+        }
+        if !self.blackout {
+            match decode_frame(&frame) {
+                Ok((buffer, width, height)) => {
+                    let fps = self.fps.tick(Instant::now());
+                    self.status = status_text(&frame.source, width, height, fps);
+                    self.frame = Some((buffer, width, height));
+                    self.mode = mode;
+                }
+                Err(err) => {
+                    error!(?err, "解码视频帧失败");
+                    self.keyframes.request(Instant::now());
+                    return;
+                }
+            }
+        }
+        self.broadcasting = true;
+        self.refresh(event_loop);
+    }
+
+    /// Open, resize or close the window to match what should be on screen, then redraw it.
+    fn refresh(&mut self, event_loop: &ActiveEventLoop) {
+        let on_air = (self.broadcasting && !self.closed_by_user) || self.blackout;
+        if !on_air && self.notices.current().is_none() {
+            self.window = None;
+        } else if self.window.is_none() {
+            match self.open_window(event_loop, !on_air) {
+                Ok(window) => self.window = Some(window),
+                Err(err) => error!(?err, "无法创建视频窗口"),
+            }
+        }
+
+        if let Some(strip) = self.window.as_mut() {
+            let window = &strip.window;
+            if strip.compact == on_air {
+                strip.compact = !on_air;
+                let (width, height) = if on_air {
+                    WINDOW_SIZE
+                } else {
+                    NOTICE_WINDOW_SIZE
+                };
+                let _ = window.request_inner_size(PhysicalSize::new(width as u32, height as u32));
+            }
+            let fullscreen = on_air && self.mode == BroadcastMode::Fullscreen;
+            if window.fullscreen().is_some() != fullscreen {
+                window.set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)));
+            }
+            window.set_title(&window_title(self.notices.current()));
+            window.request_redraw();
+        }
+
+        if let Some(lock) = &self.input_lock {
+            lock.follow_handle(
+                self.window
+                    .as_ref()
+                    .map_or(0, |strip| raw_handle(&strip.window)),
+            );
+        }
+    }
+
+    fn open_window(&self, event_loop: &ActiveEventLoop, compact: bool) -> Result<StripWindow> {
+        let (width, height) = if compact {
+            NOTICE_WINDOW_SIZE
+        } else {
+            WINDOW_SIZE
+        };
+        let mut attributes = Window::default_attributes()
+            .with_title(window_title(self.notices.current()))
+            .with_inner_size(PhysicalSize::new(width as u32, height as u32));
+        if self.policy.keep_on_top || compact {
+            attributes = attributes.with_window_level(WindowLevel::AlwaysOnTop);
+        }
+        let window = Rc::new(event_loop.create_window(attributes)?);
+        // softbuffer errors carry raw handles, which anyhow cannot hold.
+        let context = Context::new(window.clone()).map_err(|err| anyhow!("{err}"))?;
+        let surface = Surface::new(&context, window.clone()).map_err(|err| anyhow!("{err}"))?;
+        Ok(StripWindow {
+            window,
+            surface,
+            compact,
+            focused: None,
+        })
+    }
+
+    fn paint(&mut self) {
+        let Some(strip) = self.window.as_mut() else {
+            return;
+        };
+        let size = strip.window.inner_size();
+        // A minimized window has no area to paint.
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            return;
+        };
+        if let Err(err) = strip.surface.resize(width, height) {
+            error!(?err, "调整视频窗口缓冲区失败");
+            return;
+        }
+        let mut buffer = match strip.surface.buffer_mut() {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!(?err, "获取视频窗口缓冲区失败");
+                return;
+            }
+        };
+
+        let notice = self.notices.current();
+        let scene = Scene {
+            notice,
+            font: notice.and_then(|_| self.font.get_or_init(NoticeFont::system).as_ref()),
+            status: (self.broadcasting && !self.blackout).then_some(self.status.as_str()),
+            frame: self
+                .frame
+                .as_ref()
+                .filter(|_| !self.blackout)
+                .map(|(pixels, width, height)| (pixels.as_slice(), *width, *height)),
+            native_size: self.policy.native_size,
+        };
+        compose(
+            &mut buffer,
+            width.get() as usize,
+            height.get() as usize,
+            &scene,
+        );
+        if let Err(err) = buffer.present() {
+            error!(?err, "刷新视频窗口失败");
+        }
+    }
+
+    fn focus_changed(&mut self, active: bool) {
+        let Some(strip) = self.window.as_mut() else {
+            return;
+        };
+        if strip.focused == Some(active) {
+            return;
+        }
+        let first_check = strip.focused.is_none();
+        strip.focused = Some(active);
+        if !active && self.policy.keep_on_top {
+            strip.window.set_window_level(WindowLevel::AlwaysOnTop);
+        }
+        // Only report transitions, not the initial state of a freshly opened window.
+        if !first_check {
+            report_focus(&self.policy, active);
+        }
+    }
+}
+
+impl ApplicationHandler<VideoCommand> for StripApp {
+    fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            if self.notices.advance(Instant::now()) {
+                self.refresh(event_loop);
+            }
+        }
+    }
+
+    // Windows are opened on demand, once there is something to show.
+    fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, command: VideoCommand) {
+        if self.notices.advance(Instant::now()) {
+            self.refresh(event_loop);
+        }
+        self.handle(event_loop, command);
+    }
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested if self.forced => {
+                info!("教师端要求全屏广播，忽略关闭窗口");
+            }
+            WindowEvent::CloseRequested => {
+                self.window = None;
+                if !self.policy.reopen_closed {
+                    info!("广播窗口已被关闭，本次广播不再显示");
+                    self.closed_by_user = true;
+                }
+            }
+            WindowEvent::RedrawRequested => self.paint(),
+            WindowEvent::Resized(_) => {
+                if let Some(strip) = &self.window {
+                    strip.window.request_redraw();
+                }
+            }
+            WindowEvent::Focused(active) => self.focus_changed(active),
+            _ => {}
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        self.show_pending(event_loop);
+        event_loop.set_control_flow(match self.notices.deadline() {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        });
+    }
+}
+
+/// `HWND` of the window for the input lock; 0 on other platforms.
+fn raw_handle(window: &Window) -> isize {
+    match window.window_handle().map(|handle| handle.as_raw()) {
+        Ok(RawWindowHandle::Win32(handle)) => handle.hwnd.get(),
+        _ => 0,
+    }
+}
+
+/// Frame rate over the last second.
+#[derive(Default)]
+struct FpsMeter {
+    frames: VecDeque<Instant>,
+}
+
+impl FpsMeter {
+    /// Count a frame shown at `now`, returning the frames shown in the second up to it.
+    fn tick(&mut self, now: Instant) -> usize {
+        self.frames.push_back(now);
+        while self
+            .frames
+            .front()
+            .is_some_and(|&shown| now.duration_since(shown) >= Duration::from_secs(1))
+        {
+            self.frames.pop_front();
+        }
+        self.frames.len()
+    }
+}
+
+fn status_text(source: &BroadcastSource, width: usize, height: usize, fps: usize) -> String {
+    let source = match source {
+        BroadcastSource::Teacher => "Teacher".to_string(),
+        BroadcastSource::Student { student_id, .. } => format!("Student {student_id}"),
+    };
+    format!("{source}  {width}x{height}  {fps} fps")
+}
+
+/// What one repaint of the window shows.
+struct Scene<'a> {
+    notice: Option<&'a Notice>,
+    /// Font for the notice text; without one the strip keeps the status text.
+    font: Option<&'a NoticeFont>,
+    status: Option<&'a str>,
+    frame: Option<(&'a [u32], usize, usize)>,
+    native_size: bool,
+}
+
+/// Paint `scene` into an XRGB canvas: the strip on top, the frame centered below it.
+fn compose(canvas: &mut [u32], width: usize, height: usize, scene: &Scene) {
+    canvas.fill(0);
+    let strip_height = STRIP_HEIGHT.min(height);
+    if scene.notice.is_some() || scene.status.is_some() {
+        let color = scene
+            .notice
+            .map_or(STRIP_COLOR, |notice| notice_color(notice.severity));
+        canvas[..width * strip_height].fill(color);
+    }
+    if let (Some(notice), Some(font)) = (scene.notice, scene.font) {
+        let size = strip_height as f32 * 0.6;
+        let top = (strip_height as f32 * 0.2) as usize;
+        font.draw(
+            canvas,
+            (width, strip_height),
+            (8, top),
+            size,
+            NOTICE_TEXT_COLOR,
+            &notice.text,
+        );
+    } else if let Some(text) = scene.status {
+        let top = strip_height.saturating_sub(7 * TEXT_SCALE) / 2;
+        draw_text(canvas, width, strip_height, (8, top), text);
+    }
+
+    let area = (width, height - strip_height);
+    let Some((pixels, frame_width, frame_height)) = scene.frame else {
+        return;
+    };
+    if area.0 == 0 || area.1 == 0 {
+        return;
+    }
+    if scene.native_size {
+        blit_centered(
+            canvas,
+            width,
+            strip_height,
+            area,
+            (pixels, frame_width, frame_height),
+        );
+    } else {
+        let (fitted, fitted_width, fitted_height) =
+            letterbox(pixels, frame_width, frame_height, area);
+        blit_centered(
+            canvas,
+            width,
+            strip_height,
+            area,
+            (&fitted, fitted_width, fitted_height),
+        );
+    }
+}
+
+/// Copy a frame into the `area` of the canvas starting at row `top`, centered and cropped
+/// to fit.
+fn blit_centered(
+    canvas: &mut [u32],
+    width: usize,
+    top: usize,
+    (area_width, area_height): (usize, usize),
+    (pixels, frame_width, frame_height): (&[u32], usize, usize),
+) {
+    let visible_width = frame_width.min(area_width);
+    let visible_height = frame_height.min(area_height);
+    let (dest_x, dest_y) = (
+        (area_width - visible_width) / 2,
+        top + (area_height - visible_height) / 2,
+    );
+    let (src_x, src_y) = (
+        (frame_width - visible_width) / 2,
+        (frame_height - visible_height) / 2,
+    );
+    for row in 0..visible_height {
+        let src = (src_y + row) * frame_width + src_x;
+        let dest = (dest_y + row) * width + dest_x;
+        canvas[dest..dest + visible_width].copy_from_slice(&pixels[src..src + visible_width]);
+    }
+}
+
+/// Draw `text` with the built-in font, clipped to `width` x `height`. Letters are shown in
+/// upper case; characters the font lacks show as `?`.
+fn draw_text(canvas: &mut [u32], width: usize, height: usize, (x, y): (usize, usize), text: &str) {
+    for (index, ch) in text.chars().enumerate() {
+        let left = x + index * 6 * TEXT_SCALE;
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for column in 0..5 {
+                if bits & (0b10000 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..TEXT_SCALE {
+                    for dx in 0..TEXT_SCALE {
+                        let (px, py) = (left + column * TEXT_SCALE + dx, y + row * TEXT_SCALE + dy);
+                        if px < width && py < height {
+                            canvas[py * width + px] = TEXT_COLOR;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 bitmap of `ch`, one row per entry with the leftmost pixel in bit 4.
+fn glyph(ch: char) -> [u8; 7] {
+    match ch.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '/' => [0x01, 0x02, 0x02, 0x04, 0x08, 0x08, 0x10],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fps_counts_the_frames_of_the_last_second() {
+        let mut meter = FpsMeter::default();
+        let start = Instant::now();
+        for frame in 0..30 {
+            meter.tick(start + Duration::from_millis(frame * 50));
+        }
+        // Frames at 500..=1450 ms fall within the second up to 1450 ms.
+        assert_eq!(meter.tick(start + Duration::from_millis(1500)), 20);
+    }
+
+    #[test]
+    fn strip_names_the_source_above_the_letterboxed_frame() {
+        let source = BroadcastSource::Student {
+            student_id: "S01".into(),
+            student_name: Some("Alice".into()),
+        };
+        assert_eq!(
+            status_text(&source, 1920, 1080, 30),
+            "Student S01  1920x1080  30 fps"
+        );
+
+        let (width, height) = (160, STRIP_HEIGHT + 40);
+        let mut canvas = vec![0x12_34_56; width * height];
+        let white = vec![0xff_ff_ff; 20 * 20];
+        let scene = Scene {
+            notice: None,
+            font: None,
+            status: Some("TEACHER"),
+            frame: Some((&white, 20, 20)),
+            native_size: false,
+        };
+        compose(&mut canvas, width, height, &scene);
+
+        assert_eq!(canvas[0], STRIP_COLOR);
+        assert!(canvas[..width * STRIP_HEIGHT].contains(&TEXT_COLOR));
+        // The square frame fills the 40 rows below the strip, with black bars either side.
+        let row = &canvas[(STRIP_HEIGHT + 20) * width..(STRIP_HEIGHT + 21) * width];
+        assert!(row[..60].iter().all(|&pixel| pixel == 0));
+        assert!(row[60..100].iter().all(|&pixel| pixel == 0xff_ff_ff));
+        assert!(row[100..].iter().all(|&pixel| pixel == 0));
+    }
+}
@@ -0,0 +1,109 @@
+//! Notice text drawn into the broadcast window. Neither minifb nor the strip's built-in font
+//! can draw Chinese, so glyphs come from a CJK font installed on the system.
+
+use std::fs;
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use tracing::{info, warn};
+
+/// Fonts tried in order, all covering CJK text; the first that loads is used.
+const FONT_CANDIDATES: &[&str] = &[
+    r"C:\Windows\Fonts\msyh.ttc",
+    r"C:\Windows\Fonts\msyh.ttf",
+    r"C:\Windows\Fonts\simhei.ttf",
+    r"C:\Windows\Fonts\simsun.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+];
+
+pub(super) struct NoticeFont {
+    font: FontVec,
+}
+
+impl NoticeFont {
+    /// The first of [`FONT_CANDIDATES`] that loads, or `None` when none is installed and
+    /// notices can only go in the window title.
+    pub(super) fn system() -> Option<Self> {
+        for path in FONT_CANDIDATES {
+            let Ok(data) = fs::read(path) else {
+                continue;
+            };
+            match FontVec::try_from_vec_and_index(data, 0) {
+                Ok(font) => {
+                    info!(path, "已加载通知字体");
+                    return Some(Self { font });
+                }
+                Err(err) => warn!(path, %err, "无法解析字体文件"),
+            }
+        }
+        warn!("未找到中文字体，通知文字只显示在窗口标题中");
+        None
+    }
+
+    /// Draw one line of `text` in `color`, `size` pixels tall with its top at `y`, blended
+    /// over an XRGB canvas and clipped to `width` x `height`.
+    pub(super) fn draw(
+        &self,
+        canvas: &mut [u32],
+        (width, height): (usize, usize),
+        (x, y): (usize, usize),
+        size: f32,
+        color: u32,
+        text: &str,
+    ) {
+        let scale = PxScale::from(size);
+        let scaled = self.font.as_scaled(scale);
+        let baseline = y as f32 + scaled.ascent();
+        let mut caret = x as f32;
+        let mut previous = None;
+        for ch in text.chars() {
+            if caret >= width as f32 {
+                break;
+            }
+            let id = scaled.glyph_id(ch);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+            let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+            caret += scaled.h_advance(id);
+            let Some(outlined) = self.font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i64 + i64::from(gx);
+                let py = bounds.min.y as i64 + i64::from(gy);
+                if (0..width as i64).contains(&px) && (0..height as i64).contains(&py) {
+                    let pixel = &mut canvas[py as usize * width + px as usize];
+                    *pixel = blend(*pixel, color, coverage);
+                }
+            });
+        }
+    }
+}
+
+/// Mix `color` over `base` by `coverage` (0.0-1.0), per XRGB channel.
+fn blend(base: u32, color: u32, coverage: f32) -> u32 {
+    let coverage = coverage.clamp(0.0, 1.0);
+    [16, 8, 0].iter().fold(0, |mixed, shift| {
+        let from = ((base >> shift) & 0xff) as f32;
+        let to = ((color >> shift) & 0xff) as f32;
+        mixed | (((from + (to - from) * coverage).round() as u32) << shift)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blending_mixes_each_channel_by_coverage() {
+        assert_eq!(blend(0x00_00_00, 0xff_ff_ff, 1.0), 0xff_ff_ff);
+        assert_eq!(blend(0x12_34_56, 0xff_ff_ff, 0.0), 0x12_34_56);
+        assert_eq!(blend(0x00_00_00, 0xff_80_00, 0.5), 0x80_40_00);
+    }
+}
@@ -2,93 +2,182 @@ use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{SampleFormat, StreamConfig};
+use cpal::{SampleFormat, StreamConfig, SupportedStreamConfig};
 use parking_lot::Mutex;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tokio::time;
+use tracing::{debug, error, info, warn, Instrument};
 
+use shared::audio::{device_name_matches, OpusFrameEncoder, OPUS_SAMPLE_RATE};
 use shared::prelude::*;
 
 use crate::server::TeacherState;
 
+/// Attempts to reopen the input device after the capture stream died, e.g. on unplugging.
+const CAPTURE_RESTART_ATTEMPTS: u32 = 5;
+/// Pause before each of those attempts, giving a replugged device time to show up.
+const CAPTURE_RESTART_DELAY: Duration = Duration::from_secs(2);
+
 #[derive(Clone)]
 pub struct AudioBroadcaster {
     state: Arc<TeacherState>,
     tasks: Arc<Mutex<Option<AudioTasks>>>,
+    /// Whether audio was switched on and should be kept running.
+    active: Arc<AtomicBool>,
+    /// Whether an input stream is actually recording right now.
     running: Arc<AtomicBool>,
     force_play: Arc<AtomicBool>,
     frame_counter: Arc<AtomicU64>,
+    /// Name filter for the recording device (`TeacherConfig::audio_input_device`).
+    input_device: Option<String>,
 }
 
 struct AudioTasks {
-    capture: JoinHandle<Result<()>>,
+    supervisor: JoinHandle<()>,
     dispatch: JoinHandle<()>,
 }
 
+/// Everything a capture thread needs, kept by the supervisor to start it again.
+#[derive(Clone)]
+struct Capture {
+    tx: mpsc::UnboundedSender<AudioPacket>,
+    active: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    clock: SessionClock,
+    input_device: Option<String>,
+}
+
+impl Capture {
+    /// Start a capture thread and wait until its stream plays or fails to open.
+    async fn spawn(&self) -> Result<JoinHandle<()>> {
+        self.running.store(true, Ordering::SeqCst);
+        let capture = self.clone();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let handle = tokio::task::spawn_blocking(move || run_capture(capture, ready_tx));
+        let ready = ready_rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("录音线程意外退出")));
+        match ready {
+            Ok(()) => Ok(handle),
+            Err(err) => {
+                self.running.store(false, Ordering::SeqCst);
+                Err(err)
+            }
+        }
+    }
+}
+
 struct AudioPacket {
+    /// Session clock time of the packet's first sample.
+    captured_ms: u64,
     data: Vec<u8>,
     sample_rate: u32,
     channels: u16,
 }
 
 impl AudioBroadcaster {
-    pub fn new(state: Arc<TeacherState>, force_play: bool) -> Self {
+    pub fn new(state: Arc<TeacherState>, force_play: bool, input_device: Option<String>) -> Self {
         Self {
             state,
             tasks: Arc::new(Mutex::new(None)),
+            active: Arc::new(AtomicBool::new(false)),
             running: Arc::new(AtomicBool::new(false)),
             force_play: Arc::new(AtomicBool::new(force_play)),
             frame_counter: Arc::new(AtomicU64::new(0)),
+            input_device,
         }
     }
 
+    /// Start recording and broadcasting; returns once the input stream is playing, or with
+    /// the reason it could not be opened.
     pub async fn start(&self) -> Result<()> {
-        if self.tasks.lock().is_some() {
+        if self.active.swap(true, Ordering::SeqCst) {
             debug!("Audio broadcaster already running");
             return Ok(());
         }
+        // Left over from a capture that could not be restarted.
+        if let Some(tasks) = self.tasks.lock().take() {
+            tasks.supervisor.abort();
+            tasks.dispatch.abort();
+        }
 
         let (tx, mut rx) = mpsc::unbounded_channel::<AudioPacket>();
-        self.running.store(true, Ordering::SeqCst);
-
-        let running_capture = self.running.clone();
-        let capture_sender = tx.clone();
-        let capture_handle =
-            tokio::task::spawn_blocking(move || run_capture(capture_sender, running_capture));
+        let capture = Capture {
+            tx,
+            active: self.active.clone(),
+            running: self.running.clone(),
+            clock: self.state.session_clock(),
+            input_device: self.input_device.clone(),
+        };
+        let capture_handle = match capture.spawn().await {
+            Ok(handle) => handle,
+            Err(err) => {
+                self.active.store(false, Ordering::SeqCst);
+                return Err(err);
+            }
+        };
 
         let state = self.state.clone();
-        let running_dispatch = self.running.clone();
         let force_flag = self.force_play.clone();
         let frame_counter = self.frame_counter.clone();
 
-        let dispatch_handle = tokio::spawn(async move {
-            while let Some(packet) = rx.recv().await {
-                let frame_id = frame_counter.fetch_add(1, Ordering::Relaxed) + 1;
-                let timestamp_ms = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis() as u64;
-
-                let frame = AudioFrame {
-                    frame_id,
-                    timestamp_ms,
-                    sample_rate: packet.sample_rate,
-                    channels: packet.channels as u8,
-                    force_play: force_flag.load(Ordering::SeqCst),
-                    data: packet.data,
-                };
-
-                state.broadcast_audio(frame);
-            }
+        let dispatch_handle = tokio::spawn(
+            async move {
+                // Only encode Opus while someone can decode it; PCM-only classes skip the cost.
+                let mut opus: Option<OpusFrameEncoder> = None;
+                while let Some(packet) = rx.recv().await {
+                    let force_play = force_flag.load(Ordering::SeqCst);
+                    let next_frame = |codec, sample_rate, channels, data: Vec<u8>| AudioFrame {
+                        frame_id: frame_counter.fetch_add(1, Ordering::Relaxed) + 1,
+                        timestamp_ms: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64,
+                        capture_monotonic_ms: packet.captured_ms,
+                        sample_rate,
+                        channels,
+                        force_play,
+                        codec,
+                        data: data.into(),
+                    };
 
-            running_dispatch.store(false, Ordering::SeqCst);
-        });
+                    if state.has_audio_listeners(AudioCodec::Opus) {
+                        match encode_opus(&mut opus, &packet) {
+                            Ok(packets) => {
+                                let channels = opus.as_ref().map_or(1, OpusFrameEncoder::channels);
+                                for data in packets {
+                                    state.broadcast_audio(next_frame(
+                                        AudioCodec::Opus,
+                                        OPUS_SAMPLE_RATE,
+                                        channels,
+                                        data,
+                                    ));
+                                }
+                            }
+                            Err(err) => warn!("Opus 编码失败: {err:#}"),
+                        }
+                    } else {
+                        opus = None;
+                    }
+
+                    if state.has_audio_listeners(AudioCodec::Pcm) {
+                        state.broadcast_audio(next_frame(
+                            AudioCodec::Pcm,
+                            packet.sample_rate,
+                            packet.channels as u8,
+                            packet.data,
+                        ));
+                    }
+                }
+            }
+            .in_current_span(),
+        );
 
         *self.tasks.lock() = Some(AudioTasks {
-            capture: capture_handle,
+            supervisor: tokio::spawn(supervise_capture(capture, capture_handle).in_current_span()),
             dispatch: dispatch_handle,
         });
 
@@ -97,9 +186,10 @@ impl AudioBroadcaster {
     }
 
     pub async fn stop(&self) {
+        self.active.store(false, Ordering::SeqCst);
         self.running.store(false, Ordering::SeqCst);
         if let Some(tasks) = self.tasks.lock().take() {
-            tasks.capture.abort();
+            tasks.supervisor.abort();
             tasks.dispatch.abort();
         }
         info!("音频广播已停止");
@@ -109,22 +199,90 @@ impl AudioBroadcaster {
         self.force_play.store(force, Ordering::SeqCst);
     }
 
-    #[cfg(feature = "ui")]
+    /// Whether audio is being recorded; false while a lost input device is being reopened.
     pub fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
 
-    #[cfg(feature = "ui")]
     pub fn is_force_play(&self) -> bool {
         self.force_play.load(Ordering::SeqCst)
     }
 }
 
-fn run_capture(tx: mpsc::UnboundedSender<AudioPacket>, running: Arc<AtomicBool>) -> Result<()> {
+/// Watch the capture thread and reopen the input device when its stream dies while audio
+/// is still on. Gives up after [`CAPTURE_RESTART_ATTEMPTS`] failed attempts in a row, which
+/// also ends the dispatch task once `capture` is dropped.
+async fn supervise_capture(capture: Capture, mut handle: JoinHandle<()>) {
+    loop {
+        let _ = (&mut handle).await;
+        if !capture.active.load(Ordering::SeqCst) {
+            return;
+        }
+        warn!("录音意外中断，尝试重新打开录音设备");
+        let mut attempt = 0;
+        handle = loop {
+            if attempt == CAPTURE_RESTART_ATTEMPTS {
+                error!(attempts = attempt, "无法恢复录音，音频广播已停止");
+                capture.active.store(false, Ordering::SeqCst);
+                return;
+            }
+            attempt += 1;
+            time::sleep(CAPTURE_RESTART_DELAY).await;
+            if !capture.active.load(Ordering::SeqCst) {
+                return;
+            }
+            match capture.spawn().await {
+                Ok(handle) => {
+                    info!(attempt, "录音已恢复");
+                    break handle;
+                }
+                Err(err) => warn!(attempt, "重新打开录音设备失败: {err:#}"),
+            }
+        };
+    }
+}
+
+/// Opus packets for the captured PCM, creating the encoder on first use. Samples that do
+/// not fill a whole 20 ms frame stay buffered in the encoder.
+fn encode_opus(
+    encoder: &mut Option<OpusFrameEncoder>,
+    packet: &AudioPacket,
+) -> Result<Vec<Vec<u8>>> {
+    let encoder = match encoder {
+        Some(encoder) => encoder,
+        None => encoder.insert(OpusFrameEncoder::new(packet.channels)?),
+    };
+    let samples: Vec<i16> = packet
+        .data
+        .chunks_exact(2)
+        .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    encoder.encode(packet.sample_rate, packet.channels as usize, &samples)
+}
+
+/// Input device whose name contains `preferred`, falling back to the system default.
+fn find_input_device(host: &cpal::Host, preferred: Option<&str>) -> Option<cpal::Device> {
+    if let Some(wanted) = preferred {
+        let found = host.input_devices().ok().and_then(|mut devices| {
+            devices.find(|device| {
+                device
+                    .name()
+                    .is_ok_and(|name| device_name_matches(&name, wanted))
+            })
+        });
+        if found.is_some() {
+            return found;
+        }
+        warn!(wanted, "未找到配置的录音设备，改用系统默认设备");
+    }
+    host.default_input_device()
+}
+
+/// Configured (or default) input device and the highest-rate i16 configuration it supports.
+fn select_input(preferred: Option<&str>) -> Result<(cpal::Device, SupportedStreamConfig)> {
     let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .ok_or_else(|| anyhow!("未检测到可用的录音设备"))?;
+    let device =
+        find_input_device(&host, preferred).ok_or_else(|| anyhow!("未检测到可用的录音设备"))?;
 
     let supported_configs = device.supported_input_configs()?;
     let desired_config = supported_configs
@@ -132,56 +290,109 @@ fn run_capture(tx: mpsc::UnboundedSender<AudioPacket>, running: Arc<AtomicBool>)
         .max_by_key(|cfg| cfg.max_sample_rate().0)
         .ok_or_else(|| anyhow!("录音设备不支持 i16 格式"))?;
 
-    let config = desired_config.with_max_sample_rate();
+    Ok((device, desired_config.with_max_sample_rate()))
+}
+
+/// Describe the device audio broadcasts would record from, for `--diagnose`.
+pub fn probe_input_device(preferred: Option<&str>) -> Result<String> {
+    let (device, config) = select_input(preferred)?;
+    let name = device.name().unwrap_or_else(|_| "未知设备".to_string());
+    Ok(format!(
+        "{name} ({} Hz, {} 声道)",
+        config.sample_rate().0,
+        config.channels()
+    ))
+}
+
+/// Record until audio is switched off or the stream fails, reporting on `ready` whether the
+/// stream could start.
+fn run_capture(capture: Capture, ready: oneshot::Sender<Result<()>>) {
+    let stream = match open_capture(
+        capture.tx.clone(),
+        capture.running.clone(),
+        capture.clock,
+        capture.input_device.as_deref(),
+    ) {
+        Ok(stream) => stream,
+        Err(err) => {
+            let _ = ready.send(Err(err));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+
+    while capture.running.load(Ordering::SeqCst) && capture.active.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    drop(stream);
+}
+
+/// Open the input device and start a stream that feeds ~20 ms packets into `tx`.
+fn open_capture(
+    tx: mpsc::UnboundedSender<AudioPacket>,
+    running: Arc<AtomicBool>,
+    clock: SessionClock,
+    input_device: Option<&str>,
+) -> Result<cpal::Stream> {
+    let (device, config) = select_input(input_device)?;
     let sample_rate = config.sample_rate().0;
     let channels = config.channels();
+    info!(
+        device = %device.name().unwrap_or_else(|_| "未知设备".to_string()),
+        sample_rate,
+        channels,
+        "录音设备已选定"
+    );
     let stream_config: StreamConfig = config.into();
 
     let frame_samples_per_channel = (sample_rate as usize / 50).max(1); // ~20ms per frame
     let frame_samples = frame_samples_per_channel * channels as usize;
 
     let running_callback = running.clone();
-    let running_error = running.clone();
-    let sender_callback = tx.clone();
+    let running_error = running;
+    let sender_callback = tx;
 
     let mut sample_buffer: Vec<i16> = Vec::with_capacity(frame_samples * 2);
 
-    let stream = device.build_input_stream(
-        &stream_config,
-        move |data: &[i16], _| {
-            sample_buffer.extend_from_slice(data);
-            while sample_buffer.len() >= frame_samples {
-                let frame: Vec<i16> = sample_buffer.drain(..frame_samples).collect();
-                let mut bytes = Vec::with_capacity(frame.len() * 2);
-                for sample in frame {
-                    bytes.extend_from_slice(&sample.to_le_bytes());
+    let stream = device
+        .build_input_stream(
+            &stream_config,
+            move |data: &[i16], _| {
+                let now_ms = clock.elapsed_ms();
+                sample_buffer.extend_from_slice(data);
+                while sample_buffer.len() >= frame_samples {
+                    // The newest buffered sample was captured roughly now; back-date to the frame start.
+                    let buffered_ms = (sample_buffer.len() / channels as usize) as u64 * 1000
+                        / sample_rate as u64;
+                    let captured_ms = now_ms.saturating_sub(buffered_ms);
+                    let frame: Vec<i16> = sample_buffer.drain(..frame_samples).collect();
+                    let mut bytes = Vec::with_capacity(frame.len() * 2);
+                    for sample in frame {
+                        bytes.extend_from_slice(&sample.to_le_bytes());
+                    }
+                    if sender_callback
+                        .send(AudioPacket {
+                            captured_ms,
+                            data: bytes,
+                            sample_rate,
+                            channels,
+                        })
+                        .is_err()
+                    {
+                        running_callback.store(false, Ordering::SeqCst);
+                        break;
+                    }
                 }
-                if sender_callback
-                    .send(AudioPacket {
-                        data: bytes,
-                        sample_rate,
-                        channels,
-                    })
-                    .is_err()
-                {
-                    running_callback.store(false, Ordering::SeqCst);
-                    break;
-                }
-            }
-        },
-        move |err| {
-            error!(?err, "音频输入流发生错误");
-            running_error.store(false, Ordering::SeqCst);
-        },
-        None,
-    )?;
+            },
+            move |err| {
+                error!(?err, "音频输入流发生错误");
+                running_error.store(false, Ordering::SeqCst);
+            },
+            None,
+        )
+        .context("无法打开音频输入流")?;
 
-    stream.play()?;
-
-    while running.load(Ordering::SeqCst) {
-        std::thread::sleep(Duration::from_millis(20));
-    }
-
-    drop(stream);
-    Ok(())
+    stream.play().context("无法开始录音")?;
+    Ok(stream)
 }
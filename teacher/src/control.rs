@@ -0,0 +1,316 @@
+//! Optional local control socket for scripts, e.g. a bell schedule starting the broadcast.
+//!
+//! Clients write the same commands as the console, one per line. The server loop runs each
+//! line through the console command handler and answers `done` once it has run, or
+//! `error: <reason>`. What a command prints still goes to the console and the log. Listens
+//! on a Unix socket at `control_socket_path` (not on Windows) and/or on 127.0.0.1 at
+//! `control_port`.
+//!
+//! When `control_token` is set the first line must be that token; the TCP port will not
+//! start without one. A wrong token, a line that looks like HTTP or an unknown command
+//! closes the connection, so a web page posting to the port cannot probe for commands.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{SinkExt, StreamExt};
+use shared::prelude::*;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::codec::{Framed, LinesCodec};
+use tracing::{debug, info, warn, Instrument};
+
+/// Longest command line accepted; longer lines close the connection.
+const MAX_LINE_BYTES: usize = 4096;
+/// Commands waiting for the server loop before clients have to wait to send more.
+const PENDING_COMMANDS: usize = 16;
+
+/// A command line from a control client, waiting for the server loop to run it.
+pub struct ControlCommand {
+    pub line: String,
+    pub reply: oneshot::Sender<Result<()>>,
+}
+
+/// Error the console command handler returns for a line it does not know; control
+/// connections are closed after answering it.
+#[derive(Debug)]
+pub struct UnknownCommand(pub String);
+
+impl fmt::Display for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "未知命令 {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownCommand {}
+
+/// The running control listeners.
+pub struct ControlSocket {
+    commands: mpsc::Receiver<ControlCommand>,
+    tasks: Vec<JoinHandle<()>>,
+    socket_path: Option<PathBuf>,
+}
+
+impl ControlSocket {
+    /// Start the listeners set in `config`; `None` when neither is configured.
+    pub async fn start(config: &TeacherConfig) -> Result<Option<Self>> {
+        if config.control_socket_path.is_none() && config.control_port.is_none() {
+            return Ok(None);
+        }
+        if config.control_port.is_some() && config.control_token.is_none() {
+            bail!("设置 control_port 时必须同时设置 control_token");
+        }
+        let token: Option<Arc<str>> = config.control_token.as_deref().map(Arc::from);
+        let (sender, commands) = mpsc::channel(PENDING_COMMANDS);
+        let mut control = Self {
+            commands,
+            tasks: Vec::new(),
+            socket_path: None,
+        };
+        if let Some(path) = &config.control_socket_path {
+            let task = listen_unix(path, sender.clone(), token.clone())?;
+            control.tasks.push(task);
+            control.socket_path = Some(path.clone());
+        }
+        if let Some(port) = config.control_port {
+            // Loopback only: anyone who can connect controls the classroom.
+            let addr = format!("127.0.0.1:{port}");
+            let listener = TcpListener::bind(&addr)
+                .await
+                .with_context(|| format!("控制端口无法监听 {addr}"))?;
+            info!(%addr, "控制端口已启动");
+            control
+                .tasks
+                .push(spawn_accept_loop(listener, sender, token));
+        }
+        Ok(Some(control))
+    }
+
+    /// Next command line to run; `None` once every listener has stopped.
+    pub async fn next_command(&mut self) -> Option<ControlCommand> {
+        self.commands.recv().await
+    }
+
+    /// Stop listening, close open connections and remove the socket file.
+    pub fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(
+    path: &Path,
+    sender: mpsc::Sender<ControlCommand>,
+    token: Option<Arc<str>>,
+) -> Result<JoinHandle<()>> {
+    // A socket file left by a teacher that did not exit cleanly would block the bind.
+    match std::fs::remove_file(path) {
+        Ok(()) => debug!(path = %path.display(), "已删除残留的控制套接字"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| format!("无法删除旧的控制套接字 {}", path.display()))
+        }
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("控制套接字无法监听 {}", path.display()))?;
+    info!(path = %path.display(), "控制套接字已启动");
+    Ok(spawn_accept_loop(listener, sender, token))
+}
+
+#[cfg(not(unix))]
+fn listen_unix(
+    path: &Path,
+    _sender: mpsc::Sender<ControlCommand>,
+    _token: Option<Arc<str>>,
+) -> Result<JoinHandle<()>> {
+    anyhow::bail!(
+        "此平台不支持 Unix 套接字 {}，请改用 control_port",
+        path.display()
+    )
+}
+
+/// A listener the control socket can accept connections on.
+trait Listener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    fn accept_stream(&self) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Stream = tokio::net::TcpStream;
+
+    async fn accept_stream(&self) -> io::Result<Self::Stream> {
+        self.accept().await.map(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept_stream(&self) -> io::Result<Self::Stream> {
+        self.accept().await.map(|(stream, _)| stream)
+    }
+}
+
+/// Accept connections until aborted, serving each one on its own task. Aborting the loop
+/// closes the connections it accepted.
+fn spawn_accept_loop<L: Listener>(
+    listener: L,
+    sender: mpsc::Sender<ControlCommand>,
+    token: Option<Arc<str>>,
+) -> JoinHandle<()> {
+    tokio::spawn(
+        async move {
+            let mut connections = JoinSet::new();
+            loop {
+                match listener.accept_stream().await {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        let token = token.clone();
+                        connections.spawn(
+                            async move {
+                                let token = token.as_deref();
+                                if let Err(err) = serve_connection(stream, sender, token).await {
+                                    debug!(?err, "控制连接已断开");
+                                }
+                            }
+                            .in_current_span(),
+                        );
+                    }
+                    Err(err) => warn!(?err, "接受控制连接失败"),
+                }
+                // Reap finished connections so the set does not grow without bound.
+                while connections.try_join_next().is_some() {}
+            }
+        }
+        .in_current_span(),
+    )
+}
+
+/// Answer command lines until the client hangs up. With a `token`, the first line must
+/// match it before any command runs.
+async fn serve_connection<S>(
+    stream: S,
+    commands: mpsc::Sender<ControlCommand>,
+    token: Option<&str>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut lines = Framed::new(stream, LinesCodec::new_with_max_length(MAX_LINE_BYTES));
+    let mut authorized = token.is_none();
+    while let Some(line) = lines.next().await {
+        let line = line.context("读取控制命令失败")?;
+        if looks_like_http(&line) {
+            // Most likely a browser sent here by a web page; give it nothing to read.
+            warn!("控制连接收到 HTTP 请求，已断开");
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !authorized {
+            if token.is_some_and(|token| tokens_match(token, line.trim())) {
+                authorized = true;
+                continue;
+            }
+            warn!("控制连接口令错误，已断开");
+            lines
+                .send("error: 控制口令错误")
+                .await
+                .context("回复控制命令失败")?;
+            return Ok(());
+        }
+        info!(command = %line.trim(), "收到控制命令");
+        let (reply, result) = oneshot::channel();
+        commands
+            .send(ControlCommand { line, reply })
+            .await
+            .map_err(|_| anyhow!("服务器已停止"))?;
+        let (answer, unknown) = match result.await {
+            Ok(Ok(())) => ("done".to_string(), false),
+            Ok(Err(err)) => (format!("error: {err:#}"), err.is::<UnknownCommand>()),
+            Err(_) => break,
+        };
+        lines.send(answer).await.context("回复控制命令失败")?;
+        if unknown {
+            bail!("控制连接发送了未知命令");
+        }
+    }
+    Ok(())
+}
+
+/// An HTTP request or header line, which no console command resembles.
+fn looks_like_http(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or("");
+    first.ends_with(':') || words.last().is_some_and(|last| last.starts_with("HTTP/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve one connection with `token`, running "stop" and rejecting everything else.
+    fn serve_test_connection(
+        token: Option<&'static str>,
+    ) -> Framed<tokio::io::DuplexStream, LinesCodec> {
+        let (client, server) = tokio::io::duplex(1024);
+        let (sender, mut commands) = mpsc::channel(PENDING_COMMANDS);
+        tokio::spawn(serve_connection(server, sender, token));
+        tokio::spawn(async move {
+            while let Some(command) = commands.recv().await {
+                let result = match command.line.as_str() {
+                    "stop" => Ok(()),
+                    other => Err(UnknownCommand(other.to_string()).into()),
+                };
+                let _ = command.reply.send(result);
+            }
+        });
+        Framed::new(client, LinesCodec::new())
+    }
+
+    #[tokio::test]
+    async fn lines_are_answered_after_the_server_runs_them() {
+        let mut client = serve_test_connection(Some("secret"));
+        client.send("secret").await.unwrap();
+        client.send("stop").await.unwrap();
+        client.send("").await.unwrap();
+        client.send("bogus").await.unwrap();
+        client.send("stop").await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), "done");
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            "error: 未知命令 bogus"
+        );
+        // The unknown command closed the connection before the second stop ran.
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_wrong_token_or_http_request_closes_the_connection() {
+        let mut client = serve_test_connection(Some("secret"));
+        client.send("stop").await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), "error: 控制口令错误");
+        assert!(client.next().await.is_none());
+
+        for request in ["POST / HTTP/1.1", "Host: 127.0.0.1:9100"] {
+            let mut client = serve_test_connection(None);
+            client.send(request).await.unwrap();
+            client.send("stop").await.unwrap();
+            assert!(client.next().await.is_none(), "{request} was answered");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! `--diagnose`: check everything the teacher console needs before a lesson starts.
+
+use std::net::TcpListener;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use shared::diagnose::{probe_screen_capture, DiagnosticReport};
+use shared::prelude::*;
+
+use crate::audio;
+
+/// Run every check and print the report. Returns `false` if a critical check failed.
+pub fn run(config_path: &Path) -> bool {
+    let mut report = DiagnosticReport::new("教师端");
+
+    let config = load_config(config_path);
+    let (broadcast, input_device) = match &config {
+        Ok(config) => (config.broadcast.clone(), config.audio_input_device.clone()),
+        Err(_) => (BroadcastConfig::default(), None),
+    };
+    match config {
+        Ok(config) => {
+            report.check("配置文件", true, Ok(config_path.display().to_string()));
+            report.check("监听端口", true, probe_listen(&config.bind_addrs()));
+        }
+        Err(err) => {
+            report.check("配置文件", true, Err(err));
+            report.skip("监听端口", "配置文件无效");
+        }
+    }
+
+    report.check("屏幕截取", true, probe_screen_capture(&broadcast));
+    report.check(
+        "录音设备",
+        false,
+        audio::probe_input_device(input_device.as_deref()),
+    );
+
+    report.finish()
+}
+
+/// `--check-config`: load the config like startup does and print the values in effect,
+/// without binding sockets or opening devices.
+pub fn check_config(path: &Path, strict: bool) -> Result<()> {
+    if !path.exists() {
+        bail!("配置文件 {} 不存在", path.display());
+    }
+    let config = if strict {
+        TeacherConfig::load_strict(path)?
+    } else {
+        TeacherConfig::load_from_path(path)?
+    };
+    let effective = toml::to_string_pretty(&config).context("无法序列化教师端配置")?;
+    println!(
+        "配置文件 {} 有效，生效配置如下：\n{effective}",
+        path.display()
+    );
+    Ok(())
+}
+
+fn load_config(path: &Path) -> Result<TeacherConfig> {
+    // Loading would otherwise write a default file and report success.
+    if !path.exists() {
+        bail!("配置文件 {} 不存在", path.display());
+    }
+    // Strict, so values that would be clamped at startup show up in the report.
+    TeacherConfig::load_strict(path)
+}
+
+/// Like startup, passes as long as one of `addrs` can be bound.
+fn probe_listen(addrs: &[String]) -> Result<String> {
+    let mut available = Vec::new();
+    let mut failed = Vec::new();
+    for addr in addrs {
+        match TcpListener::bind(addr) {
+            Ok(_) => available.push(addr.as_str()),
+            Err(err) => failed.push(format!("{addr} ({err})")),
+        }
+    }
+    if available.is_empty() {
+        bail!("无法绑定 {}，端口可能已被占用", failed.join("、"));
+    }
+    let mut summary = format!("{} 可用", available.join("、"));
+    if !failed.is_empty() {
+        summary.push_str(&format!("；无法绑定 {}", failed.join("、")));
+    }
+    Ok(summary)
+}
@@ -0,0 +1,127 @@
+//! Optional read-only status page, compiled in with the `http-status` feature.
+//!
+//! `GET /status` returns the server status and the student roster as JSON. Requests are
+//! answered by the server loop via [`StatusHttp::next_request`], so nothing here can change
+//! server state. Without the feature the type still exists, but starting it fails.
+
+#[cfg(not(feature = "http-status"))]
+use std::convert::Infallible;
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::server::{ServerStatus, StudentSummary};
+
+/// Body of `GET /status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub status: ServerStatus,
+    pub students: Vec<StudentSummary>,
+}
+
+/// A pending HTTP request waiting for the current [`StatusReport`].
+pub type StatusRequest = oneshot::Sender<StatusReport>;
+
+/// The running status page.
+pub struct StatusHttp {
+    #[cfg(feature = "http-status")]
+    requests: tokio::sync::mpsc::Receiver<StatusRequest>,
+    #[cfg(feature = "http-status")]
+    shutdown: tokio_util::sync::CancellationToken,
+    #[cfg(feature = "http-status")]
+    task: tokio::task::JoinHandle<()>,
+    #[cfg(not(feature = "http-status"))]
+    never: Infallible,
+}
+
+#[cfg(feature = "http-status")]
+mod imp {
+    use anyhow::{Context, Result};
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_util::sync::CancellationToken;
+    use tracing::{info, warn, Instrument};
+
+    use super::{StatusHttp, StatusReport, StatusRequest};
+
+    /// Requests waiting for the server loop before new ones get 503.
+    const PENDING_REQUESTS: usize = 16;
+
+    impl StatusHttp {
+        /// Bind `addr` and serve the status page in the background.
+        pub async fn start(addr: &str) -> Result<Self> {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("状态页无法监听 {addr}"))?;
+            let (sender, requests) = mpsc::channel(PENDING_REQUESTS);
+            let app = Router::new()
+                .route("/", get(status))
+                .route("/status", get(status))
+                .with_state(sender);
+
+            let shutdown = CancellationToken::new();
+            let signal = shutdown.clone().cancelled_owned();
+            let task = tokio::spawn(
+                async move {
+                    if let Err(err) = axum::serve(listener, app)
+                        .with_graceful_shutdown(signal)
+                        .await
+                    {
+                        warn!(?err, "状态页异常退出");
+                    }
+                }
+                .in_current_span(),
+            );
+            info!(%addr, "状态页已启动");
+            Ok(Self {
+                requests,
+                shutdown,
+                task,
+            })
+        }
+
+        /// Next request to answer; `None` once the HTTP server has stopped.
+        pub async fn next_request(&mut self) -> Option<StatusRequest> {
+            self.requests.recv().await
+        }
+
+        /// Stop accepting requests and wait for open ones to finish.
+        pub async fn shutdown(self) {
+            self.shutdown.cancel();
+            drop(self.requests);
+            let _ = self.task.await;
+        }
+    }
+
+    async fn status(
+        State(requests): State<mpsc::Sender<StatusRequest>>,
+    ) -> Result<Json<StatusReport>, StatusCode> {
+        let (reply, report) = oneshot::channel();
+        requests
+            .try_send(reply)
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+        report
+            .await
+            .map(Json)
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+#[cfg(not(feature = "http-status"))]
+impl StatusHttp {
+    pub async fn start(_addr: &str) -> anyhow::Result<Self> {
+        anyhow::bail!("配置了 status_http_port，但此构建未启用 `http-status` Feature")
+    }
+
+    pub async fn next_request(&mut self) -> Option<StatusRequest> {
+        match self.never {}
+    }
+
+    pub async fn shutdown(self) {
+        match self.never {}
+    }
+}
@@ -0,0 +1,19 @@
+//! The classroom server behind the teacher console. Applications with their own UI can run a
+//! [`server::TeacherServer`] directly and pass an [`server::EventSender`] to hear about
+//! students joining, uploads and raised hands.
+
+mod audio;
+mod control;
+pub mod diagnose;
+mod http_status;
+mod metrics;
+mod pacing;
+mod pipeline;
+mod queue;
+mod ratelimit;
+mod recording;
+mod screen;
+pub mod server;
+mod static_screen;
+
+pub use metrics::{StudentMetrics, LAGGING_QUEUE_DEPTH};
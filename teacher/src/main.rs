@@ -1,6 +1,3 @@
-mod audio;
-mod screen;
-mod server;
 #[cfg(feature = "ui")]
 mod ui;
 
@@ -19,9 +16,10 @@ use tracing::error;
 #[cfg(not(feature = "ui"))]
 use tracing::warn;
 
+use teacher::diagnose;
 #[cfg(feature = "ui")]
-use crate::server::ServerCommand;
-use crate::server::TeacherServer;
+use teacher::server::ServerCommand;
+use teacher::server::TeacherServer;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "FJCPC Classroom Teacher Console")]
@@ -34,18 +32,51 @@ struct Cli {
     #[arg(long)]
     auto_start_broadcast: bool,
 
+    /// Display mode for --auto-start-broadcast: window or fullscreen (overrides `auto_start_mode`)
+    #[arg(long, value_parser = parse_broadcast_mode)]
+    auto_start_mode: Option<BroadcastMode>,
+
     /// Run without launching the Windows control panel UI
     #[arg(long)]
     headless: bool,
+
+    /// Check config, listen port, screen capture and audio input, then exit
+    #[arg(long)]
+    diagnose: bool,
+
+    /// Load the config, print the values in effect and exit without starting
+    #[arg(long)]
+    check_config: bool,
+
+    /// Refuse to start on out-of-range config values instead of clamping them
+    #[arg(long)]
+    strict_config: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_tracing("teacher")?;
+    let log_guard = init_tracing("teacher")?;
 
     let cli = Cli::parse();
-    let config = TeacherConfig::load_from_path(&cli.config)?;
-    let server = Arc::new(TeacherServer::new(config)?);
+    if cli.diagnose {
+        let passed = diagnose::run(&cli.config);
+        // `exit` skips destructors, so flush the log file first.
+        drop(log_guard);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if cli.check_config {
+        return diagnose::check_config(&cli.config, cli.strict_config);
+    }
+
+    let config = if cli.strict_config {
+        TeacherConfig::load_strict(&cli.config)?
+    } else {
+        TeacherConfig::load_from_path(&cli.config)?
+    };
+    let auto_start = cli
+        .auto_start_broadcast
+        .then(|| cli.auto_start_mode.unwrap_or(config.auto_start_mode));
+    let server = Arc::new(TeacherServer::new(config, None)?);
 
     #[cfg(feature = "ui")]
     {
@@ -53,11 +84,7 @@ async fn main() -> Result<()> {
             let (command_tx, command_rx) = mpsc::unbounded_channel();
             let server_task = {
                 let server_clone = Arc::clone(&server);
-                tokio::spawn(async move {
-                    server_clone
-                        .run(cli.auto_start_broadcast, Some(command_rx))
-                        .await
-                })
+                tokio::spawn(async move { server_clone.run(auto_start, Some(command_rx)).await })
             };
 
             let ui_context = ui::UiContext::new(command_tx.clone(), cli.config.clone());
@@ -92,5 +119,15 @@ async fn main() -> Result<()> {
         }
     }
 
-    server.run(cli.auto_start_broadcast, None).await
+    server.run(auto_start, None).await
+}
+
+fn parse_broadcast_mode(value: &str) -> Result<BroadcastMode, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "window" => Ok(BroadcastMode::Window),
+        "fullscreen" => Ok(BroadcastMode::Fullscreen),
+        other => Err(format!(
+            "unknown broadcast mode `{other}`, expected `window` or `fullscreen`"
+        )),
+    }
 }
@@ -0,0 +1,209 @@
+//! Per-student send counters, used to find the one connection that holds the class back.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tokio::io::AsyncWrite;
+
+/// Queued messages at which a student counts as falling behind.
+pub const LAGGING_QUEUE_DEPTH: usize = 30;
+/// Span over which the send rates are averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Snapshot of what the teacher has pushed to one student.
+#[derive(Debug, Clone)]
+pub struct StudentMetrics {
+    pub student_id: String,
+    pub display_name: String,
+    /// Bytes written to the socket, after compression.
+    pub bytes_sent: u64,
+    /// Video frames written to the socket.
+    pub frames_sent: u64,
+    /// Messages waiting in the send queue.
+    pub queue_depth: usize,
+    /// Stale video/audio messages dropped because the send queue was full.
+    pub dropped_frames: u64,
+    pub bytes_per_sec: u64,
+    pub fps: f64,
+    /// Time the writer spent held back by the bandwidth caps.
+    pub throttled_ms: u64,
+}
+
+impl StudentMetrics {
+    pub fn is_lagging(&self) -> bool {
+        self.queue_depth >= LAGGING_QUEUE_DEPTH
+    }
+}
+
+/// Live counters of one student connection, updated by its sender and writer task.
+pub struct StudentCounters {
+    bytes_sent: AtomicU64,
+    frames_sent: AtomicU64,
+    dropped_frames: AtomicU64,
+    throttled_ms: AtomicU64,
+    rate: Mutex<RateWindow>,
+}
+
+struct RateWindow {
+    started: Instant,
+    bytes: u64,
+    frames: u64,
+    bytes_per_sec: u64,
+    fps: f64,
+}
+
+impl RateWindow {
+    /// Close the window once it spans [`RATE_WINDOW`], so idle links decay to zero.
+    fn roll(&mut self) {
+        let elapsed = self.started.elapsed();
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        self.bytes_per_sec = (self.bytes as f64 / secs) as u64;
+        self.fps = self.frames as f64 / secs;
+        self.started = Instant::now();
+        self.bytes = 0;
+        self.frames = 0;
+    }
+}
+
+impl StudentCounters {
+    pub fn new() -> Self {
+        Self {
+            bytes_sent: AtomicU64::new(0),
+            frames_sent: AtomicU64::new(0),
+            dropped_frames: AtomicU64::new(0),
+            throttled_ms: AtomicU64::new(0),
+            rate: Mutex::new(RateWindow {
+                started: Instant::now(),
+                bytes: 0,
+                frames: 0,
+                bytes_per_sec: 0,
+                fps: 0.0,
+            }),
+        }
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a pause imposed by the bandwidth caps.
+    pub fn record_throttled(&self, wait: Duration) {
+        self.throttled_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Count one message written to the socket as `bytes` on the wire.
+    pub fn record_sent(&self, bytes: u64, video_frame: bool) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        let mut rate = self.rate.lock();
+        rate.bytes += bytes;
+        if video_frame {
+            self.frames_sent.fetch_add(1, Ordering::Relaxed);
+            rate.frames += 1;
+        }
+        rate.roll();
+    }
+
+    pub fn snapshot(
+        &self,
+        student_id: &str,
+        display_name: &str,
+        queue_depth: usize,
+    ) -> StudentMetrics {
+        let (bytes_per_sec, fps) = {
+            let mut rate = self.rate.lock();
+            rate.roll();
+            (rate.bytes_per_sec, rate.fps)
+        };
+        StudentMetrics {
+            student_id: student_id.to_string(),
+            display_name: display_name.to_string(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            queue_depth,
+            dropped_frames: self.dropped_frames.load(Ordering::Relaxed),
+            bytes_per_sec,
+            fps,
+            throttled_ms: self.throttled_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writer wrapper that tallies the bytes passing through it.
+pub struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    /// Bytes written since the previous call.
+    pub fn take_written(&mut self) -> u64 {
+        std::mem::take(&mut self.written)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn counting_writer_reports_bytes_per_message() {
+        let mut writer = CountingWriter::new(Vec::new());
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b", world").await.unwrap();
+        assert_eq!(writer.take_written(), 12);
+        assert_eq!(writer.take_written(), 0);
+    }
+
+    #[test]
+    fn counters_track_totals() {
+        let counters = StudentCounters::new();
+        assert!(counters
+            .snapshot("S01", "Alice", LAGGING_QUEUE_DEPTH)
+            .is_lagging());
+
+        counters.record_sent(1000, true);
+        counters.record_sent(20, false);
+        counters.record_dropped();
+        let metrics = counters.snapshot("S01", "Alice", 0);
+        assert_eq!(metrics.bytes_sent, 1020);
+        assert_eq!(metrics.frames_sent, 1);
+        assert_eq!(metrics.dropped_frames, 1);
+        assert!(!metrics.is_lagging());
+    }
+}
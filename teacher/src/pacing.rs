@@ -0,0 +1,103 @@
+//! Pacing of spotlighted student uploads. A link that cannot carry the student's frame rate
+//! shows up as frames arriving later and later; the teacher then asks the student to
+//! capture fewer frames, and a few more again each time they arrive on time.
+
+/// Span over which arrivals are judged before the rate changes.
+const PACING_WINDOW_MS: u64 = 2000;
+/// Delay past the shortest seen at which a frame counts as held up.
+const LATE_MS: i64 = 300;
+/// Gap between frames after which the student counts as spotlighted afresh.
+const RESET_GAP_MS: u64 = 5000;
+
+#[derive(Debug, Default)]
+pub struct SharePacer {
+    /// Rate last asked for; `None` while the student captures at its own rate.
+    fps: Option<u32>,
+    /// Smallest `arrival - sent` seen: the delay of a frame that did not queue anywhere.
+    base_delay_ms: i64,
+    window_start_ms: u64,
+    last_frame_ms: Option<u64>,
+    frames: u32,
+    late: u32,
+}
+
+impl SharePacer {
+    /// Note a frame sent at `sent_ms` (student clock) that arrived at `now_ms`. Returns the
+    /// frame rate to ask the student for when it changes, 0 once no limit is needed.
+    pub fn observe(&mut self, sent_ms: u64, now_ms: u64, max_fps: u32) -> Option<u32> {
+        let delay = now_ms as i64 - sent_ms as i64;
+        let restarted = self
+            .last_frame_ms
+            .is_none_or(|last| now_ms.saturating_sub(last) > RESET_GAP_MS);
+        if restarted {
+            // A new spotlight starts at the student's own rate.
+            let lifted = self.fps.is_some();
+            *self = Self {
+                base_delay_ms: delay,
+                window_start_ms: now_ms,
+                last_frame_ms: Some(now_ms),
+                ..Self::default()
+            };
+            return lifted.then_some(0);
+        }
+        self.last_frame_ms = Some(now_ms);
+        self.base_delay_ms = self.base_delay_ms.min(delay);
+        self.frames += 1;
+        if delay - self.base_delay_ms > LATE_MS {
+            self.late += 1;
+        }
+
+        let elapsed = now_ms - self.window_start_ms;
+        if elapsed < PACING_WINDOW_MS {
+            return None;
+        }
+        let observed = (u64::from(self.frames) * 1000 / elapsed) as u32;
+        // Arrivals show what the link carries; ask for a bit less so the backlog drains.
+        let next = if self.late * 2 > self.frames {
+            (observed * 3 / 4).max(1)
+        } else if self.late == 0 {
+            self.fps.map_or(max_fps, |fps| fps + 1)
+        } else {
+            self.fps.unwrap_or(max_fps)
+        };
+        self.window_start_ms = now_ms;
+        self.frames = 0;
+        self.late = 0;
+
+        if next >= max_fps {
+            return self.fps.take().map(|_| 0);
+        }
+        if self.fps == Some(next) {
+            return None;
+        }
+        self.fps = Some(next);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn congested_uploads_are_slowed_down_and_recover() {
+        let mut pacer = SharePacer::default();
+        let mut asked = Vec::new();
+        // 10 fps, each frame held up 100 ms more than the one before.
+        for frame in 0..=20 {
+            let sent = frame * 100;
+            asked.extend(pacer.observe(sent, sent + 50 + frame * 100, 15));
+        }
+        // Frames arrive at about 5 fps, so the student is asked for a little less.
+        assert_eq!(asked, [3]);
+
+        // Back on time: one more frame per second after every on-time window, up to the full rate.
+        let mut asked = Vec::new();
+        for frame in 0..=200 {
+            let sent = 4000 + frame * 200;
+            asked.extend(pacer.observe(sent, sent + 50, 15));
+        }
+        assert_eq!(asked[..3], [4, 5, 6]);
+        assert_eq!(asked.last(), Some(&0));
+    }
+}
@@ -0,0 +1,120 @@
+//! Pipelining of screen capture and JPEG encoding. Captures wait in a short queue that
+//! sheds the oldest one when the encoders fall behind; several workers encode at once and
+//! [`InOrder`] puts their frames back in capture order before they are broadcast.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+/// Bounded queue of captures waiting for an encoder, numbering them as they are taken.
+pub struct CaptureQueue<T> {
+    capacity: usize,
+    inner: Mutex<QueueInner<T>>,
+    ready: Notify,
+}
+
+struct QueueInner<T> {
+    items: VecDeque<T>,
+    next_seq: u64,
+}
+
+impl<T> CaptureQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(QueueInner {
+                items: VecDeque::with_capacity(capacity),
+                next_seq: 0,
+            }),
+            ready: Notify::new(),
+        }
+    }
+
+    /// Queue a capture, shedding the oldest waiting one when full; returns whether one was.
+    pub fn push(&self, item: T) -> bool {
+        let shed = {
+            let mut inner = self.inner.lock();
+            let shed = inner.items.len() >= self.capacity;
+            if shed {
+                inner.items.pop_front();
+            }
+            inner.items.push_back(item);
+            shed
+        };
+        self.ready.notify_one();
+        shed
+    }
+
+    /// Wait for the oldest capture. Captures are numbered from 0 in the order they are
+    /// taken, with no gaps, so results can be put back in order by that number.
+    pub async fn pop(&self) -> (u64, T) {
+        loop {
+            let notified = self.ready.notified();
+            if let Some(taken) = self.try_pop() {
+                return taken;
+            }
+            notified.await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<(u64, T)> {
+        let mut inner = self.inner.lock();
+        let item = inner.items.pop_front()?;
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        Some((seq, item))
+    }
+}
+
+/// Releases results numbered by [`CaptureQueue::pop`] in that order, holding back any that
+/// finish before an earlier one.
+#[derive(Debug)]
+pub struct InOrder<T> {
+    next: u64,
+    waiting: BTreeMap<u64, T>,
+}
+
+impl<T> Default for InOrder<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            waiting: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> InOrder<T> {
+    /// Accept result number `seq`; returns the results now due, oldest first.
+    pub fn push(&mut self, seq: u64, item: T) -> Vec<T> {
+        self.waiting.insert(seq, item);
+        let mut due = Vec::new();
+        while let Some(item) = self.waiting.remove(&self.next) {
+            due.push(item);
+            self.next += 1;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_queue_sheds_the_oldest_and_results_come_back_in_order() {
+        let queue = CaptureQueue::new(2);
+        assert!(!queue.push("a"));
+        assert!(!queue.push("b"));
+        assert!(queue.push("c"));
+        assert_eq!(queue.try_pop(), Some((0, "b")));
+        assert_eq!(queue.try_pop(), Some((1, "c")));
+        assert_eq!(queue.try_pop(), None);
+
+        let mut order = InOrder::default();
+        assert!(order.push(1, "second").is_empty());
+        assert!(order.push(2, "third").is_empty());
+        assert_eq!(order.push(0, "first"), ["first", "second", "third"]);
+        assert_eq!(order.push(3, "fourth"), ["fourth"]);
+    }
+}
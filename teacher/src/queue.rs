@@ -0,0 +1,530 @@
+//! Per-student send queue that sheds stale media and holds back file data instead of growing
+//! without bound.
+
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use shared::prelude::TeacherToStudent;
+
+/// File messages one queue holds before [`SendQueue::file_room`] makes the sender wait, about
+/// 1 MB of 64 KB chunks.
+pub const FILE_CAPACITY: usize = 16;
+
+/// While commands and media keep the main lane busy, every this many of them file data still
+/// gets a turn, so a saturated link slows a transfer down instead of stalling it.
+const FILE_TURN: usize = 4;
+
+/// The student's writer has stopped; nothing more will be delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueClosed;
+
+/// Which lanes of a [`SendQueue`] the writer may take from; it shuts a lane while that lane's
+/// bandwidth cap is paying off a burst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lanes {
+    pub main: bool,
+    pub files: bool,
+}
+
+impl Lanes {
+    #[cfg(test)]
+    pub const ALL: Self = Self {
+        main: true,
+        files: true,
+    };
+}
+
+/// Two FIFO lanes between the broadcast code and one student's writer task.
+///
+/// The main lane carries commands and video/audio. At most `media_capacity` video/audio
+/// messages wait in it; a new one past that replaces the oldest queued message of its kind.
+/// Commands and stills are never dropped.
+///
+/// File offers, chunks and completions wait in their own lane, which is never shed and goes
+/// after the main lane. File senders wait in [`SendQueue::file_room`] while [`FILE_CAPACITY`]
+/// file messages are queued, and a closed queue delivers only what is left in the main lane.
+///
+/// Messages are shared, so a broadcast frame is held once however many queues it waits in;
+/// `broadcast_frames_are_shared_not_copied_per_student` queues a 2 MB frame for 40 students
+/// and finds a single 2 MB buffer behind all of them.
+pub struct SendQueue {
+    state: Mutex<QueueState>,
+    ready: Notify,
+    /// Signalled when file data leaves the queue or it closes.
+    file_room: Notify,
+    media_capacity: usize,
+}
+
+struct QueueState {
+    messages: VecDeque<Arc<TeacherToStudent>>,
+    media: usize,
+    files: VecDeque<Arc<TeacherToStudent>>,
+    /// Main lane messages taken while file data waited, since file data last had a turn.
+    files_passed_over: usize,
+    closed: bool,
+}
+
+/// Video and audio of a running broadcast; a still screenshot is kept like a command.
+fn is_media(message: &TeacherToStudent) -> bool {
+    match message {
+        TeacherToStudent::Video(frame) => !frame.still,
+        TeacherToStudent::Audio(_) => true,
+        _ => false,
+    }
+}
+
+/// Messages that travel in the file lane.
+pub fn is_file_data(message: &TeacherToStudent) -> bool {
+    matches!(
+        message,
+        TeacherToStudent::FileOffer(_)
+            | TeacherToStudent::FileChunk(_)
+            | TeacherToStudent::FileComplete(_)
+    )
+}
+
+impl SendQueue {
+    pub fn new(media_capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::new(),
+                media: 0,
+                files: VecDeque::new(),
+                files_passed_over: 0,
+                closed: false,
+            }),
+            ready: Notify::new(),
+            file_room: Notify::new(),
+            media_capacity: media_capacity.max(1),
+        }
+    }
+
+    /// Queue `message`, returning whether a stale media message was dropped to make room.
+    pub fn push(&self, message: Arc<TeacherToStudent>) -> Result<bool, QueueClosed> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(QueueClosed);
+        }
+        let mut dropped = false;
+        if is_file_data(&message) {
+            state.files.push_back(message);
+        } else {
+            if is_media(&message) {
+                if state.media >= self.media_capacity {
+                    let kind = mem::discriminant(&*message);
+                    let stale = state
+                        .messages
+                        .iter()
+                        .position(|queued| is_media(queued) && mem::discriminant(&**queued) == kind)
+                        .or_else(|| state.messages.iter().position(|queued| is_media(queued)));
+                    if let Some(index) = stale {
+                        state.messages.remove(index);
+                        state.media -= 1;
+                        dropped = true;
+                    }
+                }
+                state.media += 1;
+            }
+            state.messages.push_back(message);
+        }
+        drop(state);
+        self.ready.notify_one();
+        Ok(dropped)
+    }
+
+    #[cfg(test)]
+    pub fn try_pop(&self) -> Option<Arc<TeacherToStudent>> {
+        self.try_pop_from(Lanes::ALL)
+    }
+
+    /// Take the next message from the open `lanes`, the main lane first.
+    fn try_pop_from(&self, lanes: Lanes) -> Option<Arc<TeacherToStudent>> {
+        let mut state = self.state.lock();
+        let files_waiting = lanes.files && !state.closed && !state.files.is_empty();
+        if files_waiting
+            && (!lanes.main || state.messages.is_empty() || state.files_passed_over >= FILE_TURN)
+        {
+            state.files_passed_over = 0;
+            let message = state.files.pop_front();
+            drop(state);
+            self.file_room.notify_waiters();
+            return message;
+        }
+        if !lanes.main {
+            return None;
+        }
+        let message = state.messages.pop_front()?;
+        if is_media(&message) {
+            state.media -= 1;
+        }
+        if files_waiting {
+            state.files_passed_over += 1;
+        }
+        Some(message)
+    }
+
+    /// Wait until fewer than [`FILE_CAPACITY`] file messages are queued, or the queue closes.
+    /// File senders call this before each chunk so a slow student holds the file back instead
+    /// of it piling up in memory.
+    pub async fn file_room(&self) {
+        loop {
+            let notified = self.file_room.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let state = self.state.lock();
+                if state.closed || state.files.len() < FILE_CAPACITY {
+                    return;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait for the next message from the open `lanes`; `None` once the queue is closed and
+    /// its main lane drained.
+    pub async fn pop(&self, lanes: Lanes) -> Option<Arc<TeacherToStudent>> {
+        loop {
+            if let Some(message) = self.try_pop_from(lanes) {
+                return Some(message);
+            }
+            {
+                let state = self.state.lock();
+                if state.closed && state.messages.is_empty() {
+                    return None;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    /// Refuse further messages, e.g. once the connection is gone.
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.ready.notify_one();
+        self.file_room.notify_waiters();
+    }
+
+    /// Close the queue with `message` as the last delivery, dropping any video/audio still
+    /// waiting so it goes out promptly.
+    pub fn close_with(&self, message: Arc<TeacherToStudent>) {
+        let mut state = self.state.lock();
+        if !state.closed {
+            state.messages.retain(|queued| !is_media(queued));
+            state.media = 0;
+            state.messages.push_back(message);
+            state.closed = true;
+        }
+        drop(state);
+        self.ready.notify_one();
+        self.file_room.notify_waiters();
+    }
+
+    pub fn depth(&self) -> usize {
+        let state = self.state.lock();
+        state.messages.len() + state.files.len()
+    }
+
+    /// Whether part of a file transfer is still waiting to be written.
+    pub fn holds_file_data(&self) -> bool {
+        !self.state.lock().files.is_empty()
+    }
+
+    /// Drop the file data still waiting, returning the transfers the student has already
+    /// started receiving and now gets only part of. Transfers whose offer was still queued
+    /// vanish without a trace, and one with nothing but its completion left stays queued.
+    pub fn discard_file_data(&self) -> Vec<Uuid> {
+        let mut state = self.state.lock();
+        let mut unoffered = HashSet::new();
+        let mut cut = Vec::new();
+        state.files.retain(|queued| {
+            let transfer_id = match &**queued {
+                TeacherToStudent::FileOffer(offer) => {
+                    unoffered.insert(offer.transfer_id);
+                    return false;
+                }
+                TeacherToStudent::FileChunk(chunk) => chunk.transfer_id,
+                TeacherToStudent::FileComplete(done) => {
+                    let id = &done.transfer_id;
+                    return !unoffered.contains(id) && !cut.contains(id);
+                }
+                _ => return true,
+            };
+            if !unoffered.contains(&transfer_id) && !cut.contains(&transfer_id) {
+                cut.push(transfer_id);
+            }
+            false
+        });
+        drop(state);
+        self.file_room.notify_waiters();
+        cut
+    }
+
+    /// Drop everything still queued for `transfer_id`, e.g. once its send is cancelled.
+    /// Returns whether the student may already hold part of the file, i.e. its offer was no
+    /// longer waiting here.
+    pub fn discard_transfer(&self, transfer_id: Uuid) -> bool {
+        let mut state = self.state.lock();
+        let mut offered = true;
+        state.files.retain(|queued| match &**queued {
+            TeacherToStudent::FileOffer(offer) if offer.transfer_id == transfer_id => {
+                offered = false;
+                false
+            }
+            TeacherToStudent::FileChunk(chunk) => chunk.transfer_id != transfer_id,
+            TeacherToStudent::FileComplete(done) => done.transfer_id != transfer_id,
+            _ => true,
+        });
+        drop(state);
+        self.file_room.notify_waiters();
+        offered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::prelude::*;
+
+    use super::*;
+
+    fn video(frame_id: u64) -> Arc<TeacherToStudent> {
+        Arc::new(TeacherToStudent::Video(video_frame(frame_id)))
+    }
+
+    fn video_frame(frame_id: u64) -> VideoFrame {
+        VideoFrame {
+            frame_id,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 1,
+            height: 1,
+            fullscreen: false,
+            thumbnail: false,
+            data: Default::default(),
+            still: false,
+        }
+    }
+
+    fn command(text: &str) -> Arc<TeacherToStudent> {
+        Arc::new(TeacherToStudent::Error(text.into()))
+    }
+
+    #[test]
+    fn full_queue_drops_oldest_video_but_keeps_commands() {
+        let queue = SendQueue::new(2);
+        assert_eq!(queue.push(video(1)), Ok(false));
+        assert_eq!(queue.push(command("cmd")), Ok(false));
+        assert_eq!(queue.push(video(2)), Ok(false));
+        assert_eq!(queue.push(video(3)), Ok(true));
+        assert_eq!(queue.depth(), 3);
+
+        let drained: Vec<_> = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert!(matches!(&*drained[0], TeacherToStudent::Error(text) if text == "cmd"));
+        assert!(matches!(&*drained[1], TeacherToStudent::Video(frame) if frame.frame_id == 2));
+        assert!(matches!(&*drained[2], TeacherToStudent::Video(frame) if frame.frame_id == 3));
+    }
+
+    #[test]
+    fn still_frames_are_never_shed() {
+        let still = Arc::new(TeacherToStudent::Video(VideoFrame {
+            still: true,
+            ..video_frame(1)
+        }));
+        let queue = SendQueue::new(1);
+        queue.push(still).unwrap();
+        assert_eq!(queue.push(video(2)), Ok(false));
+        assert_eq!(queue.push(video(3)), Ok(true));
+
+        let drained: Vec<_> = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert!(matches!(&*drained[0], TeacherToStudent::Video(frame) if frame.still));
+        assert!(matches!(&*drained[1], TeacherToStudent::Video(frame) if frame.frame_id == 3));
+    }
+
+    #[tokio::test]
+    async fn closed_queue_refuses_and_wakes_the_writer() {
+        let queue = SendQueue::new(4);
+        queue.push(video(1)).unwrap();
+        queue.close();
+        assert_eq!(queue.push(video(2)), Err(QueueClosed));
+        assert!(queue.pop(Lanes::ALL).await.is_some());
+        assert!(queue.pop(Lanes::ALL).await.is_none());
+    }
+
+    #[test]
+    fn close_with_skips_media_and_delivers_the_last_message() {
+        let queue = SendQueue::new(4);
+        queue.push(video(1)).unwrap();
+        queue.push(command("cmd")).unwrap();
+        queue.close_with(command("bye"));
+        assert_eq!(queue.push(video(2)), Err(QueueClosed));
+
+        let drained: Vec<_> = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert_eq!(drained.len(), 2);
+        assert!(matches!(&*drained[1], TeacherToStudent::Error(text) if text == "bye"));
+    }
+
+    #[tokio::test]
+    async fn file_senders_wait_while_the_queue_holds_a_full_window() {
+        let queue = SendQueue::new(4);
+        let chunk = || {
+            Arc::new(TeacherToStudent::FileChunk(FileChunk::new(
+                Uuid::new_v4(),
+                0,
+                vec![1],
+                false,
+            )))
+        };
+        for _ in 0..FILE_CAPACITY {
+            queue.file_room().await;
+            queue.push(chunk()).unwrap();
+        }
+        let waiting = queue.file_room();
+        tokio::pin!(waiting);
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+
+        queue.try_pop();
+        assert!(futures::poll!(waiting.as_mut()).is_ready());
+        // A closed queue never holds a sender back.
+        queue.push(chunk()).unwrap();
+        queue.close();
+        queue.file_room().await;
+    }
+
+    #[tokio::test]
+    async fn file_data_waits_in_its_own_lane() {
+        let queue = SendQueue::new(8);
+        let chunk = || {
+            Arc::new(TeacherToStudent::FileChunk(FileChunk::new(
+                Uuid::new_v4(),
+                0,
+                vec![1],
+                false,
+            )))
+        };
+        queue.push(chunk()).unwrap();
+        for frame_id in 0..8 {
+            queue.push(video(frame_id)).unwrap();
+        }
+
+        // A shut file lane leaves the broadcast flowing, and a shut main lane the file.
+        let media_only = Lanes {
+            main: true,
+            files: false,
+        };
+        assert!(matches!(
+            queue.pop(media_only).await.as_deref(),
+            Some(TeacherToStudent::Video(_))
+        ));
+        let files_only = Lanes {
+            main: false,
+            files: true,
+        };
+        assert!(matches!(
+            queue.pop(files_only).await.as_deref(),
+            Some(TeacherToStudent::FileChunk(_))
+        ));
+
+        // With both open, busy media still lets file data through every FILE_TURN messages.
+        for _ in 0..3 {
+            queue.push(chunk()).unwrap();
+        }
+        let order: Vec<bool> = (0..10)
+            .map(|_| is_file_data(&queue.try_pop().unwrap()))
+            .collect();
+        let first_file = order.iter().position(|&file| file).unwrap();
+        assert_eq!(first_file, FILE_TURN);
+        assert_eq!(order.iter().filter(|&&file| file).count(), 3);
+    }
+
+    #[test]
+    fn discarding_file_data_reports_only_transfers_the_student_started() {
+        let started = Uuid::new_v4();
+        let unoffered = Uuid::new_v4();
+        let finished = Uuid::new_v4();
+        let chunk = |transfer_id| {
+            Arc::new(TeacherToStudent::FileChunk(FileChunk::new(
+                transfer_id,
+                0,
+                vec![1],
+                false,
+            )))
+        };
+        let complete = |transfer_id| {
+            Arc::new(TeacherToStudent::FileComplete(FileTransferComplete {
+                transfer_id,
+                success: true,
+                message: None,
+            }))
+        };
+        let queue = SendQueue::new(4);
+        queue.push(chunk(started)).unwrap();
+        queue.push(complete(finished)).unwrap();
+        queue.push(command("cmd")).unwrap();
+        queue
+            .push(Arc::new(TeacherToStudent::FileOffer(FileOffer {
+                transfer_id: unoffered,
+                file_name: "a.txt".into(),
+                total_size: 1,
+                auto_open: false,
+                resume: false,
+            })))
+            .unwrap();
+        queue.push(chunk(unoffered)).unwrap();
+        queue.push(complete(started)).unwrap();
+        assert!(queue.holds_file_data());
+
+        assert_eq!(queue.discard_file_data(), [started]);
+        let drained: Vec<_> = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert_eq!(drained.len(), 2);
+        assert!(
+            matches!(&*drained[1], TeacherToStudent::FileComplete(done) if done.transfer_id == finished)
+        );
+        assert!(!queue.holds_file_data());
+    }
+
+    #[test]
+    fn discarding_a_transfer_drops_only_its_queued_data() {
+        let cancelled = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let chunk = |transfer_id| {
+            Arc::new(TeacherToStudent::FileChunk(FileChunk::new(
+                transfer_id,
+                0,
+                vec![1],
+                false,
+            )))
+        };
+        let offer = |transfer_id| {
+            Arc::new(TeacherToStudent::FileOffer(FileOffer {
+                transfer_id,
+                file_name: "a.txt".into(),
+                total_size: 1,
+                auto_open: false,
+                resume: false,
+            }))
+        };
+        let queue = SendQueue::new(4);
+        queue.push(chunk(cancelled)).unwrap();
+        queue.push(chunk(other)).unwrap();
+        queue.push(chunk(cancelled)).unwrap();
+        // The offer already went out, so the student holds part of the file.
+        assert!(queue.discard_transfer(cancelled));
+        assert_eq!(queue.depth(), 1);
+
+        queue.push(offer(cancelled)).unwrap();
+        queue.push(chunk(cancelled)).unwrap();
+        assert!(!queue.discard_transfer(cancelled));
+        let drained: Vec<_> = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert_eq!(drained.len(), 1);
+        assert!(
+            matches!(&*drained[0], TeacherToStudent::FileChunk(chunk) if chunk.transfer_id == other)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Bandwidth caps for one student connection. The writer task pays for each message after
+//! writing it and pauses while in debt; meanwhile the send queue sheds stale video on its own.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Token bucket refilled at a fixed byte rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    /// Refill rate in bytes per second.
+    rate: f64,
+    /// Tokens saved up while idle are capped at one second of traffic.
+    burst: f64,
+    /// Goes negative when a message larger than the balance was sent.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Bucket for `kbps` kilobits per second; `None` for 0, which means no cap.
+    pub fn from_kbps(kbps: u32, now: Instant) -> Option<Self> {
+        if kbps == 0 {
+            return None;
+        }
+        let rate = f64::from(kbps) * 1000.0 / 8.0;
+        Some(Self {
+            rate,
+            burst: rate,
+            tokens: rate,
+            updated: now,
+        })
+    }
+
+    /// Pay for `bytes` just sent; returns how long to wait before sending anything else.
+    pub fn consume(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_pass_then_the_rate_holds() {
+        let start = Instant::now();
+        assert!(TokenBucket::from_kbps(0, start).is_none());
+
+        // 80 kbit/s is 10 000 bytes per second, all of which may go out at once.
+        let mut bucket = TokenBucket::from_kbps(80, start).unwrap();
+        assert_eq!(bucket.consume(10_000, start), Duration::ZERO);
+        assert_eq!(bucket.consume(5_000, start), Duration::from_millis(500));
+        // Waiting out the debt leaves nothing to spare.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.consume(1_000, later), Duration::from_millis(100));
+        // A long pause refills no more than one second's worth.
+        let idle = later + Duration::from_secs(10);
+        assert_eq!(bucket.consume(10_000, idle), Duration::ZERO);
+        assert!(bucket.consume(1, idle) > Duration::ZERO);
+    }
+}
@@ -0,0 +1,163 @@
+//! Saves the outgoing broadcast for absent students: one JPEG per frame plus an `index.csv`
+//! with the timing of each frame, written on a background thread.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::{debug, warn};
+
+use shared::prelude::{BroadcastSource, VideoCodec, VideoFrame};
+
+/// Frames waiting for the disk before new ones are dropped.
+const WRITE_QUEUE: usize = 64;
+const INDEX_FILE: &str = "index.csv";
+
+/// A recording in progress; frames from any broadcast source go into the same directory.
+pub struct Recording {
+    dir: PathBuf,
+    started: Instant,
+    sender: SyncSender<(u64, VideoFrame)>,
+    writer: JoinHandle<Result<u64>>,
+}
+
+impl Recording {
+    /// Start recording into `dir`, which is created if needed but must not hold a recording.
+    pub fn start(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("无法创建录制目录 {}", dir.display()))?;
+        let index_path = dir.join(INDEX_FILE);
+        if index_path.exists() {
+            bail!("{} 中已有录像，请换一个目录", dir.display());
+        }
+        let index = File::create(&index_path)
+            .with_context(|| format!("无法创建 {}", index_path.display()))?;
+
+        let (sender, receiver) = mpsc::sync_channel(WRITE_QUEUE);
+        let frames_dir = dir.to_path_buf();
+        let writer = thread::Builder::new()
+            .name("recording".into())
+            .spawn(move || write_frames(&frames_dir, BufWriter::new(index), receiver))
+            .context("无法启动录制线程")?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            started: Instant::now(),
+            sender,
+            writer,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Queue `frame` for writing; frames the disk cannot keep up with are skipped.
+    pub fn record(&self, frame: &VideoFrame) {
+        if frame.codec != VideoCodec::Jpeg {
+            debug!(codec = ?frame.codec, "非 JPEG 帧不写入录像");
+            return;
+        }
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        match self.sender.try_send((elapsed_ms, frame.clone())) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(frame_id = frame.frame_id, "录像写入过慢，已跳过一帧")
+            }
+        }
+    }
+
+    /// Finish writing and return the number of frames saved.
+    pub fn stop(self) -> Result<u64> {
+        drop(self.sender);
+        self.writer.join().map_err(|_| anyhow!("录制线程崩溃"))?
+    }
+}
+
+fn write_frames(
+    dir: &Path,
+    mut index: BufWriter<File>,
+    frames: mpsc::Receiver<(u64, VideoFrame)>,
+) -> Result<u64> {
+    writeln!(index, "file,elapsed_ms,timestamp_ms,width,height,source")?;
+    let mut count = 0;
+    for (elapsed_ms, frame) in frames {
+        count += 1;
+        let name = format!("{count:06}.jpg");
+        let path = dir.join(&name);
+        fs::write(&path, &frame.data)
+            .with_context(|| format!("无法写入录像帧 {}", path.display()))?;
+        writeln!(
+            index,
+            "{name},{elapsed_ms},{},{},{},{}",
+            frame.timestamp_ms,
+            frame.width,
+            frame.height,
+            source_label(&frame.source)
+        )?;
+    }
+    index.flush().context("无法写入录像索引")?;
+    Ok(count)
+}
+
+/// CSV-safe name of the broadcast source, e.g. `student:S01`.
+fn source_label(source: &BroadcastSource) -> String {
+    match source {
+        BroadcastSource::Teacher => "teacher".to_string(),
+        BroadcastSource::Student { student_id, .. } => {
+            format!("student:{}", student_id.replace([',', '\n', '\r'], "_"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::prelude::*;
+
+    use super::*;
+
+    fn jpeg_frame(frame_id: u64, source: BroadcastSource) -> VideoFrame {
+        VideoFrame {
+            frame_id,
+            timestamp_ms: 1_000 + frame_id,
+            capture_monotonic_ms: 0,
+            source,
+            codec: VideoCodec::Jpeg,
+            width: 4,
+            height: 3,
+            fullscreen: false,
+            thumbnail: false,
+            data: vec![0xff, 0xd8, frame_id as u8].into(),
+            still: false,
+        }
+    }
+
+    #[test]
+    fn frames_from_every_source_land_in_one_recording() {
+        let dir = std::env::temp_dir().join(format!("teacher-recording-{}", uuid::Uuid::new_v4()));
+        let recording = Recording::start(&dir).unwrap();
+        recording.record(&jpeg_frame(1, BroadcastSource::Teacher));
+        recording.record(&jpeg_frame(
+            2,
+            BroadcastSource::Student {
+                student_id: "S01".into(),
+                student_name: None,
+            },
+        ));
+        assert_eq!(recording.stop().unwrap(), 2);
+
+        let index = fs::read_to_string(dir.join(INDEX_FILE)).unwrap();
+        let rows: Vec<&str> = index.lines().skip(1).collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with("000001.jpg,") && rows[0].ends_with(",teacher"));
+        assert!(rows[1].ends_with(",student:S01"));
+        assert_eq!(
+            fs::read(dir.join("000002.jpg")).unwrap(),
+            vec![0xff, 0xd8, 2]
+        );
+        assert!(Recording::start(&dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,22 +1,31 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use image::{codecs::jpeg::JpegEncoder, ColorType};
+use anyhow::{anyhow, bail, Result};
 use parking_lot::Mutex;
-use screenshots::Screen;
-use tokio::task::JoinHandle;
+use shared::frame::{encode_jpeg, probe_turbojpeg, RawFrame, ScreenSource};
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
 use tokio::time;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn, Instrument};
 
 use shared::prelude::*;
 
+use crate::pipeline::{CaptureQueue, InOrder};
 use crate::server::TeacherState;
+use crate::static_screen::StaticScreenFilter;
 
 #[derive(Clone)]
 pub struct ScreenBroadcaster {
     state: Arc<TeacherState>,
     task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Encoder in use while broadcasting, after any fallback.
+    encoder: Arc<Mutex<Option<EncoderKind>>>,
+    /// Whether the broadcast is paused because `capture_window` is not on the display.
+    window_missing: Arc<AtomicBool>,
+    /// Off only in tests, where `start` must not grab the real screen.
+    capture: bool,
 }
 
 impl ScreenBroadcaster {
@@ -24,23 +33,55 @@ impl ScreenBroadcaster {
         Self {
             state,
             task: Arc::new(Mutex::new(None)),
+            encoder: Arc::new(Mutex::new(None)),
+            window_missing: Arc::new(AtomicBool::new(false)),
+            capture: true,
         }
     }
 
+    /// A broadcaster that goes through `start` and `stop` without capturing anything.
+    #[cfg(test)]
+    pub fn without_capture(state: Arc<TeacherState>) -> Self {
+        Self {
+            capture: false,
+            ..Self::new(state)
+        }
+    }
+
+    pub fn active_encoder(&self) -> Option<EncoderKind> {
+        *self.encoder.lock()
+    }
+
+    /// Whether the running broadcast is paused because its window is not on the display.
+    pub fn window_missing(&self) -> bool {
+        self.window_missing.load(Ordering::Relaxed)
+    }
+
     pub async fn start(&self, mode: BroadcastMode) -> Result<()> {
         if self.task.lock().is_some() {
             debug!("Screen broadcaster already running");
             return Ok(());
         }
 
+        let encoder = select_encoder(self.state.broadcast_config().encoder);
+        *self.encoder.lock() = Some(encoder);
+
+        if !self.capture {
+            *self.task.lock() = Some(tokio::spawn(std::future::pending()));
+            return Ok(());
+        }
         let state = self.state.clone();
-        let handle = tokio::spawn(async move {
-            if let Err(err) = capture_loop(state, mode).await {
-                error!(?err, "Screen capture loop exited with error");
-            } else {
-                debug!("Screen capture loop terminated");
+        let window_missing = self.window_missing.clone();
+        let handle = tokio::spawn(
+            async move {
+                if let Err(err) = capture_loop(state, mode, encoder, window_missing).await {
+                    error!(?err, "Screen capture loop exited with error");
+                } else {
+                    debug!("Screen capture loop terminated");
+                }
             }
-        });
+            .in_current_span(),
+        );
 
         *self.task.lock() = Some(handle);
         Ok(())
@@ -50,91 +91,351 @@ impl ScreenBroadcaster {
         if let Some(handle) = self.task.lock().take() {
             handle.abort();
         }
+        self.encoder.lock().take();
+        self.window_missing.store(false, Ordering::Relaxed);
+    }
+
+    /// Capture the screen once, with the broadcast settings in effect, and send it to every
+    /// student as a still that stays up until the next frame. Works without a broadcast.
+    pub async fn send_screenshot(&self) -> Result<()> {
+        let mut cfg = self.state.broadcast_config();
+        // Outside a broadcast no encoder has been probed; the CPU one always works.
+        cfg.encoder = self.active_encoder().unwrap_or(EncoderKind::Cpu);
+        let clock = self.state.session_clock();
+        let mode = self.state.broadcast_mode();
+        let frame = tokio::task::spawn_blocking(move || {
+            let source = ScreenSource::select(cfg.monitor_index)?;
+            let window = cfg.capture_window.clone();
+            // A fresh filter never holds back its first capture.
+            match capture_raw(
+                &source,
+                clock,
+                mode,
+                cfg,
+                &mut StaticScreenFilter::default(),
+            )? {
+                Captured::Frame(capture) => encode_capture(*capture),
+                Captured::Unchanged => bail!("截图被静态画面过滤"),
+                Captured::WindowMissing => bail!(
+                    "找不到标题包含“{}”的窗口，未发送截图",
+                    window.unwrap_or_default()
+                ),
+            }
+        })
+        .await
+        .map_err(|err| anyhow!("截图线程崩溃: {err}"))??;
+        let frame = VideoFrame {
+            frame_id: self.state.next_frame_id(),
+            still: true,
+            ..frame
+        };
+        info!(
+            frame_id = frame.frame_id,
+            width = frame.width,
+            height = frame.height,
+            "已向学生发送截图"
+        );
+        self.state.broadcast_still(frame);
+        Ok(())
     }
 }
 
-async fn capture_loop(state: Arc<TeacherState>, mode: BroadcastMode) -> Result<()> {
-    let base_cfg = state.broadcast_config();
-    let interval_duration = Duration::from_millis((1000.0 / base_cfg.fps as f64) as u64);
-    let primary = Screen::from_point(0, 0).context("无法找到主显示器")?;
-    let screen = Arc::new(primary);
-    let mut ticker = time::interval(interval_duration.max(Duration::from_millis(16)));
+/// Resolve the configured encoder to one that works on this machine.
+fn select_encoder(requested: EncoderKind) -> EncoderKind {
+    let active = match requested {
+        EncoderKind::Cpu => EncoderKind::Cpu,
+        EncoderKind::Turbojpeg => match probe_turbojpeg() {
+            Ok(()) => EncoderKind::Turbojpeg,
+            Err(err) => {
+                warn!(?err, "libjpeg-turbo 不可用，改用 image 编码器");
+                EncoderKind::Cpu
+            }
+        },
+    };
+    info!(encoder = ?active, "屏幕广播编码器");
+    active
+}
+
+/// Captures waiting for an encoder; the oldest is shed beyond this many.
+const CAPTURE_QUEUE_CAPACITY: usize = 2;
+/// JPEG encodes running at once.
+const ENCODE_WORKERS: usize = 2;
+/// How often the achieved frame rate is logged.
+const FPS_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A capture on its way to an encoder, with what the frame needs besides the JPEG.
+struct Capture {
+    raw: RawFrame,
+    frame_id: u64,
+    timestamp_ms: u64,
+    capture_monotonic_ms: u64,
+    fullscreen: bool,
+    cfg: BroadcastConfig,
+}
+
+/// What one capture produced.
+enum Captured {
+    Frame(Box<Capture>),
+    /// The screen has not changed since the last frame sent.
+    Unchanged,
+    /// `capture_window` is set but the window is not on the display. Nothing is captured
+    /// rather than the whole desktop, which may show what the window was meant to keep out.
+    WindowMissing,
+}
+
+/// Capture on every tick and hand the frames to [`ENCODE_WORKERS`] encoders, so encoding a
+/// large frame does not hold up the next capture. Dropping the future stops the workers.
+/// `window_missing` is kept up to date for the status.
+async fn capture_loop(
+    state: Arc<TeacherState>,
+    mode: BroadcastMode,
+    encoder: EncoderKind,
+    window_missing: Arc<AtomicBool>,
+) -> Result<()> {
+    let mut monitor = state.broadcast_config().monitor_index;
+    let mut screen = Arc::new(ScreenSource::select(monitor)?);
+    let clock = state.session_clock();
+    let mut fps = state.broadcast_config().fps;
+    let mut ticker = time::interval(frame_interval(fps));
+    let mut filter = StaticScreenFilter::default();
 
+    let queue = Arc::new(CaptureQueue::new(CAPTURE_QUEUE_CAPACITY));
+    let sent = Arc::new(AtomicU64::new(0));
+    let (done_tx, done_rx) = mpsc::unbounded_channel();
+    let mut workers = JoinSet::new();
+    for _ in 0..ENCODE_WORKERS {
+        workers.spawn(encode_worker(queue.clone(), done_tx.clone()).in_current_span());
+    }
+    workers.spawn(dispatch_frames(state.clone(), done_rx, sent.clone()).in_current_span());
+
+    let mut report = FpsReport::new(Instant::now());
     loop {
         ticker.tick().await;
 
-        let frame_id = state.next_frame_id();
-        let screen_clone = screen.clone();
-        let cfg = base_cfg.clone();
+        // Settings may change mid-broadcast; pick them up on every tick. The encoder stays
+        // the one resolved at start, so a fallback is not retried on every frame.
+        let mut cfg = state.broadcast_config();
+        cfg.encoder = encoder;
+        if cfg.fps != fps {
+            fps = cfg.fps;
+            ticker = time::interval(frame_interval(fps));
+            debug!(fps, "屏幕捕获帧率已更新");
+        }
+        if cfg.monitor_index != monitor {
+            monitor = cfg.monitor_index;
+            match ScreenSource::select(monitor) {
+                Ok(source) => screen = Arc::new(source),
+                Err(err) => warn!(?err, "切换显示器失败，继续捕获原显示器"),
+            }
+        }
 
-        let result =
-            tokio::task::spawn_blocking(move || capture_frame(screen_clone, frame_id, mode, &cfg))
-                .await;
+        let window = cfg.capture_window.clone();
+        let screen_clone = screen.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let capture = capture_raw(&screen_clone, clock, mode, cfg, &mut filter);
+            (capture, filter)
+        })
+        .await;
 
-        match result {
-            Ok(Ok(frame)) => {
-                state.broadcast_video(frame);
-            }
-            Ok(Err(err)) => {
-                warn!(?err, "屏幕捕获失败");
+        let capture = match result {
+            Ok((capture, returned)) => {
+                filter = returned;
+                capture
             }
             Err(join_err) => {
                 if join_err.is_cancelled() {
                     debug!("屏幕捕获任务已取消");
-                    break;
-                } else if let Some(err) = join_err.try_into_panic().err() {
-                    error!("屏幕捕获线程崩溃: {:?}", err);
-                    break;
+                } else {
+                    error!("屏幕捕获线程崩溃: {:?}", join_err);
+                }
+                break;
+            }
+        };
+        if matches!(capture, Ok(Captured::WindowMissing)) {
+            if !window_missing.swap(true, Ordering::Relaxed) {
+                warn!(
+                    ?window,
+                    "找不到要广播的窗口，已暂停广播画面，学生端停留在最后一帧"
+                );
+            }
+        } else if capture.is_ok() && window_missing.swap(false, Ordering::Relaxed) {
+            info!(?window, "广播画面已恢复");
+        }
+        match capture {
+            Ok(Captured::Frame(mut capture)) => {
+                report.captured += 1;
+                capture.frame_id = state.next_frame_id();
+                if queue.push(*capture) {
+                    report.shed += 1;
                 }
             }
+            Ok(Captured::Unchanged) => {
+                report.captured += 1;
+                report.unchanged += 1;
+            }
+            Ok(Captured::WindowMissing) => {}
+            Err(err) => warn!(?err, "屏幕捕获失败"),
         }
+        report.log_if_due(fps, sent.load(Ordering::Relaxed), Instant::now());
     }
 
     Ok(())
 }
 
-impl ScreenBroadcaster {
-    pub fn handle_disconnection(&self, _student_id: &str) {}
-}
-
-fn capture_frame(
-    screen: Arc<Screen>,
-    frame_id: u64,
+/// Capture the screen, limited to `capture_window` when one is set. The frame ID is left
+/// for the caller to assign.
+fn capture_raw(
+    source: &ScreenSource,
+    clock: SessionClock,
     mode: BroadcastMode,
-    cfg: &BroadcastConfig,
-) -> Result<VideoFrame> {
-    let image = screen.capture().context("执行屏幕截取失败")?;
-    let width = image.width();
-    let height = image.height();
-    let raw = image.into_raw();
-
-    let mut rgb = Vec::with_capacity((width * height * 3) as usize);
-    for pixel in raw.chunks_exact(4) {
-        // BGRA -> RGB
-        rgb.push(pixel[2]);
-        rgb.push(pixel[1]);
-        rgb.push(pixel[0]);
+    mut cfg: BroadcastConfig,
+    filter: &mut StaticScreenFilter,
+) -> Result<Captured> {
+    if let Some(title) = &cfg.capture_window {
+        // Looked up every capture so the broadcast follows the window around.
+        match source.window_rect(title) {
+            Some(rect) => cfg.capture_rect = Some(rect),
+            None => return Ok(Captured::WindowMissing),
+        }
     }
-
-    let mut jpeg_bytes = Vec::new();
-    {
-        let mut encoder = JpegEncoder::new_with_quality(&mut jpeg_bytes, cfg.jpeg_quality);
-        encoder.encode(&rgb, width as u32, height as u32, ColorType::Rgb8)?;
+    let capture_monotonic_ms = clock.elapsed_ms();
+    let raw = source.capture_with_cursor(cfg.show_cursor)?;
+    if !filter.should_send(&raw, &cfg, Instant::now()) {
+        return Ok(Captured::Unchanged);
     }
-
     let timestamp_ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_millis() as u64;
+    Ok(Captured::Frame(Box::new(Capture {
+        raw,
+        frame_id: 0,
+        timestamp_ms,
+        capture_monotonic_ms,
+        fullscreen: matches!(mode, BroadcastMode::Fullscreen),
+        cfg,
+    })))
+}
+
+/// Encode queued captures until the dispatcher goes away.
+async fn encode_worker(
+    queue: Arc<CaptureQueue<Capture>>,
+    done: mpsc::UnboundedSender<(u64, Result<VideoFrame>)>,
+) {
+    loop {
+        let (seq, capture) = queue.pop().await;
+        let frame = tokio::task::spawn_blocking(move || encode_capture(capture))
+            .await
+            .unwrap_or_else(|err| Err(anyhow!("JPEG 编码线程崩溃: {err}")));
+        if done.send((seq, frame)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Broadcast encoded frames in capture order, counting them in `sent`.
+async fn dispatch_frames(
+    state: Arc<TeacherState>,
+    mut done: mpsc::UnboundedReceiver<(u64, Result<VideoFrame>)>,
+    sent: Arc<AtomicU64>,
+) {
+    let mut order = InOrder::default();
+    while let Some((seq, frame)) = done.recv().await {
+        for frame in order.push(seq, frame) {
+            match frame {
+                Ok(frame) => {
+                    state.broadcast_video(frame);
+                    sent.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => warn!(?err, "屏幕帧编码失败"),
+            }
+        }
+    }
+}
 
+/// Frame counts since the last log line about the achieved frame rate.
+struct FpsReport {
+    since: Instant,
+    sent_before: u64,
+    captured: u64,
+    unchanged: u64,
+    shed: u64,
+}
+
+impl FpsReport {
+    fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            sent_before: 0,
+            captured: 0,
+            unchanged: 0,
+            shed: 0,
+        }
+    }
+
+    /// Log the rates every [`FPS_REPORT_INTERVAL`] and start counting afresh.
+    fn log_if_due(&mut self, target: u32, sent: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.since);
+        if elapsed < FPS_REPORT_INTERVAL {
+            return;
+        }
+        let rate = |count: u64| (count as f64 / elapsed.as_secs_f64() * 10.0).round() / 10.0;
+        let captured_fps = rate(self.captured);
+        let sent_fps = rate(sent - self.sent_before);
+        if captured_fps < f64::from(target) * 0.9 || self.shed > 0 {
+            warn!(
+                target_fps = target,
+                captured_fps,
+                sent_fps,
+                unchanged = self.unchanged,
+                shed = self.shed,
+                "屏幕广播未达到目标帧率"
+            );
+        } else {
+            info!(
+                target_fps = target,
+                captured_fps,
+                sent_fps,
+                unchanged = self.unchanged,
+                "屏幕广播帧率"
+            );
+        }
+        *self = Self {
+            sent_before: sent,
+            ..Self::new(now)
+        };
+    }
+}
+
+fn frame_interval(fps: u32) -> Duration {
+    Duration::from_millis((1000.0 / fps.max(1) as f64) as u64).max(Duration::from_millis(16))
+}
+
+impl ScreenBroadcaster {
+    /// End a student spotlight whose student went away, so the source does not stay wedged.
+    pub fn handle_disconnection(&self, student_id: &str) {
+        if !self.state.is_student_broadcasting(student_id) {
+            return;
+        }
+        self.state.set_broadcast_source(None, BroadcastMode::Window);
+        self.state.broadcast_command(BroadcastCommand::Stop);
+        info!(student_id, "被广播的学生已断开，广播已停止");
+    }
+}
+
+fn encode_capture(capture: Capture) -> Result<VideoFrame> {
+    let (jpeg_bytes, width, height) = encode_jpeg(&capture.raw, &capture.cfg)?;
     Ok(VideoFrame {
-        frame_id,
-        timestamp_ms,
+        frame_id: capture.frame_id,
+        timestamp_ms: capture.timestamp_ms,
+        capture_monotonic_ms: capture.capture_monotonic_ms,
         source: BroadcastSource::Teacher,
         codec: VideoCodec::Jpeg,
         width,
         height,
-        fullscreen: matches!(mode, BroadcastMode::Fullscreen),
-        data: jpeg_bytes,
+        fullscreen: capture.fullscreen,
+        thumbnail: false,
+        data: jpeg_bytes.into(),
+        still: false,
     })
 }
@@ -1,27 +1,103 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io::SeekFrom;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use parking_lot::{Mutex, RwLock};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
 use tokio::signal;
-use tokio::sync::{mpsc, oneshot};
-use tokio::time::Instant;
-use tracing::{error, info, warn};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{self, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
+use shared::frame::{list_monitors, window_bounds};
+use shared::net::Transport;
 use shared::prelude::*;
+use shared::tls::ServerTls;
 
 use crate::audio::AudioBroadcaster;
+use crate::control::{ControlCommand, ControlSocket, UnknownCommand};
+use crate::http_status::{StatusHttp, StatusReport};
+use crate::metrics::{CountingWriter, StudentCounters, StudentMetrics, LAGGING_QUEUE_DEPTH};
+use crate::pacing::SharePacer;
+use crate::queue::{is_file_data, Lanes, SendQueue};
+use crate::ratelimit::TokenBucket;
+use crate::recording::Recording;
 use crate::screen::ScreenBroadcaster;
 
+/// Number of per-student errors kept for the detail view.
+const RECENT_ERROR_LIMIT: usize = 5;
+/// How often the reaper looks for students whose heartbeats stopped.
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a console notice stays on student screens.
+pub const NOTICE_DURATION_MS: u64 = 10_000;
+/// Reason shown to a kicked student when the teacher gives none.
+pub const DEFAULT_KICK_REASON: &str = "你已被教师移出课堂";
+/// How long a closing connection may take to deliver what is left in its queue,
+/// such as the reason for a kick.
+const WRITER_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long shutting down waits for file sends to reach the students before giving up on
+/// them, so a stuck student cannot hold up the exit.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a draining shutdown checks whether the file data has gone out.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a sent file stays resumable for students whose connection dropped during it.
+const INTERRUPTED_FILE_TTL: Duration = Duration::from_secs(30 * 60);
+/// How long a student ID seen on two machines stays flagged in the status.
+const DUPLICATE_WARNING_WINDOW: Duration = Duration::from_secs(300);
+/// How long a refused spotlight stays in the status.
+const DECLINE_WARNING_WINDOW: Duration = Duration::from_secs(60);
+/// Chat lines kept for the control panel.
+const CHAT_LOG_LIMIT: usize = 200;
+/// Name teacher chat lines are shown under.
+pub const TEACHER_CHAT_NAME: &str = "教师";
+
 #[cfg(feature = "ui")]
 pub type CommandSender = mpsc::UnboundedSender<ServerCommand>;
 pub type CommandReceiver = mpsc::UnboundedReceiver<ServerCommand>;
+pub type EventSender = mpsc::UnboundedSender<ServerEvent>;
+
+/// Something that happened in the classroom, reported to whoever passed an [`EventSender`]
+/// to [`TeacherServer::new`], e.g. an application embedding the server with its own UI.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    StudentJoined {
+        student_id: String,
+        student_name: String,
+        addr: SocketAddr,
+    },
+    StudentLeft {
+        student_id: String,
+    },
+    /// A broadcast started or switched to another source.
+    BroadcastStarted {
+        source: BroadcastSource,
+        mode: BroadcastMode,
+    },
+    BroadcastStopped,
+    /// A student upload finished and was saved to `path`.
+    FileReceived {
+        student_id: String,
+        path: PathBuf,
+        size: u64,
+    },
+    HandRaised {
+        student_id: String,
+        raised: bool,
+    },
+    ChatReceived {
+        student_id: String,
+        text: String,
+    },
+}
 
 #[derive(Debug)]
 pub enum ServerCommand {
@@ -36,11 +112,84 @@ pub enum ServerCommand {
     StopBroadcast {
         respond_to: Option<oneshot::Sender<Result<(), String>>>,
     },
+    /// Capture the screen once and leave it on every student screen.
+    SendScreenshot {
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
     SendFile {
         path: PathBuf,
         auto_open_override: bool,
         respond_to: Option<oneshot::Sender<Result<(), String>>>,
     },
+    SendFileTo {
+        student_id: String,
+        path: PathBuf,
+        auto_open_override: bool,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    CancelFile {
+        transfer_id: Uuid,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    Blackout {
+        enabled: bool,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    SendNotice {
+        notice: Notice,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    /// Chat to one student, or to the whole class when `student_id` is `None`.
+    SendChat {
+        student_id: Option<String>,
+        text: String,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    KickStudent {
+        student_id: String,
+        reason: String,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    Thumbnails {
+        interval_secs: u64,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    StartRecording {
+        path: PathBuf,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    StopRecording {
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    SetBroadcastQuality {
+        jpeg_quality: Option<u8>,
+        fps: Option<u32>,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    /// Capture display `index` of `list_monitors`, or the primary display when `None`.
+    SetMonitor {
+        index: Option<usize>,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    /// Broadcast only the window whose title contains `title`, or the whole display again
+    /// when `None`.
+    SetCaptureWindow {
+        title: Option<String>,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    /// Black out these parts of the captured display, replacing the previous set.
+    SetRedactRects {
+        rects: Vec<Rect>,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    Zoom {
+        action: ZoomAction,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
+    SpotlightTimer {
+        action: SpotlightTimerAction,
+        respond_to: Option<oneshot::Sender<Result<(), String>>>,
+    },
     AudioStart {
         respond_to: Option<oneshot::Sender<Result<(), String>>>,
     },
@@ -52,18 +201,68 @@ pub enum ServerCommand {
         respond_to: Option<oneshot::Sender<Result<(), String>>>,
     },
     #[cfg(feature = "ui")]
+    QueryStudent {
+        student_id: String,
+        respond_to: oneshot::Sender<Result<StudentDetail, String>>,
+    },
+    #[cfg(feature = "ui")]
     ListStudents {
         respond_to: oneshot::Sender<Result<Vec<StudentSummary>, String>>,
     },
     #[cfg(feature = "ui")]
+    QueryMetrics {
+        respond_to: oneshot::Sender<Vec<StudentMetrics>>,
+    },
+    #[cfg(feature = "ui")]
     QueryStatus {
         respond_to: oneshot::Sender<ServerStatus>,
     },
+    #[cfg(feature = "ui")]
+    ListMonitors {
+        respond_to: oneshot::Sender<Result<Vec<shared::frame::MonitorInfo>, String>>,
+    },
+    #[cfg(feature = "ui")]
+    QueryChat {
+        respond_to: oneshot::Sender<Vec<ChatEntry>>,
+    },
     Quit,
 }
 
-#[cfg(feature = "ui")]
+#[derive(Debug, Clone, Copy)]
+pub enum SpotlightTimerAction {
+    /// Push the auto-revert back by the given number of seconds.
+    Extend(u64),
+    /// Keep the current spotlight until it is stopped manually.
+    Cancel,
+}
+
 #[derive(Debug, Clone)]
+pub enum ZoomAction {
+    /// Apply the pending zoom request of this student to the whole class.
+    Accept(String),
+    /// Drop the pending zoom request of this student.
+    Reject(String),
+    /// Broadcast the full screen again.
+    Reset,
+}
+
+/// Latest monitoring snapshot of one student.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub frame: VideoFrame,
+    pub received: Instant,
+}
+
+/// The running student spotlight and what to go back to once it ends.
+struct SpotlightTimer {
+    student_id: String,
+    /// Teacher broadcast mode to restore, or `None` to go idle.
+    revert_to: Option<BroadcastMode>,
+    /// When `max_spotlight_secs` ends it; `None` when not time limited.
+    deadline: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ServerStatus {
     pub listen_addr: String,
     pub broadcast_mode: BroadcastMode,
@@ -71,6 +270,43 @@ pub struct ServerStatus {
     pub audio_enabled: bool,
     pub audio_forced: bool,
     pub connected_students: usize,
+    /// Whether the broadcast is cropped to a zoomed region.
+    pub zoomed: bool,
+    /// Seconds until the current student spotlight reverts, if it is time limited.
+    pub spotlight_remaining_secs: Option<u64>,
+    /// Encoder of the running teacher broadcast, after any fallback to the CPU.
+    pub encoder: Option<EncoderKind>,
+    /// Whether the teacher broadcast is paused because `capture_window` is not on the display.
+    pub capture_window_missing: bool,
+    /// Display the teacher broadcast captures; `None` for the primary display.
+    pub monitor_index: Option<usize>,
+    /// Live JPEG quality and frame rate of the broadcast.
+    pub jpeg_quality: u8,
+    pub fps: u32,
+    /// Parts of the captured display blacked out before encoding.
+    pub redact_rects: Vec<Rect>,
+    /// File currently being sent to all students.
+    pub file_progress: Option<FileSendProgress>,
+    /// Whether student screens are blacked out.
+    pub blackout: bool,
+    /// Directory the broadcast is being recorded into.
+    pub recording: Option<PathBuf>,
+    /// Student with the most messages waiting in its send queue, and that queue depth.
+    pub worst_queue: Option<(String, usize)>,
+    /// Student IDs that recently connected from two machines at once, sorted.
+    pub duplicate_student_ids: Vec<String>,
+    /// Student who recently refused a spotlight request.
+    pub spotlight_declined_by: Option<String>,
+}
+
+/// How much of a file has been queued for the students so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSendProgress {
+    pub transfer_id: Uuid,
+    pub file_name: String,
+    /// Bytes pushed into the student send queues.
+    pub sent: u64,
+    pub total: u64,
 }
 
 pub struct TeacherServer {
@@ -78,40 +314,81 @@ pub struct TeacherServer {
     screen: ScreenBroadcaster,
     audio: AudioBroadcaster,
     running: AtomicBool,
+    spotlight_timer: Mutex<Option<SpotlightTimer>>,
+    /// Name of the student who last refused a spotlight, and when.
+    last_declined: Mutex<Option<(String, Instant)>>,
 }
 
 impl TeacherServer {
-    pub fn new(config: TeacherConfig) -> Result<Self> {
+    /// Server for `config`; events are reported on `event_tx` when one is given.
+    pub fn new(config: TeacherConfig, event_tx: Option<EventSender>) -> Result<Self> {
         let config = Arc::new(config);
-        let state = Arc::new(TeacherState::new(config.clone()));
+        let mut state = TeacherState::new(config.clone());
+        state.events = event_tx;
+        let state = Arc::new(state);
         let screen = ScreenBroadcaster::new(state.clone());
-        let audio = AudioBroadcaster::new(state.clone(), config.force_audio);
+        let audio = AudioBroadcaster::new(
+            state.clone(),
+            config.force_audio,
+            config.audio_input_device.clone(),
+        );
         Ok(Self {
             state,
             screen,
             audio,
             running: AtomicBool::new(false),
+            spotlight_timer: Mutex::new(None),
+            last_declined: Mutex::new(None),
         })
     }
 
     pub async fn run(
         &self,
-        auto_start_broadcast: bool,
+        auto_start: Option<BroadcastMode>,
+        command_rx: Option<CommandReceiver>,
+    ) -> Result<()> {
+        // Students adopt the id from `HelloAck`, so both sides' logs of this run carry it.
+        let span = info_span!("session", session = %self.state.session_id);
+        self.serve(auto_start, command_rx).instrument(span).await
+    }
+
+    async fn serve(
+        &self,
+        auto_start: Option<BroadcastMode>,
         command_rx: Option<CommandReceiver>,
     ) -> Result<()> {
         if self.running.swap(true, Ordering::SeqCst) {
             bail!("Teacher server already running");
         }
 
-        let addr = self.state.config.listen_addr();
-        info!(%addr, "教师端监听启动");
-        let listener = TcpListener::bind(&addr)
-            .await
-            .with_context(|| format!("无法监听 {addr}"))?;
+        let tls = match (
+            &self.state.config.tls_cert_path,
+            &self.state.config.tls_key_path,
+        ) {
+            (Some(cert), Some(key)) => Some(ServerTls::load(cert, key)?),
+            (None, None) => None,
+            _ => bail!("tls_cert_path 与 tls_key_path 需同时设置"),
+        };
 
-        if auto_start_broadcast {
-            self.start_teacher_broadcast(BroadcastMode::Fullscreen)
-                .await?;
+        let addrs = self.state.config.bind_addrs();
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in &addrs {
+            match TcpListener::bind(addr).await {
+                Ok(listener) => {
+                    info!(%addr, tls = tls.is_some(), "教师端监听启动");
+                    listeners.push(listener);
+                }
+                Err(err) => warn!(?err, %addr, "无法监听该地址，已跳过"),
+            }
+        }
+        ensure!(
+            !listeners.is_empty(),
+            "无法监听任何地址: {}",
+            addrs.join(", ")
+        );
+
+        if let Some(mode) = auto_start {
+            self.start_teacher_broadcast(mode).await?;
         }
 
         if self.state.config.enable_audio_by_default {
@@ -120,49 +397,89 @@ impl TeacherServer {
             }
         }
 
-        let state = self.state.clone();
-        let screen = self.screen.clone();
-        let accept_task = tokio::spawn(async move {
-            loop {
-                match listener.accept().await {
-                    Ok((stream, addr)) => {
-                        let state = state.clone();
-                        let screen = screen.clone();
-                        tokio::spawn(async move {
-                            if let Err(err) =
-                                handle_student_connection(state, screen, stream, addr).await
-                            {
-                                error!(?err, %addr, "学生连接异常");
-                            }
-                        });
-                    }
+        let accept_tasks: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| {
+                tokio::spawn(
+                    accept_students(
+                        listener,
+                        self.state.clone(),
+                        self.screen.clone(),
+                        tls.clone(),
+                    )
+                    .in_current_span(),
+                )
+            })
+            .collect();
+
+        let reaper_task = (self.state.config.idle_timeout_secs > 0)
+            .then(|| tokio::spawn(reap_idle_students(self.state.clone()).in_current_span()));
+        let heartbeat_task = (self.state.config.heartbeat_interval_secs > 0)
+            .then(|| tokio::spawn(send_heartbeats(self.state.clone()).in_current_span()));
+
+        let mut status_http = match self.state.config.status_http_port {
+            Some(port) => {
+                let addr = format!("{}:{port}", self.state.config.listen_host);
+                match StatusHttp::start(&addr).await {
+                    Ok(http) => Some(http),
                     Err(err) => {
-                        error!(?err, "监听器异常");
-                        break;
+                        warn!(?err, "状态页启动失败");
+                        None
                     }
                 }
             }
-        });
+            None => None,
+        };
+
+        let mut control = match ControlSocket::start(&self.state.config).await {
+            Ok(control) => control,
+            Err(err) => {
+                warn!(?err, "控制接口启动失败");
+                None
+            }
+        };
 
         let console_enabled = command_rx.is_none();
         if console_enabled {
             info!("输入 help 查看命令");
         }
         tokio::select! {
-            result = self.command_loop(command_rx, console_enabled) => {
+            result = self.command_loop(command_rx, console_enabled, control.as_mut()) => {
                 if let Err(err) = result {
                     error!(?err, "命令循环异常");
                 }
             }
+            _ = self.answer_status_requests(status_http.as_mut()) => {}
             _ = signal::ctrl_c() => {
                 info!("收到退出信号");
             }
         }
 
         self.running.store(false, Ordering::SeqCst);
+        for task in accept_tasks {
+            task.abort();
+        }
+        if let Some(http) = status_http {
+            http.shutdown().await;
+        }
+        if let Some(control) = control {
+            control.shutdown();
+        }
+        if let Err(err) = self.stop_recording().await {
+            warn!(?err, "结束录制失败");
+        }
         self.screen.stop().await;
         self.audio.stop().await;
-        accept_task.abort();
+        // Heartbeats keep going meanwhile so students do not give up on a long drain.
+        tokio::select! {
+            _ = self.state.drain_file_sends(SHUTDOWN_DRAIN_TIMEOUT) => {}
+            _ = signal::ctrl_c() => {
+                warn!("再次收到退出信号，不再等待文件发送");
+            }
+        }
+        for task in reaper_task.into_iter().chain(heartbeat_task) {
+            task.abort();
+        }
         self.state.disconnect_all();
         Ok(())
     }
@@ -171,6 +488,7 @@ impl TeacherServer {
         &self,
         mut external: Option<CommandReceiver>,
         enable_console: bool,
+        mut control: Option<&mut ControlSocket>,
     ) -> Result<()> {
         let mut lines = if enable_console {
             Some(BufReader::new(tokio::io::stdin()).lines())
@@ -179,7 +497,20 @@ impl TeacherServer {
         };
 
         loop {
+            let spotlight_deadline = self.spotlight_deadline();
             tokio::select! {
+                _ = time::sleep_until(spotlight_deadline.unwrap_or_else(Instant::now)),
+                    if spotlight_deadline.is_some() =>
+                {
+                    if let Err(err) = self.expire_spotlight().await {
+                        error!(?err, "自动结束学生广播失败");
+                    }
+                }
+                _ = self.state.spotlight_declined.notified() => {
+                    if let Err(err) = self.end_declined_spotlight().await {
+                        error!(?err, "结束被拒绝的学生广播失败");
+                    }
+                }
                 maybe_cmd = async {
                     if let Some(rx) = external.as_mut() {
                         rx.recv().await
@@ -204,14 +535,41 @@ impl TeacherServer {
                     }
                 }, if lines.is_some() => {
                     match line {
-                        Ok(Some(content)) => {
-                            if self.handle_console_command(content).await? {
+                        Ok(Some(content)) => match self.handle_console_command(content).await {
+                            Ok(true) => break,
+                            Ok(false) => {}
+                            Err(err) => match err.downcast::<UnknownCommand>() {
+                                Ok(UnknownCommand(other)) => warn!(%other, "未知命令"),
+                                Err(err) => return Err(err),
+                            },
+                        },
+                        // Without a terminal, scripts may still drive the server.
+                        Ok(None) if control.is_some() => lines = None,
+                        Ok(None) => break,
+                        Err(err) => {
+                            error!(?err, "读取命令失败");
+                        }
+                    }
+                }
+                command = async {
+                    match control.as_mut() {
+                        Some(control) => control.next_command().await,
+                        None => None,
+                    }
+                }, if control.is_some() => {
+                    let Some(ControlCommand { line, reply }) = command else {
+                        control = None;
+                        continue;
+                    };
+                    match self.handle_console_command(line).await {
+                        Ok(quit) => {
+                            let _ = reply.send(Ok(()));
+                            if quit {
                                 break;
                             }
                         }
-                        Ok(None) => break,
                         Err(err) => {
-                            error!(?err, "读取命令失败");
+                            let _ = reply.send(Err(err));
                         }
                     }
                 }
@@ -238,6 +596,18 @@ impl TeacherServer {
                 self.print_students();
                 Ok(false)
             }
+            "metrics" => {
+                self.print_metrics();
+                Ok(false)
+            }
+            "info" => {
+                if let Some(student_id) = parts.next() {
+                    self.print_student_detail(student_id);
+                } else {
+                    warn!("用法: info <student_id>");
+                }
+                Ok(false)
+            }
             "start" => {
                 let mode = match parts.next() {
                     Some("window") => BroadcastMode::Window,
@@ -274,6 +644,67 @@ impl TeacherServer {
                     Ok(false)
                 }
             }
+            "zoom" => {
+                let action = match (parts.next(), parts.next()) {
+                    (Some("accept"), Some(student_id)) => {
+                        ZoomAction::Accept(student_id.to_string())
+                    }
+                    (Some("reject"), Some(student_id)) => {
+                        ZoomAction::Reject(student_id.to_string())
+                    }
+                    (Some("reset"), None) => ZoomAction::Reset,
+                    _ => {
+                        warn!("用法: zoom <accept|reject> <student_id> 或 zoom reset");
+                        return Ok(false);
+                    }
+                };
+                self.invoke_console_command(
+                    ServerCommand::Zoom {
+                        action,
+                        respond_to: None,
+                    },
+                    "处理放大请求失败",
+                )
+                .await
+            }
+            "timer" => match parts.next() {
+                None => {
+                    self.print_spotlight_timer();
+                    Ok(false)
+                }
+                Some("extend") => {
+                    let secs = match parts.next().map(str::parse::<u64>) {
+                        Some(Ok(secs)) => secs,
+                        Some(Err(_)) => {
+                            warn!("用法: timer extend [秒]");
+                            return Ok(false);
+                        }
+                        None => self.state.config.max_spotlight_secs,
+                    };
+                    self.invoke_console_command(
+                        ServerCommand::SpotlightTimer {
+                            action: SpotlightTimerAction::Extend(secs),
+                            respond_to: None,
+                        },
+                        "延长学生广播失败",
+                    )
+                    .await
+                }
+                Some("cancel") => {
+                    self.invoke_console_command(
+                        ServerCommand::SpotlightTimer {
+                            action: SpotlightTimerAction::Cancel,
+                            respond_to: None,
+                        },
+                        "取消学生广播计时失败",
+                    )
+                    .await
+                }
+                _ => {
+                    warn!("用法: timer [extend [秒]|cancel]");
+                    Ok(false)
+                }
+            },
             "send" => {
                 if let Some(path) = parts.next() {
                     let auto_open = matches!(parts.next(), Some("open"));
@@ -291,60 +722,353 @@ impl TeacherServer {
                     Ok(false)
                 }
             }
-            "audio" => match parts.next() {
-                Some("on") => {
+            "sendto" => {
+                if let (Some(student_id), Some(path)) = (parts.next(), parts.next()) {
+                    let auto_open = matches!(parts.next(), Some("open"));
                     self.invoke_console_command(
-                        ServerCommand::AudioStart { respond_to: None },
-                        "音频广播启动失败",
+                        ServerCommand::SendFileTo {
+                            student_id: student_id.to_string(),
+                            path: PathBuf::from(path),
+                            auto_open_override: auto_open,
+                            respond_to: None,
+                        },
+                        "文件发送失败",
                     )
                     .await
+                } else {
+                    warn!("用法: sendto <ID> <路径> [open]");
+                    Ok(false)
                 }
-                Some("off") => {
+            }
+            "cancel" => match self.state.current_file_send() {
+                Some(transfer_id) => {
                     self.invoke_console_command(
-                        ServerCommand::AudioStop { respond_to: None },
-                        "停止音频广播失败",
+                        ServerCommand::CancelFile {
+                            transfer_id,
+                            respond_to: None,
+                        },
+                        "取消文件分发失败",
                     )
                     .await
                 }
-                Some("force") => {
+                None => {
+                    warn!("当前没有正在分发的文件");
+                    Ok(false)
+                }
+            },
+            "notice" => {
+                let text = trimmed["notice".len()..].trim();
+                if text.is_empty() {
+                    warn!("用法: notice <文字>");
+                    return Ok(false);
+                }
+                self.invoke_console_command(
+                    ServerCommand::SendNotice {
+                        notice: Notice {
+                            text: text.to_string(),
+                            duration_ms: NOTICE_DURATION_MS,
+                            severity: NoticeSeverity::Info,
+                        },
+                        respond_to: None,
+                    },
+                    "发送通知失败",
+                )
+                .await
+            }
+            "say" => {
+                let text = trimmed["say".len()..].trim();
+                if text.is_empty() {
+                    warn!("用法: say <文字>");
+                    return Ok(false);
+                }
+                self.invoke_console_command(
+                    ServerCommand::SendChat {
+                        student_id: None,
+                        text: text.to_string(),
+                        respond_to: None,
+                    },
+                    "发送聊天消息失败",
+                )
+                .await
+            }
+            "tell" => {
+                let (Some(student_id), text) = (
+                    parts.next(),
+                    trimmed["tell".len()..]
+                        .trim()
+                        .split_once(char::is_whitespace),
+                ) else {
+                    warn!("用法: tell <ID> <文字>");
+                    return Ok(false);
+                };
+                let Some((_, text)) = text else {
+                    warn!("用法: tell <ID> <文字>");
+                    return Ok(false);
+                };
+                self.invoke_console_command(
+                    ServerCommand::SendChat {
+                        student_id: Some(student_id.to_string()),
+                        text: text.trim().to_string(),
+                        respond_to: None,
+                    },
+                    "发送聊天消息失败",
+                )
+                .await
+            }
+            "kick" => {
+                let Some(student_id) = parts.next() else {
+                    warn!("用法: kick <ID> [原因]");
+                    return Ok(false);
+                };
+                let reason = trimmed["kick".len()..].trim()[student_id.len()..].trim();
+                let reason = if reason.is_empty() {
+                    DEFAULT_KICK_REASON
+                } else {
+                    reason
+                };
+                self.invoke_console_command(
+                    ServerCommand::KickStudent {
+                        student_id: student_id.to_string(),
+                        reason: reason.to_string(),
+                        respond_to: None,
+                    },
+                    "移出学生失败",
+                )
+                .await
+            }
+            "thumbs" => {
+                let interval_secs = match parts.next() {
+                    Some("off") => Some(0),
+                    Some(value) => value.parse::<u64>().ok().filter(|secs| *secs > 0),
+                    None => None,
+                };
+                let Some(interval_secs) = interval_secs else {
+                    warn!("用法: thumbs <秒|off>");
+                    return Ok(false);
+                };
+                self.invoke_console_command(
+                    ServerCommand::Thumbnails {
+                        interval_secs,
+                        respond_to: None,
+                    },
+                    "设置学生缩略图失败",
+                )
+                .await
+            }
+            "record" => match (parts.next(), parts.next()) {
+                (Some("start"), Some(path)) => {
                     self.invoke_console_command(
-                        ServerCommand::AudioForce {
-                            force: true,
+                        ServerCommand::StartRecording {
+                            path: PathBuf::from(path),
                             respond_to: None,
                         },
-                        "设置音频强制播放失败",
+                        "开始录制失败",
                     )
                     .await
                 }
-                Some("allow") => {
+                (Some("stop"), None) => {
                     self.invoke_console_command(
-                        ServerCommand::AudioForce {
-                            force: false,
-                            respond_to: None,
-                        },
-                        "取消音频强制播放失败",
+                        ServerCommand::StopRecording { respond_to: None },
+                        "停止录制失败",
                     )
                     .await
                 }
                 _ => {
-                    warn!("用法: audio <on|off|force|allow>");
+                    warn!("用法: record start <目录> | record stop");
                     Ok(false)
                 }
             },
-            "quit" | "exit" => self.execute_command(ServerCommand::Quit).await,
-            other => {
-                warn!(%other, "未知命令");
-                Ok(false)
+            "snap" => {
+                self.invoke_console_command(
+                    ServerCommand::SendScreenshot { respond_to: None },
+                    "发送截图失败",
+                )
+                .await
             }
-        }
-    }
-
-    async fn invoke_console_command(
-        &self,
-        command: ServerCommand,
-        error_message: &str,
-    ) -> Result<bool> {
-        match self.execute_command(command).await {
+            "blackout" => {
+                let enabled = match parts.next() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => {
+                        warn!("用法: blackout <on|off>");
+                        return Ok(false);
+                    }
+                };
+                self.invoke_console_command(
+                    ServerCommand::Blackout {
+                        enabled,
+                        respond_to: None,
+                    },
+                    "切换学生黑屏失败",
+                )
+                .await
+            }
+            "audio" => match parts.next() {
+                Some("on") => {
+                    self.invoke_console_command(
+                        ServerCommand::AudioStart { respond_to: None },
+                        "音频广播启动失败",
+                    )
+                    .await
+                }
+                Some("off") => {
+                    self.invoke_console_command(
+                        ServerCommand::AudioStop { respond_to: None },
+                        "停止音频广播失败",
+                    )
+                    .await
+                }
+                Some("force") => {
+                    self.invoke_console_command(
+                        ServerCommand::AudioForce {
+                            force: true,
+                            respond_to: None,
+                        },
+                        "设置音频强制播放失败",
+                    )
+                    .await
+                }
+                Some("allow") => {
+                    self.invoke_console_command(
+                        ServerCommand::AudioForce {
+                            force: false,
+                            respond_to: None,
+                        },
+                        "取消音频强制播放失败",
+                    )
+                    .await
+                }
+                _ => {
+                    warn!("用法: audio <on|off|force|allow>");
+                    Ok(false)
+                }
+            },
+            "monitor" => match parts.next() {
+                None | Some("list") => {
+                    self.print_monitors();
+                    Ok(false)
+                }
+                Some(value) => {
+                    let index = match value {
+                        "primary" => None,
+                        value => match value.parse() {
+                            Ok(index) => Some(index),
+                            Err(_) => {
+                                warn!("用法: monitor [list|primary|<编号>]");
+                                return Ok(false);
+                            }
+                        },
+                    };
+                    self.invoke_console_command(
+                        ServerCommand::SetMonitor {
+                            index,
+                            respond_to: None,
+                        },
+                        "切换显示器失败",
+                    )
+                    .await
+                }
+            },
+            "window" => {
+                let title = trimmed["window".len()..].trim();
+                let title = match title {
+                    "" => {
+                        warn!("用法: window <窗口标题中的文字> 或 window off");
+                        return Ok(false);
+                    }
+                    "off" => None,
+                    title => Some(title.to_string()),
+                };
+                self.invoke_console_command(
+                    ServerCommand::SetCaptureWindow {
+                        title,
+                        respond_to: None,
+                    },
+                    "切换广播窗口失败",
+                )
+                .await
+            }
+            "redact" => {
+                let args: Vec<&str> = parts.collect();
+                let rects = match args.as_slice() {
+                    [] | ["list"] => {
+                        self.print_redact_rects();
+                        return Ok(false);
+                    }
+                    ["clear"] => Vec::new(),
+                    [x, y, width, height] => {
+                        let parsed = (x.parse(), y.parse(), width.parse(), height.parse());
+                        let (Ok(x), Ok(y), Ok(width), Ok(height)) = parsed else {
+                            warn!("用法: redact [list|clear|<x> <y> <宽> <高>]（像素）");
+                            return Ok(false);
+                        };
+                        let mut rects = self.state.broadcast_config().redact_rects;
+                        rects.push(Rect {
+                            x,
+                            y,
+                            width,
+                            height,
+                        });
+                        rects
+                    }
+                    _ => {
+                        warn!("用法: redact [list|clear|<x> <y> <宽> <高>]（像素）");
+                        return Ok(false);
+                    }
+                };
+                self.invoke_console_command(
+                    ServerCommand::SetRedactRects {
+                        rects,
+                        respond_to: None,
+                    },
+                    "更新遮挡区域失败",
+                )
+                .await
+            }
+            "fps" | "quality" => {
+                let command = trimmed.split_whitespace().next().unwrap_or("");
+                let Some(value) = parts.next() else {
+                    warn!("用法: fps <1-60> 或 quality <1-100>");
+                    return Ok(false);
+                };
+                let (jpeg_quality, fps) = if command == "fps" {
+                    match value.parse() {
+                        Ok(fps) => (None, Some(fps)),
+                        Err(_) => {
+                            warn!(%value, "无效的帧率");
+                            return Ok(false);
+                        }
+                    }
+                } else {
+                    match value.parse() {
+                        Ok(quality) => (Some(quality), None),
+                        Err(_) => {
+                            warn!(%value, "无效的 JPEG 质量");
+                            return Ok(false);
+                        }
+                    }
+                };
+                self.invoke_console_command(
+                    ServerCommand::SetBroadcastQuality {
+                        jpeg_quality,
+                        fps,
+                        respond_to: None,
+                    },
+                    "更新广播参数失败",
+                )
+                .await
+            }
+            "quit" | "exit" => self.execute_command(ServerCommand::Quit).await,
+            other => Err(UnknownCommand(other.to_string()).into()),
+        }
+    }
+
+    async fn invoke_console_command(
+        &self,
+        command: ServerCommand,
+        error_message: &str,
+    ) -> Result<bool> {
+        match self.execute_command(command).await {
             Ok(should_exit) => Ok(should_exit),
             Err(err) => {
                 error!(?err, "{error_message}");
@@ -411,7 +1135,147 @@ impl TeacherServer {
                 auto_open_override,
                 respond_to,
             } => {
-                let result = self.send_file_to_all(path, auto_open_override).await;
+                // Runs in the background so status queries (and progress) keep flowing.
+                let state = self.state.clone();
+                tokio::spawn(
+                    async move {
+                        let result = state.send_file_to_all(path, auto_open_override).await;
+                        if let Err(err) = &result {
+                            error!(?err, "文件分发失败");
+                        }
+                        if let Some(tx) = respond_to {
+                            let _ = tx.send(result.map_err(|err| format!("{:#}", err)));
+                        }
+                    }
+                    .in_current_span(),
+                );
+                Ok(false)
+            }
+            ServerCommand::SendFileTo {
+                student_id,
+                path,
+                auto_open_override,
+                respond_to,
+            } => {
+                let state = self.state.clone();
+                tokio::spawn(
+                    async move {
+                        let result = state
+                            .send_file_to_student(&student_id, path, auto_open_override)
+                            .await;
+                        if let Err(err) = &result {
+                            error!(?err, student_id, "文件发送失败");
+                        }
+                        if let Some(tx) = respond_to {
+                            let _ = tx.send(result.map_err(|err| format!("{:#}", err)));
+                        }
+                    }
+                    .in_current_span(),
+                );
+                Ok(false)
+            }
+            ServerCommand::CancelFile {
+                transfer_id,
+                respond_to,
+            } => {
+                let result = self.state.cancel_file(transfer_id);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SetBroadcastQuality {
+                jpeg_quality,
+                fps,
+                respond_to,
+            } => {
+                let result = self.set_broadcast_quality(jpeg_quality, fps);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SetMonitor { index, respond_to } => {
+                let result = self.set_monitor(index);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SetCaptureWindow { title, respond_to } => {
+                let result = self.set_capture_window(title);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SetRedactRects { rects, respond_to } => {
+                self.set_redact_rects(rects);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(Ok(()));
+                }
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::ListMonitors { respond_to } => {
+                let monitors = tokio::task::spawn_blocking(list_monitors).await?;
+                let _ = respond_to.send(monitors.map_err(|err| format!("{:#}", err)));
+                Ok(false)
+            }
+            ServerCommand::Zoom { action, respond_to } => {
+                let result = self.handle_zoom(action);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SpotlightTimer { action, respond_to } => {
+                let result = self.adjust_spotlight_timer(action);
                 if let Some(tx) = respond_to {
                     let _ = tx.send(
                         result
@@ -449,45 +1313,236 @@ impl TeacherServer {
                 }
                 Ok(false)
             }
-            ServerCommand::AudioForce { force, respond_to } => {
-                self.audio.set_force_play(force);
+            ServerCommand::SendNotice { notice, respond_to } => {
+                info!(text = %notice.text, "向学生发送通知");
+                self.state.broadcast_notice(notice);
                 if let Some(tx) = respond_to {
                     let _ = tx.send(Ok(()));
                 }
                 Ok(false)
             }
-            #[cfg(feature = "ui")]
-            ServerCommand::ListStudents { respond_to } => {
-                let list = self.state.list_students();
-                let _ = respond_to.send(Ok(list));
+            ServerCommand::SendChat {
+                student_id,
+                text,
+                respond_to,
+            } => {
+                let result = self.state.send_chat(student_id.as_deref(), &text);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
                 Ok(false)
             }
-            #[cfg(feature = "ui")]
-            ServerCommand::QueryStatus { respond_to } => {
-                let status = self.status_snapshot();
-                let _ = respond_to.send(status);
+            ServerCommand::KickStudent {
+                student_id,
+                reason,
+                respond_to,
+            } => {
+                let result = self.state.kick_student(&student_id, &reason);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
                 Ok(false)
             }
-            ServerCommand::Quit => Ok(true),
-        }
-    }
-
-    #[cfg(feature = "ui")]
-    fn status_snapshot(&self) -> ServerStatus {
-        let students = self.state.list_students();
-        ServerStatus {
-            listen_addr: self.state.config().listen_addr(),
-            broadcast_mode: self.state.broadcast_mode(),
-            broadcast_source: self.state.broadcast_source(),
-            audio_enabled: self.audio.is_running(),
+            ServerCommand::Thumbnails {
+                interval_secs,
+                respond_to,
+            } => {
+                self.state.set_thumbnail_interval(interval_secs);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(Ok(()));
+                }
+                Ok(false)
+            }
+            ServerCommand::StartRecording { path, respond_to } => {
+                let result = self.state.start_recording(&path);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::StopRecording { respond_to } => {
+                let result = self.stop_recording().await;
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::SendScreenshot { respond_to } => {
+                let result = self.screen.send_screenshot().await;
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(
+                        result
+                            .as_ref()
+                            .map(|_| ())
+                            .map_err(|err| format!("{:#}", err)),
+                    );
+                    if result.is_err() {
+                        return Ok(false);
+                    }
+                }
+                result?;
+                Ok(false)
+            }
+            ServerCommand::Blackout {
+                enabled,
+                respond_to,
+            } => {
+                self.state.set_blackout(enabled);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(Ok(()));
+                }
+                Ok(false)
+            }
+            ServerCommand::AudioForce { force, respond_to } => {
+                self.audio.set_force_play(force);
+                if let Some(tx) = respond_to {
+                    let _ = tx.send(Ok(()));
+                }
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::QueryStudent {
+                student_id,
+                respond_to,
+            } => {
+                let detail = self
+                    .state
+                    .student_detail(&student_id)
+                    .ok_or_else(|| format!("学生 {student_id} 不在线"));
+                let _ = respond_to.send(detail);
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::ListStudents { respond_to } => {
+                let list = self.state.list_students();
+                let _ = respond_to.send(Ok(list));
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::QueryMetrics { respond_to } => {
+                let _ = respond_to.send(self.state.student_metrics());
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::QueryStatus { respond_to } => {
+                let status = self.status_snapshot();
+                let _ = respond_to.send(status);
+                Ok(false)
+            }
+            #[cfg(feature = "ui")]
+            ServerCommand::QueryChat { respond_to } => {
+                let _ = respond_to.send(self.state.chat_log());
+                Ok(false)
+            }
+            ServerCommand::Quit => Ok(true),
+        }
+    }
+
+    /// Flush and close the active recording, if any.
+    async fn stop_recording(&self) -> Result<()> {
+        let Some(recording) = self.state.take_recording() else {
+            return Ok(());
+        };
+        let dir = recording.dir().to_path_buf();
+        let frames = tokio::task::spawn_blocking(move || recording.stop()).await??;
+        info!(dir = %dir.display(), frames, "录制已结束");
+        Ok(())
+    }
+
+    fn status_snapshot(&self) -> ServerStatus {
+        let students = self.state.list_students();
+        let broadcast = self.state.broadcast_config();
+        ServerStatus {
+            listen_addr: self.state.config().bind_addrs().join(", "),
+            broadcast_mode: self.state.broadcast_mode(),
+            broadcast_source: self.state.broadcast_source(),
+            audio_enabled: self.audio.is_running(),
             audio_forced: self.audio.is_force_play(),
             connected_students: students.len(),
+            zoomed: broadcast.capture_region.is_some(),
+            spotlight_remaining_secs: self.spotlight_remaining_secs(),
+            encoder: self.screen.active_encoder(),
+            capture_window_missing: self.screen.window_missing(),
+            monitor_index: broadcast.monitor_index,
+            jpeg_quality: broadcast.jpeg_quality,
+            fps: broadcast.fps,
+            redact_rects: broadcast.redact_rects.clone(),
+            file_progress: self.state.file_progress.lock().clone(),
+            blackout: self.state.is_blackout(),
+            recording: self.state.recording_dir(),
+            worst_queue: self
+                .state
+                .student_metrics()
+                .into_iter()
+                .max_by_key(|metrics| metrics.queue_depth)
+                .filter(|metrics| metrics.queue_depth > 0)
+                .map(|metrics| (metrics.student_id, metrics.queue_depth)),
+            duplicate_student_ids: self.state.recent_duplicate_ids(),
+            spotlight_declined_by: self
+                .last_declined
+                .lock()
+                .as_ref()
+                .filter(|(_, at)| at.elapsed() < DECLINE_WARNING_WINDOW)
+                .map(|(name, _)| name.clone()),
+        }
+    }
+
+    fn status_report(&self) -> StatusReport {
+        StatusReport {
+            status: self.status_snapshot(),
+            students: self.state.list_students(),
         }
     }
 
+    /// Answer status page requests for as long as the server runs; never completes.
+    async fn answer_status_requests(&self, http: Option<&mut StatusHttp>) {
+        if let Some(http) = http {
+            while let Some(reply) = http.next_request().await {
+                let _ = reply.send(self.status_report());
+            }
+        }
+        std::future::pending().await
+    }
+
     fn print_help(&self) {
         println!(
-            "命令:\n  help                 显示帮助\n  students             列出在线学生\n  start [window]       开启教师屏幕广播\n  stop                 停止当前广播\n  spotlight <ID>       请求学生屏幕广播\n  send <路径> [open]   分发文件，可选参数 open 自动打开\n  audio <on|off|force|allow> 控制音频广播\n  quit                 退出程序"
+            "命令:\n  help                 显示帮助\n  students             列出在线学生\n  metrics              查看每位学生的发送流量与队列\n  info <ID>            查看学生详情\n  start [window]       开启教师屏幕广播\n  stop                 停止当前广播\n  snap                 截取当前屏幕发给学生，保持显示\n  spotlight <ID>       请求学生屏幕广播\n  timer [extend [秒]|cancel] 查看/延长/取消学生广播计时\n  zoom <accept|reject> <ID> | zoom reset 处理学生放大请求\n  send <路径> [open]   分发文件，可选参数 open 自动打开\n  sendto <ID> <路径> [open] 只向指定学生发送文件\n  cancel               取消正在分发的文件\n  blackout <on|off>    让所有学生屏幕黑屏/恢复\n  notice <文字>        在学生屏幕上显示通知\n  say <文字>           向全班发送聊天消息\n  tell <ID> <文字>     向指定学生发送聊天消息\n  kick <ID> [原因]     断开指定学生并告知原因\n  record start <目录> | record stop 录制/停止录制广播画面\n  thumbs <秒|off>      定时收集每位学生的屏幕缩略图\n  audio <on|off|force|allow> 控制音频广播\n  monitor [list|primary|<编号>] 查看/切换广播的显示器\n  window <标题>|off     只广播标题含该文字的窗口/恢复整个屏幕\n  redact [list|clear|<x> <y> <宽> <高>] 查看/清除/添加广播遮挡区域（像素）\n  fps <1-60>           调整广播帧率\n  quality <1-100>      调整 JPEG 质量\n  quit                 退出程序"
         );
     }
 
@@ -498,27 +1553,173 @@ impl TeacherServer {
             return;
         }
         println!("在线学生:");
+        for entry in entries {
+            println!("- {}", entry.roster_line());
+        }
+    }
+
+    fn print_monitors(&self) {
+        let monitors = match list_monitors() {
+            Ok(monitors) => monitors,
+            Err(err) => {
+                warn!(?err, "无法枚举显示器");
+                return;
+            }
+        };
+        let current = self.state.broadcast_config().monitor_index;
+        println!("显示器列表:");
+        for monitor in monitors {
+            let selected = match current {
+                Some(index) => index == monitor.index,
+                None => monitor.primary,
+            };
+            println!(
+                "- {}: {}x{} 位于 ({}, {}){}{}",
+                monitor.index,
+                monitor.width,
+                monitor.height,
+                monitor.x,
+                monitor.y,
+                if monitor.primary {
+                    " [主显示器]"
+                } else {
+                    ""
+                },
+                if selected { " [广播中]" } else { "" }
+            );
+        }
+    }
+
+    fn print_metrics(&self) {
+        let mut entries = self.state.student_metrics();
+        if entries.is_empty() {
+            println!("暂无学生在线");
+            return;
+        }
+        // Furthest behind first: that is usually the laptop slowing everyone down.
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.queue_depth));
+        println!("学生发送统计:");
         for entry in entries {
             println!(
-                "- {} ({}) @ {}",
-                entry.display_name, entry.student_id, entry.addr
+                "- {} ({}): 已发送 {} KB / {} 帧, {} KB/s, {:.1} fps, 队列 {}, 丢弃 {} 帧, 限速等待 {} ms{}",
+                entry.display_name,
+                entry.student_id,
+                entry.bytes_sent / 1024,
+                entry.frames_sent,
+                entry.bytes_per_sec / 1024,
+                entry.fps,
+                entry.queue_depth,
+                entry.dropped_frames,
+                entry.throttled_ms,
+                if entry.is_lagging() { " [积压]" } else { "" }
             );
         }
     }
 
+    fn print_student_detail(&self, student_id: &str) {
+        let Some(detail) = self.state.student_detail(student_id) else {
+            println!("学生 {student_id} 不在线");
+            return;
+        };
+
+        let summary = &detail.summary;
+        let caps = &detail.capabilities;
+        let audio = match summary.audio_state {
+            Some(state) if state.muted => format!("已静音 (音量 {}%)", state.volume),
+            Some(state) => format!("播放中 (音量 {}%)", state.volume),
+            None => "未上报".to_string(),
+        };
+        println!(
+            "{} ({}) @ {}\n  客户端版本: {} (协议 {})\n  能力: 收视频={} 发视频={} 收音频={} 发音频={} 文件={}\n  最后心跳: {} 秒前\n  已发送: {} KB\n  正在广播该学生: {}\n  举手: {}\n  放大请求: {}\n  音频: {}\n  缩略图: {}",
+            summary.display_name,
+            summary.student_id,
+            summary.addr,
+            detail.client_version,
+            detail.protocol_version,
+            caps.receive_video,
+            caps.send_video,
+            caps.receive_audio,
+            caps.send_audio,
+            caps.file_transfer,
+            detail.last_seen_secs,
+            detail.bytes_sent / 1024,
+            if detail.spotlighted { "是" } else { "否" },
+            if detail.hand_raised { "是" } else { "否" },
+            match detail.pending_zoom {
+                None => "无".to_string(),
+                Some(None) => "恢复整个屏幕".to_string(),
+                Some(Some(region)) => format!(
+                    "区域 ({:.2}, {:.2}) {:.2}x{:.2}",
+                    region.x, region.y, region.width, region.height
+                ),
+            },
+            audio,
+            detail.thumbnail.as_ref().map_or_else(
+                || "无".to_string(),
+                |thumbnail| format!(
+                    "{} 秒前 ({}x{}, {} KB)",
+                    thumbnail.received.elapsed().as_secs(),
+                    thumbnail.frame.width,
+                    thumbnail.frame.height,
+                    thumbnail.frame.data.len() / 1024
+                )
+            )
+        );
+        if !detail.settings_current {
+            println!("  广播参数: 尚未确认最新设置");
+        }
+        match &detail.registration {
+            Some(registration) => println!(
+                "  名单: {} 座位 {} IP {}",
+                registration.student_name.as_deref().unwrap_or("-"),
+                registration.seat.as_deref().unwrap_or("-"),
+                registration
+                    .ip
+                    .map_or_else(|| "-".to_string(), |ip| ip.to_string())
+            ),
+            None => println!("  名单: 未登记"),
+        }
+        if !detail.identity_issues.is_empty() {
+            println!("  身份可疑:");
+            for issue in &detail.identity_issues {
+                println!("    - {issue}");
+            }
+        }
+        if detail.recent_errors.is_empty() {
+            println!("  最近错误: 无");
+        } else {
+            println!("  最近错误:");
+            for err in &detail.recent_errors {
+                println!("    - {err}");
+            }
+        }
+    }
+
     async fn start_teacher_broadcast(&self, mode: BroadcastMode) -> Result<()> {
+        self.spotlight_timer.lock().take();
         self.state
             .set_broadcast_source(Some(BroadcastSource::Teacher), mode);
         self.screen.start(mode).await?;
         self.state.broadcast_command(BroadcastCommand::Start {
             source: BroadcastSource::Teacher,
             mode,
+            lock_input: self.state.config.lock_student_input && mode == BroadcastMode::Fullscreen,
         });
         info!(?mode, "教师屏幕广播启动");
         Ok(())
     }
 
     async fn start_student_broadcast(&self, student_id: &str) -> Result<()> {
+        let revert_to = match self.state.broadcast_source() {
+            Some(BroadcastSource::Teacher) => Some(self.state.broadcast_mode()),
+            // Chained spotlights all fall back to whatever preceded the first one.
+            Some(BroadcastSource::Student { .. }) => self
+                .spotlight_timer
+                .lock()
+                .as_ref()
+                .and_then(|timer| timer.revert_to),
+            None => None,
+        };
         self.screen.stop().await;
         let student_name = self
             .state
@@ -533,127 +1734,604 @@ impl TeacherServer {
         self.state.broadcast_command(BroadcastCommand::Start {
             source,
             mode: BroadcastMode::Fullscreen,
+            lock_input: false,
         });
         info!(student_id, "已请求学生屏幕广播");
+
+        let limit = self.state.config.max_spotlight_secs;
+        *self.spotlight_timer.lock() = Some(SpotlightTimer {
+            student_id: student_id.to_string(),
+            revert_to,
+            deadline: (limit > 0).then(|| Instant::now() + Duration::from_secs(limit)),
+        });
+        if limit > 0 {
+            info!(student_id, limit_secs = limit, "学生广播将在到时后自动结束");
+        }
         Ok(())
     }
 
-    async fn stop_broadcast(&self) -> Result<()> {
-        self.screen.stop().await;
-        self.state.set_broadcast_source(None, BroadcastMode::Window);
-        self.state.broadcast_command(BroadcastCommand::Stop);
-        info!("广播已停止");
+    fn handle_zoom(&self, action: ZoomAction) -> Result<()> {
+        let mut broadcast = self.state.broadcast_config();
+        match action {
+            ZoomAction::Accept(student_id) => {
+                let student = self
+                    .state
+                    .find_student(&student_id)
+                    .ok_or_else(|| anyhow!("学生 {student_id} 不在线"))?;
+                let Some(region) = student.take_zoom_request() else {
+                    bail!("学生 {student_id} 没有待处理的放大请求");
+                };
+                // The request is relative to what students currently see, which may already be zoomed.
+                let current = broadcast.capture_region.unwrap_or(Region::FULL);
+                broadcast.capture_region = region.map(|region| region.within(current));
+                info!(student = %student_id, region = ?broadcast.capture_region, "已同意学生放大请求");
+            }
+            ZoomAction::Reject(student_id) => {
+                let student = self
+                    .state
+                    .find_student(&student_id)
+                    .ok_or_else(|| anyhow!("学生 {student_id} 不在线"))?;
+                if student.take_zoom_request().is_none() {
+                    bail!("学生 {student_id} 没有待处理的放大请求");
+                }
+                student.send(TeacherToStudent::Error("教师拒绝了放大请求".into()));
+                info!(student = %student_id, "已拒绝学生放大请求");
+                return Ok(());
+            }
+            ZoomAction::Reset => {
+                broadcast.capture_region = None;
+                info!("已恢复全屏广播画面");
+            }
+        }
+        self.state.update_broadcast_config(broadcast);
         Ok(())
     }
 
-    async fn send_file_to_all(&self, path: PathBuf, auto_open_override: bool) -> Result<()> {
-        use tokio::fs::File;
+    fn spotlight_deadline(&self) -> Option<Instant> {
+        self.spotlight_timer
+            .lock()
+            .as_ref()
+            .and_then(|timer| timer.deadline)
+    }
 
-        let metadata = tokio::fs::metadata(&path)
-            .await
-            .with_context(|| format!("无法读取文件信息: {}", path.display()))?;
-        if !metadata.is_file() {
-            bail!("{} 不是有效文件", path.display());
+    fn spotlight_remaining_secs(&self) -> Option<u64> {
+        self.spotlight_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_secs())
+    }
+
+    fn adjust_spotlight_timer(&self, action: SpotlightTimerAction) -> Result<()> {
+        let mut guard = self.spotlight_timer.lock();
+        let Some(timer) = guard.as_mut().filter(|timer| timer.deadline.is_some()) else {
+            bail!("当前没有计时中的学生广播");
+        };
+        match action {
+            SpotlightTimerAction::Extend(secs) => {
+                timer.deadline = timer
+                    .deadline
+                    .map(|deadline| deadline.max(Instant::now()) + Duration::from_secs(secs));
+                info!(student = %timer.student_id, extra_secs = secs, "学生广播计时已延长");
+            }
+            SpotlightTimerAction::Cancel => {
+                info!(student = %timer.student_id, "学生广播计时已取消");
+                timer.deadline = None;
+            }
         }
+        Ok(())
+    }
 
-        let file_name = path
-            .file_name()
-            .map(|name| name.to_string_lossy().into_owned())
-            .ok_or_else(|| anyhow!("无法解析文件名"))?;
+    async fn expire_spotlight(&self) -> Result<()> {
+        let Some(timer) = self.spotlight_timer.lock().take() else {
+            return Ok(());
+        };
+        if !self.state.is_student_broadcasting(&timer.student_id) {
+            // The student disconnected and the spotlight already ended.
+            return Ok(());
+        }
+        info!(student = %timer.student_id, "学生广播已到时，自动恢复之前的广播");
+        let name = self
+            .state
+            .find_student_name(&timer.student_id)
+            .unwrap_or_else(|| timer.student_id.clone());
+        let text = match timer.revert_to {
+            Some(mode) => {
+                self.start_teacher_broadcast(mode).await?;
+                format!("{name} 的屏幕广播时间已到，已恢复教师广播")
+            }
+            None => {
+                self.stop_broadcast().await?;
+                format!("{name} 的屏幕广播时间已到，广播已结束")
+            }
+        };
+        self.state.broadcast_notice(Notice {
+            text,
+            duration_ms: NOTICE_DURATION_MS,
+            severity: NoticeSeverity::Info,
+        });
+        Ok(())
+    }
 
-        let transfer_id = Uuid::new_v4();
-        let auto_open = auto_open_override || self.state.config.file_auto_open;
+    /// End the spotlight a student refused and go back to what ran before it.
+    async fn end_declined_spotlight(&self) -> Result<()> {
+        let Some(student_id) = self.state.declined_spotlight.lock().take() else {
+            return Ok(());
+        };
+        if !self.state.is_student_broadcasting(&student_id) {
+            // Something else was broadcast since the request.
+            return Ok(());
+        }
+        let revert_to = self
+            .spotlight_timer
+            .lock()
+            .take()
+            .and_then(|timer| timer.revert_to);
+        warn!(student = %student_id, "学生拒绝了屏幕广播请求，恢复之前的广播");
+        let name = self
+            .state
+            .find_student_name(&student_id)
+            .unwrap_or(student_id);
+        *self.last_declined.lock() = Some((name, Instant::now()));
+        match revert_to {
+            Some(mode) => self.start_teacher_broadcast(mode).await,
+            None => self.stop_broadcast().await,
+        }
+    }
 
-        self.state.broadcast(TeacherToStudent::FileOffer(FileOffer {
-            transfer_id,
-            file_name: file_name.clone(),
-            total_size: metadata.len(),
-            auto_open,
-        }));
+    fn print_spotlight_timer(&self) {
+        match self.spotlight_remaining_secs() {
+            Some(secs) => println!("当前学生广播将在 {secs} 秒后自动结束"),
+            None => println!("当前没有计时中的学生广播"),
+        }
+    }
 
-        let mut file = File::open(&path).await?;
-        let mut buffer = vec![0u8; 64 * 1024];
-        let mut offset = 0u64;
-        loop {
-            let read = file.read(&mut buffer).await?;
-            if read == 0 {
-                break;
+    fn set_broadcast_quality(&self, jpeg_quality: Option<u8>, fps: Option<u32>) -> Result<()> {
+        let mut broadcast = self.state.broadcast_config();
+        if let Some(quality) = jpeg_quality {
+            if !(1..=100).contains(&quality) {
+                bail!("JPEG 质量必须在 1-100 之间, 收到 {quality}");
             }
-            self.state.broadcast(TeacherToStudent::FileChunk(FileChunk {
-                transfer_id,
-                offset,
-                bytes: buffer[..read].to_vec(),
-                final_chunk: offset + read as u64 >= metadata.len(),
-            }));
-            offset += read as u64;
+            broadcast.jpeg_quality = quality;
         }
-
-        self.state
-            .broadcast(TeacherToStudent::FileComplete(FileTransferComplete {
-                transfer_id,
-                success: true,
-                message: Some(format!("文件 {} 已发送", file_name)),
-            }));
-
-        info!(file = %file_name, size = metadata.len(), "文件分发完成");
+        if let Some(fps) = fps {
+            if !(1..=60).contains(&fps) {
+                bail!("帧率必须在 1-60 之间, 收到 {fps}");
+            }
+            broadcast.fps = fps;
+        }
+        let revision = self.state.update_broadcast_config(broadcast.clone());
+        info!(
+            revision,
+            fps = broadcast.fps,
+            jpeg_quality = broadcast.jpeg_quality,
+            "广播参数已更新"
+        );
         Ok(())
     }
-}
 
-async fn handle_student_connection(
-    state: Arc<TeacherState>,
-    screen: ScreenBroadcaster,
-    stream: TcpStream,
-    addr: SocketAddr,
-) -> Result<()> {
-    let (mut reader, mut writer) = stream.into_split();
+    fn set_monitor(&self, index: Option<usize>) -> Result<()> {
+        if let Some(index) = index {
+            let count = list_monitors()?.len();
+            ensure!(
+                index < count,
+                "显示器编号 {index} 超出范围，共 {count} 个显示器"
+            );
+        }
+        let mut broadcast = self.state.broadcast_config();
+        broadcast.monitor_index = index;
+        let revision = self.state.update_broadcast_config(broadcast);
+        info!(revision, monitor = ?index, "广播显示器已切换");
+        Ok(())
+    }
 
-    let greeting: StudentToTeacher = read_message(&mut reader).await?;
-    let hello = match greeting {
-        StudentToTeacher::Hello(payload) => payload,
-        other => bail!("期望 Hello 消息, 收到 {:?}", other),
-    };
+    fn set_capture_window(&self, title: Option<String>) -> Result<()> {
+        if let Some(title) = &title {
+            ensure!(
+                window_bounds(title).is_some(),
+                "找不到标题包含“{title}”的可见窗口（仅支持 Windows）"
+            );
+        }
+        let mut broadcast = self.state.broadcast_config();
+        broadcast.capture_window = title;
+        let window = broadcast.capture_window.clone();
+        let revision = self.state.update_broadcast_config(broadcast);
+        match window {
+            Some(window) => info!(revision, %window, "只广播指定窗口"),
+            None => info!(revision, "已恢复广播整个屏幕"),
+        }
+        Ok(())
+    }
 
-    info!(student = %hello.student_id, %addr, "学生已连接");
+    fn set_redact_rects(&self, mut rects: Vec<Rect>) {
+        rects.retain(|rect| rect.width > 0 && rect.height > 0);
+        let mut broadcast = self.state.broadcast_config();
+        broadcast.redact_rects = rects;
+        let count = broadcast.redact_rects.len();
+        let revision = self.state.update_broadcast_config(broadcast);
+        info!(revision, count, "遮挡区域已更新");
+    }
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<TeacherToStudent>();
-    let connection_id = Uuid::new_v4();
-    let student_handle = Arc::new(StudentHandle::new(
-        connection_id,
-        addr,
-        hello.student_id.clone(),
-        hello.student_name.clone(),
-        hello.capabilities.clone(),
-        tx.clone(),
-    ));
+    fn print_redact_rects(&self) {
+        let rects = self.state.broadcast_config().redact_rects;
+        if rects.is_empty() {
+            println!("未设置遮挡区域");
+        }
+        for (index, rect) in rects.iter().enumerate() {
+            println!(
+                "  {index}: ({}, {}) {}x{}",
+                rect.x, rect.y, rect.width, rect.height
+            );
+        }
+    }
 
-    state.add_student(student_handle.clone());
+    async fn stop_broadcast(&self) -> Result<()> {
+        self.spotlight_timer.lock().take();
+        self.screen.stop().await;
+        self.state.set_broadcast_source(None, BroadcastMode::Window);
+        self.state.broadcast_command(BroadcastCommand::Stop);
+        info!("广播已停止");
+        Ok(())
+    }
+}
 
-    let welcome = TeacherToStudent::Welcome(HelloAck {
-        server_version: env!("CARGO_PKG_VERSION").to_string(),
-        force_fullscreen: matches!(state.broadcast_mode(), BroadcastMode::Fullscreen),
-        broadcast_mode: state.broadcast_mode(),
-    });
-    student_handle.send(welcome);
+/// Send the file behind `offer` from `offset` on as `FileChunk`s, followed by `FileComplete`.
+/// Each chunk waits for `room` first, so the file goes out at the pace of its slowest reader.
+/// Returns `false` when `cancel` was set first, leaving it to the caller to call the transfer
+/// off with the students.
+async fn stream_file<R: Future<Output = ()>>(
+    path: &Path,
+    offer: &FileOffer,
+    offset: u64,
+    cancel: &AtomicBool,
+    room: impl Fn() -> R,
+    send: impl Fn(TeacherToStudent),
+) -> Result<bool> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("无法打开文件 {}", path.display()))?;
+    let len = file.metadata().await?.len();
+    ensure!(
+        len == offer.total_size,
+        "文件 {} 已被修改，无法继续发送",
+        path.display()
+    );
+    file.seek(SeekFrom::Start(offset)).await?;
 
-    let writer_task = tokio::spawn(async move {
-        while let Some(message) = rx.recv().await {
-            if let Err(err) = write_message(&mut writer, &message).await {
-                error!(?err, "发送给学生失败");
-                break;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut offset = offset;
+    loop {
+        // A cancellation must not wait for a stalled student to make room.
+        while time::timeout(DRAIN_POLL_INTERVAL, room()).await.is_err() {
+            if cancel.load(Ordering::SeqCst) {
+                return Ok(false);
             }
         }
-    });
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        send(TeacherToStudent::FileChunk(FileChunk::new(
+            offer.transfer_id,
+            offset,
+            buffer[..read].to_vec(),
+            offset + read as u64 >= offer.total_size,
+        )));
+        offset += read as u64;
+    }
 
-    let mut uploads: HashMap<Uuid, UploadSession> = HashMap::new();
+    send(TeacherToStudent::FileComplete(FileTransferComplete {
+        transfer_id: offer.transfer_id,
+        success: true,
+        message: Some(format!("文件 {} 已发送", offer.file_name)),
+    }));
+    Ok(true)
+}
+
+/// Continue an interrupted transfer for one student. Its writer remembers the file again if
+/// the student drops out once more.
+async fn resume_file(student: Arc<StudentHandle>, path: PathBuf, offer: FileOffer, offset: u64) {
+    info!(student = %student.student_id, file = %offer.file_name, offset, "继续发送中断的文件");
+    // Resumes only go to a single student and are not cancellable.
+    let cancel = AtomicBool::new(false);
+    if let Err(err) = stream_file(
+        &path,
+        &offer,
+        offset,
+        &cancel,
+        || student.queue.file_room(),
+        |message| student.send(message),
+    )
+    .await
+    {
+        warn!(?err, student = %student.student_id, "续传文件失败");
+        student.send(TeacherToStudent::FileComplete(FileTransferComplete {
+            transfer_id: offer.transfer_id,
+            success: false,
+            message: Some(format!("{err:#}")),
+        }));
+    }
+}
 
+async fn accept_students(
+    listener: TcpListener,
+    state: Arc<TeacherState>,
+    screen: ScreenBroadcaster,
+    tls: Option<ServerTls>,
+) {
     loop {
-        let message = match read_message(&mut reader).await {
-            Ok(msg) => msg,
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let state = state.clone();
+                let screen = screen.clone();
+                let tls = tls.clone();
+                tokio::spawn(
+                    async move {
+                        let transport: Box<dyn Transport> = match &tls {
+                            Some(tls) => match tls.accept(stream).await {
+                                Ok(transport) => transport,
+                                Err(err) => {
+                                    warn!(?err, %addr, "TLS 握手失败，已拒绝连接");
+                                    return;
+                                }
+                            },
+                            None => Box::new(stream),
+                        };
+                        if let Err(err) =
+                            handle_student_connection(state, screen, transport, addr).await
+                        {
+                            error!(?err, %addr, "学生连接异常");
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
             Err(err) => {
-                warn!(?err, student = %hello.student_id, "学生连接断开");
+                error!(?err, "监听器异常");
                 break;
             }
+        }
+    }
+}
+
+/// Keep every connection busy so students can tell a silent teacher from a dead one.
+async fn send_heartbeats(state: Arc<TeacherState>) {
+    let mut ticker = time::interval(Duration::from_secs(state.config.heartbeat_interval_secs));
+    loop {
+        ticker.tick().await;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        state.broadcast(TeacherToStudent::Heartbeat(Heartbeat { timestamp_ms }));
+    }
+}
+
+/// Drop students whose heartbeats stopped without the TCP connection closing, e.g. after
+/// a Wi-Fi drop. Their connection task then runs the normal disconnect cleanup.
+async fn reap_idle_students(state: Arc<TeacherState>) {
+    let timeout = Duration::from_secs(state.config.idle_timeout_secs);
+    let mut ticker = time::interval(REAP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        for student in state.idle_students(timeout) {
+            warn!(
+                student = %student.student_id,
+                addr = %student.addr,
+                idle_secs = student.last_seen.lock().elapsed().as_secs(),
+                "学生心跳超时，断开连接"
+            );
+            state.remove_student(student.connection_id);
+            student.disconnect();
+        }
+    }
+}
+
+async fn handle_student_connection(
+    state: Arc<TeacherState>,
+    screen: ScreenBroadcaster,
+    transport: Box<dyn Transport>,
+    addr: SocketAddr,
+) -> Result<()> {
+    let connection_id = Uuid::new_v4();
+    // Tags every log line of this connection, including its writer and file tasks.
+    let span = info_span!("connection", connection = %connection_id);
+    serve_student(state, screen, transport, addr, connection_id)
+        .instrument(span)
+        .await
+}
+
+async fn serve_student(
+    state: Arc<TeacherState>,
+    screen: ScreenBroadcaster,
+    transport: Box<dyn Transport>,
+    addr: SocketAddr,
+    connection_id: Uuid,
+) -> Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(transport);
+
+    let greeting: StudentToTeacher = read_message(&mut reader, Framing::Plain).await?;
+    let hello = match greeting {
+        StudentToTeacher::Hello(payload) => payload,
+        other => bail!("期望 Hello 消息, 收到 {:?}", other),
+    };
+
+    if !PROTOCOL_VERSION.is_compatible(hello.protocol_version) {
+        warn!(
+            student = %hello.student_id,
+            %addr,
+            protocol = %hello.protocol_version,
+            "学生端协议版本不兼容，已拒绝连接"
+        );
+        let rejection = TeacherToStudent::Error(format!(
+            "协议版本不兼容: 学生端 {}，教师端 {}，请更新学生端程序",
+            hello.protocol_version, PROTOCOL_VERSION
+        ));
+        write_message(&mut writer, Framing::Plain, &rejection).await?;
+        return Ok(());
+    }
+
+    if !state.join_token_matches(&hello) {
+        warn!(student = %hello.student_id, %addr, "加入口令不匹配，已拒绝连接");
+        let rejection = TeacherToStudent::Error("加入口令错误".into());
+        write_message(&mut writer, Framing::Plain, &rejection).await?;
+        return Ok(());
+    }
+
+    if !state.registration_allows(&hello.student_id) {
+        warn!(student = %hello.student_id, %addr, "学号不在名单中，已拒绝连接");
+        let rejection = TeacherToStudent::Error(format!("学号 {} 未登记", hello.student_id));
+        write_message(&mut writer, Framing::Plain, &rejection).await?;
+        return Ok(());
+    }
+
+    if let Some(existing) = state.find_student(&hello.student_id) {
+        match state.duplicate_outcome(&existing, addr) {
+            Duplicate::Reject(message) => {
+                write_message(
+                    &mut writer,
+                    Framing::Plain,
+                    &TeacherToStudent::Error(message),
+                )
+                .await?;
+                return Ok(());
+            }
+            // Gone already if it disconnected meanwhile, which is just as good.
+            Duplicate::Replace(reason) => state.replace_student(&existing, &reason),
+        }
+    }
+
+    let codec = MessageCodec::negotiate(&hello.codecs, hello.protocol_version);
+    let framing = codec.map_or(Framing::Plain, Framing::Tagged);
+    info!(student = %hello.student_id, %addr, ?framing, "学生已连接");
+
+    let student_handle = Arc::new(StudentHandle::new(
+        connection_id,
+        addr,
+        &hello,
+        state.config.send_queue_capacity,
+    ));
+
+    let welcome = TeacherToStudent::Welcome(HelloAck {
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        force_fullscreen: matches!(state.broadcast_mode(), BroadcastMode::Fullscreen),
+        broadcast_mode: state.broadcast_mode(),
+        codec,
+        session_id: Some(state.session_id),
+        max_upload_bytes: state.config.max_upload_bytes,
+    });
+    // Welcome is always a plain JSON frame; the negotiated framing only applies after it.
+    write_message(&mut writer, Framing::Plain, &welcome).await?;
+    if hello.protocol_version >= SETTINGS_PROTOCOL_VERSION {
+        student_handle.send(TeacherToStudent::SettingsUpdate(state.settings_update()));
+    }
+    // A late joiner sees the running broadcast right away instead of at the next capture.
+    if let Some(frame) = state.keyframe_for(&hello.student_id) {
+        student_handle.send(TeacherToStudent::Video(frame));
+    }
+    // Registered only after Welcome is written so no broadcast traffic can overtake it.
+    // A connection with the same ID may have registered while Welcome was on its way.
+    if let Err(message) = state.add_student(student_handle.clone()) {
+        write_message(&mut writer, framing, &TeacherToStudent::Error(message)).await?;
+        return Ok(());
+    }
+    state.offer_interrupted_files(&student_handle);
+    if state.is_blackout() && hello.protocol_version >= BLACKOUT_PROTOCOL_VERSION {
+        student_handle.send(TeacherToStudent::Broadcast(BroadcastCommand::Blackout {
+            enabled: true,
+        }));
+    }
+    let interval_secs = state.thumbnail_interval_secs();
+    if interval_secs > 0 {
+        student_handle.request_thumbnails(interval_secs);
+    }
+
+    // Students on an older protocol never announce compression and get plain frames.
+    let compress = hello.compression && compression_supported();
+    let writer_state = state.clone();
+    let writer_student = student_handle.clone();
+    let mut writer = CountingWriter::new(writer);
+    let mut media_limit = TokenBucket::from_kbps(state.config.max_kbps_per_student, Instant::now());
+    let mut file_limit =
+        TokenBucket::from_kbps(state.config.max_file_kbps_per_student, Instant::now());
+    // Each cap shuts only its own lane while paying off a burst, so a throttled transfer
+    // never holds up the broadcast and the other way round.
+    let mut writer_task = tokio::spawn(
+        async move {
+            let mut media_until = Instant::now();
+            let mut files_until = Instant::now();
+            loop {
+                let now = Instant::now();
+                let lanes = Lanes {
+                    main: media_until <= now,
+                    files: files_until <= now,
+                };
+                let reopen = match (lanes.main, lanes.files) {
+                    (true, true) => None,
+                    (false, true) => Some(media_until),
+                    (true, false) => Some(files_until),
+                    (false, false) => Some(media_until.min(files_until)),
+                };
+                let message = tokio::select! {
+                    message = writer_student.queue.pop(lanes) => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = time::sleep_until(reopen.unwrap_or(now)), if reopen.is_some() => continue,
+                };
+                let level = writer_state.broadcast_config().compression_level;
+                let result = match framing {
+                    Framing::Tagged(codec)
+                        if compress && level > 0 && message.is_compressible() =>
+                    {
+                        write_message_compressed(&mut writer, codec, level, &*message).await
+                    }
+                    _ => write_message(&mut writer, framing, &*message).await,
+                };
+                if let Err(err) = result {
+                    error!(?err, "发送给学生失败");
+                    writer_student.queue.close();
+                    break;
+                }
+                writer_student.note_written(&message);
+                let written = writer.take_written();
+                writer_student
+                    .counters
+                    .record_sent(written, matches!(*message, TeacherToStudent::Video(_)));
+                let (limit, until) = if is_file_data(&message) {
+                    (file_limit.as_mut(), &mut files_until)
+                } else {
+                    (media_limit.as_mut(), &mut media_until)
+                };
+                if let Some(limit) = limit {
+                    let now = Instant::now();
+                    let wait = limit.consume(written, now);
+                    if !wait.is_zero() {
+                        writer_student.counters.record_throttled(wait);
+                        *until = now + wait;
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    let mut uploads: HashMap<Uuid, UploadSession> = HashMap::new();
+    // Uploads refused by `allow_uploads`; their chunks are dropped quietly.
+    let mut refused_uploads: HashSet<Uuid> = HashSet::new();
+    let mut pacer = SharePacer::default();
+
+    loop {
+        let message = tokio::select! {
+            result = read_message(&mut reader, framing) => match result {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!(?err, student = %hello.student_id, "学生连接断开");
+                    break;
+                }
+            },
+            _ = student_handle.disconnected() => break,
         };
 
         match message {
@@ -663,51 +2341,152 @@ async fn handle_student_connection(
             StudentToTeacher::Heartbeat(_) => {
                 student_handle.touch();
             }
+            StudentToTeacher::Video(frame) if frame.thumbnail => {
+                state.store_thumbnail(&hello.student_id, frame);
+            }
             StudentToTeacher::Video(frame) => {
                 if state.is_student_broadcasting(&hello.student_id) {
-                    state.broadcast_except(
-                        TeacherToStudent::Video(frame.clone()),
-                        Some(connection_id),
-                    );
+                    let now_ms = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    let max_fps = state.broadcast_config().fps;
+                    if let Some(fps) = pacer.observe(frame.timestamp_ms, now_ms, max_fps) {
+                        if hello.protocol_version >= PACING_PROTOCOL_VERSION {
+                            info!(student = %hello.student_id, fps, "调整学生分享帧率");
+                            student_handle.send(TeacherToStudent::Pacing { fps });
+                        }
+                    }
+                    state.record_frame(&frame);
+                    *state.latest_frame.lock() = Some(frame.clone());
+                    state.broadcast_except(TeacherToStudent::Video(frame), Some(connection_id));
                 }
             }
             StudentToTeacher::Audio(frame) => {
                 state.broadcast_except(TeacherToStudent::Audio(frame.clone()), Some(connection_id));
             }
+            StudentToTeacher::FileOffer(offer) if !state.uploads_allowed(&hello.student_id) => {
+                warn!(student = %hello.student_id, file = %offer.file_name, "该学生不允许上传文件，已拒绝");
+                refused_uploads.insert(offer.transfer_id);
+                student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id: offer.transfer_id,
+                    success: false,
+                    message: Some("教师端不允许你上传文件".into()),
+                }));
+            }
+            StudentToTeacher::FileOffer(offer) if state.upload_too_large(offer.total_size) => {
+                warn!(student = %hello.student_id, file = %offer.file_name, size = offer.total_size, "上传文件超出大小限制，已拒绝");
+                refused_uploads.insert(offer.transfer_id);
+                student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id: offer.transfer_id,
+                    success: false,
+                    message: Some(state.upload_limit_message(&offer.file_name)),
+                }));
+            }
             StudentToTeacher::FileOffer(offer) => {
-                let path = state.prepare_upload_path(&hello, &offer.file_name).await?;
-                let file = tokio::fs::File::create(&path)
-                    .await
-                    .with_context(|| format!("无法创建文件 {}", path.display()))?;
-                uploads.insert(
-                    offer.transfer_id,
-                    UploadSession {
-                        file,
-                        path,
-                        expected: offer.total_size,
-                        received: 0,
-                    },
-                );
-                info!(student = %hello.student_id, file = %offer.file_name, "准备接收文件");
+                match state.create_upload_file(&hello, &offer.file_name).await {
+                    Ok((path, file)) => {
+                        uploads.insert(
+                            offer.transfer_id,
+                            UploadSession {
+                                file,
+                                path,
+                                expected: offer.total_size,
+                                received: 0,
+                            },
+                        );
+                        info!(student = %hello.student_id, file = %offer.file_name, "准备接收文件");
+                    }
+                    // Only this upload fails; the student stays connected.
+                    Err(err) => {
+                        warn!(?err, student = %hello.student_id, file = %offer.file_name, "无法保存学生上传的文件");
+                        refused_uploads.insert(offer.transfer_id);
+                        student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                            transfer_id: offer.transfer_id,
+                            success: false,
+                            message: Some(format!("教师端无法保存文件 {}", offer.file_name)),
+                        }));
+                    }
+                }
+            }
+            StudentToTeacher::FileChunk(chunk) if refused_uploads.contains(&chunk.transfer_id) => {}
+            StudentToTeacher::FileChunk(chunk) if !chunk.checksum_matches() => {
+                warn!(student = %hello.student_id, offset = chunk.offset, "上传文件分片校验失败，已放弃该文件");
+                if let Some(session) = uploads.remove(&chunk.transfer_id) {
+                    drop(session.file);
+                    if let Err(err) = tokio::fs::remove_file(&session.path).await {
+                        warn!(?err, path = %session.path.display(), "删除损坏的上传文件失败");
+                    }
+                }
+                student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id: chunk.transfer_id,
+                    success: false,
+                    message: Some("文件分片校验失败，请重新上传".into()),
+                }));
+            }
+            StudentToTeacher::FileChunk(chunk)
+                if uploads.get(&chunk.transfer_id).is_some_and(|session| {
+                    state.upload_too_large(session.received + chunk.bytes.len() as u64)
+                }) =>
+            {
+                // The offer understated the size; stop before the disk fills up.
+                warn!(student = %hello.student_id, "上传文件超出大小限制，已中止");
+                refused_uploads.insert(chunk.transfer_id);
+                if let Some(session) = uploads.remove(&chunk.transfer_id) {
+                    drop(session.file);
+                    if let Err(err) = tokio::fs::remove_file(&session.path).await {
+                        warn!(?err, path = %session.path.display(), "删除超限的上传文件失败");
+                    }
+                    let file_name = session
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy();
+                    student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                        transfer_id: chunk.transfer_id,
+                        success: false,
+                        message: Some(state.upload_limit_message(&file_name)),
+                    }));
+                }
             }
             StudentToTeacher::FileChunk(chunk) => {
                 if let Some(session) = uploads.get_mut(&chunk.transfer_id) {
-                    session
-                        .file
-                        .write_all(&chunk.bytes)
-                        .await
-                        .context("写入上传文件失败")?;
+                    if let Err(err) = session.file.write_all(&chunk.bytes).await {
+                        warn!(?err, student = %hello.student_id, path = %session.path.display(), "写入上传文件失败，已放弃该文件");
+                        refused_uploads.insert(chunk.transfer_id);
+                        if let Some(session) = uploads.remove(&chunk.transfer_id) {
+                            drop(session.file);
+                            let _ = tokio::fs::remove_file(&session.path).await;
+                        }
+                        student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                            transfer_id: chunk.transfer_id,
+                            success: false,
+                            message: Some("教师端写入文件失败".into()),
+                        }));
+                        continue;
+                    }
                     session.received += chunk.bytes.len() as u64;
                 } else {
                     warn!(transfer = %chunk.transfer_id, "收到未知文件分片");
                 }
             }
+            StudentToTeacher::FileComplete(done) if refused_uploads.remove(&done.transfer_id) => {}
             StudentToTeacher::FileComplete(done) => {
                 if let Some(mut session) = uploads.remove(&done.transfer_id) {
                     session.file.flush().await?;
                     if done.success {
-                        info!(student = %hello.student_id, path = %session.path.display(), "学生上传完成");
-                        let _ = tx.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                        info!(
+                            student = %hello.student_id,
+                            path = %session.path.display(),
+                            layout = ?state.config.upload_organize,
+                            "学生上传完成"
+                        );
+                        state.emit(ServerEvent::FileReceived {
+                            student_id: hello.student_id.clone(),
+                            path: session.path.clone(),
+                            size: session.received,
+                        });
+                        student_handle.send(TeacherToStudent::FileComplete(FileTransferComplete {
                             transfer_id: done.transfer_id,
                             success: true,
                             message: Some("文件上传完成".into()),
@@ -715,17 +2494,124 @@ async fn handle_student_connection(
                     } else {
                         warn!(student = %hello.student_id, "学生上传失败");
                     }
+                } else if !done.success {
+                    // A download the student had to give up, e.g. on a checksum mismatch.
+                    let message = done.message.unwrap_or_else(|| "文件接收失败".into());
+                    warn!(student = %hello.student_id, %message, "学生接收文件失败");
+                    student_handle.record_error(message);
+                }
+            }
+            StudentToTeacher::AudioState(audio_state) => {
+                info!(
+                    student = %hello.student_id,
+                    muted = audio_state.muted,
+                    volume = audio_state.volume,
+                    "学生音频状态更新"
+                );
+                student_handle.set_audio_state(audio_state);
+            }
+            StudentToTeacher::FocusState(focus) => {
+                if focus.focused {
+                    info!(student = %hello.student_id, "学生已回到广播窗口");
+                } else {
+                    warn!(student = %hello.student_id, "学生离开了广播窗口");
+                }
+                student_handle.set_window_focused(focus.focused);
+            }
+            StudentToTeacher::RequestZoom { region } => {
+                if region.is_some_and(|region| !region.is_valid()) {
+                    warn!(student = %hello.student_id, ?region, "放大区域无效，已忽略");
+                    student_handle.send(TeacherToStudent::Error("放大区域无效".into()));
+                } else if state.config.honor_zoom_requests {
+                    warn!(
+                        student = %hello.student_id,
+                        ?region,
+                        "学生请求放大画面，输入 zoom accept/reject {} 处理",
+                        hello.student_id
+                    );
+                    student_handle.set_zoom_request(region);
+                } else {
+                    info!(student = %hello.student_id, "未开启 honor_zoom_requests，已忽略放大请求");
+                    student_handle.send(TeacherToStudent::Error("教师端未开启画面放大请求".into()));
+                }
+            }
+            StudentToTeacher::SettingsAck(revision) => {
+                student_handle.acknowledge_settings(revision);
+            }
+            StudentToTeacher::RaiseHand { raised } => {
+                if raised {
+                    warn!(student = %hello.student_id, "学生举手");
+                } else {
+                    info!(student = %hello.student_id, "学生已放下手");
+                }
+                student_handle.hand_raised.store(raised, Ordering::SeqCst);
+                state.emit(ServerEvent::HandRaised {
+                    student_id: hello.student_id.clone(),
+                    raised,
+                });
+            }
+            StudentToTeacher::Chat { text } => {
+                if let Some(text) = sanitize_chat(&text) {
+                    info!(student = %hello.student_id, %text, "学生发来消息");
+                    state.record_chat(ChatEntry {
+                        from: format!("{} ({})", hello.student_name, hello.student_id),
+                        to: None,
+                        text: text.clone(),
+                    });
+                    state.emit(ServerEvent::ChatReceived {
+                        student_id: hello.student_id.clone(),
+                        text,
+                    });
+                }
+            }
+            StudentToTeacher::ShareEnded => {
+                if state.is_student_broadcasting(&hello.student_id) {
+                    info!(student = %hello.student_id, "学生屏幕分享已结束");
+                    // Late joiners should not be shown the last frame of a finished share.
+                    state.latest_frame.lock().take();
+                }
+            }
+            StudentToTeacher::ShareDeclined => {
+                if state.is_student_broadcasting(&hello.student_id) {
+                    warn!(student = %hello.student_id, "学生拒绝共享屏幕");
+                    state.decline_spotlight(&hello.student_id);
                 }
             }
+            StudentToTeacher::RequestKeyframe => {
+                if let Some(frame) = state.keyframe_for(&hello.student_id) {
+                    student_handle.send(TeacherToStudent::Video(frame));
+                }
+            }
+            StudentToTeacher::ResumeFrom {
+                transfer_id,
+                offset,
+            } => match state.take_interrupted_file(transfer_id, &hello.student_id) {
+                Some((path, offer)) => {
+                    tokio::spawn(
+                        resume_file(student_handle.clone(), path, offer, offset).in_current_span(),
+                    );
+                }
+                None => {
+                    warn!(student = %hello.student_id, transfer = %transfer_id, "收到未知的续传请求");
+                }
+            },
             StudentToTeacher::Ack(_) => {}
             StudentToTeacher::Error(msg) => {
                 warn!(student = %hello.student_id, %msg, "学生报告错误");
+                student_handle.record_error(msg);
             }
         }
     }
 
     state.remove_student(connection_id);
-    writer_task.abort();
+    student_handle.queue.close();
+    if time::timeout(WRITER_DRAIN_TIMEOUT, &mut writer_task)
+        .await
+        .is_err()
+    {
+        writer_task.abort();
+    }
+    state.record_interrupted_files(&student_handle);
     screen.handle_disconnection(&hello.student_id);
     info!(student = %hello.student_id, "学生已断开");
     Ok(())
@@ -745,178 +2631,2725 @@ pub struct TeacherState {
     broadcast_source: Arc<RwLock<Option<BroadcastSource>>>,
     broadcast_mode: Arc<RwLock<BroadcastMode>>,
     frame_counter: AtomicU64,
+    broadcast_settings: RwLock<BroadcastConfig>,
+    settings_revision: AtomicU64,
+    clock: SessionClock,
+    /// Recently sent files, keyed by transfer, so a student that drops out during one can
+    /// resume it.
+    sent_files: Mutex<HashMap<Uuid, SentFile>>,
+    /// Sent files that some students missed the end of, keyed by transfer.
+    interrupted_files: Mutex<HashMap<Uuid, InterruptedFile>>,
+    file_progress: Mutex<Option<FileSendProgress>>,
+    /// Cancellation flags of running file sends.
+    file_cancels: Mutex<HashMap<Uuid, Arc<AtomicBool>>>,
+    /// Student screens are blacked out; re-sent to students as they join.
+    blackout: AtomicBool,
+    /// Seconds between the thumbnails requested from every student; 0 when off.
+    thumbnail_interval_secs: AtomicU64,
+    /// Latest monitoring thumbnail per student ID.
+    thumbnails: Mutex<HashMap<String, Thumbnail>>,
+    /// Active recording; fed from every broadcast source so it survives source switches.
+    recording: Mutex<Option<Recording>>,
+    /// Last frame of the running broadcast, replayed to students that join late or ask again.
+    latest_frame: Mutex<Option<VideoFrame>>,
+    /// Recent chat in both directions, oldest first.
+    chat: Mutex<VecDeque<ChatEntry>>,
+    /// When this run started (UTC), naming its upload folder under `UploadOrganize::PerSession`.
+    session_started: SystemTime,
+    /// Identifies this run in the logs of the teacher and its students.
+    session_id: Uuid,
+    /// When each student ID was last seen connecting from a second machine.
+    duplicate_ids: Mutex<HashMap<String, Instant>>,
+    /// Student who refused the spotlight, until the server loop ends it.
+    declined_spotlight: Mutex<Option<String>>,
+    spotlight_declined: Notify,
+    events: Option<EventSender>,
 }
 
 impl TeacherState {
     fn new(config: Arc<TeacherConfig>) -> Self {
         Self {
-            config,
+            config: config.clone(),
             students: Arc::new(RwLock::new(HashMap::new())),
             broadcast_source: Arc::new(RwLock::new(None)),
             broadcast_mode: Arc::new(RwLock::new(BroadcastMode::Window)),
             frame_counter: AtomicU64::new(0),
+            broadcast_settings: RwLock::new(config.broadcast.clone()),
+            settings_revision: AtomicU64::new(1),
+            clock: SessionClock::start(),
+            sent_files: Mutex::new(HashMap::new()),
+            interrupted_files: Mutex::new(HashMap::new()),
+            file_progress: Mutex::new(None),
+            file_cancels: Mutex::new(HashMap::new()),
+            blackout: AtomicBool::new(false),
+            thumbnail_interval_secs: AtomicU64::new(0),
+            thumbnails: Mutex::new(HashMap::new()),
+            recording: Mutex::new(None),
+            latest_frame: Mutex::new(None),
+            chat: Mutex::new(VecDeque::new()),
+            session_started: SystemTime::now(),
+            session_id: Uuid::new_v4(),
+            duplicate_ids: Mutex::new(HashMap::new()),
+            declined_spotlight: Mutex::new(None),
+            spotlight_declined: Notify::new(),
+            events: None,
         }
     }
 
-    #[cfg(feature = "ui")]
     pub fn config(&self) -> Arc<TeacherConfig> {
         Arc::clone(&self.config)
     }
 
     pub(crate) fn broadcast_config(&self) -> BroadcastConfig {
-        self.config.broadcast.clone()
+        self.broadcast_settings.read().clone()
+    }
+
+    fn settings_update(&self) -> SettingsUpdate {
+        let broadcast = self.broadcast_settings.read();
+        SettingsUpdate {
+            revision: self.settings_revision.load(Ordering::SeqCst),
+            broadcast: broadcast.clone(),
+        }
+    }
+
+    /// Replace the live broadcast settings and push them to every student that understands
+    /// `SettingsUpdate`.
+    fn update_broadcast_config(&self, broadcast: BroadcastConfig) -> u64 {
+        let update = {
+            let mut current = self.broadcast_settings.write();
+            *current = broadcast;
+            SettingsUpdate {
+                revision: self.settings_revision.fetch_add(1, Ordering::SeqCst) + 1,
+                broadcast: current.clone(),
+            }
+        };
+        let revision = update.revision;
+        self.broadcast_since(
+            SETTINGS_PROTOCOL_VERSION,
+            TeacherToStudent::SettingsUpdate(update),
+        );
+        revision
+    }
+
+    pub(crate) fn session_clock(&self) -> SessionClock {
+        self.clock
     }
 
     pub(crate) fn next_frame_id(&self) -> u64 {
         self.frame_counter.fetch_add(1, Ordering::Relaxed) + 1
     }
 
-    fn add_student(&self, student: Arc<StudentHandle>) {
-        self.students.write().insert(student.connection_id, student);
+    /// Report `event` to the embedder, if there is one listening.
+    fn emit(&self, event: ServerEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
     }
 
-    fn remove_student(&self, connection_id: Uuid) {
-        self.students.write().remove(&connection_id);
+    /// Register `student`, first settling any connection with its ID that registered since
+    /// its Hello was checked. Checking and inserting under one lock means two connections
+    /// racing with the same ID never both end up in class. `Err` carries the rejection to
+    /// send when the duplicate policy keeps the other connection.
+    fn add_student(&self, student: Arc<StudentHandle>) -> std::result::Result<(), String> {
+        let replaced = {
+            let mut students = self.students.write();
+            let mut replaced = Vec::new();
+            for existing in students.values() {
+                if existing.student_id != student.student_id {
+                    continue;
+                }
+                match self.duplicate_outcome(existing, student.addr) {
+                    Duplicate::Reject(message) => return Err(message),
+                    Duplicate::Replace(reason) => replaced.push((existing.clone(), reason)),
+                }
+            }
+            students.insert(student.connection_id, student.clone());
+            replaced
+        };
+        for (existing, reason) in replaced {
+            self.replace_student(&existing, &reason);
+        }
+        self.emit(ServerEvent::StudentJoined {
+            student_id: student.student_id.clone(),
+            student_name: student.student_name.clone(),
+            addr: student.addr,
+        });
+        Ok(())
     }
 
-    fn list_students(&self) -> Vec<StudentSummary> {
-        self.students
-            .read()
-            .values()
-            .map(|student| StudentSummary {
+    /// Decide what a new connection from `addr` does about `existing`, which has the same
+    /// student ID. The same address again is the student reconnecting before its old
+    /// session timed out and always takes over; another machine follows the policy.
+    fn duplicate_outcome(&self, existing: &StudentHandle, addr: SocketAddr) -> Duplicate {
+        let student_id = &existing.student_id;
+        if existing.addr.ip() == addr.ip() {
+            info!(student = %student_id, old = %existing.addr, new = %addr, "学生重新连接，替换旧连接");
+            return Duplicate::Replace("已在本机重新连接".to_string());
+        }
+        warn!(
+            student = %student_id,
+            existing = %existing.addr,
+            %addr,
+            "同一学号从另一台机器连接，请检查学生端配置"
+        );
+        self.note_duplicate_id(student_id);
+        match self.config.duplicate_student_policy {
+            DuplicateStudentPolicy::RejectNew => Duplicate::Reject(format!(
+                "学号 {student_id} 已在另一台机器上连接，请检查学生端配置"
+            )),
+            DuplicateStudentPolicy::ReplaceOld => Duplicate::Replace(format!(
+                "学号 {student_id} 已在另一台机器 ({addr}) 上登录，请检查学生端配置后重新启动"
+            )),
+        }
+    }
+
+    fn remove_student(&self, connection_id: Uuid) {
+        let removed = self.students.write().remove(&connection_id);
+        if let Some(student) = removed {
+            self.thumbnails.lock().remove(&student.student_id);
+            self.emit(ServerEvent::StudentLeft {
                 student_id: student.student_id.clone(),
-                display_name: student.student_name.clone(),
-                addr: student.addr,
-            })
-            .collect()
+            });
+        }
     }
 
-    fn broadcast(&self, message: TeacherToStudent) {
-        self.broadcast_except(message, None);
+    /// Remember `offer` as resumable by students that drop out while it is sent.
+    fn remember_sent_file(&self, path: &Path, offer: &FileOffer) {
+        self.expire_file_records(Instant::now());
+        self.sent_files.lock().insert(
+            offer.transfer_id,
+            SentFile {
+                path: path.to_path_buf(),
+                offer: offer.clone(),
+                sent_at: Instant::now(),
+            },
+        );
     }
 
-    fn broadcast_except(&self, message: TeacherToStudent, exclude: Option<Uuid>) {
-        let recipients: Vec<Arc<StudentHandle>> = self
-            .students
-            .read()
-            .iter()
-            .filter(|(id, _)| exclude.map_or(true, |ex| ex != **id))
-            .map(|(_, handle)| Arc::clone(handle))
-            .collect();
+    /// Forget sent files and interruptions older than [`INTERRUPTED_FILE_TTL`].
+    fn expire_file_records(&self, now: Instant) {
+        let fresh = |since: Instant| now.saturating_duration_since(since) < INTERRUPTED_FILE_TTL;
+        self.sent_files.lock().retain(|_, file| fresh(file.sent_at));
+        self.interrupted_files.lock().retain(|_, file| {
+            let keep = fresh(file.recorded_at);
+            if !keep {
+                info!(file = %file.offer.file_name, students = ?file.students, "中断的文件已过期，不再续传");
+            }
+            keep
+        });
+    }
 
-        for student in recipients {
-            student.send(message.clone());
+    /// Once the writer of `student`'s connection has stopped, remember every sent file whose
+    /// end it never wrote, and re-offer those right away if the student is already back.
+    fn record_interrupted_files(&self, student: &StudentHandle) {
+        let unfinished: Vec<Uuid> = student.unfinished_files.lock().drain().collect();
+        if unfinished.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        self.expire_file_records(now);
+        let mut recorded = Vec::new();
+        {
+            let sent = self.sent_files.lock();
+            let mut interrupted = self.interrupted_files.lock();
+            // Cancelled or failed sends are forgotten and not resumed.
+            for file in unfinished.iter().filter_map(|id| sent.get(id)) {
+                warn!(student = %student.student_id, file = %file.offer.file_name, "学生在文件传输中断开，将在重连后续传");
+                interrupted
+                    .entry(file.offer.transfer_id)
+                    .or_insert_with(|| InterruptedFile {
+                        path: file.path.clone(),
+                        offer: file.offer.clone(),
+                        students: HashSet::new(),
+                        recorded_at: now,
+                    })
+                    .students
+                    .insert(student.student_id.clone());
+                recorded.push(file.offer.clone());
+            }
         }
-    }
 
-    fn broadcast_command(&self, command: BroadcastCommand) {
-        self.broadcast(TeacherToStudent::Broadcast(command));
+        if let Some(current) = self.find_student(&student.student_id) {
+            for offer in recorded {
+                current.send(TeacherToStudent::FileOffer(FileOffer {
+                    resume: true,
+                    ..offer
+                }));
+            }
+        }
     }
 
-    pub(crate) fn broadcast_video(&self, frame: VideoFrame) {
-        self.broadcast(TeacherToStudent::Video(frame));
+    /// Re-offer every file `student` missed the end of; it answers with `ResumeFrom`.
+    fn offer_interrupted_files(&self, student: &StudentHandle) {
+        self.expire_file_records(Instant::now());
+        for file in self.interrupted_files.lock().values() {
+            if file.students.contains(&student.student_id) {
+                student.send(TeacherToStudent::FileOffer(FileOffer {
+                    resume: true,
+                    ..file.offer.clone()
+                }));
+            }
+        }
     }
 
-    pub(crate) fn broadcast_audio(&self, frame: AudioFrame) {
-        self.broadcast(TeacherToStudent::Audio(frame));
+    /// Claim an interrupted transfer for `student_id`, so a duplicate `ResumeFrom` cannot
+    /// start a second stream.
+    fn take_interrupted_file(
+        &self,
+        transfer_id: Uuid,
+        student_id: &str,
+    ) -> Option<(PathBuf, FileOffer)> {
+        let mut files = self.interrupted_files.lock();
+        let file = files.get_mut(&transfer_id)?;
+        if !file.students.remove(student_id) {
+            return None;
+        }
+        let claimed = (file.path.clone(), file.offer.clone());
+        if file.students.is_empty() {
+            files.remove(&transfer_id);
+        }
+        Some(claimed)
     }
 
-    fn set_broadcast_source(&self, source: Option<BroadcastSource>, mode: BroadcastMode) {
-        *self.broadcast_source.write() = source;
-        *self.broadcast_mode.write() = mode;
+    /// Tell every connection of `student_id` why it is dropped, then drop it.
+    fn kick_student(&self, student_id: &str, reason: &str) -> Result<()> {
+        let kicked: Vec<Arc<StudentHandle>> = self
+            .students
+            .read()
+            .values()
+            .filter(|student| student.student_id == student_id)
+            .cloned()
+            .collect();
+        ensure!(!kicked.is_empty(), "学生 {student_id} 不在线");
+        for student in kicked {
+            warn!(student = %student_id, addr = %student.addr, %reason, "已将学生移出课堂");
+            self.remove_student(student.connection_id);
+            student
+                .queue
+                .close_with(kick_message(&student, reason).into());
+            student.disconnect();
+        }
+        Ok(())
     }
 
-    #[cfg(feature = "ui")]
-    pub fn broadcast_source(&self) -> Option<BroadcastSource> {
-        self.broadcast_source.read().clone()
+    /// Drop `student` because a newer connection with its ID takes its place. Students that
+    /// know `Replaced` stop reconnecting; older ones are kicked and only hold off a while.
+    fn replace_student(&self, student: &StudentHandle, reason: &str) {
+        info!(student = %student.student_id, addr = %student.addr, %reason, "旧连接已被替换");
+        self.remove_student(student.connection_id);
+        let farewell = if student.protocol_version >= REPLACE_PROTOCOL_VERSION {
+            TeacherToStudent::Replaced {
+                reason: reason.to_string(),
+            }
+        } else {
+            kick_message(student, reason)
+        };
+        student.queue.close_with(farewell.into());
+        student.disconnect();
     }
 
-    pub fn broadcast_mode(&self) -> BroadcastMode {
-        *self.broadcast_mode.read()
+    /// Send a chat line to `student_id`, or to every student whose version supports chat.
+    fn send_chat(&self, student_id: Option<&str>, text: &str) -> Result<()> {
+        let text = sanitize_chat(text).context("聊天内容为空")?;
+        let recipients: Vec<Arc<StudentHandle>> = self
+            .students
+            .read()
+            .values()
+            .filter(|student| student_id.is_none_or(|id| student.student_id == id))
+            .cloned()
+            .collect();
+        if let Some(id) = student_id {
+            ensure!(!recipients.is_empty(), "学生 {id} 不在线");
+        }
+        let (supported, outdated): (Vec<_>, Vec<_>) = recipients
+            .into_iter()
+            .partition(|student| student.protocol_version >= CHAT_PROTOCOL_VERSION);
+        if let Some(id) = student_id {
+            ensure!(!supported.is_empty(), "学生 {id} 的版本不支持聊天");
+        } else if !outdated.is_empty() {
+            warn!(count = outdated.len(), "部分学生端版本过旧，收不到聊天消息");
+        }
+        for student in &supported {
+            student.send(TeacherToStudent::Chat {
+                from: TEACHER_CHAT_NAME.to_string(),
+                text: text.clone(),
+            });
+        }
+        info!(to = student_id.unwrap_or("全班"), %text, "已发送聊天消息");
+        self.record_chat(ChatEntry {
+            from: TEACHER_CHAT_NAME.to_string(),
+            to: student_id.map(str::to_string),
+            text,
+        });
+        Ok(())
     }
 
-    fn is_student_broadcasting(&self, student_id: &str) -> bool {
-        matches!(
-            &*self.broadcast_source.read(),
-            Some(BroadcastSource::Student { student_id: sid, .. }) if sid == student_id
-        )
+    fn record_chat(&self, entry: ChatEntry) {
+        let mut chat = self.chat.lock();
+        if chat.len() == CHAT_LOG_LIMIT {
+            chat.pop_front();
+        }
+        chat.push_back(entry);
     }
 
-    async fn prepare_upload_path(&self, hello: &HelloMessage, file_name: &str) -> Result<PathBuf> {
-        let student_dir = self
-            .config
-            .save_upload_dir
-            .join(sanitize_filename(&hello.student_id));
-        tokio::fs::create_dir_all(&student_dir).await?;
-        let safe_name = sanitize_filename(file_name);
-        Ok(student_dir.join(safe_name))
+    #[cfg_attr(not(feature = "ui"), allow(dead_code))]
+    fn chat_log(&self) -> Vec<ChatEntry> {
+        self.chat.lock().iter().cloned().collect()
     }
 
-    fn find_student_name(&self, student_id: &str) -> Option<String> {
+    fn idle_students(&self, timeout: Duration) -> Vec<Arc<StudentHandle>> {
         self.students
             .read()
             .values()
-            .find(|student| student.student_id == student_id)
-            .map(|student| student.student_name.clone())
-            .or_else(|| {
-                self.config
-                    .expected_students
+            .filter(|student| student.last_seen.lock().elapsed() > timeout)
+            .cloned()
+            .collect()
+    }
+
+    fn list_students(&self) -> Vec<StudentSummary> {
+        let connected: Vec<Arc<StudentHandle>> = self.students.read().values().cloned().collect();
+        let mut summaries: Vec<StudentSummary> = connected
+            .iter()
+            .map(|student| student.summary(!self.identity_issues(student, &connected).is_empty()))
+            .collect();
+        // The map has no order of its own; keep the roster from reshuffling on every refresh.
+        summaries.sort_by(|a, b| {
+            a.display_name
+                .cmp(&b.display_name)
+                .then_with(|| a.student_id.cmp(&b.student_id))
+        });
+        summaries
+    }
+
+    fn student_metrics(&self) -> Vec<StudentMetrics> {
+        self.students
+            .read()
+            .values()
+            .map(|student| student.metrics())
+            .collect()
+    }
+
+    fn student_detail(&self, student_id: &str) -> Option<StudentDetail> {
+        let connected: Vec<Arc<StudentHandle>> = self.students.read().values().cloned().collect();
+        let student = connected
+            .iter()
+            .find(|student| student.student_id == student_id)?;
+        let last_seen_secs = student.last_seen.lock().elapsed().as_secs();
+        let recent_errors = student.recent_errors.lock().iter().cloned().collect();
+        let pending_zoom = *student.zoom_request.lock();
+        let identity_issues = self.identity_issues(student, &connected);
+        Some(StudentDetail {
+            summary: student.summary(!identity_issues.is_empty()),
+            client_version: student.client_version.clone(),
+            protocol_version: student.protocol_version,
+            capabilities: student.capabilities.clone(),
+            registration: self.registration(student_id).cloned(),
+            identity_issues,
+            last_seen_secs,
+            bytes_sent: student.metrics().bytes_sent,
+            hand_raised: student.hand_raised.load(Ordering::SeqCst),
+            pending_zoom,
+            spotlighted: self.is_student_broadcasting(student_id),
+            settings_current: student.settings_acked.load(Ordering::SeqCst)
+                >= self.settings_revision.load(Ordering::SeqCst),
+            recent_errors,
+            thumbnail: self.thumbnails.lock().get(student_id).cloned(),
+        })
+    }
+
+    fn registration(&self, student_id: &str) -> Option<&StudentRegistration> {
+        self.config
+            .expected_students
+            .iter()
+            .find(|registration| registration.student_id == student_id)
+    }
+
+    /// Whether `student_id` may join; only listed students do under `enforce_registration`.
+    fn registration_allows(&self, student_id: &str) -> bool {
+        !self.config.enforce_registration || self.registration(student_id).is_some()
+    }
+
+    /// Whether `student_id` may upload files; unlisted students may unless registration
+    /// is enforced, in which case they never get this far.
+    fn uploads_allowed(&self, student_id: &str) -> bool {
+        self.registration(student_id)
+            .is_none_or(|registration| registration.allow_uploads)
+    }
+
+    /// Whether an upload of `size` bytes goes past `max_upload_bytes`.
+    fn upload_too_large(&self, size: u64) -> bool {
+        self.config.max_upload_bytes != 0 && size > self.config.max_upload_bytes
+    }
+
+    /// Reason sent to a student whose upload was refused by [`Self::upload_too_large`].
+    fn upload_limit_message(&self, file_name: &str) -> String {
+        format!(
+            "文件 {file_name} 超过上传大小限制 {} MB，已被教师端拒绝",
+            self.config.max_upload_bytes.div_ceil(1024 * 1024)
+        )
+    }
+
+    /// Whether `hello` may join; always true when no `join_token` is configured.
+    fn join_token_matches(&self, hello: &HelloMessage) -> bool {
+        match &self.config.join_token {
+            Some(expected) => hello
+                .join_token
+                .as_deref()
+                .is_some_and(|given| tokens_match(expected, given)),
+            None => true,
+        }
+    }
+
+    /// Hand `student_id`'s refusal of the spotlight to the server loop, which ends it.
+    fn decline_spotlight(&self, student_id: &str) {
+        *self.declined_spotlight.lock() = Some(student_id.to_string());
+        self.spotlight_declined.notify_one();
+    }
+
+    fn note_duplicate_id(&self, student_id: &str) {
+        self.duplicate_ids
+            .lock()
+            .insert(student_id.to_string(), Instant::now());
+    }
+
+    /// Student IDs seen on two machines within [`DUPLICATE_WARNING_WINDOW`], sorted.
+    fn recent_duplicate_ids(&self) -> Vec<String> {
+        let mut duplicates = self.duplicate_ids.lock();
+        duplicates.retain(|_, seen| seen.elapsed() < DUPLICATE_WARNING_WINDOW);
+        let mut ids: Vec<String> = duplicates.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Reasons to doubt that `student` is who it claims to be; empty when nothing stands out.
+    fn identity_issues(
+        &self,
+        student: &StudentHandle,
+        connected: &[Arc<StudentHandle>],
+    ) -> Vec<String> {
+        if !self.config.verify_identities {
+            return Vec::new();
+        }
+
+        let mut issues = Vec::new();
+        for other in connected
+            .iter()
+            .filter(|other| other.connection_id != student.connection_id)
+        {
+            if other.student_id == student.student_id {
+                issues.push(format!("同一学号另有连接 @ {}", other.addr));
+            } else if other.addr.ip() == student.addr.ip() {
+                issues.push(format!("与 {} 来自同一 IP", other.student_id));
+            }
+        }
+
+        match self.registration(&student.student_id) {
+            Some(registration) => {
+                if let Some(name) = registration
+                    .student_name
+                    .as_deref()
+                    .filter(|name| *name != student.student_name)
+                {
+                    issues.push(format!("姓名与名单不符 (名单: {name})"));
+                }
+                if let Some(ip) = registration.ip.filter(|ip| *ip != student.addr.ip()) {
+                    issues.push(format!("IP 与名单不符 (名单: {ip})"));
+                }
+            }
+            None if !self.config.expected_students.is_empty() => {
+                issues.push("学号不在名单中".to_string());
+            }
+            None => {}
+        }
+
+        if student.client_version != env!("CARGO_PKG_VERSION") {
+            issues.push(format!(
+                "客户端版本 {} 与教师端 {} 不一致",
+                student.client_version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        issues
+    }
+
+    fn broadcast(&self, message: TeacherToStudent) {
+        self.broadcast_except(message, None);
+    }
+
+    /// Send `message` to every student on `version` or later; returns how many were older
+    /// and left out.
+    fn broadcast_since(&self, version: ProtocolVersion, message: TeacherToStudent) -> usize {
+        let (current, older): (Vec<_>, Vec<_>) = self
+            .students
+            .read()
+            .values()
+            .cloned()
+            .partition(|student| student.protocol_version >= version);
+        let message = Arc::new(message);
+        for student in current {
+            student.send(Arc::clone(&message));
+        }
+        older.len()
+    }
+
+    fn broadcast_except(&self, message: TeacherToStudent, exclude: Option<Uuid>) {
+        let recipients: Vec<Arc<StudentHandle>> = self
+            .students
+            .read()
+            .iter()
+            .filter(|(id, _)| exclude.map_or(true, |ex| ex != **id))
+            .map(|(_, handle)| Arc::clone(handle))
+            .collect();
+
+        // One copy for everyone; each queue holds a pointer to it.
+        let message = Arc::new(message);
+        for student in recipients {
+            student.send(Arc::clone(&message));
+        }
+    }
+
+    /// Send `command` to every student. Students too old for `lock_input` get the start
+    /// command without it; students too old for blackout keep their screens.
+    pub(crate) fn broadcast_command(&self, command: BroadcastCommand) {
+        if let BroadcastCommand::Blackout { .. } = command {
+            let older = self.broadcast_since(
+                BLACKOUT_PROTOCOL_VERSION,
+                TeacherToStudent::Broadcast(command),
+            );
+            if older > 0 {
+                warn!(count = older, "部分学生端版本过旧，无法黑屏");
+            }
+            return;
+        }
+        let BroadcastCommand::Start {
+            source,
+            mode,
+            lock_input: true,
+        } = &command
+        else {
+            self.broadcast(TeacherToStudent::Broadcast(command));
+            return;
+        };
+        let (current, older): (Vec<_>, Vec<_>) = self
+            .students
+            .read()
+            .values()
+            .cloned()
+            .partition(|student| student.protocol_version >= LOCK_INPUT_PROTOCOL_VERSION);
+        if !older.is_empty() {
+            warn!(count = older.len(), "部分学生端版本过旧，无法锁定键盘");
+            let unlocked = Arc::new(TeacherToStudent::Broadcast(BroadcastCommand::Start {
+                source: source.clone(),
+                mode: *mode,
+                lock_input: false,
+            }));
+            for student in older {
+                student.send(Arc::clone(&unlocked));
+            }
+        }
+        let message = Arc::new(TeacherToStudent::Broadcast(command));
+        for student in current {
+            student.send(Arc::clone(&message));
+        }
+    }
+
+    /// Show `notice` on every student screen. Students too old for notices get its text as
+    /// an error instead, which they log.
+    fn broadcast_notice(&self, notice: Notice) {
+        let (current, older): (Vec<_>, Vec<_>) = self
+            .students
+            .read()
+            .values()
+            .cloned()
+            .partition(|student| student.protocol_version >= NOTICE_PROTOCOL_VERSION);
+        if !older.is_empty() {
+            let fallback = Arc::new(TeacherToStudent::Error(notice.text.clone()));
+            for student in older {
+                student.send(Arc::clone(&fallback));
+            }
+        }
+        let message = Arc::new(TeacherToStudent::Notice(notice));
+        for student in current {
+            student.send(Arc::clone(&message));
+        }
+    }
+
+    /// Black out (or restore) every student screen, including students who join later.
+    fn set_blackout(&self, enabled: bool) {
+        self.blackout.store(enabled, Ordering::SeqCst);
+        self.broadcast_command(BroadcastCommand::Blackout { enabled });
+        info!(enabled, "学生黑屏状态已切换");
+    }
+
+    fn is_blackout(&self) -> bool {
+        self.blackout.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn broadcast_video(&self, frame: VideoFrame) {
+        self.record_frame(&frame);
+        *self.latest_frame.lock() = Some(frame.clone());
+        self.broadcast(TeacherToStudent::Video(frame));
+    }
+
+    /// Send a one-shot screenshot. Unlike broadcast frames it is not recorded, nor kept for
+    /// students who join later.
+    pub(crate) fn broadcast_still(&self, frame: VideoFrame) {
+        self.broadcast(TeacherToStudent::Video(frame));
+    }
+
+    /// The latest broadcast frame for `student_id`, unless the broadcast is their own screen.
+    fn keyframe_for(&self, student_id: &str) -> Option<VideoFrame> {
+        let frame = self.latest_frame.lock().clone()?;
+        match &frame.source {
+            BroadcastSource::Student {
+                student_id: source, ..
+            } if source == student_id => None,
+            _ => Some(frame),
+        }
+    }
+
+    /// Ask every student, including later ones, for a thumbnail every `interval_secs`.
+    fn set_thumbnail_interval(&self, interval_secs: u64) {
+        self.thumbnail_interval_secs
+            .store(interval_secs, Ordering::SeqCst);
+        if interval_secs == 0 {
+            self.thumbnails.lock().clear();
+        }
+        info!(interval_secs, "更新学生缩略图间隔");
+        let recipients: Vec<Arc<StudentHandle>> = self.students.read().values().cloned().collect();
+        for student in recipients {
+            student.request_thumbnails(interval_secs);
+        }
+    }
+
+    fn thumbnail_interval_secs(&self) -> u64 {
+        self.thumbnail_interval_secs.load(Ordering::SeqCst)
+    }
+
+    fn store_thumbnail(&self, student_id: &str, frame: VideoFrame) {
+        self.thumbnails.lock().insert(
+            student_id.to_string(),
+            Thumbnail {
+                frame,
+                received: Instant::now(),
+            },
+        );
+    }
+
+    fn record_frame(&self, frame: &VideoFrame) {
+        if let Some(recording) = self.recording.lock().as_ref() {
+            recording.record(frame);
+        }
+    }
+
+    fn start_recording(&self, dir: &Path) -> Result<()> {
+        let mut recording = self.recording.lock();
+        if let Some(active) = recording.as_ref() {
+            bail!("已在录制到 {}", active.dir().display());
+        }
+        *recording = Some(Recording::start(dir)?);
+        info!(dir = %dir.display(), "开始录制广播");
+        Ok(())
+    }
+
+    fn take_recording(&self) -> Option<Recording> {
+        self.recording.lock().take()
+    }
+
+    fn recording_dir(&self) -> Option<PathBuf> {
+        self.recording
+            .lock()
+            .as_ref()
+            .map(|recording| recording.dir().to_path_buf())
+    }
+
+    /// Send `frame` to the students that negotiated its codec.
+    pub(crate) fn broadcast_audio(&self, frame: AudioFrame) {
+        let recipients: Vec<Arc<StudentHandle>> = self
+            .students
+            .read()
+            .values()
+            .filter(|handle| handle.audio_codec == frame.codec)
+            .cloned()
+            .collect();
+        for student in recipients {
+            student.send(TeacherToStudent::Audio(frame.clone()));
+        }
+    }
+
+    /// Whether any connected student expects audio encoded with `codec`.
+    pub(crate) fn has_audio_listeners(&self, codec: AudioCodec) -> bool {
+        self.students
+            .read()
+            .values()
+            .any(|handle| handle.audio_codec == codec)
+    }
+
+    pub(crate) fn set_broadcast_source(
+        &self,
+        source: Option<BroadcastSource>,
+        mode: BroadcastMode,
+    ) {
+        *self.broadcast_source.write() = source.clone();
+        *self.broadcast_mode.write() = mode;
+        // Frames of the previous source must not reach students joining the next one.
+        self.latest_frame.lock().take();
+        self.emit(match source {
+            Some(source) => ServerEvent::BroadcastStarted { source, mode },
+            None => ServerEvent::BroadcastStopped,
+        });
+    }
+
+    pub fn broadcast_source(&self) -> Option<BroadcastSource> {
+        self.broadcast_source.read().clone()
+    }
+
+    pub fn broadcast_mode(&self) -> BroadcastMode {
+        *self.broadcast_mode.read()
+    }
+
+    pub(crate) fn is_student_broadcasting(&self, student_id: &str) -> bool {
+        matches!(
+            &*self.broadcast_source.read(),
+            Some(BroadcastSource::Student { student_id: sid, .. }) if sid == student_id
+        )
+    }
+
+    async fn send_file_to_all(&self, path: PathBuf, auto_open_override: bool) -> Result<()> {
+        self.send_file(path, auto_open_override, None).await
+    }
+
+    async fn send_file_to_student(
+        &self,
+        student_id: &str,
+        path: PathBuf,
+        auto_open_override: bool,
+    ) -> Result<()> {
+        let student = self
+            .find_student(student_id)
+            .ok_or_else(|| anyhow!("学生 {student_id} 未连接，无法发送文件"))?;
+        self.send_file(path, auto_open_override, Some(student))
+            .await
+    }
+
+    /// Send a file to `target`, or to every connected student when `None`.
+    async fn send_file(
+        &self,
+        path: PathBuf,
+        auto_open_override: bool,
+        target: Option<Arc<StudentHandle>>,
+    ) -> Result<()> {
+        let deliver = |message: TeacherToStudent| match &target {
+            Some(student) => student.send(message),
+            None => self.broadcast(message),
+        };
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("无法读取文件信息: {}", path.display()))?;
+        if !metadata.is_file() {
+            bail!("{} 不是有效文件", path.display());
+        }
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow!("无法解析文件名"))?;
+
+        let offer = FileOffer {
+            transfer_id: Uuid::new_v4(),
+            file_name: file_name.clone(),
+            total_size: metadata.len(),
+            auto_open: auto_open_override || self.config.file_auto_open,
+            resume: false,
+        };
+
+        {
+            let mut progress = self.file_progress.lock();
+            if let Some(current) = progress.as_ref() {
+                bail!(
+                    "文件 {} 正在发送 ({}/{} 字节)，请稍后再试",
+                    current.file_name,
+                    current.sent,
+                    current.total
+                );
+            }
+            *progress = Some(FileSendProgress {
+                transfer_id: offer.transfer_id,
+                file_name: file_name.clone(),
+                sent: 0,
+                total: offer.total_size,
+            });
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.file_cancels
+            .lock()
+            .insert(offer.transfer_id, cancel.clone());
+
+        self.remember_sent_file(&path, &offer);
+        deliver(TeacherToStudent::FileOffer(offer.clone()));
+        let room = || self.file_room(target.as_ref());
+        let result = stream_file(&path, &offer, 0, &cancel, room, |message| {
+            if let TeacherToStudent::FileChunk(chunk) = &message {
+                if let Some(progress) = self.file_progress.lock().as_mut() {
+                    progress.sent = chunk.offset + chunk.bytes.len() as u64;
+                }
+            }
+            deliver(message);
+        })
+        .await;
+        self.file_cancels.lock().remove(&offer.transfer_id);
+        *self.file_progress.lock() = None;
+
+        match result {
+            Ok(true) => {
+                info!(file = %file_name, size = metadata.len(), "文件分发完成");
+                Ok(())
+            }
+            Ok(false) => {
+                self.sent_files.lock().remove(&offer.transfer_id);
+                self.call_off_file(&offer, target.as_ref());
+                info!(file = %file_name, "文件分发已取消");
+                Ok(())
+            }
+            Err(err) => {
+                self.sent_files.lock().remove(&offer.transfer_id);
+                // Tell students to drop what they received so far.
+                deliver(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id: offer.transfer_id,
+                    success: false,
+                    message: Some(format!("{err:#}")),
+                }));
+                Err(err)
+            }
+        }
+    }
+
+    /// Drop the queued data of a cancelled send right away rather than after it is written,
+    /// and tell the students that already got part of it to discard the file.
+    fn call_off_file(&self, offer: &FileOffer, target: Option<&Arc<StudentHandle>>) {
+        let students: Vec<Arc<StudentHandle>> = match target {
+            Some(student) => vec![student.clone()],
+            None => self.students.read().values().cloned().collect(),
+        };
+        for student in students {
+            if student.queue.discard_transfer(offer.transfer_id) {
+                student.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id: offer.transfer_id,
+                    success: false,
+                    message: Some("文件发送已取消".into()),
+                }));
+            }
+        }
+    }
+
+    /// Stop the file send `transfer_id` after its current chunk.
+    fn cancel_file(&self, transfer_id: Uuid) -> Result<()> {
+        let cancel = self
+            .file_cancels
+            .lock()
+            .get(&transfer_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("没有正在发送的文件 {transfer_id}"))?;
+        cancel.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for file sends in progress to be written to every student. What
+    /// is still unsent then is dropped, and students are told to discard those files rather
+    /// than keep a partial download.
+    async fn drain_file_sends(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        if !self.file_data_pending() {
+            return;
+        }
+        info!("等待文件发送完成后退出");
+        while self.file_data_pending() {
+            if Instant::now() >= deadline {
+                self.abandon_file_sends().await;
+                return;
+            }
+            time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        info!("文件已全部发出");
+    }
+
+    /// Wait until `target`, or every connected student, can queue more file data.
+    async fn file_room(&self, target: Option<&Arc<StudentHandle>>) {
+        let students: Vec<Arc<StudentHandle>> = match target {
+            Some(student) => vec![student.clone()],
+            None => self.students.read().values().cloned().collect(),
+        };
+        for student in students {
+            student.queue.file_room().await;
+        }
+    }
+
+    fn file_data_pending(&self) -> bool {
+        !self.file_cancels.lock().is_empty()
+            || self
+                .students
+                .read()
+                .values()
+                .any(|student| student.queue.holds_file_data())
+    }
+
+    async fn abandon_file_sends(&self) {
+        warn!("文件发送超时，未发完的文件已放弃");
+        for cancel in self.file_cancels.lock().values() {
+            cancel.store(true, Ordering::SeqCst);
+        }
+        let deadline = Instant::now() + WRITER_DRAIN_TIMEOUT;
+        while !self.file_cancels.lock().is_empty() && Instant::now() < deadline {
+            time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        let students: Vec<Arc<StudentHandle>> = self.students.read().values().cloned().collect();
+        for student in &students {
+            for transfer_id in student.queue.discard_file_data() {
+                student.send(TeacherToStudent::FileComplete(FileTransferComplete {
+                    transfer_id,
+                    success: false,
+                    message: Some("教师端已退出，文件未发送完".into()),
+                }));
+            }
+        }
+        // Give the writers a moment to deliver those; a stuck student just misses it.
+        let deadline = Instant::now() + WRITER_DRAIN_TIMEOUT;
+        while students
+            .iter()
+            .any(|student| student.queue.holds_file_data())
+            && Instant::now() < deadline
+        {
+            time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    fn current_file_send(&self) -> Option<Uuid> {
+        self.file_progress
+            .lock()
+            .as_ref()
+            .map(|progress| progress.transfer_id)
+    }
+
+    /// Create the file for an upload from `hello`'s student, refused unless it resolves to a
+    /// file directly in that student's folder, laid out under `save_upload_dir` as
+    /// `upload_organize` says. Flat uploads never replace a file, even one created meanwhile
+    /// by another upload of the same name.
+    async fn create_upload_file(
+        &self,
+        hello: &HelloMessage,
+        file_name: &str,
+    ) -> Result<(PathBuf, tokio::fs::File)> {
+        let upload_dir = &self.config.save_upload_dir;
+        let mut relative_dir = PathBuf::new();
+        match self.config.upload_organize {
+            UploadOrganize::Flat => {}
+            UploadOrganize::PerDate => relative_dir.push(local_date(SystemTime::now())),
+            UploadOrganize::PerSession => relative_dir.push(utc_timestamp(self.session_started)),
+        }
+        relative_dir.push(sanitize_filename(&hello.student_id));
+        let student_dir = upload_dir.join(&relative_dir);
+        tokio::fs::create_dir_all(&student_dir)
+            .await
+            .with_context(|| format!("无法创建上传目录 {}", student_dir.display()))?;
+        let file_name = sanitize_filename(file_name);
+        let flat = self.config.upload_organize == UploadOrganize::Flat;
+
+        // Sanitizing leaves single path components, but a symlinked folder or file could
+        // still lead elsewhere; check where the path really ends up.
+        let root = tokio::fs::canonicalize(upload_dir).await?;
+        let real_dir = tokio::fs::canonicalize(&student_dir).await?;
+        if real_dir != root.join(&relative_dir) {
+            bail!("上传路径 {} 超出上传目录，已拒绝", student_dir.display());
+        }
+        let mut copy = 0;
+        loop {
+            let path = match copy {
+                0 => student_dir.join(&file_name),
+                copy => student_dir.join(numbered_file_name(&file_name, copy)),
+            };
+            // A symlinked file, even one pointing nowhere yet, would take the write elsewhere.
+            if let Ok(existing) = tokio::fs::symlink_metadata(&path).await {
+                if existing.file_type().is_symlink() {
+                    bail!("上传路径 {} 是符号链接，已拒绝", path.display());
+                }
+            }
+            let mut options = tokio::fs::OpenOptions::new();
+            options.write(true);
+            if flat {
+                options.create_new(true);
+            } else {
+                options.create(true).truncate(true);
+            }
+            match options.open(&path).await {
+                Ok(file) => return Ok((path, file)),
+                Err(err) if flat && err.kind() == std::io::ErrorKind::AlreadyExists => copy += 1,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("无法创建文件 {}", path.display()))
+                }
+            }
+        }
+    }
+
+    fn find_student(&self, student_id: &str) -> Option<Arc<StudentHandle>> {
+        self.students
+            .read()
+            .values()
+            .find(|student| student.student_id == student_id)
+            .cloned()
+    }
+
+    fn find_student_name(&self, student_id: &str) -> Option<String> {
+        self.students
+            .read()
+            .values()
+            .find(|student| student.student_id == student_id)
+            .map(|student| student.student_name.clone())
+            .or_else(|| {
+                self.config
+                    .expected_students
                     .iter()
                     .find(|s| s.student_id == student_id)
                     .and_then(|s| s.student_name.clone())
             })
     }
 
-    fn disconnect_all(&self) {
-        self.students.write().clear();
+    fn disconnect_all(&self) {
+        self.students.write().clear();
+    }
+}
+
+struct SentFile {
+    path: PathBuf,
+    offer: FileOffer,
+    sent_at: Instant,
+}
+
+struct InterruptedFile {
+    path: PathBuf,
+    offer: FileOffer,
+    /// Students still missing the end of the file.
+    students: HashSet<String>,
+    recorded_at: Instant,
+}
+
+struct StudentHandle {
+    connection_id: Uuid,
+    addr: SocketAddr,
+    student_id: String,
+    student_name: String,
+    client_version: String,
+    protocol_version: ProtocolVersion,
+    capabilities: StudentCapabilities,
+    /// Encoding of the broadcast audio this student receives.
+    audio_codec: AudioCodec,
+    queue: SendQueue,
+    connected_at: Instant,
+    last_seen: Mutex<Instant>,
+    audio_state: Mutex<Option<AudioState>>,
+    window_focused: Mutex<Option<bool>>,
+    /// Pending zoom request; the inner `None` asks for the full screen again.
+    zoom_request: Mutex<Option<Option<Region>>>,
+    /// Cleared when the student lowers the hand; a reconnect starts lowered.
+    hand_raised: AtomicBool,
+    recent_errors: Mutex<VecDeque<String>>,
+    settings_acked: AtomicU64,
+    /// Signalled when the teacher drops this connection, e.g. on heartbeat timeout.
+    disconnect: Notify,
+    counters: StudentCounters,
+    /// File transfers whose offer was written to this connection but not their completion.
+    unfinished_files: Mutex<HashSet<Uuid>>,
+}
+
+impl StudentHandle {
+    fn new(
+        connection_id: Uuid,
+        addr: SocketAddr,
+        hello: &HelloMessage,
+        queue_capacity: usize,
+    ) -> Self {
+        Self {
+            connection_id,
+            addr,
+            student_id: hello.student_id.clone(),
+            student_name: hello.student_name.clone(),
+            client_version: hello.client_version.clone(),
+            protocol_version: hello.protocol_version,
+            capabilities: hello.capabilities.clone(),
+            audio_codec: AudioCodec::negotiate(&hello.capabilities.audio_codecs),
+            queue: SendQueue::new(queue_capacity),
+            connected_at: Instant::now(),
+            last_seen: Mutex::new(Instant::now()),
+            audio_state: Mutex::new(None),
+            window_focused: Mutex::new(None),
+            zoom_request: Mutex::new(None),
+            hand_raised: AtomicBool::new(false),
+            recent_errors: Mutex::new(VecDeque::with_capacity(RECENT_ERROR_LIMIT)),
+            settings_acked: AtomicU64::new(0),
+            disconnect: Notify::new(),
+            counters: StudentCounters::new(),
+            unfinished_files: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Track file transfers as the writer puts their offer and completion on the wire.
+    fn note_written(&self, message: &TeacherToStudent) {
+        match message {
+            TeacherToStudent::FileOffer(offer) => {
+                self.unfinished_files.lock().insert(offer.transfer_id);
+            }
+            TeacherToStudent::FileComplete(done) => {
+                self.unfinished_files.lock().remove(&done.transfer_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn disconnect(&self) {
+        self.disconnect.notify_one();
+    }
+
+    async fn disconnected(&self) {
+        self.disconnect.notified().await;
+    }
+
+    /// Queue `message` for the writer; a full queue sheds the oldest video/audio instead.
+    fn send(&self, message: impl Into<Arc<TeacherToStudent>>) {
+        match self.queue.push(message.into()) {
+            Ok(false) => {}
+            Ok(true) => self.counters.record_dropped(),
+            Err(err) => {
+                warn!(student = %self.student_id, ?err, "发送消息失败");
+                self.record_error("发送消息失败".to_string());
+            }
+        }
+    }
+
+    /// Older students would fail to parse the request, so they are skipped.
+    fn request_thumbnails(&self, interval_secs: u64) {
+        if self.protocol_version >= THUMBNAIL_PROTOCOL_VERSION {
+            self.send(TeacherToStudent::Broadcast(
+                BroadcastCommand::RequestThumbnail { interval_secs },
+            ));
+        }
+    }
+
+    fn metrics(&self) -> StudentMetrics {
+        self.counters
+            .snapshot(&self.student_id, &self.student_name, self.queue.depth())
+    }
+
+    fn record_error(&self, message: String) {
+        let mut errors = self.recent_errors.lock();
+        if errors.len() == RECENT_ERROR_LIMIT {
+            errors.pop_front();
+        }
+        errors.push_back(message);
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock() = Instant::now();
+    }
+
+    fn set_audio_state(&self, state: AudioState) {
+        *self.audio_state.lock() = Some(state);
+    }
+
+    fn audio_state(&self) -> Option<AudioState> {
+        *self.audio_state.lock()
+    }
+
+    fn set_window_focused(&self, focused: bool) {
+        *self.window_focused.lock() = Some(focused);
+    }
+
+    fn set_zoom_request(&self, region: Option<Region>) {
+        *self.zoom_request.lock() = Some(region);
+    }
+
+    fn take_zoom_request(&self) -> Option<Option<Region>> {
+        self.zoom_request.lock().take()
+    }
+
+    fn acknowledge_settings(&self, revision: u64) {
+        self.settings_acked.fetch_max(revision, Ordering::SeqCst);
+    }
+
+    fn summary(&self, identity_suspect: bool) -> StudentSummary {
+        StudentSummary {
+            student_id: self.student_id.clone(),
+            display_name: self.student_name.clone(),
+            addr: self.addr,
+            connected_at: self.connected_at,
+            receives_video: self.capabilities.receive_video,
+            receives_audio: self.capabilities.receive_audio,
+            lagging: self.queue.depth() >= LAGGING_QUEUE_DEPTH,
+            audio_state: self.audio_state(),
+            window_focused: *self.window_focused.lock(),
+            zoom_requested: self.zoom_request.lock().is_some(),
+            hand_raised: self.hand_raised.load(Ordering::SeqCst),
+            identity_suspect,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StudentSummary {
+    pub student_id: String,
+    pub display_name: String,
+    pub addr: SocketAddr,
+    /// When the teacher accepted this connection; the status page gets the seconds since.
+    #[serde(rename = "connected_secs", serialize_with = "serialize_elapsed_secs")]
+    pub connected_at: Instant,
+    /// Capabilities from the student's `Hello`.
+    pub receives_video: bool,
+    pub receives_audio: bool,
+    /// Whether the send queue is backing up; see [`StudentMetrics::is_lagging`].
+    pub lagging: bool,
+    /// Last playback state reported by the student; `None` until the first report.
+    pub audio_state: Option<AudioState>,
+    /// Focus of the student's broadcast window; only reported when the student enables it.
+    pub window_focused: Option<bool>,
+    /// Whether the student has a zoom request waiting for the teacher.
+    pub zoom_requested: bool,
+    /// Whether the student has raised their hand.
+    pub hand_raised: bool,
+    /// Whether the identity checks found anything; see [`StudentDetail::identity_issues`].
+    pub identity_suspect: bool,
+}
+
+impl StudentSummary {
+    /// Health prefix for plain-text lists, e.g. `[!] [A-] `: lagging, no audio, no video.
+    pub fn health_prefix(&self) -> String {
+        let mut prefix = String::new();
+        if self.lagging {
+            prefix.push_str("[!] ");
+        }
+        if !self.receives_audio {
+            prefix.push_str("[A-] ");
+        }
+        if !self.receives_video {
+            prefix.push_str("[V-] ");
+        }
+        prefix
+    }
+
+    /// One roster line, the same in the console and the control panel.
+    pub fn roster_line(&self) -> String {
+        format!(
+            "{}{} ({}) @ {} 已连接 {}{}",
+            self.health_prefix(),
+            self.display_name,
+            self.student_id,
+            self.addr,
+            self.connected_for(),
+            self.markers()
+        )
+    }
+
+    /// Time since the student connected, e.g. `45s`, `12m` or `1h05m`.
+    pub fn connected_for(&self) -> String {
+        format_connected_for(self.connected_at.elapsed())
+    }
+
+    /// All roster markers of this student, e.g. ` [举手] [静音]`.
+    pub fn markers(&self) -> String {
+        let hand = if self.hand_raised { " [举手]" } else { "" };
+        let zoom = if self.zoom_requested {
+            " [请求放大]"
+        } else {
+            ""
+        };
+        let identity = if self.identity_suspect {
+            " [身份可疑]"
+        } else {
+            ""
+        };
+        format!(
+            "{}{}{}{}{}",
+            hand,
+            self.audio_label(),
+            self.focus_label(),
+            zoom,
+            identity
+        )
+    }
+
+    /// Short audio marker for roster displays, e.g. `[静音]`.
+    fn audio_label(&self) -> &'static str {
+        match self.audio_state {
+            Some(AudioState { muted: true, .. }) => " [静音]",
+            Some(AudioState { volume: 0, .. }) => " [音量 0]",
+            _ => "",
+        }
+    }
+
+    /// Marker shown while the student has switched away from the broadcast window.
+    fn focus_label(&self) -> &'static str {
+        match self.window_focused {
+            Some(false) => " [离开窗口]",
+            _ => "",
+        }
+    }
+}
+
+/// What a new connection does about another one with the same student ID.
+enum Duplicate {
+    /// Drop the other connection, telling it this reason.
+    Replace(String),
+    /// Refuse the new connection with this message.
+    Reject(String),
+}
+
+/// Why `student` is dropped, in the newest form its version understands. Students that know
+/// `Kicked` hold off reconnecting; older ones only see the reason as an error.
+fn kick_message(student: &StudentHandle, reason: &str) -> TeacherToStudent {
+    if student.protocol_version >= KICK_PROTOCOL_VERSION {
+        TeacherToStudent::Kicked {
+            reason: reason.to_string(),
+        }
+    } else {
+        TeacherToStudent::Error(reason.to_string())
+    }
+}
+
+/// One line of the chat log.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatEntry {
+    pub from: String,
+    /// Student a teacher line went to; `None` for lines to the class and lines from students.
+    pub to: Option<String>,
+    pub text: String,
+}
+
+/// Everything the teacher knows about a single connected student.
+#[derive(Debug, Clone)]
+pub struct StudentDetail {
+    pub summary: StudentSummary,
+    pub client_version: String,
+    pub protocol_version: ProtocolVersion,
+    pub capabilities: StudentCapabilities,
+    /// Matching entry of `expected_students`, if any.
+    pub registration: Option<StudentRegistration>,
+    /// Mismatches against the roster and other connections, e.g. a duplicate student ID.
+    pub identity_issues: Vec<String>,
+    /// Seconds since the last heartbeat.
+    pub last_seen_secs: u64,
+    /// Bytes written to this student since it connected.
+    pub bytes_sent: u64,
+    /// Whether the student has raised their hand.
+    pub hand_raised: bool,
+    /// Zoom request waiting for the teacher; the inner `None` asks for the full screen again.
+    pub pending_zoom: Option<Option<Region>>,
+    /// Whether this student's screen is currently being broadcast.
+    pub spotlighted: bool,
+    /// Whether the student acknowledged the latest broadcast settings.
+    pub settings_current: bool,
+    /// Most recent errors reported by or about this student, oldest first.
+    pub recent_errors: Vec<String>,
+    /// Latest monitoring thumbnail, once `thumbs` is on and one arrived.
+    pub thumbnail: Option<Thumbnail>,
+}
+
+/// `elapsed` rounded down for rosters, e.g. `45s`, `12m` or `1h05m`.
+fn format_connected_for(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn serialize_elapsed_secs<S: serde::Serializer>(
+    since: &Instant,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u64(since.elapsed().as_secs())
+}
+
+/// `time` as `YYYY-MM-DD_HHMMSS` in UTC, usable as a folder name.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's `civil_from_days`).
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}{:02}{:02}",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// `YYYY-MM-DD` of `time` in the local time zone, so uploads land in the folder of the
+/// day the class sees on its clocks.
+fn local_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// `notes.txt` -> `notes (2).txt` for `copy` 2.
+fn numbered_file_name(file_name: &str, copy: u32) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{stem} ({copy}).{extension}"),
+        _ => format!("{file_name} ({copy})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect_student(state: &TeacherState) -> Arc<StudentHandle> {
+        connect_as(state, "S01", "127.0.0.1:9000")
+    }
+
+    fn connect_as(state: &TeacherState, student_id: &str, addr: &str) -> Arc<StudentHandle> {
+        connect_speaking(state, student_id, addr, PROTOCOL_VERSION)
+    }
+
+    fn connect_speaking(
+        state: &TeacherState,
+        student_id: &str,
+        addr: &str,
+        protocol_version: ProtocolVersion,
+    ) -> Arc<StudentHandle> {
+        let hello = HelloMessage {
+            student_id: student_id.into(),
+            student_name: "测试学生".into(),
+            client_version: env!("CARGO_PKG_VERSION").into(),
+            protocol_version,
+            capabilities: StudentCapabilities::default(),
+            codecs: Vec::new(),
+            compression: false,
+            join_token: None,
+        };
+        let handle = Arc::new(StudentHandle::new(
+            Uuid::new_v4(),
+            addr.parse().unwrap(),
+            &hello,
+            TeacherConfig::default().send_queue_capacity,
+        ));
+        state.add_student(handle.clone()).unwrap();
+        handle
+    }
+
+    #[test]
+    fn settings_change_propagates_and_is_acked() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_student(&state);
+
+        let mut broadcast = state.broadcast_config();
+        broadcast.fps = 5;
+        broadcast.jpeg_quality = 40;
+        let revision = state.update_broadcast_config(broadcast);
+
+        assert_eq!(state.broadcast_config().fps, 5);
+        let update = match handle.queue.try_pop().as_deref() {
+            Some(TeacherToStudent::SettingsUpdate(update)) => update.clone(),
+            other => panic!("expected settings update, got {other:?}"),
+        };
+        assert_eq!(update.revision, revision);
+        assert_eq!(update.broadcast.fps, 5);
+        assert_eq!(update.broadcast.jpeg_quality, 40);
+        assert!(!state.student_detail("S01").unwrap().settings_current);
+
+        handle.acknowledge_settings(update.revision);
+        assert!(state.student_detail("S01").unwrap().settings_current);
+    }
+
+    #[test]
+    fn detail_reports_traffic_hand_and_pending_zoom() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_student(&state);
+        handle.counters.record_sent(4096, false);
+        handle.hand_raised.store(true, Ordering::SeqCst);
+        handle.set_zoom_request(None);
+
+        let detail = state.student_detail("S01").unwrap();
+        assert_eq!(detail.bytes_sent, 4096);
+        assert!(detail.hand_raised);
+        assert_eq!(detail.pending_zoom, Some(None));
+    }
+
+    #[test]
+    fn identity_mismatches_are_flagged() {
+        let config = TeacherConfig {
+            expected_students: vec![StudentRegistration {
+                student_id: "S01".into(),
+                student_name: Some("测试学生".into()),
+                ip: Some("10.0.0.1".parse().unwrap()),
+                ..StudentRegistration::default()
+            }],
+            ..TeacherConfig::default()
+        };
+        let state = TeacherState::new(Arc::new(config));
+
+        let _first = connect_as(&state, "S01", "10.0.0.1:9000");
+        assert!(state
+            .student_detail("S01")
+            .unwrap()
+            .identity_issues
+            .is_empty());
+
+        // A second ID from the same machine; the same ID twice can no longer both register.
+        let _second = connect_as(&state, "S02", "10.0.0.1:9001");
+        let _unknown = connect_as(&state, "S99", "10.0.0.3:9000");
+        let suspects: Vec<String> = state
+            .list_students()
+            .into_iter()
+            .filter(|summary| summary.identity_suspect)
+            .map(|summary| summary.addr.to_string())
+            .collect();
+        assert_eq!(suspects.len(), 3, "flagged: {suspects:?}");
+
+        let issues = state.student_detail("S99").unwrap().identity_issues;
+        assert_eq!(issues, vec!["学号不在名单中".to_string()]);
+    }
+
+    #[test]
+    fn silent_students_are_reported_idle() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_student(&state);
+        let timeout = Duration::from_secs(30);
+        assert!(state.idle_students(timeout).is_empty());
+
+        *handle.last_seen.lock() = Instant::now() - Duration::from_secs(31);
+        let idle = state.idle_students(timeout);
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].connection_id, handle.connection_id);
+
+        handle.touch();
+        assert!(state.idle_students(timeout).is_empty());
+    }
+
+    #[test]
+    fn interrupted_file_is_reoffered_once_on_reconnect() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let first = connect_student(&state);
+        let offer = FileOffer {
+            transfer_id: Uuid::new_v4(),
+            file_name: "notes.txt".into(),
+            total_size: 10,
+            auto_open: false,
+            resume: false,
+        };
+        state.remember_sent_file(Path::new("notes.txt"), &offer);
+
+        // Written through to the end: nothing to resume.
+        first.note_written(&TeacherToStudent::FileOffer(offer.clone()));
+        first.note_written(&TeacherToStudent::FileComplete(FileTransferComplete {
+            transfer_id: offer.transfer_id,
+            success: true,
+            message: None,
+        }));
+        state.record_interrupted_files(&first);
+        assert!(state
+            .take_interrupted_file(offer.transfer_id, "S01")
+            .is_none());
+
+        // The connection dropped before the end was written.
+        first.note_written(&TeacherToStudent::FileOffer(offer.clone()));
+        state.remove_student(first.connection_id);
+        state.record_interrupted_files(&first);
+        let second = connect_student(&state);
+        state.offer_interrupted_files(&second);
+        match second.queue.try_pop().as_deref() {
+            Some(TeacherToStudent::FileOffer(reoffer)) => {
+                assert!(reoffer.resume);
+                assert_eq!(reoffer.transfer_id, offer.transfer_id);
+            }
+            other => panic!("expected resumed offer, got {other:?}"),
+        }
+
+        assert!(state
+            .take_interrupted_file(offer.transfer_id, "S01")
+            .is_some());
+        assert!(state
+            .take_interrupted_file(offer.transfer_id, "S01")
+            .is_none());
+    }
+
+    #[test]
+    fn interrupted_files_expire() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let student = connect_student(&state);
+        let offer = FileOffer {
+            transfer_id: Uuid::new_v4(),
+            file_name: "notes.txt".into(),
+            total_size: 10,
+            auto_open: false,
+            resume: false,
+        };
+        state.remember_sent_file(Path::new("notes.txt"), &offer);
+        student.note_written(&TeacherToStudent::FileOffer(offer.clone()));
+        state.remove_student(student.connection_id);
+        state.record_interrupted_files(&student);
+
+        state.expire_file_records(Instant::now() + INTERRUPTED_FILE_TTL);
+        assert!(state.sent_files.lock().is_empty());
+        assert!(state
+            .take_interrupted_file(offer.transfer_id, "S01")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn uploads_cannot_leave_the_upload_dir() {
+        let dir = std::env::temp_dir().join(format!("teacher-uploads-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TeacherConfig {
+            save_upload_dir: dir.clone(),
+            ..TeacherConfig::default()
+        };
+        let state = TeacherState::new(Arc::new(config));
+
+        for name in ["..\\..\\evil.exe", "../../evil.exe", "..", "/etc/passwd"] {
+            let (path, _) = state
+                .create_upload_file(&hello_from("S01"), name)
+                .await
+                .unwrap();
+            assert_eq!(path.parent(), Some(dir.join("S01").as_path()), "{name}");
+        }
+        let (path, _) = state
+            .create_upload_file(&hello_from(".."), "notes.txt")
+            .await
+            .unwrap();
+        assert!(path.starts_with(&dir));
+
+        // Flat uploads never overwrite an earlier file of the same name.
+        std::fs::create_dir_all(dir.join("S03")).unwrap();
+        std::fs::write(dir.join("S03").join("notes.txt"), b"x").unwrap();
+        std::fs::write(dir.join("S03").join("notes (1).txt"), b"x").unwrap();
+        let (path, _) = state
+            .create_upload_file(&hello_from("S03"), "notes.txt")
+            .await
+            .unwrap();
+        assert_eq!(path, dir.join("S03").join("notes (2).txt"));
+        // Nor does one that arrives at the same time.
+        let hello = hello_from("S04");
+        let (first, second) = tokio::join!(
+            state.create_upload_file(&hello, "notes.txt"),
+            state.create_upload_file(&hello, "notes.txt")
+        );
+        assert_ne!(first.unwrap().0, second.unwrap().0);
+
+        #[cfg(unix)]
+        {
+            let outside = std::env::temp_dir();
+            std::os::unix::fs::symlink(&outside, dir.join("S02")).unwrap();
+            assert!(state
+                .create_upload_file(&hello_from("S02"), "notes.txt")
+                .await
+                .is_err());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn uploads_can_be_grouped_by_session() {
+        let dir = std::env::temp_dir().join(format!("teacher-uploads-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TeacherConfig {
+            save_upload_dir: dir.clone(),
+            upload_organize: UploadOrganize::PerSession,
+            ..TeacherConfig::default()
+        };
+        let mut state = TeacherState::new(Arc::new(config));
+        state.session_started = UNIX_EPOCH + Duration::from_secs(1_709_164_800 + 3_723);
+
+        let (path, _) = state
+            .create_upload_file(&hello_from("S01"), "notes.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            path,
+            dir.join("2024-02-29_010203").join("S01").join("notes.txt")
+        );
+
+        // Uploads here overwrite, so a dangling symlink must not be followed either.
+        #[cfg(unix)]
+        {
+            let outside = std::env::temp_dir().join(format!("outside-{}", Uuid::new_v4()));
+            std::os::unix::fs::symlink(&outside, path.with_file_name("report.txt")).unwrap();
+            assert!(state
+                .create_upload_file(&hello_from("S01"), "report.txt")
+                .await
+                .is_err());
+            assert!(!outside.exists());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn canceled_send_purges_queued_chunks_and_tells_students_to_discard() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let student = connect_student(&state);
+        let path = std::env::temp_dir().join(format!("teacher-send-{}", Uuid::new_v4()));
+        std::fs::write(&path, vec![7u8; 200 * 1024]).unwrap();
+        let offer = FileOffer {
+            transfer_id: Uuid::new_v4(),
+            file_name: "big.bin".into(),
+            total_size: 200 * 1024,
+            auto_open: false,
+            resume: false,
+        };
+        let cancel = AtomicBool::new(false);
+        // The writer already took the offer off the queue.
+        student.send(TeacherToStudent::FileOffer(offer.clone()));
+        student.queue.try_pop();
+
+        let room = || student.queue.file_room();
+        let finished = stream_file(&path, &offer, 0, &cancel, room, |message| {
+            if matches!(message, TeacherToStudent::FileChunk(_)) {
+                cancel.store(true, Ordering::SeqCst);
+            }
+            student.send(message);
+        })
+        .await
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(!finished);
+        assert_eq!(
+            student.queue.depth(),
+            1,
+            "one chunk before the cancellation"
+        );
+
+        state.call_off_file(&offer, None);
+        let left: Vec<_> = std::iter::from_fn(|| student.queue.try_pop()).collect();
+        assert_eq!(left.len(), 1);
+        assert!(matches!(
+            &*left[0],
+            TeacherToStudent::FileComplete(done) if !done.success
+        ));
+    }
+
+    #[tokio::test]
+    async fn targeted_send_reaches_only_that_student() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let first = connect_as(&state, "S01", "127.0.0.1:9000");
+        let second = connect_as(&state, "S02", "127.0.0.1:9001");
+        let path = std::env::temp_dir().join(format!("teacher-sendto-{}", Uuid::new_v4()));
+        std::fs::write(&path, b"worksheet").unwrap();
+
+        let missing = state.send_file_to_student("S03", path.clone(), false).await;
+        state
+            .send_file_to_student("S02", path.clone(), false)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(missing.is_err());
+        let received: Vec<Arc<TeacherToStudent>> =
+            std::iter::from_fn(|| second.queue.try_pop()).collect();
+        assert!(received.iter().any(
+            |message| matches!(&**message, TeacherToStudent::FileOffer(offer) if offer.total_size == 9)
+        ));
+        assert!(received.iter().any(
+            |message| matches!(&**message, TeacherToStudent::FileComplete(done) if done.success)
+        ));
+        assert!(std::iter::from_fn(|| first.queue.try_pop())
+            .all(|message| !matches!(*message, TeacherToStudent::FileOffer(_))));
+    }
+
+    #[test]
+    fn broadcast_frames_are_shared_not_copied_per_student() {
+        const FRAME_BYTES: usize = 2 * 1024 * 1024;
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let class: Vec<_> = (0..40)
+            .map(|n| {
+                connect_as(
+                    &state,
+                    &format!("S{n:02}"),
+                    &format!("127.0.0.1:{}", 4000 + n),
+                )
+            })
+            .collect();
+        state.broadcast_video(VideoFrame {
+            frame_id: 1,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 4,
+            height: 3,
+            fullscreen: false,
+            thumbnail: false,
+            data: vec![0; FRAME_BYTES].into(),
+            still: false,
+        });
+
+        let queued: Vec<_> = class
+            .iter()
+            .map(|handle| {
+                std::iter::from_fn(|| handle.queue.try_pop())
+                    .find(|message| matches!(**message, TeacherToStudent::Video(_)))
+                    .unwrap()
+            })
+            .collect();
+        assert!(queued.iter().all(|frame| Arc::ptr_eq(frame, &queued[0])));
+        // Add up the frame buffers actually allocated, counting each distinct one once.
+        let buffers: HashSet<_> = queued
+            .iter()
+            .filter_map(|message| match &**message {
+                TeacherToStudent::Video(frame) => Some((frame.data.as_ptr(), frame.data.len())),
+                _ => None,
+            })
+            .chain(
+                state
+                    .latest_frame
+                    .lock()
+                    .as_ref()
+                    .map(|frame| (frame.data.as_ptr(), frame.data.len())),
+            )
+            .collect();
+        let held: usize = buffers.iter().map(|&(_, len)| len).sum();
+        assert_eq!(
+            held,
+            FRAME_BYTES,
+            "40 queued copies would hold {} bytes",
+            40 * FRAME_BYTES
+        );
+    }
+
+    #[test]
+    fn thumbnails_are_requested_and_kept_per_student() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_student(&state);
+
+        state.set_thumbnail_interval(15);
+        assert_eq!(state.thumbnail_interval_secs(), 15);
+        assert!(
+            std::iter::from_fn(|| handle.queue.try_pop()).any(|message| matches!(
+                *message,
+                TeacherToStudent::Broadcast(BroadcastCommand::RequestThumbnail {
+                    interval_secs: 15
+                })
+            ))
+        );
+
+        let frame = VideoFrame {
+            frame_id: 1,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 320,
+            height: 180,
+            fullscreen: false,
+            thumbnail: true,
+            data: vec![0xff, 0xd8].into(),
+            still: false,
+        };
+        state.store_thumbnail("S01", frame);
+        let thumbnail = state.student_detail("S01").unwrap().thumbnail.unwrap();
+        assert_eq!(thumbnail.frame.data, vec![0xff, 0xd8]);
+
+        state.remove_student(handle.connection_id);
+        assert!(state.thumbnails.lock().is_empty());
     }
-}
 
-struct StudentHandle {
-    connection_id: Uuid,
-    addr: SocketAddr,
-    student_id: String,
-    student_name: String,
-    #[allow(dead_code)]
-    capabilities: StudentCapabilities,
-    sender: mpsc::UnboundedSender<TeacherToStudent>,
-    last_seen: Mutex<Instant>,
-}
+    #[test]
+    fn blackout_is_broadcast_and_remembered() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_student(&state);
 
-impl StudentHandle {
-    fn new(
-        connection_id: Uuid,
-        addr: SocketAddr,
-        student_id: String,
-        student_name: String,
-        capabilities: StudentCapabilities,
-        sender: mpsc::UnboundedSender<TeacherToStudent>,
-    ) -> Self {
-        Self {
-            connection_id,
-            addr,
-            student_id,
-            student_name,
-            capabilities,
-            sender,
-            last_seen: Mutex::new(Instant::now()),
+        state.set_blackout(true);
+        assert!(state.is_blackout());
+        assert!(
+            std::iter::from_fn(|| handle.queue.try_pop()).any(|message| matches!(
+                *message,
+                TeacherToStudent::Broadcast(BroadcastCommand::Blackout { enabled: true })
+            ))
+        );
+
+        state.set_blackout(false);
+        assert!(!state.is_blackout());
+    }
+
+    #[test]
+    fn registration_gates_joining_and_uploads() {
+        let roster = vec![
+            StudentRegistration {
+                student_id: "S01".into(),
+                ..StudentRegistration::default()
+            },
+            StudentRegistration {
+                student_id: "S02".into(),
+                allow_uploads: false,
+                ..StudentRegistration::default()
+            },
+        ];
+        let open = TeacherState::new(Arc::new(TeacherConfig {
+            expected_students: roster.clone(),
+            ..TeacherConfig::default()
+        }));
+        let enforced = TeacherState::new(Arc::new(TeacherConfig {
+            expected_students: roster,
+            enforce_registration: true,
+            ..TeacherConfig::default()
+        }));
+
+        assert!(open.registration_allows("S99"));
+        assert!(enforced.registration_allows("S01"));
+        assert!(!enforced.registration_allows("S99"));
+        assert!(enforced.uploads_allowed("S01"));
+        assert!(!enforced.uploads_allowed("S02"));
+        assert!(open.uploads_allowed("S99"));
+    }
+
+    /// Run `hello` against a fresh connection handler; returns the teacher's first reply.
+    async fn first_reply(
+        state: &Arc<TeacherState>,
+        hello: HelloMessage,
+    ) -> (TeacherToStudent, tokio::task::JoinHandle<Result<()>>) {
+        let screen = ScreenBroadcaster::new(state.clone());
+        let (student, teacher) = tokio::io::duplex(4096);
+        let connection = tokio::spawn(handle_student_connection(
+            state.clone(),
+            screen,
+            Box::new(teacher),
+            "127.0.0.1:9000".parse().unwrap(),
+        ));
+
+        let (mut reader, mut writer) = tokio::io::split(student);
+        write_message(&mut writer, Framing::Plain, &StudentToTeacher::Hello(hello))
+            .await
+            .unwrap();
+        (
+            read_message(&mut reader, Framing::Plain).await.unwrap(),
+            connection,
+        )
+    }
+
+    fn hello_from(student_id: &str) -> HelloMessage {
+        HelloMessage {
+            student_id: student_id.into(),
+            student_name: "测试学生".into(),
+            client_version: env!("CARGO_PKG_VERSION").into(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: StudentCapabilities::default(),
+            codecs: Vec::new(),
+            compression: false,
+            join_token: None,
         }
     }
 
-    fn send(&self, message: TeacherToStudent) {
-        if let Err(err) = self.sender.send(message) {
-            warn!(student = %self.student_id, ?err, "发送消息失败");
+    #[tokio::test]
+    async fn wrong_join_token_is_rejected_before_registration() {
+        let config = TeacherConfig {
+            join_token: Some("secret".into()),
+            ..TeacherConfig::default()
+        };
+        let state = Arc::new(TeacherState::new(Arc::new(config)));
+        let hello = HelloMessage {
+            join_token: Some("guess".into()),
+            ..hello_from("S01")
+        };
+
+        let (reply, connection) = first_reply(&state, hello).await;
+        assert!(matches!(reply, TeacherToStudent::Error(_)), "got {reply:?}");
+        connection.await.unwrap().unwrap();
+        assert!(state.list_students().is_empty());
+    }
+
+    #[tokio::test]
+    async fn welcome_carries_the_session_id() {
+        let state = Arc::new(TeacherState::new(Arc::new(TeacherConfig::default())));
+        let (reply, _connection) = first_reply(&state, hello_from("S01")).await;
+        match reply {
+            TeacherToStudent::Welcome(ack) => assert_eq!(ack.session_id, Some(state.session_id)),
+            other => panic!("got {other:?}"),
         }
     }
 
-    fn touch(&self) {
-        *self.last_seen.lock() = Instant::now();
+    #[tokio::test]
+    async fn duplicate_student_id_is_rejected_or_replaces_the_old_session() {
+        let state = Arc::new(TeacherState::new(Arc::new(TeacherConfig::default())));
+        let other_machine = connect_as(&state, "S01", "10.0.0.5:9000");
+        let (reply, connection) = first_reply(&state, hello_from("S01")).await;
+        assert!(matches!(reply, TeacherToStudent::Error(_)), "got {reply:?}");
+        connection.await.unwrap().unwrap();
+        assert_eq!(state.find_student("S01").unwrap().addr, other_machine.addr);
+        assert_eq!(state.recent_duplicate_ids(), ["S01"]);
+
+        // A reconnect from the same address takes over whatever the policy.
+        let stale = connect_as(&state, "S02", "127.0.0.1:8000");
+        let (reply, _connection) = first_reply(&state, hello_from("S02")).await;
+        assert!(
+            matches!(reply, TeacherToStudent::Welcome(_)),
+            "got {reply:?}"
+        );
+        assert!(stale
+            .queue
+            .push(Arc::new(TeacherToStudent::Error("late".into())))
+            .is_err());
+
+        let config = TeacherConfig {
+            duplicate_student_policy: DuplicateStudentPolicy::ReplaceOld,
+            ..TeacherConfig::default()
+        };
+        let state = Arc::new(TeacherState::new(Arc::new(config)));
+        let replaced = connect_as(&state, "S01", "10.0.0.5:9000");
+        let (reply, _connection) = first_reply(&state, hello_from("S01")).await;
+        assert!(
+            matches!(reply, TeacherToStudent::Welcome(_)),
+            "got {reply:?}"
+        );
+        let last = std::iter::from_fn(|| replaced.queue.try_pop()).last();
+        assert!(matches!(
+            last.as_deref(),
+            Some(TeacherToStudent::Replaced { .. })
+        ));
+        assert_eq!(state.recent_duplicate_ids(), ["S01"]);
+
+        // A student too old for `Replaced` is kicked instead.
+        let outdated = connect_speaking(&state, "S02", "10.0.0.6:9000", KICK_PROTOCOL_VERSION);
+        let (_reply, _connection) = first_reply(&state, hello_from("S02")).await;
+        let last = std::iter::from_fn(|| outdated.queue.try_pop()).last();
+        assert!(matches!(
+            last.as_deref(),
+            Some(TeacherToStudent::Kicked { .. })
+        ));
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct StudentSummary {
-    pub student_id: String,
-    pub display_name: String,
-    pub addr: SocketAddr,
+    #[test]
+    fn a_duplicate_registering_during_the_handshake_is_settled_by_the_policy() {
+        // Both connections passed the Hello check before either registered.
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let first = connect_as(&state, "S01", "10.0.0.5:9000");
+        let late = Arc::new(StudentHandle::new(
+            Uuid::new_v4(),
+            "10.0.0.6:9000".parse().unwrap(),
+            &hello_from("S01"),
+            TeacherConfig::default().send_queue_capacity,
+        ));
+        assert!(state.add_student(late.clone()).is_err());
+        assert_eq!(state.list_students().len(), 1);
+        assert_eq!(state.find_student("S01").unwrap().addr, first.addr);
+
+        let config = TeacherConfig {
+            duplicate_student_policy: DuplicateStudentPolicy::ReplaceOld,
+            ..TeacherConfig::default()
+        };
+        let state = TeacherState::new(Arc::new(config));
+        let first = connect_as(&state, "S01", "10.0.0.5:9000");
+        state.add_student(late.clone()).unwrap();
+        assert_eq!(state.list_students().len(), 1);
+        assert_eq!(state.find_student("S01").unwrap().addr, late.addr);
+        let last = std::iter::from_fn(|| first.queue.try_pop()).last();
+        assert!(matches!(
+            last.as_deref(),
+            Some(TeacherToStudent::Replaced { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn protocol_major_mismatch_is_rejected_but_minor_skew_is_not() {
+        let state = Arc::new(TeacherState::new(Arc::new(TeacherConfig::default())));
+
+        let skewed = HelloMessage {
+            protocol_version: ProtocolVersion {
+                minor: PROTOCOL_VERSION.minor + 1,
+                ..PROTOCOL_VERSION
+            },
+            ..hello_from("S01")
+        };
+        let (reply, _connection) = first_reply(&state, skewed).await;
+        assert!(
+            matches!(reply, TeacherToStudent::Welcome(ref ack) if ack.protocol_version == PROTOCOL_VERSION),
+            "got {reply:?}"
+        );
+
+        let incompatible = HelloMessage {
+            protocol_version: ProtocolVersion {
+                major: PROTOCOL_VERSION.major + 1,
+                minor: 0,
+            },
+            ..hello_from("S02")
+        };
+        let (reply, connection) = first_reply(&state, incompatible).await;
+        assert!(
+            matches!(reply, TeacherToStudent::Error(ref message) if message.contains("协议版本")),
+            "got {reply:?}"
+        );
+        connection.await.unwrap().unwrap();
+        assert!(state.student_detail("S02").is_none());
+
+        // A first-release student announces no version at all. Nothing the teacher does may
+        // send it a message that release cannot parse.
+        state.set_blackout(true);
+        let (student, teacher) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_student_connection(
+            state.clone(),
+            ScreenBroadcaster::new(state.clone()),
+            Box::new(teacher),
+            "127.0.0.1:9001".parse().unwrap(),
+        ));
+        let (mut reader, mut writer) = tokio::io::split(student);
+        let first_release = HelloMessage {
+            protocol_version: ProtocolVersion::default(),
+            ..hello_from("S03")
+        };
+        write_message(
+            &mut writer,
+            Framing::Plain,
+            &StudentToTeacher::Hello(first_release),
+        )
+        .await
+        .unwrap();
+        let reply: TeacherToStudent = read_message(&mut reader, Framing::Plain).await.unwrap();
+        assert!(
+            matches!(reply, TeacherToStudent::Welcome(_)),
+            "got {reply:?}"
+        );
+        let joined = loop {
+            match state.find_student("S03") {
+                Some(student) => break student,
+                None => time::sleep(Duration::from_millis(5)).await,
+            }
+        };
+
+        state.update_broadcast_config(BroadcastConfig::default());
+        state.broadcast_notice(Notice {
+            text: "下课".into(),
+            duration_ms: 1000,
+            severity: NoticeSeverity::Info,
+        });
+        state.broadcast_command(BroadcastCommand::Start {
+            source: BroadcastSource::Teacher,
+            mode: BroadcastMode::Fullscreen,
+            lock_input: true,
+        });
+        state.set_thumbnail_interval(5);
+        let _ = state.send_chat(None, "你好");
+        state.set_blackout(false);
+        state.replace_student(&joined, "新连接");
+
+        let mut received = Vec::new();
+        while let Ok(Ok(message)) = time::timeout(
+            Duration::from_millis(200),
+            read_message::<_, TeacherToStudent>(&mut reader, Framing::Plain),
+        )
+        .await
+        {
+            received.push(message);
+        }
+        assert!(
+            received.iter().any(|message| matches!(
+                message,
+                TeacherToStudent::Broadcast(BroadcastCommand::Start { .. })
+            )),
+            "got {received:?}"
+        );
+        for message in &received {
+            assert!(known_to_first_release(message), "got {message:?}");
+        }
+    }
+
+    /// Whether a student from the first release, before versions were exchanged, can parse
+    /// `message`. Lists every variant so a new one has to be placed here.
+    fn known_to_first_release(message: &TeacherToStudent) -> bool {
+        match message {
+            TeacherToStudent::Broadcast(command) => matches!(
+                command,
+                BroadcastCommand::Start {
+                    lock_input: false,
+                    ..
+                } | BroadcastCommand::Stop
+                    | BroadcastCommand::RequestStudentShare { .. }
+            ),
+            TeacherToStudent::Welcome(_)
+            | TeacherToStudent::Video(_)
+            | TeacherToStudent::Audio(_)
+            | TeacherToStudent::FileOffer(_)
+            | TeacherToStudent::FileChunk(_)
+            | TeacherToStudent::FileComplete(_)
+            | TeacherToStudent::Heartbeat(_)
+            | TeacherToStudent::Error(_) => true,
+            TeacherToStudent::SettingsUpdate(_)
+            | TeacherToStudent::Notice(_)
+            | TeacherToStudent::Chat { .. }
+            | TeacherToStudent::Pacing { .. }
+            | TeacherToStudent::Kicked { .. }
+            | TeacherToStudent::Replaced { .. } => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn late_joiner_gets_the_latest_frame_and_can_ask_again() {
+        let state = Arc::new(TeacherState::new(Arc::new(TeacherConfig::default())));
+        state.set_broadcast_source(Some(BroadcastSource::Teacher), BroadcastMode::Window);
+        state.broadcast_video(VideoFrame {
+            frame_id: 7,
+            timestamp_ms: 0,
+            capture_monotonic_ms: 0,
+            source: BroadcastSource::Teacher,
+            codec: VideoCodec::Jpeg,
+            width: 4,
+            height: 3,
+            fullscreen: false,
+            thumbnail: false,
+            data: vec![0xff, 0xd8].into(),
+            still: false,
+        });
+
+        let screen = ScreenBroadcaster::new(state.clone());
+        let (student, teacher) = tokio::io::duplex(4096);
+        tokio::spawn(handle_student_connection(
+            state.clone(),
+            screen,
+            Box::new(teacher),
+            "127.0.0.1:9000".parse().unwrap(),
+        ));
+        let (mut reader, mut writer) = tokio::io::split(student);
+        write_message(
+            &mut writer,
+            Framing::Plain,
+            &StudentToTeacher::Hello(hello_from("S01")),
+        )
+        .await
+        .unwrap();
+
+        let mut next_video = async || loop {
+            if let TeacherToStudent::Video(frame) =
+                read_message(&mut reader, Framing::Plain).await.unwrap()
+            {
+                break frame;
+            }
+        };
+        assert_eq!(next_video().await.frame_id, 7);
+        write_message(
+            &mut writer,
+            Framing::Plain,
+            &StudentToTeacher::RequestKeyframe,
+        )
+        .await
+        .unwrap();
+        assert_eq!(next_video().await.frame_id, 7);
+
+        state.set_broadcast_source(None, BroadcastMode::Window);
+        assert!(state.keyframe_for("S01").is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_uploads_are_refused_and_removed() {
+        let dir = std::env::temp_dir().join(format!("teacher-uploads-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TeacherConfig {
+            save_upload_dir: dir.clone(),
+            max_upload_bytes: 100,
+            ..TeacherConfig::default()
+        };
+        let state = Arc::new(TeacherState::new(Arc::new(config)));
+        let screen = ScreenBroadcaster::new(state.clone());
+        let (student, teacher) = tokio::io::duplex(4096);
+        tokio::spawn(handle_student_connection(
+            state.clone(),
+            screen,
+            Box::new(teacher),
+            "127.0.0.1:9000".parse().unwrap(),
+        ));
+        let (mut reader, mut writer) = tokio::io::split(student);
+        let offer = |file_name: &str, total_size| {
+            StudentToTeacher::FileOffer(FileOffer {
+                transfer_id: Uuid::new_v4(),
+                file_name: file_name.into(),
+                total_size,
+                auto_open: false,
+                resume: false,
+            })
+        };
+        let big = offer("big.bin", 500);
+        // Claims to fit, then sends more than the limit.
+        let sneaky = offer("sneaky.bin", 50);
+        let StudentToTeacher::FileOffer(FileOffer { transfer_id, .. }) = sneaky else {
+            unreachable!()
+        };
+        let mut messages = vec![StudentToTeacher::Hello(hello_from("S01")), big, sneaky];
+        for offset in [0, 64] {
+            messages.push(StudentToTeacher::FileChunk(FileChunk::new(
+                transfer_id,
+                offset,
+                vec![1; 64],
+                false,
+            )));
+        }
+        for message in &messages {
+            write_message(&mut writer, Framing::Plain, message)
+                .await
+                .unwrap();
+        }
+
+        let TeacherToStudent::Welcome(ack) =
+            read_message(&mut reader, Framing::Plain).await.unwrap()
+        else {
+            panic!("expected Welcome");
+        };
+        assert_eq!(ack.max_upload_bytes, 100);
+        let mut next_refusal = async || loop {
+            if let TeacherToStudent::FileComplete(done) =
+                read_message(&mut reader, Framing::Plain).await.unwrap()
+            {
+                assert!(!done.success);
+                break done.message.unwrap_or_default();
+            }
+        };
+        assert!(next_refusal().await.contains("big.bin"));
+        assert!(next_refusal().await.contains("sneaky.bin"));
+        assert!(!dir.join("S01").join("sneaky.bin").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn unsaveable_upload_is_refused_without_dropping_the_student() {
+        // A file where the upload folder should be, so nothing can be saved.
+        let blocked = std::env::temp_dir().join(format!("teacher-uploads-{}", Uuid::new_v4()));
+        std::fs::write(&blocked, b"").unwrap();
+        let config = TeacherConfig {
+            save_upload_dir: blocked.clone(),
+            ..TeacherConfig::default()
+        };
+        let state = Arc::new(TeacherState::new(Arc::new(config)));
+        let screen = ScreenBroadcaster::new(state.clone());
+        let (student, teacher) = tokio::io::duplex(4096);
+        tokio::spawn(handle_student_connection(
+            state.clone(),
+            screen,
+            Box::new(teacher),
+            "127.0.0.1:9000".parse().unwrap(),
+        ));
+        let (mut reader, mut writer) = tokio::io::split(student);
+        let transfer_id = Uuid::new_v4();
+        let messages = [
+            StudentToTeacher::Hello(hello_from("S01")),
+            StudentToTeacher::FileOffer(FileOffer {
+                transfer_id,
+                file_name: "notes.txt".into(),
+                total_size: 4,
+                auto_open: false,
+                resume: false,
+            }),
+        ];
+        for message in &messages {
+            write_message(&mut writer, Framing::Plain, message)
+                .await
+                .unwrap();
+        }
+
+        let done = loop {
+            if let TeacherToStudent::FileComplete(done) =
+                read_message(&mut reader, Framing::Plain).await.unwrap()
+            {
+                break done;
+            }
+        };
+        let _ = std::fs::remove_file(&blocked);
+        assert_eq!(done.transfer_id, transfer_id);
+        assert!(!done.success);
+        assert_eq!(state.list_students().len(), 1);
+    }
+
+    #[test]
+    fn embedders_hear_about_joins_broadcasts_and_departures() {
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        let mut state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        state.events = Some(event_tx);
+
+        let handle = connect_as(&state, "S01", "127.0.0.1:4001");
+        state.set_broadcast_source(Some(BroadcastSource::Teacher), BroadcastMode::Fullscreen);
+        state.set_broadcast_source(None, BroadcastMode::Window);
+        state.remove_student(handle.connection_id);
+        state.remove_student(handle.connection_id);
+
+        let events: Vec<ServerEvent> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(events.len(), 4, "{events:?}");
+        assert!(
+            matches!(&events[0], ServerEvent::StudentJoined { student_id, .. } if student_id == "S01")
+        );
+        assert!(matches!(
+            &events[1],
+            ServerEvent::BroadcastStarted {
+                source: BroadcastSource::Teacher,
+                mode: BroadcastMode::Fullscreen
+            }
+        ));
+        assert!(matches!(&events[2], ServerEvent::BroadcastStopped));
+        assert!(
+            matches!(&events[3], ServerEvent::StudentLeft { student_id } if student_id == "S01")
+        );
+    }
+
+    #[test]
+    fn raised_hand_shows_in_the_roster_until_lowered() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_as(&state, "S01", "127.0.0.1:4001");
+        handle.hand_raised.store(true, Ordering::SeqCst);
+        let summary = &state.list_students()[0];
+        assert!(summary.hand_raised);
+        assert!(summary.markers().contains("[举手]"));
+
+        handle.hand_raised.store(false, Ordering::SeqCst);
+        assert!(!state.list_students()[0].markers().contains("[举手]"));
+    }
+
+    #[test]
+    fn roster_is_sorted_and_shows_time_connected() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        for (id, port) in [("S03", 4003), ("S01", 4001), ("S02", 4002)] {
+            connect_as(&state, id, &format!("127.0.0.1:{port}"));
+        }
+        let roster = state.list_students();
+        let ids: Vec<&str> = roster.iter().map(|s| s.student_id.as_str()).collect();
+        assert_eq!(ids, ["S01", "S02", "S03"]);
+
+        assert!(roster[0].connected_for().ends_with('s'));
+        assert!(roster[0].roster_line().contains("已连接 0s"));
+        assert_eq!(
+            format_connected_for(Duration::from_secs(12 * 60 + 5)),
+            "12m"
+        );
+        assert_eq!(format_connected_for(Duration::from_secs(65 * 60)), "1h05m");
+    }
+
+    #[test]
+    fn roster_prefix_flags_missing_capabilities_and_lag() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        connect_as(&state, "S01", "127.0.0.1:4001");
+        let mut summary = state.list_students().remove(0);
+        // The test student announces no capabilities at all.
+        assert_eq!(summary.health_prefix(), "[A-] [V-] ");
+
+        summary.receives_audio = true;
+        summary.receives_video = true;
+        assert_eq!(summary.health_prefix(), "");
+        summary.lagging = true;
+        assert_eq!(summary.health_prefix(), "[!] ");
+    }
+
+    #[test]
+    fn chat_reaches_the_class_or_one_student_and_is_logged() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let s01 = connect_as(&state, "S01", "127.0.0.1:4001");
+        let s02 = connect_as(&state, "S02", "127.0.0.1:4002");
+
+        state.send_chat(None, "  hello\nclass ").unwrap();
+        state.send_chat(Some("S02"), "just you").unwrap();
+        assert!(state.send_chat(Some("S99"), "anyone?").is_err());
+        assert!(state.send_chat(None, " \n ").is_err());
+
+        let chats = |handle: &StudentHandle| -> Vec<String> {
+            std::iter::from_fn(|| handle.queue.try_pop())
+                .filter_map(|message| match &*message {
+                    TeacherToStudent::Chat { text, .. } => Some(text.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(chats(&s01), vec!["hello class"]);
+        assert_eq!(chats(&s02), vec!["hello class", "just you"]);
+        let log = state.chat_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[1].to.as_deref(), Some("S02"));
+    }
+
+    #[test]
+    fn kick_sends_the_reason_last_and_drops_the_student() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let handle = connect_as(&state, "S01", "127.0.0.1:4001");
+        connect_as(&state, "S02", "127.0.0.1:4002");
+        handle.send(TeacherToStudent::Notice(Notice {
+            text: "hi".into(),
+            duration_ms: 1,
+            severity: NoticeSeverity::Info,
+        }));
+
+        assert!(state.kick_student("S03", "离开").is_err());
+        state.kick_student("S01", "请专心听课").unwrap();
+
+        assert!(matches!(
+            handle.queue.try_pop().as_deref(),
+            Some(TeacherToStudent::Notice(_))
+        ));
+        assert!(matches!(
+            handle.queue.try_pop().as_deref(),
+            Some(TeacherToStudent::Kicked { reason }) if reason == "请专心听课"
+        ));
+        assert!(handle.queue.try_pop().is_none());
+        let remaining: Vec<String> = state
+            .list_students()
+            .into_iter()
+            .map(|student| student.student_id)
+            .collect();
+        assert_eq!(remaining, vec!["S02".to_string()]);
+    }
+
+    #[test]
+    fn lock_input_only_reaches_students_that_understand_it() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let current = connect_as(&state, "S01", "127.0.0.1:4001");
+        let older = connect_speaking(&state, "S02", "127.0.0.1:4002", KICK_PROTOCOL_VERSION);
+        state.broadcast_command(BroadcastCommand::Start {
+            source: BroadcastSource::Teacher,
+            mode: BroadcastMode::Fullscreen,
+            lock_input: true,
+        });
+
+        let locks = |student: &StudentHandle| match student.queue.try_pop().as_deref() {
+            Some(TeacherToStudent::Broadcast(BroadcastCommand::Start { lock_input, .. })) => {
+                *lock_input
+            }
+            other => panic!("expected start command, got {other:?}"),
+        };
+        assert!(locks(&current));
+        assert!(!locks(&older));
+    }
+
+    #[cfg(feature = "http-status")]
+    #[tokio::test]
+    async fn status_page_serves_the_roster_as_json() {
+        let server = TeacherServer::new(TeacherConfig::default(), None).unwrap();
+        connect_as(&server.state, "S01", "127.0.0.1:4001");
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{port}");
+        let mut http = StatusHttp::start(&addr).await.unwrap();
+
+        let get = async {
+            let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+            stream
+                .write_all(b"GET /status HTTP/1.1\r\nHost: teacher\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = tokio::select! {
+            response = get => response,
+            _ = server.answer_status_requests(Some(&mut http)) => unreachable!(),
+        };
+        http.shutdown().await;
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains(r#""connected_students":1"#));
+        assert!(response.contains(r#""student_id":"S01""#));
+    }
+
+    #[tokio::test]
+    async fn spotlight_expiry_restores_the_teacher_broadcast_and_tells_the_room() {
+        let config = TeacherConfig {
+            max_spotlight_secs: 60,
+            ..TeacherConfig::default()
+        };
+        let mut server = TeacherServer::new(config, None).unwrap();
+        server.screen = ScreenBroadcaster::without_capture(server.state.clone());
+        let student = connect_as(&server.state, "S01", "127.0.0.1:4001");
+        server
+            .start_teacher_broadcast(BroadcastMode::Fullscreen)
+            .await
+            .unwrap();
+        server.start_student_broadcast("S01").await.unwrap();
+        assert!(server.state.is_student_broadcasting("S01"));
+        while student.queue.try_pop().is_some() {}
+
+        server.expire_spotlight().await.unwrap();
+        server.screen.stop().await;
+
+        assert_eq!(
+            server.state.broadcast_source(),
+            Some(BroadcastSource::Teacher)
+        );
+        assert_eq!(server.state.broadcast_mode(), BroadcastMode::Fullscreen);
+        assert!(server.spotlight_deadline().is_none());
+        let sent: Vec<_> = std::iter::from_fn(|| student.queue.try_pop()).collect();
+        assert!(sent.iter().any(|msg| matches!(
+            &**msg,
+            TeacherToStudent::Broadcast(BroadcastCommand::Start {
+                source: BroadcastSource::Teacher,
+                ..
+            })
+        )));
+        assert!(sent
+            .iter()
+            .any(|msg| matches!(&**msg, TeacherToStudent::Notice(_))));
+    }
+
+    #[tokio::test]
+    async fn refused_spotlight_restores_the_teacher_broadcast_and_shows_in_the_status() {
+        // No time limit, so only the refusal can end the spotlight.
+        let mut server = TeacherServer::new(TeacherConfig::default(), None).unwrap();
+        server.screen = ScreenBroadcaster::without_capture(server.state.clone());
+        let _student = connect_as(&server.state, "S01", "127.0.0.1:4001");
+        server
+            .start_teacher_broadcast(BroadcastMode::Fullscreen)
+            .await
+            .unwrap();
+        server.start_student_broadcast("S01").await.unwrap();
+
+        server.state.decline_spotlight("S01");
+        server.end_declined_spotlight().await.unwrap();
+        server.screen.stop().await;
+
+        assert_eq!(
+            server.state.broadcast_source(),
+            Some(BroadcastSource::Teacher)
+        );
+        assert_eq!(server.state.broadcast_mode(), BroadcastMode::Fullscreen);
+        assert_eq!(
+            server.status_snapshot().spotlight_declined_by,
+            server.state.find_student_name("S01")
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_calls_off_file_sends_a_stuck_student_never_received() {
+        let state = TeacherState::new(Arc::new(TeacherConfig::default()));
+        let stuck = connect_as(&state, "S01", "127.0.0.1:4001");
+        let idle = connect_as(&state, "S02", "127.0.0.1:4002");
+        let transfer_id = Uuid::new_v4();
+        stuck.send(TeacherToStudent::FileChunk(FileChunk::new(
+            transfer_id,
+            0,
+            vec![0; 16],
+            true,
+        )));
+        stuck.send(TeacherToStudent::FileComplete(FileTransferComplete {
+            transfer_id,
+            success: true,
+            message: None,
+        }));
+
+        // Nobody writes the queue, so the drain gives up and replaces the rest of the file.
+        state.drain_file_sends(Duration::from_millis(100)).await;
+        let left: Vec<_> = std::iter::from_fn(|| stuck.queue.try_pop()).collect();
+        assert_eq!(left.len(), 1);
+        assert!(matches!(
+            &*left[0],
+            TeacherToStudent::FileComplete(done) if done.transfer_id == transfer_id && !done.success
+        ));
+        assert_eq!(idle.queue.depth(), 0);
+    }
 }
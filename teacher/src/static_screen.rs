@@ -0,0 +1,167 @@
+//! Skipping captures of a static screen. Each capture is summed up as one hash per tile;
+//! while hardly any tiles changed since the last broadcast frame, the capture is neither
+//! encoded nor sent. Students keep showing that frame, and late joiners are served it from
+//! the cached latest frame.
+
+use std::time::{Duration, Instant};
+
+use shared::frame::RawFrame;
+use shared::prelude::*;
+
+/// Edge length in pixels of the square tiles compared between captures.
+const TILE_SIZE: u32 = 32;
+/// A static screen is still sent this often, so students whose queue shed the last frame
+/// catch up.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Multiplier of the FxHash word mix; fast and good enough to tell tiles apart.
+const HASH_MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+
+/// One hash per tile of a capture, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FrameSignature {
+    width: u32,
+    height: u32,
+    tiles: Vec<u64>,
+}
+
+impl FrameSignature {
+    fn of(frame: &RawFrame) -> Self {
+        let columns = frame.width.div_ceil(TILE_SIZE) as usize;
+        let rows = frame.height.div_ceil(TILE_SIZE) as usize;
+        let mut tiles = vec![0; columns * rows];
+        let stride = frame.width as usize * 4;
+        if stride > 0 {
+            let tile_bytes = TILE_SIZE as usize * 4;
+            for (y, line) in frame
+                .data
+                .chunks_exact(stride)
+                .take(frame.height as usize)
+                .enumerate()
+            {
+                let tile_row = &mut tiles[y / TILE_SIZE as usize * columns..][..columns];
+                for (hash, pixels) in tile_row.iter_mut().zip(line.chunks(tile_bytes)) {
+                    *hash = mix(*hash, pixels);
+                }
+            }
+        }
+        Self {
+            width: frame.width,
+            height: frame.height,
+            tiles,
+        }
+    }
+
+    /// Tiles that differ from `other`; `None` when the captures are not the same size.
+    fn changed_tiles(&self, other: &Self) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        Some(
+            self.tiles
+                .iter()
+                .zip(&other.tiles)
+                .filter(|(a, b)| a != b)
+                .count(),
+        )
+    }
+}
+
+fn mix(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        let word = u64::from_le_bytes(word.try_into().expect("chunk of 8 bytes"));
+        hash = (hash.rotate_left(5) ^ word).wrapping_mul(HASH_MULTIPLIER);
+    }
+    for &byte in words.remainder() {
+        hash = (hash.rotate_left(5) ^ u64::from(byte)).wrapping_mul(HASH_MULTIPLIER);
+    }
+    hash
+}
+
+/// What was last broadcast, to judge the next capture against.
+#[derive(Debug)]
+struct Sent {
+    signature: FrameSignature,
+    config: BroadcastConfig,
+    at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct StaticScreenFilter {
+    last: Option<Sent>,
+}
+
+impl StaticScreenFilter {
+    /// Whether `frame` should be encoded and broadcast. It is skipped while at most
+    /// `cfg.static_tile_tolerance` tiles differ from the last frame sent under the same
+    /// settings, unless that frame is older than [`REFRESH_INTERVAL`].
+    pub fn should_send(&mut self, frame: &RawFrame, cfg: &BroadcastConfig, now: Instant) -> bool {
+        let signature = FrameSignature::of(frame);
+        if let Some(last) = &self.last {
+            let unchanged = last
+                .signature
+                .changed_tiles(&signature)
+                .is_some_and(|changed| changed <= cfg.static_tile_tolerance as usize);
+            let fresh = now.saturating_duration_since(last.at) < REFRESH_INTERVAL;
+            if unchanged && fresh && last.config == *cfg {
+                return false;
+            }
+        }
+        self.last = Some(Sent {
+            signature,
+            config: cfg.clone(),
+            at: now,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32) -> RawFrame {
+        RawFrame {
+            width,
+            height,
+            data: vec![0x20; (width * height * 4) as usize],
+        }
+    }
+
+    fn paint(frame: &mut RawFrame, x: u32, y: u32) {
+        let offset = ((y * frame.width + x) * 4) as usize;
+        frame.data[offset..offset + 4].copy_from_slice(&[0xff; 4]);
+    }
+
+    #[test]
+    fn static_screens_are_skipped_within_the_tolerance() {
+        let cfg = BroadcastConfig {
+            static_tile_tolerance: 1,
+            ..BroadcastConfig::default()
+        };
+        let start = Instant::now();
+        let mut filter = StaticScreenFilter::default();
+        let mut screen = frame(100, 70);
+        assert!(filter.should_send(&screen, &cfg, start));
+        assert!(!filter.should_send(&screen, &cfg, start));
+
+        // A blinking cursor touches one tile; a second change elsewhere is real content.
+        paint(&mut screen, 40, 40);
+        assert!(!filter.should_send(&screen, &cfg, start));
+        paint(&mut screen, 99, 69);
+        assert!(filter.should_send(&screen, &cfg, start));
+
+        // New settings and the periodic refresh re-send the same picture.
+        let sharper = BroadcastConfig {
+            jpeg_quality: 90,
+            ..cfg.clone()
+        };
+        assert!(filter.should_send(&screen, &sharper, start));
+        assert!(!filter.should_send(&screen, &sharper, start + Duration::from_secs(1)));
+        assert!(filter.should_send(&screen, &sharper, start + REFRESH_INTERVAL));
+
+        // A different display size never counts as the same screen.
+        assert!(filter.should_send(&frame(64, 64), &sharper, start + REFRESH_INTERVAL));
+    }
+}
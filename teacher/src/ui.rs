@@ -1,19 +1,47 @@
 #![cfg(feature = "ui")]
 
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use native_windows_gui as nwg;
 use nwg::CheckBoxState;
-use tokio::sync::oneshot;
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tracing::{error, warn};
+use uuid::Uuid;
 
-use shared::prelude::{BroadcastMode, BroadcastSource};
+use shared::frame::MonitorInfo;
+use shared::prelude::{
+    BroadcastMode, BroadcastSource, EncoderKind, Notice, NoticeSeverity, Rect, MAX_CHAT_CHARS,
+};
 
-use crate::server::{CommandSender, ServerCommand, ServerStatus, StudentSummary};
+use teacher::server::{
+    ChatEntry, CommandSender, FileSendProgress, ServerCommand, ServerStatus, StudentDetail,
+    StudentSummary, ZoomAction, DEFAULT_KICK_REASON, NOTICE_DURATION_MS,
+};
+use teacher::{StudentMetrics, LAGGING_QUEUE_DEPTH};
+
+const WINDOW_TITLE: &str = "Classroom Broadcast - Teacher Control";
+/// Virtual-key codes of the panel shortcuts: start fullscreen, stop, toggle blackout.
+const KEY_START_FULLSCREEN: u32 = 0x78; // F9
+const KEY_STOP: u32 = 0x79; // F10
+const KEY_BLACKOUT: u32 = 0x7a; // F11
+/// Sidecar file next to the teacher config holding [`UiState`].
+const UI_STATE_FILE: &str = "teacher_ui_state.json";
+/// How long the panel waits for the server to answer before reporting it as stuck.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the panel checks for server replies while any are due.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Shortest auto-refresh interval honoured from [`UiState`].
+const MIN_REFRESH_INTERVAL_MS: u64 = 500;
+/// Largest size of the display map in the redaction window; it keeps the display's shape.
+const REDACT_MAP_BOX: (u32, u32) = (480, 270);
+/// Drags shorter than this many map pixels either way are taken as stray clicks.
+const MIN_REDACT_DRAG: i32 = 3;
 
 pub struct UiContext {
     command_tx: CommandSender,
@@ -29,6 +57,62 @@ impl UiContext {
     }
 }
 
+/// Panel choices remembered between launches. Kept out of `TeacherConfig`, which holds
+/// the operational settings.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct UiState {
+    auto_open: bool,
+    /// Display last picked in the panel; `None` when it never was.
+    monitor_index: Option<usize>,
+    /// Ask before actions that take over or disconnect student screens.
+    confirm_disruptive: bool,
+    /// Refresh the panel on a timer; otherwise only "Refresh Status" does.
+    auto_refresh: bool,
+    /// Time between automatic refreshes; only editable in the file.
+    refresh_interval_ms: u64,
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            auto_open: false,
+            monitor_index: None,
+            confirm_disruptive: true,
+            auto_refresh: true,
+            refresh_interval_ms: 2000,
+        }
+    }
+}
+
+impl UiState {
+    fn path(config_path: &Path) -> PathBuf {
+        config_path.with_file_name(UI_STATE_FILE)
+    }
+
+    /// Saved state, or the defaults when there is none or it cannot be read.
+    fn load(config_path: &Path) -> Self {
+        let path = Self::path(config_path);
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&text).unwrap_or_else(|err| {
+            warn!(?err, path = %path.display(), "控制面板状态文件无法解析，已使用默认值");
+            Self::default()
+        })
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_millis(self.refresh_interval_ms.max(MIN_REFRESH_INTERVAL_MS))
+    }
+
+    fn save(&self, config_path: &Path) -> Result<()> {
+        let path = Self::path(config_path);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("无法保存控制面板状态 {}", path.display()))
+    }
+}
+
 pub fn run(context: UiContext) -> Result<()> {
     nwg::init()?;
 
@@ -36,7 +120,8 @@ pub fn run(context: UiContext) -> Result<()> {
     ControlPanel::build_ui(&app)?;
     {
         let mut panel = app.borrow_mut();
-        panel.refresh_all().log_error("failed to refresh panel");
+        panel.restore_monitor();
+        panel.refresh();
     }
 
     nwg::dispatch_thread_events();
@@ -44,57 +129,194 @@ pub fn run(context: UiContext) -> Result<()> {
     if let Some(handler) = app.borrow_mut().handler.take() {
         nwg::unbind_event_handler(&handler);
     }
+    if let Some(handler) = app.borrow_mut().redact_handler.take() {
+        nwg::unbind_event_handler(&handler);
+    }
 
     Ok(())
 }
 
 struct ControlPanel {
     ctx: UiContext,
+    /// Preferences loaded at startup, applied once the controls exist.
+    saved: UiState,
     handler: Option<nwg::EventHandler>,
     students: Vec<StudentSummary>,
+    /// Result of the file send started from this panel, polled on every refresh.
+    pending_send: Option<oneshot::Receiver<Result<(), String>>>,
+    /// Transfer shown in the progress bar, for the cancel button.
+    active_transfer: Option<Uuid>,
+    /// Replies to the refresh in flight; see [`ControlPanel::poll_replies`].
+    pending_refresh: Option<PendingRefresh>,
+    /// Another refresh was asked for while one was in flight.
+    refresh_queued: bool,
+    /// Actions sent to the server whose acknowledgement is still due.
+    pending_acks: Vec<PendingAck>,
+    /// Send statistics from the last refresh, for the detail pane.
+    metrics: Vec<StudentMetrics>,
 
     window: nwg::Window,
     status_label: nwg::Label,
     listen_label: nwg::Label,
     config_label: nwg::Label,
     student_list: nwg::ListBox<String>,
+    monitor_label: nwg::Label,
+    monitor_combo: nwg::ComboBox<String>,
+    /// Displays listed in `monitor_combo` after its leading "Primary display" entry.
+    monitors: Vec<MonitorInfo>,
+    /// Display the server reported as captured; `None` is the primary display.
+    monitor_index: Option<usize>,
+    detail_label: nwg::Label,
+    transfer_label: nwg::Label,
+    transfer_bar: nwg::ProgressBar,
+    cancel_transfer_btn: nwg::Button,
+    notice_input: nwg::TextInput,
+    send_notice_btn: nwg::Button,
+    chat_log: nwg::TextBox,
+    /// Text currently in `chat_log`, so unchanged refreshes keep the scroll position.
+    chat_text: String,
+    chat_input: nwg::TextInput,
+    chat_all_btn: nwg::Button,
+    chat_selected_btn: nwg::Button,
     start_full_btn: nwg::Button,
     start_window_btn: nwg::Button,
     start_student_btn: nwg::Button,
+    kick_btn: nwg::Button,
     stop_broadcast_btn: nwg::Button,
+    blackout_btn: nwg::Button,
+    /// Last blackout state reported by the server, for the toggle button.
+    blackout: bool,
+    quality_label: nwg::Label,
+    quality_bar: nwg::TrackBar,
+    fps_label: nwg::Label,
+    fps_input: nwg::TextInput,
+    apply_quality_btn: nwg::Button,
+    /// JPEG quality and FPS last reported by the server; the controls follow it only when
+    /// it changes, so edits in progress survive the periodic refresh.
+    broadcast_quality: Option<(u8, u32)>,
     audio_on_btn: nwg::Button,
     audio_off_btn: nwg::Button,
     audio_force_btn: nwg::Button,
     audio_allow_btn: nwg::Button,
     send_file_btn: nwg::Button,
+    send_selected_btn: nwg::Button,
     refresh_btn: nwg::Button,
+    record_btn: nwg::Button,
+    /// Whether the server reported an active recording, for the toggle button.
+    recording: bool,
+    accept_zoom_btn: nwg::Button,
+    reject_zoom_btn: nwg::Button,
+    reset_zoom_btn: nwg::Button,
+    redact_btn: nwg::Button,
+    snapshot_btn: nwg::Button,
+    /// Separate window where screen areas to black out are marked on a map of the display.
+    redact_window: nwg::Window,
+    redact_handler: Option<nwg::EventHandler>,
+    redact_map: nwg::ImageFrame,
+    redact_label: nwg::Label,
+    redact_undo_btn: nwg::Button,
+    redact_clear_btn: nwg::Button,
+    /// Redactions last reported by the server.
+    redact_rects: Vec<Rect>,
+    /// Map position where the drag in progress started.
+    redact_drag: Option<(i32, i32)>,
     auto_open_checkbox: nwg::CheckBox,
+    confirm_checkbox: nwg::CheckBox,
+    auto_refresh_checkbox: nwg::CheckBox,
+    /// Students connected at the last status refresh, named in confirmations.
+    connected_students: usize,
     timer: nwg::AnimationTimer,
+    /// Runs while server replies are due, so the UI thread never waits for them.
+    reply_timer: nwg::AnimationTimer,
+    tray_icon: nwg::Icon,
+    /// Keeps the panel reachable while it is minimized and hidden.
+    tray: nwg::TrayNotification,
+    tray_menu: nwg::Menu,
+    tray_start: nwg::MenuItem,
+    tray_stop: nwg::MenuItem,
+    tray_quit: nwg::MenuItem,
 }
 
 impl ControlPanel {
     fn new(ctx: UiContext) -> Self {
         Self {
+            saved: UiState::load(&ctx.config_path),
             ctx,
             handler: None,
             students: Vec::new(),
+            pending_send: None,
+            active_transfer: None,
+            pending_refresh: None,
+            refresh_queued: false,
+            pending_acks: Vec::new(),
+            metrics: Vec::new(),
             window: nwg::Window::default(),
             status_label: nwg::Label::default(),
             listen_label: nwg::Label::default(),
             config_label: nwg::Label::default(),
             student_list: nwg::ListBox::default(),
+            monitor_label: nwg::Label::default(),
+            monitor_combo: nwg::ComboBox::default(),
+            monitors: Vec::new(),
+            monitor_index: None,
+            detail_label: nwg::Label::default(),
+            transfer_label: nwg::Label::default(),
+            transfer_bar: nwg::ProgressBar::default(),
+            cancel_transfer_btn: nwg::Button::default(),
+            notice_input: nwg::TextInput::default(),
+            send_notice_btn: nwg::Button::default(),
+            chat_log: nwg::TextBox::default(),
+            chat_text: String::new(),
+            chat_input: nwg::TextInput::default(),
+            chat_all_btn: nwg::Button::default(),
+            chat_selected_btn: nwg::Button::default(),
             start_full_btn: nwg::Button::default(),
             start_window_btn: nwg::Button::default(),
             start_student_btn: nwg::Button::default(),
+            kick_btn: nwg::Button::default(),
             stop_broadcast_btn: nwg::Button::default(),
+            blackout_btn: nwg::Button::default(),
+            blackout: false,
+            quality_label: nwg::Label::default(),
+            quality_bar: nwg::TrackBar::default(),
+            fps_label: nwg::Label::default(),
+            fps_input: nwg::TextInput::default(),
+            apply_quality_btn: nwg::Button::default(),
+            broadcast_quality: None,
             audio_on_btn: nwg::Button::default(),
             audio_off_btn: nwg::Button::default(),
             audio_force_btn: nwg::Button::default(),
             audio_allow_btn: nwg::Button::default(),
             send_file_btn: nwg::Button::default(),
+            send_selected_btn: nwg::Button::default(),
             refresh_btn: nwg::Button::default(),
+            record_btn: nwg::Button::default(),
+            recording: false,
+            accept_zoom_btn: nwg::Button::default(),
+            reject_zoom_btn: nwg::Button::default(),
+            reset_zoom_btn: nwg::Button::default(),
+            redact_btn: nwg::Button::default(),
+            snapshot_btn: nwg::Button::default(),
+            redact_window: nwg::Window::default(),
+            redact_handler: None,
+            redact_map: nwg::ImageFrame::default(),
+            redact_label: nwg::Label::default(),
+            redact_undo_btn: nwg::Button::default(),
+            redact_clear_btn: nwg::Button::default(),
+            redact_rects: Vec::new(),
+            redact_drag: None,
             auto_open_checkbox: nwg::CheckBox::default(),
+            confirm_checkbox: nwg::CheckBox::default(),
+            auto_refresh_checkbox: nwg::CheckBox::default(),
+            connected_students: 0,
             timer: nwg::AnimationTimer::default(),
+            reply_timer: nwg::AnimationTimer::default(),
+            tray_icon: nwg::Icon::default(),
+            tray: nwg::TrayNotification::default(),
+            tray_menu: nwg::Menu::default(),
+            tray_start: nwg::MenuItem::default(),
+            tray_stop: nwg::MenuItem::default(),
+            tray_quit: nwg::MenuItem::default(),
         }
     }
 
@@ -102,30 +324,30 @@ impl ControlPanel {
         let mut panel = app.borrow_mut();
 
         nwg::Window::builder()
-            .size((720, 460))
+            .size((1020, 748))
             .position((300, 160))
-            .title("Classroom Broadcast - Teacher Control")
+            .title(WINDOW_TITLE)
             .build(&mut panel.window)?;
 
         nwg::Label::builder()
             .parent(&panel.window)
             .text("Status: Idle")
             .position((20, 20))
-            .size((680, 24))
+            .size((980, 24))
             .build(&mut panel.status_label)?;
 
         nwg::Label::builder()
             .parent(&panel.window)
             .text("Listening on: --")
-            .position((20, 380))
-            .size((680, 24))
+            .position((20, 678))
+            .size((980, 24))
             .build(&mut panel.listen_label)?;
 
         nwg::Label::builder()
             .parent(&panel.window)
             .text("Config file:")
-            .position((20, 410))
-            .size((680, 24))
+            .position((20, 704))
+            .size((980, 24))
             .build(&mut panel.config_label)?;
         panel
             .config_label
@@ -134,37 +356,252 @@ impl ControlPanel {
         nwg::ListBox::builder()
             .parent(&panel.window)
             .position((20, 60))
-            .size((340, 300))
+            .size((340, 264))
             .build(&mut panel.student_list)?;
 
+        nwg::Label::builder()
+            .parent(&panel.window)
+            .text("Display:")
+            .position((20, 338))
+            .size((70, 24))
+            .build(&mut panel.monitor_label)?;
+
+        nwg::ComboBox::builder()
+            .parent(&panel.window)
+            .collection(vec!["Primary display".to_string()])
+            .selected_index(Some(0))
+            .position((96, 334))
+            .size((264, 26))
+            .build(&mut panel.monitor_combo)?;
+
+        nwg::CheckBox::builder()
+            .parent(&panel.window)
+            .text("Confirm disruptive actions")
+            .check_state(if panel.saved.confirm_disruptive {
+                CheckBoxState::Checked
+            } else {
+                CheckBoxState::Unchecked
+            })
+            .position((20, 366))
+            .size((210, 26))
+            .build(&mut panel.confirm_checkbox)?;
+
+        nwg::CheckBox::builder()
+            .parent(&panel.window)
+            .text("Auto-refresh")
+            .check_state(if panel.saved.auto_refresh {
+                CheckBoxState::Checked
+            } else {
+                CheckBoxState::Unchecked
+            })
+            .position((240, 366))
+            .size((120, 26))
+            .build(&mut panel.auto_refresh_checkbox)?;
+
+        nwg::Label::builder()
+            .parent(&panel.window)
+            .text("Select a student to see details.")
+            .position((20, 400))
+            .size((340, 188))
+            .build(&mut panel.detail_label)?;
+
+        nwg::Label::builder()
+            .parent(&panel.window)
+            .text("File transfer: idle")
+            .position((20, 604))
+            .size((340, 24))
+            .build(&mut panel.transfer_label)?;
+
+        nwg::ProgressBar::builder()
+            .parent(&panel.window)
+            .range(0..100)
+            .pos(0)
+            .position((380, 606))
+            .size((180, 20))
+            .build(&mut panel.transfer_bar)?;
+
+        nwg::Button::builder()
+            .parent(&panel.window)
+            .text("Cancel Transfer")
+            .position((570, 600))
+            .size((110, 30))
+            .enabled(false)
+            .build(&mut panel.cancel_transfer_btn)?;
+
+        nwg::TextInput::builder()
+            .parent(&panel.window)
+            .placeholder_text(Some("Notice text, e.g. Quiz in 5 minutes"))
+            .position((20, 640))
+            .size((540, 26))
+            .build(&mut panel.notice_input)?;
+
+        nwg::Button::builder()
+            .parent(&panel.window)
+            .text("Send Notice")
+            .position((570, 638))
+            .size((110, 30))
+            .build(&mut panel.send_notice_btn)?;
+
+        nwg::TextBox::builder()
+            .parent(&panel.window)
+            .readonly(true)
+            .flags(
+                nwg::TextBoxFlags::VISIBLE
+                    | nwg::TextBoxFlags::VSCROLL
+                    | nwg::TextBoxFlags::AUTOVSCROLL,
+            )
+            .position((700, 60))
+            .size((300, 528))
+            .build(&mut panel.chat_log)?;
+
+        nwg::TextInput::builder()
+            .parent(&panel.window)
+            .placeholder_text(Some("Chat message"))
+            .limit(MAX_CHAT_CHARS)
+            .position((700, 602))
+            .size((300, 26))
+            .build(&mut panel.chat_input)?;
+
+        nwg::Button::builder()
+            .parent(&panel.window)
+            .text("Chat to All")
+            .position((700, 638))
+            .size((146, 30))
+            .build(&mut panel.chat_all_btn)?;
+
+        nwg::Button::builder()
+            .parent(&panel.window)
+            .text("Chat to Selected")
+            .position((854, 638))
+            .size((146, 30))
+            .build(&mut panel.chat_selected_btn)?;
+
         panel.build_buttons()?;
+        panel.build_tray()?;
 
         nwg::AnimationTimer::builder()
             .parent(&panel.window)
-            .interval(Duration::from_millis(2000))
+            .interval(panel.saved.refresh_interval())
+            .active(panel.saved.auto_refresh)
             .build(&mut panel.timer)?;
+        nwg::AnimationTimer::builder()
+            .parent(&panel.window)
+            .interval(REPLY_POLL_INTERVAL)
+            .active(false)
+            .build(&mut panel.reply_timer)?;
 
         let app_rc = Rc::clone(app);
-        let handler = nwg::full_bind_event_handler(&panel.window.handle, move |evt, _, handle| {
-            let mut panel = app_rc.borrow_mut();
-            match evt {
-                nwg::Event::OnButtonClick => {
-                    panel.handle_button(handle);
-                }
-                nwg::Event::OnWindowClose => {
-                    let _ = panel.ctx.command_tx.send(ServerCommand::Quit);
-                    nwg::stop_thread_dispatch();
-                }
-                nwg::Event::OnTimerTick => {
-                    if handle == panel.timer.handle {
-                        panel.refresh_all().log_error("auto refresh failed");
+        let handler =
+            nwg::full_bind_event_handler(&panel.window.handle, move |evt, evt_data, handle| {
+                let mut panel = app_rc.borrow_mut();
+                match evt {
+                    // F10 arrives as a system key, the others as plain key presses.
+                    nwg::Event::OnKeyPress | nwg::Event::OnSysKeyPress => {
+                        panel.handle_shortcut(evt_data.on_key());
+                    }
+                    nwg::Event::OnWindowMinimize => {
+                        panel.window.set_visible(false);
+                    }
+                    nwg::Event::OnMousePress(nwg::MousePressEvent::MousePressLeftUp) => {
+                        if handle == panel.tray.handle {
+                            panel.show_window();
+                        }
+                    }
+                    nwg::Event::OnContextMenu => {
+                        if handle == panel.tray.handle {
+                            let (x, y) = nwg::GlobalCursor::position();
+                            panel.tray_menu.popup(x, y);
+                        }
+                    }
+                    nwg::Event::OnMenuItemSelected => {
+                        if handle == panel.tray_start.handle {
+                            panel.start_teacher(BroadcastMode::Fullscreen);
+                        } else if handle == panel.tray_stop.handle {
+                            panel.stop_broadcast();
+                        } else if handle == panel.tray_quit.handle {
+                            panel.quit();
+                        }
+                    }
+                    nwg::Event::OnButtonClick => {
+                        panel.handle_button(handle);
+                    }
+                    nwg::Event::TrackBarUpdated => {
+                        if handle == panel.quality_bar.handle {
+                            panel.show_quality_value();
+                        }
+                    }
+                    nwg::Event::OnComboxBoxSelection => {
+                        if handle == panel.monitor_combo.handle {
+                            panel.apply_monitor();
+                        }
                     }
+                    nwg::Event::OnListBoxSelect => {
+                        if handle == panel.student_list.handle {
+                            panel.refresh();
+                        }
+                    }
+                    nwg::Event::OnListBoxDoubleClick => {
+                        // Double clicks that leave no valid row selected do nothing.
+                        if handle == panel.student_list.handle && panel.selected_student().is_some()
+                        {
+                            panel.start_student();
+                        }
+                    }
+                    nwg::Event::OnWindowClose => {
+                        panel.quit();
+                    }
+                    nwg::Event::OnTimerTick => {
+                        if handle == panel.timer.handle {
+                            panel.refresh();
+                        } else if handle == panel.reply_timer.handle {
+                            panel.poll_replies();
+                        }
+                    }
+                    _ => {}
                 }
-                _ => {}
-            }
-        });
+            });
         panel.handler = Some(handler);
 
+        panel.build_redact_window()?;
+        let app_rc = Rc::clone(app);
+        let redact_handler = nwg::full_bind_event_handler(
+            &panel.redact_window.handle,
+            move |evt, evt_data, handle| {
+                let mut panel = app_rc.borrow_mut();
+                match evt {
+                    nwg::Event::OnMousePress(nwg::MousePressEvent::MousePressLeftDown) => {
+                        if handle == panel.redact_map.handle {
+                            panel.redact_drag =
+                                Some(nwg::GlobalCursor::local_position(&panel.redact_map, None));
+                        }
+                    }
+                    nwg::Event::OnMousePress(nwg::MousePressEvent::MousePressLeftUp) => {
+                        if handle == panel.redact_map.handle {
+                            panel.finish_redact_drag();
+                        }
+                    }
+                    nwg::Event::OnButtonClick => {
+                        if handle == panel.redact_undo_btn.handle {
+                            let mut rects = panel.redact_rects.clone();
+                            rects.pop();
+                            panel.set_redact_rects(rects);
+                        } else if handle == panel.redact_clear_btn.handle {
+                            panel.set_redact_rects(Vec::new());
+                        }
+                    }
+                    nwg::Event::OnWindowClose => {
+                        // Keep the window around for next time; just hide it.
+                        if let nwg::EventData::OnWindowClose(close) = evt_data {
+                            close.close(false);
+                        }
+                        panel.redact_window.set_visible(false);
+                    }
+                    _ => {}
+                }
+            },
+        );
+        panel.redact_handler = Some(redact_handler);
+
         Ok(())
     }
 
@@ -177,7 +614,7 @@ impl ControlPanel {
 
         nwg::Button::builder()
             .parent(&self.window)
-            .text("Start Teacher (Fullscreen)")
+            .text("Start Teacher (Fullscreen) [F9]")
             .position((x, y))
             .size((width, height))
             .build(&mut self.start_full_btn)?;
@@ -191,35 +628,90 @@ impl ControlPanel {
             .build(&mut self.start_window_btn)?;
         y += height + gap;
 
+        let half = (width - gap) / 2;
         nwg::Button::builder()
             .parent(&self.window)
-            .text("Broadcast Selected Student")
+            .text("Broadcast Selected")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.start_student_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Kick Selected")
+            .position((x + half + gap, y))
+            .size((half, height))
+            .build(&mut self.kick_btn)?;
         y += height + gap;
 
         nwg::Button::builder()
             .parent(&self.window)
-            .text("Stop Broadcast")
+            .text("Stop Broadcast [F10]")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.stop_broadcast_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Blackout [F11]")
+            .position((x + half + gap, y))
+            .size((half, height))
+            .build(&mut self.blackout_btn)?;
+        y += height + gap * 2;
+
+        let label_width = 90;
+        nwg::Label::builder()
+            .parent(&self.window)
+            .text("Quality: --")
+            .position((x, y + 6))
+            .size((label_width, height - 6))
+            .build(&mut self.quality_label)?;
+
+        nwg::TrackBar::builder()
+            .parent(&self.window)
+            .range(Some(1..100))
+            .pos(Some(75))
+            .position((x + label_width + gap, y))
+            .size((width - label_width - gap, height))
+            .build(&mut self.quality_bar)?;
+        y += height + gap;
+
+        nwg::Label::builder()
+            .parent(&self.window)
+            .text("FPS (1-60):")
+            .position((x, y + 6))
+            .size((label_width, height - 6))
+            .build(&mut self.fps_label)?;
+
+        nwg::TextInput::builder()
+            .parent(&self.window)
+            .flags(nwg::TextInputFlags::VISIBLE | nwg::TextInputFlags::NUMBER)
+            .limit(2)
+            .position((x + label_width + gap, y + 3))
+            .size((50, height - 6))
+            .build(&mut self.fps_input)?;
+
+        let apply_x = x + label_width + gap + 50 + gap;
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Apply Quality")
+            .position((apply_x, y))
+            .size((x + width - apply_x, height))
+            .build(&mut self.apply_quality_btn)?;
         y += height + gap * 2;
 
         nwg::Button::builder()
             .parent(&self.window)
             .text("Start Audio")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.audio_on_btn)?;
-        y += height + gap;
 
         nwg::Button::builder()
             .parent(&self.window)
             .text("Stop Audio")
-            .position((x, y))
-            .size((width, height))
+            .position((x + half + gap, y))
+            .size((half, height))
             .build(&mut self.audio_off_btn)?;
         y += height + gap;
 
@@ -227,21 +719,25 @@ impl ControlPanel {
             .parent(&self.window)
             .text("Force Student Audio")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.audio_force_btn)?;
-        y += height + gap;
 
         nwg::Button::builder()
             .parent(&self.window)
             .text("Allow Student Mute")
-            .position((x, y))
-            .size((width, height))
+            .position((x + half + gap, y))
+            .size((half, height))
             .build(&mut self.audio_allow_btn)?;
         y += height + gap * 2;
 
         nwg::CheckBox::builder()
             .parent(&self.window)
             .text("Request auto-open on student")
+            .check_state(if self.saved.auto_open {
+                CheckBoxState::Checked
+            } else {
+                CheckBoxState::Unchecked
+            })
             .position((x, y))
             .size((width, height))
             .build(&mut self.auto_open_checkbox)?;
@@ -249,22 +745,172 @@ impl ControlPanel {
 
         nwg::Button::builder()
             .parent(&self.window)
-            .text("Send File to Students...")
+            .text("Send File to All...")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.send_file_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Send to Selected...")
+            .position((x + half + gap, y))
+            .size((half, height))
+            .build(&mut self.send_selected_btn)?;
         y += height + gap;
 
         nwg::Button::builder()
             .parent(&self.window)
             .text("Refresh Status")
             .position((x, y))
-            .size((width, height))
+            .size((half, height))
             .build(&mut self.refresh_btn)?;
 
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Start Recording...")
+            .position((x + half + gap, y))
+            .size((half, height))
+            .build(&mut self.record_btn)?;
+        y += height + gap;
+
+        let third = (width - gap * 2) / 3;
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Accept Zoom")
+            .position((x, y))
+            .size((third, height))
+            .build(&mut self.accept_zoom_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Reject Zoom")
+            .position((x + third + gap, y))
+            .size((third, height))
+            .build(&mut self.reject_zoom_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Reset Zoom")
+            .position((x + 2 * (third + gap), y))
+            .size((third, height))
+            .build(&mut self.reset_zoom_btn)?;
+        y += height + gap;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Redact Screen Areas...")
+            .position((x, y))
+            .size((half, height))
+            .build(&mut self.redact_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.window)
+            .text("Send Screenshot")
+            .position((x + half + gap, y))
+            .size((half, height))
+            .build(&mut self.snapshot_btn)?;
+
+        Ok(())
+    }
+
+    fn build_redact_window(&mut self) -> Result<()> {
+        // Not owned by the main window, so its close button cannot reach the main handler.
+        nwg::Window::builder()
+            .flags(nwg::WindowFlags::WINDOW)
+            .size((REDACT_MAP_BOX.0 as i32 + 40, REDACT_MAP_BOX.1 as i32 + 130))
+            .position((360, 220))
+            .title("Redact Screen Areas")
+            .build(&mut self.redact_window)?;
+
+        nwg::ImageFrame::builder()
+            .parent(&self.redact_window)
+            .position((20, 20))
+            .size((REDACT_MAP_BOX.0 as i32, REDACT_MAP_BOX.1 as i32))
+            .background_color(Some([48, 48, 48]))
+            .build(&mut self.redact_map)?;
+
+        let y = REDACT_MAP_BOX.1 as i32 + 30;
+        nwg::Label::builder()
+            .parent(&self.redact_window)
+            .text("")
+            .position((20, y))
+            .size((REDACT_MAP_BOX.0 as i32, 48))
+            .build(&mut self.redact_label)?;
+
+        nwg::Button::builder()
+            .parent(&self.redact_window)
+            .text("Remove Last")
+            .position((20, y + 56))
+            .size((150, 30))
+            .build(&mut self.redact_undo_btn)?;
+
+        nwg::Button::builder()
+            .parent(&self.redact_window)
+            .text("Clear All")
+            .position((180, y + 56))
+            .size((150, 30))
+            .build(&mut self.redact_clear_btn)?;
+
         Ok(())
     }
 
+    fn build_tray(&mut self) -> Result<()> {
+        nwg::Icon::builder()
+            .source_system(Some(nwg::OemIcon::Information))
+            .build(&mut self.tray_icon)?;
+
+        nwg::TrayNotification::builder()
+            .parent(&self.window)
+            .icon(Some(&self.tray_icon))
+            .tip(Some(WINDOW_TITLE))
+            .build(&mut self.tray)?;
+
+        nwg::Menu::builder()
+            .popup(true)
+            .parent(&self.window)
+            .build(&mut self.tray_menu)?;
+
+        nwg::MenuItem::builder()
+            .text("Start Teacher (Fullscreen)")
+            .parent(&self.tray_menu)
+            .build(&mut self.tray_start)?;
+
+        nwg::MenuItem::builder()
+            .text("Stop Broadcast")
+            .parent(&self.tray_menu)
+            .build(&mut self.tray_stop)?;
+
+        nwg::MenuItem::builder()
+            .text("Quit")
+            .parent(&self.tray_menu)
+            .build(&mut self.tray_quit)?;
+
+        Ok(())
+    }
+
+    /// F9/F10/F11 while the panel has focus.
+    fn handle_shortcut(&mut self, key: u32) {
+        match key {
+            KEY_START_FULLSCREEN => self.start_teacher(BroadcastMode::Fullscreen),
+            KEY_STOP => self.stop_broadcast(),
+            KEY_BLACKOUT => self.toggle_blackout(),
+            _ => {}
+        }
+    }
+
+    /// Bring the panel back from the tray.
+    fn show_window(&self) {
+        self.window.set_visible(true);
+        self.window.restore();
+        self.window.set_focus();
+    }
+
+    fn quit(&mut self) {
+        self.save_state().log_error("failed to save panel state");
+        let _ = self.ctx.command_tx.send(ServerCommand::Quit);
+        nwg::stop_thread_dispatch();
+    }
+
     fn handle_button(&mut self, handle: nwg::ControlHandle) {
         if handle == self.start_full_btn.handle {
             self.start_teacher(BroadcastMode::Fullscreen);
@@ -272,8 +918,14 @@ impl ControlPanel {
             self.start_teacher(BroadcastMode::Window);
         } else if handle == self.start_student_btn.handle {
             self.start_student();
+        } else if handle == self.kick_btn.handle {
+            self.kick_selected();
         } else if handle == self.stop_broadcast_btn.handle {
             self.stop_broadcast();
+        } else if handle == self.blackout_btn.handle {
+            self.toggle_blackout();
+        } else if handle == self.apply_quality_btn.handle {
+            self.apply_quality();
         } else if handle == self.audio_on_btn.handle {
             self.audio_on();
         } else if handle == self.audio_off_btn.handle {
@@ -283,13 +935,67 @@ impl ControlPanel {
         } else if handle == self.audio_allow_btn.handle {
             self.audio_force(false);
         } else if handle == self.send_file_btn.handle {
-            self.send_file();
+            self.send_file(None);
+        } else if handle == self.send_selected_btn.handle {
+            match self.selected_student().map(|s| s.student_id.clone()) {
+                Some(student_id) => self.send_file(Some(student_id)),
+                None => self.alert("Select a student in the list first."),
+            }
+        } else if handle == self.send_notice_btn.handle {
+            self.send_notice();
+        } else if handle == self.chat_all_btn.handle {
+            self.send_chat(None);
+        } else if handle == self.chat_selected_btn.handle {
+            match self.selected_student().map(|s| s.student_id.clone()) {
+                Some(student_id) => self.send_chat(Some(student_id)),
+                None => self.alert("Select a student in the list first."),
+            }
+        } else if handle == self.cancel_transfer_btn.handle {
+            self.cancel_transfer();
+        } else if handle == self.record_btn.handle {
+            self.toggle_recording();
         } else if handle == self.refresh_btn.handle {
-            self.refresh_all().log_error("manual refresh failed");
+            self.manual_refresh();
+        } else if handle == self.auto_refresh_checkbox.handle {
+            self.apply_auto_refresh();
+        } else if handle == self.accept_zoom_btn.handle {
+            self.zoom_selected(ZoomAction::Accept);
+        } else if handle == self.reject_zoom_btn.handle {
+            self.zoom_selected(ZoomAction::Reject);
+        } else if handle == self.reset_zoom_btn.handle {
+            self.zoom(ZoomAction::Reset);
+        } else if handle == self.redact_btn.handle {
+            self.open_redact_window();
+        } else if handle == self.snapshot_btn.handle {
+            self.send_screenshot();
+        }
+    }
+
+    /// Ask before an action that disrupts the class, unless confirmations are turned off.
+    fn confirm(&self, action: &str) -> bool {
+        if self.confirm_checkbox.check_state() != CheckBoxState::Checked {
+            return true;
         }
+        let content = format!(
+            "{action}\n\n{} student(s) are connected. Continue?",
+            self.connected_students
+        );
+        let params = nwg::MessageParams {
+            title: "Confirm",
+            content: &content,
+            buttons: nwg::MessageButtons::YesNo,
+            icons: nwg::MessageIcons::Warning,
+        };
+        nwg::modal_message(&self.window, &params) == nwg::MessageChoice::Yes
     }
 
     fn start_teacher(&mut self, mode: BroadcastMode) {
+        if mode == BroadcastMode::Fullscreen
+            && !self
+                .confirm("Start a fullscreen broadcast? Every student screen will be taken over.")
+        {
+            return;
+        }
         let (tx, rx) = oneshot::channel();
         let command = ServerCommand::StartTeacher {
             mode,
@@ -299,10 +1005,7 @@ impl ControlPanel {
             self.alert("Teacher service is not running.");
             return;
         }
-        match Self::recv_ack(rx, "操作超时") {
-            Ok(()) => self.refresh_status().log_error("refresh status failed"),
-            Err(err) => self.alert(&format!("{:#}", err)),
-        }
+        self.expect_ack(rx, Self::refresh_or_alert);
     }
 
     fn stop_broadcast(&mut self) {
@@ -318,44 +1021,19 @@ impl ControlPanel {
             self.alert("Teacher service is not running.");
             return;
         }
-        match Self::recv_ack(rx, "操作超时") {
-            Ok(()) => self.refresh_status().log_error("refresh status failed"),
-            Err(err) => self.alert(&format!("{:#}", err)),
-        }
+        self.expect_ack(rx, Self::refresh_or_alert);
     }
 
-    fn start_student(&mut self) {
-        if let Some(index) = self.student_list.selection() {
-            if let Some(student) = self.students.get(index as usize) {
-                let (tx, rx) = oneshot::channel();
-                if self
-                    .ctx
-                    .command_tx
-                    .send(ServerCommand::StartStudent {
-                        student_id: student.student_id.clone(),
-                        respond_to: Some(tx),
-                    })
-                    .is_err()
-                {
-                    self.alert("Teacher service is not running.");
-                    return;
-                }
-                match Self::recv_ack(rx, "操作超时") {
-                    Ok(()) => self.refresh_status().log_error("refresh status failed"),
-                    Err(err) => self.alert(&format!("{:#}", err)),
-                }
-            }
-        } else {
-            self.alert("Select a student in the list first.");
+    fn toggle_blackout(&mut self) {
+        if !self.blackout && !self.confirm("Black out every student screen?") {
+            return;
         }
-    }
-
-    fn audio_on(&mut self) {
         let (tx, rx) = oneshot::channel();
         if self
             .ctx
             .command_tx
-            .send(ServerCommand::AudioStart {
+            .send(ServerCommand::Blackout {
+                enabled: !self.blackout,
                 respond_to: Some(tx),
             })
             .is_err()
@@ -363,18 +1041,16 @@ impl ControlPanel {
             self.alert("Teacher service is not running.");
             return;
         }
-        match Self::recv_ack(rx, "操作超时") {
-            Ok(()) => self.refresh_status().log_error("refresh status failed"),
-            Err(err) => self.alert(&format!("{:#}", err)),
-        }
+        self.expect_ack(rx, Self::refresh_or_alert);
     }
 
-    fn audio_off(&mut self) {
+    /// Leave a screenshot of the teacher screen on every student screen.
+    fn send_screenshot(&mut self) {
         let (tx, rx) = oneshot::channel();
         if self
             .ctx
             .command_tx
-            .send(ServerCommand::AudioStop {
+            .send(ServerCommand::SendScreenshot {
                 respond_to: Some(tx),
             })
             .is_err()
@@ -382,20 +1058,29 @@ impl ControlPanel {
             self.alert("Teacher service is not running.");
             return;
         }
-        if let Err(err) = Self::recv_ack(rx, "操作超时") {
-            self.alert(&format!("{:#}", err));
-        } else {
-            self.refresh_status().log_error("refresh status failed");
-        }
+        self.expect_ack(rx, Self::refresh_or_alert);
     }
 
-    fn audio_force(&mut self, force: bool) {
+    fn show_quality_value(&self) {
+        self.quality_label
+            .set_text(&format!("Quality: {}", self.quality_bar.pos()));
+    }
+
+    fn apply_quality(&mut self) {
+        let fps = match self.fps_input.text().trim().parse::<u32>() {
+            Ok(fps) if (1..=60).contains(&fps) => fps,
+            _ => {
+                self.alert("FPS must be a number between 1 and 60.");
+                return;
+            }
+        };
         let (tx, rx) = oneshot::channel();
         if self
             .ctx
             .command_tx
-            .send(ServerCommand::AudioForce {
-                force,
+            .send(ServerCommand::SetBroadcastQuality {
+                jpeg_quality: Some(self.quality_bar.pos() as u8),
+                fps: Some(fps),
                 respond_to: Some(tx),
             })
             .is_err()
@@ -403,73 +1088,737 @@ impl ControlPanel {
             self.alert("Teacher service is not running.");
             return;
         }
-        if let Err(err) = Self::recv_ack(rx, "操作超时") {
-            self.alert(&format!("{:#}", err));
-        } else {
-            self.refresh_status().log_error("refresh status failed");
-        }
+        self.expect_ack(rx, Self::refresh_or_alert);
     }
 
-    fn send_file(&mut self) {
-        if let Some(path) = rfd::FileDialog::new()
-            .set_title("Choose a file to broadcast")
-            .pick_file()
+    /// Switch the broadcast to the display picked in `monitor_combo`.
+    fn apply_monitor(&mut self) {
+        let index = match self.monitor_combo.selection() {
+            Some(0) | None => None,
+            Some(entry) => self.monitors.get(entry - 1).map(|monitor| monitor.index),
+        };
+        if index == self.monitor_index {
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::SetMonitor {
+                index,
+                respond_to: Some(tx),
+            })
+            .is_err()
         {
-            let (tx, rx) = oneshot::channel();
-            let auto_open = self.auto_open_checkbox.check_state() == CheckBoxState::Checked;
-            if self
-                .ctx
-                .command_tx
-                .send(ServerCommand::SendFile {
-                    path: path.into(),
-                    auto_open_override: auto_open,
-                    respond_to: Some(tx),
-                })
-                .is_err()
-            {
-                self.alert("Teacher service is not running.");
-                return;
-            }
-            if let Err(err) = Self::recv_ack(rx, "文件分发结果未知") {
-                self.alert(&format!("{:#}", err));
-            }
+            self.alert("Teacher service is not running.");
+            return;
         }
+        self.expect_ack(rx, |panel, result| {
+            if result.is_err() {
+                panel.select_monitor_entry();
+            }
+            panel.refresh_or_alert(result);
+        });
     }
 
-    fn refresh_all(&mut self) -> Result<()> {
-        self.refresh_students()?;
-        self.refresh_status()?;
-        Ok(())
+    /// Show the redaction window with its map shaped like the captured display.
+    fn open_redact_window(&mut self) {
+        let Some((width, height)) = self.captured_display_size() else {
+            self.refresh();
+            self.alert("The size of the broadcast display is unknown. Try again in a moment.");
+            return;
+        };
+        let scale = f64::min(
+            f64::from(REDACT_MAP_BOX.0) / f64::from(width),
+            f64::from(REDACT_MAP_BOX.1) / f64::from(height),
+        );
+        self.redact_map.set_size(
+            (f64::from(width) * scale).round() as u32,
+            (f64::from(height) * scale).round() as u32,
+        );
+        self.show_redactions();
+        self.redact_window.set_visible(true);
+        self.redact_window.set_focus();
+    }
+
+    /// Pixel size of the display the broadcast captures.
+    fn captured_display_size(&self) -> Option<(u32, u32)> {
+        self.monitors
+            .iter()
+            .find(|monitor| match self.monitor_index {
+                Some(index) => monitor.index == index,
+                None => monitor.primary,
+            })
+            .map(|monitor| (monitor.width, monitor.height))
+    }
+
+    /// Turn the drag that just ended on the map into a redaction of that part of the display.
+    fn finish_redact_drag(&mut self) {
+        let Some(start) = self.redact_drag.take() else {
+            return;
+        };
+        let end = nwg::GlobalCursor::local_position(&self.redact_map, None);
+        let Some(display) = self.captured_display_size() else {
+            return;
+        };
+        if let Some(rect) = map_drag_to_rect(start, end, self.redact_map.size(), display) {
+            let mut rects = self.redact_rects.clone();
+            rects.push(rect);
+            self.set_redact_rects(rects);
+        }
+    }
+
+    fn set_redact_rects(&mut self, rects: Vec<Rect>) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::SetRedactRects {
+                rects,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn show_redactions(&self) {
+        let listed = if self.redact_rects.is_empty() {
+            "none".to_string()
+        } else {
+            self.redact_rects
+                .iter()
+                .map(|rect| format!("{}x{} at ({}, {})", rect.width, rect.height, rect.x, rect.y))
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        self.redact_label.set_text(&format!(
+            "Drag across the map to black out that part of the broadcast.\r\nRedacted: {listed}"
+        ));
+    }
+
+    /// Go back to the display picked in an earlier session, if it is still attached.
+    fn restore_monitor(&mut self) {
+        let Some(index) = self.saved.monitor_index else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::SetMonitor {
+                index: Some(index),
+                respond_to: Some(tx),
+            })
+            .is_ok()
+        {
+            self.expect_ack(rx, |panel, result| {
+                result.log_error("failed to restore the last display");
+                panel.refresh();
+            });
+        }
+    }
+
+    fn auto_refresh(&self) -> bool {
+        self.auto_refresh_checkbox.check_state() == CheckBoxState::Checked
+    }
+
+    /// Start or pause the refresh timer to match the checkbox.
+    fn apply_auto_refresh(&mut self) {
+        if self.auto_refresh() {
+            self.timer.start();
+            self.refresh();
+        } else {
+            self.timer.stop();
+        }
+    }
+
+    fn manual_refresh(&mut self) {
+        // Restart the timer so the next automatic refresh is a full interval away.
+        if self.auto_refresh() {
+            self.timer.stop();
+            self.timer.start();
+        }
+        self.refresh();
+    }
+
+    fn save_state(&self) -> Result<()> {
+        let state = UiState {
+            auto_open: self.auto_open_checkbox.check_state() == CheckBoxState::Checked,
+            monitor_index: self.monitor_index,
+            confirm_disruptive: self.confirm_checkbox.check_state() == CheckBoxState::Checked,
+            auto_refresh: self.auto_refresh(),
+            refresh_interval_ms: self.saved.refresh_interval_ms,
+        };
+        state.save(&self.ctx.config_path)
+    }
+
+    /// Point `monitor_combo` at the display the server captures.
+    fn select_monitor_entry(&self) {
+        let entry = self.monitor_index.and_then(|index| {
+            self.monitors
+                .iter()
+                .position(|monitor| monitor.index == index)
+                .map(|position| position + 1)
+        });
+        self.monitor_combo.set_selection(Some(entry.unwrap_or(0)));
+    }
+
+    fn send_notice(&mut self) {
+        let text = self.notice_input.text().trim().to_string();
+        if text.is_empty() {
+            self.alert("Type the notice text first.");
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        let notice = Notice {
+            text,
+            duration_ms: NOTICE_DURATION_MS,
+            severity: NoticeSeverity::Info,
+        };
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::SendNotice {
+                notice,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, |panel, result| match result {
+            Ok(()) => panel.notice_input.set_text(""),
+            Err(err) => panel.alert(&format!("{:#}", err)),
+        });
+    }
+
+    fn send_chat(&mut self, student_id: Option<String>) {
+        let text = self.chat_input.text().trim().to_string();
+        if text.is_empty() {
+            self.alert("Type the chat message first.");
+            return;
+        }
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::SendChat {
+                student_id,
+                text,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, |panel, result| {
+            if result.is_ok() {
+                panel.chat_input.set_text("");
+            }
+            panel.refresh_or_alert(result);
+        });
+    }
+
+    fn toggle_recording(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        let command = if self.recording {
+            ServerCommand::StopRecording {
+                respond_to: Some(tx),
+            }
+        } else {
+            let Some(path) = rfd::FileDialog::new()
+                .set_title("Choose an empty folder for the recording")
+                .pick_folder()
+            else {
+                return;
+            };
+            ServerCommand::StartRecording {
+                path,
+                respond_to: Some(tx),
+            }
+        };
+        if self.ctx.command_tx.send(command).is_err() {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn start_student(&mut self) {
+        if let Some(index) = self.student_list.selection() {
+            if let Some(student) = self.students.get(index as usize) {
+                let action = format!(
+                    "Broadcast {}'s screen fullscreen to the class?",
+                    student.display_name
+                );
+                if !self.confirm(&action) {
+                    return;
+                }
+                let (tx, rx) = oneshot::channel();
+                if self
+                    .ctx
+                    .command_tx
+                    .send(ServerCommand::StartStudent {
+                        student_id: student.student_id.clone(),
+                        respond_to: Some(tx),
+                    })
+                    .is_err()
+                {
+                    self.alert("Teacher service is not running.");
+                    return;
+                }
+                self.expect_ack(rx, Self::refresh_or_alert);
+            }
+        } else {
+            self.alert("Select a student in the list first.");
+        }
     }
 
-    fn refresh_students(&mut self) -> Result<()> {
+    fn kick_selected(&mut self) {
+        let Some(student_id) = self.selected_student().map(|s| s.student_id.clone()) else {
+            self.alert("Select a student in the list first.");
+            return;
+        };
+        if !self.confirm(&format!("Disconnect {student_id} from the class?")) {
+            return;
+        }
         let (tx, rx) = oneshot::channel();
-        self.ctx
+        if self
+            .ctx
             .command_tx
-            .send(ServerCommand::ListStudents { respond_to: tx })
-            .map_err(|_| anyhow!("Teacher service is not running"))?;
-        let list = Self::recv_list(rx, "学生列表请求超时")?;
+            .send(ServerCommand::KickStudent {
+                student_id,
+                reason: DEFAULT_KICK_REASON.to_string(),
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn zoom_selected(&mut self, action: fn(String) -> ZoomAction) {
+        match self.selected_student().map(|s| s.student_id.clone()) {
+            Some(student_id) => self.zoom(action(student_id)),
+            None => self.alert("Select a student in the list first."),
+        }
+    }
+
+    fn zoom(&mut self, action: ZoomAction) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::Zoom {
+                action,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn audio_on(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::AudioStart {
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn audio_off(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::AudioStop {
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    fn audio_force(&mut self, force: bool) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::AudioForce {
+                force,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, Self::refresh_or_alert);
+    }
+
+    /// Send a file to `student_id`, or to the whole class when `None`.
+    fn send_file(&mut self, student_id: Option<String>) {
+        let title = match &student_id {
+            Some(student_id) => format!("Choose a file to send to {student_id}"),
+            None => "Choose a file to broadcast".to_string(),
+        };
+        if let Some(path) = rfd::FileDialog::new().set_title(&title).pick_file() {
+            let (tx, rx) = oneshot::channel();
+            let auto_open = self.auto_open_checkbox.check_state() == CheckBoxState::Checked;
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let command = match student_id {
+                Some(student_id) => ServerCommand::SendFileTo {
+                    student_id,
+                    path,
+                    auto_open_override: auto_open,
+                    respond_to: Some(tx),
+                },
+                None => ServerCommand::SendFile {
+                    path,
+                    auto_open_override: auto_open,
+                    respond_to: Some(tx),
+                },
+            };
+            if self.ctx.command_tx.send(command).is_err() {
+                self.alert("Teacher service is not running.");
+                return;
+            }
+            // Not awaited here: the send runs in the background and progress shows up on refresh.
+            self.pending_send = Some(rx);
+            self.transfer_label
+                .set_text(&format!("Sending {file_name}..."));
+            self.transfer_bar.set_pos(0);
+        }
+    }
+
+    fn cancel_transfer(&mut self) {
+        let Some(transfer_id) = self.active_transfer else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if self
+            .ctx
+            .command_tx
+            .send(ServerCommand::CancelFile {
+                transfer_id,
+                respond_to: Some(tx),
+            })
+            .is_err()
+        {
+            self.alert("Teacher service is not running.");
+            return;
+        }
+        self.expect_ack(rx, |panel, result| match result {
+            Ok(()) => {
+                panel.cancel_transfer_btn.set_enabled(false);
+                panel.transfer_label.set_text("Canceling transfer...");
+            }
+            Err(err) => panel.alert(&format!("{:#}", err)),
+        });
+    }
+
+    /// Clear the progress display once the background send finished, reporting failures.
+    fn poll_pending_send(&mut self) {
+        let Some(rx) = self.pending_send.as_mut() else {
+            return;
+        };
+        let outcome = match rx.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Ok(result) => result,
+            Err(TryRecvError::Closed) => Err("文件分发结果未知".to_string()),
+        };
+        self.pending_send = None;
+        self.transfer_label.set_text("File transfer: idle");
+        self.transfer_bar.set_pos(0);
+        if let Err(err) = outcome {
+            self.alert(&err);
+        }
+    }
+
+    fn show_file_progress(&mut self, progress: Option<FileSendProgress>) {
+        self.active_transfer = progress.as_ref().map(|progress| progress.transfer_id);
+        self.cancel_transfer_btn
+            .set_enabled(self.active_transfer.is_some());
+        match progress {
+            Some(progress) => {
+                let percent = (progress.sent * 100)
+                    .checked_div(progress.total)
+                    .unwrap_or(100);
+                self.transfer_label.set_text(&format!(
+                    "Sending {}: {}% ({} / {} KB)",
+                    progress.file_name,
+                    percent,
+                    progress.sent / 1024,
+                    progress.total / 1024
+                ));
+                self.transfer_bar.set_pos(percent as u32);
+            }
+            None => self.poll_pending_send(),
+        }
+    }
+
+    /// Ask the server for everything the panel shows. The replies are applied by
+    /// [`Self::poll_replies`] as they come in; a refresh asked for while one is in flight
+    /// goes out once that one is done.
+    fn refresh(&mut self) {
+        if self.pending_refresh.is_some() {
+            self.refresh_queued = true;
+            return;
+        }
+        match self.send_refresh() {
+            Ok(pending) => {
+                self.pending_refresh = Some(pending);
+                self.reply_timer.start();
+            }
+            Err(err) => self.refresh_failed(err),
+        }
+    }
+
+    fn send_refresh(&self) -> Result<PendingRefresh> {
+        fn query<T>(
+            command_tx: &CommandSender,
+            command: impl FnOnce(oneshot::Sender<T>) -> ServerCommand,
+        ) -> Result<oneshot::Receiver<T>> {
+            let (tx, rx) = oneshot::channel();
+            command_tx
+                .send(command(tx))
+                .map_err(|_| anyhow!("Teacher service is not running"))?;
+            Ok(rx)
+        }
+
+        let command_tx = &self.ctx.command_tx;
+        // Metrics go first so they are in by the time the detail they belong to arrives.
+        let metrics = query(command_tx, |respond_to| ServerCommand::QueryMetrics {
+            respond_to,
+        })?;
+        let detail = match self.selected_student() {
+            Some(student) => {
+                let student_id = student.student_id.clone();
+                Some(query(command_tx, |respond_to| {
+                    ServerCommand::QueryStudent {
+                        student_id,
+                        respond_to,
+                    }
+                })?)
+            }
+            None => None,
+        };
+        Ok(PendingRefresh {
+            students: Some(query(command_tx, |respond_to| {
+                ServerCommand::ListStudents { respond_to }
+            })?),
+            status: Some(query(command_tx, |respond_to| {
+                ServerCommand::QueryStatus { respond_to }
+            })?),
+            monitors: Some(query(command_tx, |respond_to| {
+                ServerCommand::ListMonitors { respond_to }
+            })?),
+            metrics: Some(metrics),
+            detail,
+            chat: Some(query(command_tx, |respond_to| ServerCommand::QueryChat {
+                respond_to,
+            })?),
+            deadline: Instant::now() + REPLY_TIMEOUT,
+        })
+    }
+
+    /// Apply whatever server replies have arrived, on every tick of `reply_timer`.
+    fn poll_replies(&mut self) {
+        // Stopped while replies are applied, so an alert they raise does not tick it again.
+        self.reply_timer.stop();
+        let now = Instant::now();
+        for mut ack in std::mem::take(&mut self.pending_acks) {
+            match take_reply(&mut ack.rx) {
+                Some(reply) => {
+                    let result = reply.and_then(|reply| reply.map_err(|err| anyhow!(err)));
+                    (ack.then)(self, result);
+                }
+                None if now >= ack.deadline => (ack.then)(self, Err(anyhow!("操作超时"))),
+                None => self.pending_acks.push(ack),
+            }
+        }
+
+        if let Some(mut pending) = self.pending_refresh.take() {
+            if let Some(reply) = take_reply(&mut pending.students) {
+                match reply.and_then(|list| list.map_err(|err| anyhow!(err))) {
+                    Ok(list) => self.show_students(list),
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if let Some(reply) = take_reply(&mut pending.status) {
+                match reply {
+                    Ok(status) => self.update_status(status),
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if let Some(reply) = take_reply(&mut pending.monitors) {
+                match reply.and_then(|monitors| monitors.map_err(|err| anyhow!(err))) {
+                    Ok(monitors) => self.show_monitors(monitors),
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if let Some(reply) = take_reply(&mut pending.metrics) {
+                match reply {
+                    Ok(metrics) => self.metrics = metrics,
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if let Some(reply) = take_reply(&mut pending.detail) {
+                match reply {
+                    Ok(detail) => self.show_detail(detail),
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if let Some(reply) = take_reply(&mut pending.chat) {
+                match reply {
+                    Ok(entries) => self.show_chat(&entries),
+                    Err(err) => self.refresh_failed(err),
+                }
+            }
+            if !pending.is_done() && now < pending.deadline {
+                self.pending_refresh = Some(pending);
+            } else {
+                if !pending.is_done() {
+                    self.refresh_failed(anyhow!("刷新请求超时"));
+                }
+                if std::mem::take(&mut self.refresh_queued) {
+                    self.refresh();
+                }
+            }
+        }
+
+        if self.pending_refresh.is_some() || !self.pending_acks.is_empty() {
+            self.reply_timer.start();
+        }
+    }
+
+    fn refresh_failed(&self, err: anyhow::Error) {
+        error!(?err, "refresh failed");
+        self.status_label
+            .set_text(&format!("Refresh failed: {err:#}"));
+    }
+
+    /// Run `then` with the server's acknowledgement of an action once it arrives, or with
+    /// an error after [`REPLY_TIMEOUT`].
+    fn expect_ack(
+        &mut self,
+        rx: oneshot::Receiver<Result<(), String>>,
+        then: impl FnOnce(&mut Self, Result<()>) + 'static,
+    ) {
+        self.pending_acks.push(PendingAck {
+            rx,
+            deadline: Instant::now() + REPLY_TIMEOUT,
+            then: Box::new(then),
+        });
+        self.reply_timer.start();
+    }
+
+    /// Follow-up of most actions: show their effect, or tell the teacher why they failed.
+    fn refresh_or_alert(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => self.refresh(),
+            Err(err) => self.alert(&format!("{:#}", err)),
+        }
+    }
+
+    fn show_chat(&mut self, entries: &[ChatEntry]) {
+        let text = entries
+            .iter()
+            .map(format_chat_line)
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        if text != self.chat_text {
+            self.chat_log.set_text(&text);
+            self.chat_log.scroll_lastline();
+            self.chat_text = text;
+        }
+    }
+
+    fn selected_student(&self) -> Option<&StudentSummary> {
+        self.student_list
+            .selection()
+            .and_then(|index| self.students.get(index))
+    }
+
+    fn show_detail(&mut self, detail: Result<StudentDetail, String>) {
+        // The selection may have moved on while the reply was on its way.
+        if let Ok(detail) = &detail {
+            let selected = self.selected_student().map(|s| s.student_id.as_str());
+            if selected != Some(detail.summary.student_id.as_str()) {
+                return;
+            }
+        }
+        match detail {
+            Ok(detail) => {
+                let metrics = self
+                    .metrics
+                    .iter()
+                    .find(|m| m.student_id == detail.summary.student_id);
+                self.detail_label.set_text(&format_detail(&detail, metrics));
+            }
+            Err(err) => self.detail_label.set_text(&err),
+        }
+    }
+
+    fn show_students(&mut self, list: Vec<StudentSummary>) {
+        let selected_id = self.selected_student().map(|s| s.student_id.clone());
         self.students = list;
         self.student_list.clear();
         for student in &self.students {
-            let display = format!(
-                "{} ({}) @ {}",
-                student.display_name, student.student_id, student.addr
-            );
-            self.student_list.push(display);
+            self.student_list.push(student.roster_line());
         }
-        Ok(())
+        // Keep the selection across refreshes so the detail pane stays put.
+        let restored =
+            selected_id.and_then(|id| self.students.iter().position(|s| s.student_id == id));
+        self.student_list.set_selection(restored);
+        if restored.is_none() {
+            self.detail_label
+                .set_text("Select a student to see details.");
+        }
+
+        // The title shows in the taskbar too, so raised hands are noticed while minimized.
+        let hands = self.students.iter().filter(|s| s.hand_raised).count();
+        self.window.set_text(&match hands {
+            0 => WINDOW_TITLE.to_string(),
+            1 => format!("{WINDOW_TITLE} (1 hand raised)"),
+            n => format!("{WINDOW_TITLE} ({n} hands raised)"),
+        });
     }
 
-    fn refresh_status(&mut self) -> Result<()> {
-        let (tx, rx) = oneshot::channel();
-        self.ctx
-            .command_tx
-            .send(ServerCommand::QueryStatus { respond_to: tx })
-            .map_err(|_| anyhow!("Teacher service is not running"))?;
-        let status = Self::recv_status(rx, "状态请求超时")?;
-        self.update_status(status);
-        Ok(())
+    /// Show newly attached or removed displays in `monitor_combo`.
+    fn show_monitors(&mut self, monitors: Vec<MonitorInfo>) {
+        if monitors != self.monitors {
+            let mut entries = vec!["Primary display".to_string()];
+            entries.extend(monitors.iter().map(format_monitor));
+            self.monitor_combo.set_collection(entries);
+            self.monitors = monitors;
+            self.select_monitor_entry();
+        }
     }
 
     fn update_status(&mut self, status: ServerStatus) {
@@ -490,6 +1839,11 @@ impl ControlPanel {
             BroadcastMode::Window => "Window mode",
         };
 
+        let monitor_text = match status.monitor_index {
+            Some(index) => format!("Display {index}"),
+            None => "Primary display".to_string(),
+        };
+
         let audio_text = if status.audio_enabled {
             if status.audio_forced {
                 "Audio: forced"
@@ -500,46 +1854,297 @@ impl ControlPanel {
             "Audio: off"
         };
 
+        let zoom_text = if status.zoomed { " | Zoomed" } else { "" };
+        let window_text = if status.capture_window_missing {
+            " | Window not found, paused"
+        } else {
+            ""
+        };
+        let blackout_text = if status.blackout { " | Blackout" } else { "" };
+        let recording_text = if status.recording.is_some() {
+            " | Recording"
+        } else {
+            ""
+        };
+        let spotlight_text = status
+            .spotlight_remaining_secs
+            .map(|secs| format!(" | Spotlight ends in {}:{:02}", secs / 60, secs % 60))
+            .unwrap_or_default();
+        let declined_text = status
+            .spotlight_declined_by
+            .as_ref()
+            .map(|name| format!(" | {name} declined the spotlight"))
+            .unwrap_or_default();
+
+        let lag_text = match &status.worst_queue {
+            Some((student_id, depth)) if *depth >= LAGGING_QUEUE_DEPTH => {
+                format!(" | Lagging: {student_id} ({depth} queued)")
+            }
+            _ => String::new(),
+        };
+
+        let duplicate_text = if status.duplicate_student_ids.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " | Duplicate ID: {}",
+                status.duplicate_student_ids.join(", ")
+            )
+        };
+
+        let encoder_text = match status.encoder {
+            Some(EncoderKind::Cpu) => " | Encoder: CPU",
+            Some(EncoderKind::Turbojpeg) => " | Encoder: libjpeg-turbo",
+            None => "",
+        };
+
         self.status_label.set_text(&format!(
-            "Current: {} | {} | Students: {} | {}",
-            source_text, mode_text, status.connected_students, audio_text
+            "Current: {}{}{}{}{}{} | {} | {} | Students: {}{}{} | {}{}{}",
+            source_text,
+            zoom_text,
+            window_text,
+            blackout_text,
+            recording_text,
+            encoder_text,
+            mode_text,
+            monitor_text,
+            status.connected_students,
+            duplicate_text,
+            lag_text,
+            audio_text,
+            spotlight_text,
+            declined_text
         ));
         self.listen_label
             .set_text(&format!("Listening on: {}", status.listen_addr));
+        self.show_file_progress(status.file_progress);
+        self.recording = status.recording.is_some();
+        self.record_btn.set_text(if self.recording {
+            "Stop Recording"
+        } else {
+            "Start Recording..."
+        });
+        self.blackout = status.blackout;
+        if status.redact_rects != self.redact_rects {
+            self.redact_rects = status.redact_rects;
+            self.show_redactions();
+        }
+        self.blackout_btn.set_text(if status.blackout {
+            "End Blackout [F11]"
+        } else {
+            "Blackout [F11]"
+        });
+        self.connected_students = status.connected_students;
+        if self.monitor_index != status.monitor_index {
+            self.monitor_index = status.monitor_index;
+            self.select_monitor_entry();
+        }
+        let quality = (status.jpeg_quality, status.fps);
+        if self.broadcast_quality != Some(quality) {
+            self.broadcast_quality = Some(quality);
+            self.quality_bar.set_pos(usize::from(status.jpeg_quality));
+            self.fps_input.set_text(&status.fps.to_string());
+            self.show_quality_value();
+        }
     }
 
-    fn recv_ack(rx: oneshot::Receiver<Result<(), String>>, timeout_message: &str) -> Result<()> {
-        match rx.blocking_recv() {
-            Ok(Ok(())) => Ok(()),
-            Ok(Err(err)) => Err(anyhow!(err)),
-            Err(_) => Err(anyhow!(timeout_message.to_string())),
-        }
+    fn alert(&self, message: &str) {
+        nwg::simple_message("Attention", message);
     }
+}
 
-    fn recv_list(
-        rx: oneshot::Receiver<Result<Vec<StudentSummary>, String>>,
-        timeout_message: &str,
-    ) -> Result<Vec<StudentSummary>> {
-        match rx.blocking_recv() {
-            Ok(Ok(list)) => Ok(list),
-            Ok(Err(err)) => Err(anyhow!(err)),
-            Err(_) => Err(anyhow!(timeout_message.to_string())),
-        }
+/// Replies to one round of refresh queries; a slot empties once its reply is applied.
+struct PendingRefresh {
+    students: Option<oneshot::Receiver<Result<Vec<StudentSummary>, String>>>,
+    status: Option<oneshot::Receiver<ServerStatus>>,
+    monitors: Option<oneshot::Receiver<Result<Vec<MonitorInfo>, String>>>,
+    metrics: Option<oneshot::Receiver<Vec<StudentMetrics>>>,
+    /// `None` from the start when no student is selected.
+    detail: Option<oneshot::Receiver<Result<StudentDetail, String>>>,
+    chat: Option<oneshot::Receiver<Vec<ChatEntry>>>,
+    /// Past this the round is given up as stuck and the next refresh may go out.
+    deadline: Instant,
+}
+
+impl PendingRefresh {
+    fn is_done(&self) -> bool {
+        self.students.is_none()
+            && self.status.is_none()
+            && self.monitors.is_none()
+            && self.metrics.is_none()
+            && self.detail.is_none()
+            && self.chat.is_none()
     }
+}
+
+/// An action sent to the server, waiting for its acknowledgement.
+struct PendingAck {
+    rx: oneshot::Receiver<Result<(), String>>,
+    deadline: Instant,
+    then: Box<dyn FnOnce(&mut ControlPanel, Result<()>)>,
+}
+
+/// The reply in `slot` once it has arrived, emptying the slot; a server that hung up counts
+/// as a failed reply.
+fn take_reply<T>(slot: &mut Option<oneshot::Receiver<T>>) -> Option<Result<T>> {
+    let rx = slot.as_mut()?;
+    let reply = match rx.try_recv() {
+        Err(TryRecvError::Empty) => return None,
+        Ok(reply) => Ok(reply),
+        Err(TryRecvError::Closed) => Err(anyhow!("Teacher service is not running")),
+    };
+    *slot = None;
+    Some(reply)
+}
 
-    fn recv_status(
-        rx: oneshot::Receiver<ServerStatus>,
-        timeout_message: &str,
-    ) -> Result<ServerStatus> {
-        rx.blocking_recv()
-            .map_err(|_| anyhow!(timeout_message.to_string()))
+/// The display rectangle covered by a drag from `start` to `end` on a `map` sized picture
+/// of a `display` sized screen; `None` for a drag too short to mean anything.
+fn map_drag_to_rect(
+    start: (i32, i32),
+    end: (i32, i32),
+    map: (u32, u32),
+    display: (u32, u32),
+) -> Option<Rect> {
+    let clamp = |(x, y): (i32, i32)| (x.clamp(0, map.0 as i32), y.clamp(0, map.1 as i32));
+    let (start, end) = (clamp(start), clamp(end));
+    let (left, right) = (start.0.min(end.0), start.0.max(end.0));
+    let (top, bottom) = (start.1.min(end.1), start.1.max(end.1));
+    if right - left < MIN_REDACT_DRAG || bottom - top < MIN_REDACT_DRAG {
+        return None;
     }
+    // Round outwards so the redaction covers at least what was dragged across.
+    let scale = |value: i32, map_extent: u32, display_extent: u32, up: bool| {
+        let scaled = u64::from(value as u32) * u64::from(display_extent);
+        let extent = u64::from(map_extent.max(1));
+        let pixels = if up {
+            scaled.div_ceil(extent)
+        } else {
+            scaled / extent
+        };
+        pixels.min(u64::from(display_extent)) as u32
+    };
+    let x = scale(left, map.0, display.0, false);
+    let y = scale(top, map.1, display.1, false);
+    Some(Rect {
+        x,
+        y,
+        width: scale(right, map.0, display.0, true) - x,
+        height: scale(bottom, map.1, display.1, true) - y,
+    })
+}
 
-    fn alert(&self, message: &str) {
-        nwg::simple_message("Attention", message);
+/// Combo box entry for a display, e.g. `1: 1920x1080 at (1920, 0)`.
+fn format_monitor(monitor: &MonitorInfo) -> String {
+    format!(
+        "{}: {}x{} at ({}, {}){}",
+        monitor.index,
+        monitor.width,
+        monitor.height,
+        monitor.x,
+        monitor.y,
+        if monitor.primary { " (primary)" } else { "" }
+    )
+}
+
+/// One chat log line, e.g. `教师 → S01: hello`.
+fn format_chat_line(entry: &ChatEntry) -> String {
+    match &entry.to {
+        Some(student_id) => format!("{} → {}: {}", entry.from, student_id, entry.text),
+        None => format!("{}: {}", entry.from, entry.text),
     }
 }
 
+fn format_detail(detail: &StudentDetail, metrics: Option<&StudentMetrics>) -> String {
+    let summary = &detail.summary;
+    let caps = &detail.capabilities;
+    let flag = |enabled: bool| if enabled { "yes" } else { "no" };
+    let audio = match summary.audio_state {
+        Some(state) if state.muted => format!("muted (volume {}%)", state.volume),
+        Some(state) => format!("playing (volume {}%)", state.volume),
+        None => "not reported".to_string(),
+    };
+    let errors = if detail.recent_errors.is_empty() {
+        "none".to_string()
+    } else {
+        detail.recent_errors.join("; ")
+    };
+    let roster = match &detail.registration {
+        Some(registration) => format!(
+            "{} | seat {} | IP {}",
+            registration.student_name.as_deref().unwrap_or("-"),
+            registration.seat.as_deref().unwrap_or("-"),
+            registration
+                .ip
+                .map_or_else(|| "-".to_string(), |ip| ip.to_string())
+        ),
+        None => "not registered".to_string(),
+    };
+    let identity = if detail.identity_issues.is_empty() {
+        "ok".to_string()
+    } else {
+        format!("SUSPICIOUS: {}", detail.identity_issues.join("; "))
+    };
+    let zoom = match detail.pending_zoom {
+        None => "none".to_string(),
+        Some(None) => "full screen".to_string(),
+        Some(Some(region)) => format!(
+            "({:.2}, {:.2}) {:.2}x{:.2}",
+            region.x, region.y, region.width, region.height
+        ),
+    };
+    let traffic = match metrics {
+        Some(m) => format!(
+            "{} KB sent, {} frames | {} KB/s, {:.1} fps | queue {}, {} dropped, {} ms throttled{}",
+            m.bytes_sent / 1024,
+            m.frames_sent,
+            m.bytes_per_sec / 1024,
+            m.fps,
+            m.queue_depth,
+            m.dropped_frames,
+            m.throttled_ms,
+            if m.is_lagging() {
+                " (falling behind)"
+            } else {
+                ""
+            }
+        ),
+        None => format!("{} KB sent", detail.bytes_sent / 1024),
+    };
+    let thumbnail = match &detail.thumbnail {
+        Some(thumbnail) => format!(
+            "{}s ago ({}x{})",
+            thumbnail.received.elapsed().as_secs(),
+            thumbnail.frame.width,
+            thumbnail.frame.height
+        ),
+        None => "none".to_string(),
+    };
+    format!(
+        "{} ({}) @ {}\r\nClient {} (protocol {}) | last seen {}s ago | spotlighted: {} | settings synced: {}\r\nHand raised: {} | Zoom request: {}\r\nRoster: {}\r\nIdentity: {}\r\nVideo in/out: {}/{} | Audio in/out: {}/{} | Files: {}\r\nAudio: {}\r\nTraffic: {}\r\nThumbnail: {}\r\nRecent errors: {}",
+        summary.display_name,
+        summary.student_id,
+        summary.addr,
+        detail.client_version,
+        detail.protocol_version,
+        detail.last_seen_secs,
+        flag(detail.spotlighted),
+        flag(detail.settings_current),
+        flag(detail.hand_raised),
+        zoom,
+        roster,
+        identity,
+        flag(caps.receive_video),
+        flag(caps.send_video),
+        flag(caps.receive_audio),
+        flag(caps.send_audio),
+        flag(caps.file_transfer),
+        audio,
+        traffic,
+        thumbnail,
+        errors
+    )
+}
+
 trait ResultExt {
     fn log_error(self, context: &str);
 }